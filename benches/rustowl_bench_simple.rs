@@ -1,4 +1,5 @@
 use criterion::{Criterion, criterion_group, criterion_main};
+use rustowl::models::*;
 use std::hint::black_box;
 use std::process::Command;
 use std::time::Duration;
@@ -83,5 +84,204 @@ fn bench_rustowl_comprehensive(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_rustowl_check, bench_rustowl_comprehensive);
+/// Many crates with many functions, like a large workspace
+fn large_workspace() -> Workspace {
+    let decl = |id, from| MirDecl::Other {
+        local: FnLocal::new(id, 1),
+        ty: MirType {
+            name: "i32".into(),
+            reference: None,
+        },
+        lives: (0..8)
+            .map(|i| Range::new(Loc(from + i * 10), Loc(from + i * 10 + 5)).unwrap())
+            .collect(),
+        shared_borrow: Vec::new(),
+        mutable_borrow: Vec::new(),
+        drop: false,
+        drop_range: Vec::new(),
+        definitely_live_at: Vec::new(),
+        maybe_init_at: Vec::new(),
+        must_live_at: Vec::new(),
+        storage_range: Vec::new(),
+    };
+    Workspace(OutputMap::from_iter((0..32).map(|krate| {
+        let items = (0..200)
+            .map(|fn_id| Function {
+                fn_id,
+                name: format!("f{fn_id}"),
+                span: None,
+                test: false,
+                unavailable: false,
+                basic_blocks: Vec::new(),
+                decls: (0..20).map(|id| decl(id, id * 100)).collect(),
+            })
+            .collect();
+        let file = File {
+            items,
+            generated: false,
+            std: false,
+        };
+        (
+            crate_key(&format!("krate{krate}"), "0"),
+            Crate(OutputMap::from_iter([("src/lib.rs".to_owned(), file)])),
+        )
+    })))
+}
+
+fn bench_workspace_serialization(c: &mut Criterion) {
+    let ws = large_workspace();
+    let mut group = c.benchmark_group("workspace_serialization");
+    group.sample_size(20);
+    group.bench_function("serial", |b| {
+        b.iter(|| black_box(ws.to_crate_json(false).unwrap()))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| black_box(ws.to_crate_json(true).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_cache_compression(c: &mut Criterion) {
+    use rustowl::cache::CacheCompression;
+
+    let json = serde_json::to_vec(&large_workspace()).unwrap();
+    let zstd = CacheCompression::Zstd { level: 3 };
+    let compressed = zstd.compress(json.clone()).unwrap();
+    println!(
+        "cache size: {} bytes as JSON, {} bytes with zstd ({:.1}x smaller)",
+        json.len(),
+        compressed.len(),
+        json.len() as f64 / compressed.len() as f64
+    );
+
+    let mut group = c.benchmark_group("cache_compression");
+    group.sample_size(10);
+    group.bench_function("compress", |b| {
+        b.iter(|| black_box(zstd.compress(json.clone()).unwrap()))
+    });
+    group.bench_function("decompress", |b| {
+        b.iter(|| black_box(CacheCompression::decompress("zstd", &compressed).unwrap()))
+    });
+    group.finish();
+}
+
+fn bench_type_interning(c: &mut Criterion) {
+    // long generic instantiations repeated across the declarations
+    let mut ws = large_workspace();
+    let names: Vec<_> = (0..16)
+        .map(|i| {
+            format!("std::collections::HashMap<std::string::String, std::vec::Vec<Item{i}<'_>>>")
+        })
+        .collect();
+    let (mut decls, mut bytes) = (0, 0);
+    for (i, decl) in
+        ws.0.values_mut()
+            .flat_map(|krate| krate.0.values_mut())
+            .flat_map(|file| file.items.iter_mut())
+            .flat_map(|func| func.decls.iter_mut())
+            .enumerate()
+    {
+        let (MirDecl::User { ty, .. } | MirDecl::Other { ty, .. }) = decl;
+        ty.name = names[i % names.len()].as_str().into();
+        decls += 1;
+        bytes += ty.name.len();
+    }
+    let json = serde_json::to_vec(&ws).unwrap();
+
+    let mut interner = TypeInterner::default();
+    ws.intern_types(&mut interner);
+    println!(
+        "type names: {bytes} bytes in {decls} allocations, {} bytes in {} when interned",
+        names.iter().map(|v| v.len()).sum::<usize>(),
+        interner.len()
+    );
+
+    let mut group = c.benchmark_group("type_interning");
+    group.sample_size(10);
+    group.bench_function("parse", |b| {
+        b.iter(|| black_box(Workspace::from_json_reader(json.as_slice()).unwrap()))
+    });
+    group.bench_function("parse_interned", |b| {
+        b.iter(|| {
+            let mut ws = Workspace::from_json_reader(json.as_slice()).unwrap();
+            ws.intern_types(&mut TypeInterner::default());
+            black_box(ws)
+        })
+    });
+    group.finish();
+}
+
+/// `exclude_ranges` before the sweep, splitting each range by the first
+/// overlapping exclude until none overlaps; `O(n·m)`
+fn exclude_ranges_baseline(mut from: Vec<Range>, excludes: Vec<Range>) -> Vec<Range> {
+    use rustowl::utils::{common_range, eliminated_ranges};
+    let mut i = 0;
+    'outer: while i < from.len() {
+        let mut j = 0;
+        while j < excludes.len() {
+            if let Some(common) = common_range(from[i], excludes[j]) {
+                if let Some(r) = Range::new(from[i].from(), common.from() - 1) {
+                    from.push(r);
+                }
+                if let Some(r) = Range::new(common.until() + 1, from[i].until()) {
+                    from.push(r);
+                }
+                from.remove(i);
+                continue 'outer;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    eliminated_ranges(from)
+}
+
+fn bench_exclude_ranges(c: &mut Criterion) {
+    let range = |from, until| Range::new(Loc(from), Loc(until)).unwrap();
+    // 10k ranges with many borrows cutting into them, like a large function
+    let from: Vec<_> = (0..10_000).map(|i| range(i * 20, i * 20 + 15)).collect();
+    let excludes: Vec<_> = (0..10_000).map(|i| range(i * 20 + 5, i * 20 + 8)).collect();
+
+    let mut group = c.benchmark_group("exclude_ranges");
+    group.sample_size(10);
+    group.bench_function("baseline", |b| {
+        b.iter(|| black_box(exclude_ranges_baseline(from.clone(), excludes.clone())))
+    });
+    group.bench_function("sweep", |b| {
+        b.iter(|| {
+            black_box(rustowl::utils::exclude_ranges(
+                from.clone(),
+                excludes.clone(),
+            ))
+        })
+    });
+    // borrows overlapping each other and the drops after them
+    let overlapping: Vec<_> = (0..10_000)
+        .flat_map(|i| {
+            [
+                range(i * 20 + 5, i * 20 + 8),
+                range(i * 20 + 7, i * 20 + 10),
+            ]
+        })
+        .collect();
+    group.bench_function("sweep_overlapping", |b| {
+        b.iter(|| {
+            black_box(rustowl::utils::exclude_ranges(
+                from.clone(),
+                overlapping.clone(),
+            ))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_rustowl_check,
+    bench_rustowl_comprehensive,
+    bench_workspace_serialization,
+    bench_cache_compression,
+    bench_type_interning,
+    bench_exclude_ranges
+);
 criterion_main!(benches);
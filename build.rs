@@ -1,5 +1,4 @@
 use clap::CommandFactory;
-use clap_complete::generate_to;
 use std::env;
 use std::fs;
 use std::io::Error;
@@ -38,12 +37,16 @@ fn main() -> Result<(), Error> {
     let out_dir =
         std::path::Path::new(&env::var("OUT_DIR").expect("OUT_DIR unset. Expected path."))
             .join("rustowl-build-time-out");
-    let mut cmd = Cli::command();
+    let cmd = Cli::command();
     let completion_out_dir = out_dir.join("completions");
     fs::create_dir_all(&completion_out_dir)?;
 
     for shell in Shell::value_variants() {
-        generate_to(*shell, &mut cmd, "rustowl", &completion_out_dir)?;
+        if shell.unsupported_reason().is_some() {
+            continue;
+        }
+        let mut file = fs::File::create(completion_out_dir.join(shell.file_name("rustowl")))?;
+        generate_completion(*shell, "rustowl", &mut file);
     }
     let man_out_dir = out_dir.join("man");
     fs::create_dir_all(&man_out_dir)?;
@@ -0,0 +1,14 @@
+pub fn always() -> usize {
+    let s = String::from("always");
+    s.len()
+}
+
+#[cfg(feature = "gated")]
+pub fn gated() -> usize {
+    let v = vec![1, 2, 3];
+    let r = &v;
+    r.len()
+}
+
+#[cfg(feature = "broken")]
+compile_error!("the `broken` feature is enabled");
@@ -0,0 +1,152 @@
+//! Analysis as a library call, for tools embedding RustOwl
+//!
+//! This drives `cargo check` with `rustowlc` like `rustowl check` does and
+//! merges the results, so callers get a [`Workspace`] without parsing the
+//! output themselves.
+
+use crate::config::Config;
+use crate::lsp::analyze::{Analyzer, AnalyzerEvent};
+use crate::models::Workspace;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum AnalysisError {
+    /// The path is neither in a cargo workspace nor a Rust source file
+    InvalidTarget(PathBuf),
+    /// The compiler failed, with its diagnostics
+    Analysis(String),
+    /// The shutdown signal fired before the analysis finished
+    Cancelled,
+}
+
+impl std::fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidTarget(path) => write!(f, "invalid analysis target: {}", path.display()),
+            Self::Analysis(stderr) => write!(f, "analysis failed:\n{stderr}"),
+            Self::Cancelled => write!(f, "analysis cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+impl AnalysisError {
+    /// The failure of an analysis with the compiler diagnostics `stderr`.
+    /// A single file has no dependencies, so crates it could not find are
+    /// pointed out along with the way to analyze them.
+    pub fn from_diagnostics(stderr: String, single_file: bool) -> Self {
+        let missing = if single_file {
+            unresolved_crates(&stderr)
+        } else {
+            Vec::new()
+        };
+        if missing.is_empty() {
+            return Self::Analysis(stderr);
+        }
+        Self::Analysis(format!(
+            "{stderr}\nexternal crate(s) `{}` are not available when analyzing a single file; \
+             add them to a cargo project and run `rustowl check` in its directory",
+            missing.join("`, `")
+        ))
+    }
+}
+
+/// Crates that rustc reported as missing in the diagnostics `stderr`
+fn unresolved_crates(stderr: &str) -> Vec<String> {
+    const MARKERS: [&str; 3] = [
+        "can't find crate for `",
+        "unlinked crate `",
+        "missing crate `",
+    ];
+    let mut crates = Vec::new();
+    for line in stderr.lines() {
+        for marker in MARKERS {
+            if let Some((_, rest)) = line.split_once(marker)
+                && let Some((name, _)) = rest.split_once('`')
+                && !crates.iter().any(|v| v == name)
+            {
+                crates.push(name.to_owned());
+            }
+        }
+    }
+    crates
+}
+
+/// Analyze the workspace or file at `path` with the options of its
+/// `rustowl.toml`, merging the results of all crates.
+///
+/// When `shutdown` completes first, the compiler is killed and
+/// [`AnalysisError::Cancelled`] is returned.
+pub async fn analyze_workspace(
+    path: &Path,
+    shutdown: impl Future<Output = ()>,
+) -> Result<Workspace, AnalysisError> {
+    let config = Config::discover(path);
+    let analyzer = Analyzer::new(path)
+        .await
+        .map_err(|_| AnalysisError::InvalidTarget(path.to_path_buf()))?
+        .capture_stderr();
+    let single_file = analyzer.is_single_file();
+    let mut ws = Workspace::with_capacity(analyzer.expected_crates());
+    let mut iter = analyzer
+        .analyze(config.all_targets, config.all_features)
+        .await;
+
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            event = iter.next_event() => match event {
+                Some(AnalyzerEvent::Analyzed(analyzed)) => ws.merge(analyzed),
+                Some(AnalyzerEvent::CrateChecked { .. }) => {}
+                None => break,
+            },
+            // dropping the iterator kills the compiler
+            _ = &mut shutdown => return Err(AnalysisError::Cancelled),
+        }
+    }
+
+    let (success, stderr) = iter.finish().await;
+    if success {
+        Ok(ws)
+    } else {
+        Err(AnalysisError::from_diagnostics(stderr, single_file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unresolved_crates_of_single_file() {
+        let stderr = "\
+error[E0463]: can't find crate for `rand`
+error[E0433]: failed to resolve: use of unresolved module or unlinked crate `serde`
+error[E0432]: unresolved import `regex`
+  |
+  = help: you might be missing crate `regex`
+error[E0463]: can't find crate for `rand`
+error[E0308]: mismatched types
+";
+        assert_eq!(unresolved_crates(stderr), ["rand", "serde", "regex"]);
+        assert!(unresolved_crates("error[E0308]: mismatched types").is_empty());
+
+        let AnalysisError::Analysis(message) =
+            AnalysisError::from_diagnostics(stderr.to_owned(), true)
+        else {
+            panic!("not an analysis error");
+        };
+        assert!(message.starts_with(stderr));
+        assert!(message.contains("`rand`, `serde`, `regex`"));
+        assert!(message.contains("cargo project"));
+
+        let AnalysisError::Analysis(message) =
+            AnalysisError::from_diagnostics(stderr.to_owned(), false)
+        else {
+            panic!("not an analysis error");
+        };
+        assert_eq!(message, stderr);
+    }
+}
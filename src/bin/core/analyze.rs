@@ -1,14 +1,210 @@
 mod dataflow_analyzer;
+mod nll_analyzer;
 mod polonius_analyzer;
 
+pub use polonius_analyzer::BORROWCK_TRACE_TARGET;
+
 use super::cache;
 pub use super::compiler::*;
+use super::options::AnalysisOptions;
 use indexmap::IndexMap;
-use rustowl::models::*;
-use std::collections::HashMap;
+use rustowl::{models::*, utils};
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::path::PathBuf;
 use std::pin::Pin;
+use std::str::FromStr;
+
+/// Set of range categories to compute and emit, e.g. `borrows,drops`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecorationKinds {
+    /// `lives`
+    pub lives: bool,
+    /// `shared_borrow` and `mutable_borrow`
+    pub borrows: bool,
+    /// `drop_range`
+    pub drops: bool,
+    /// `must_live_at`
+    pub must_live: bool,
+}
+
+impl DecorationKinds {
+    pub const ALL: Self = Self {
+        lives: true,
+        borrows: true,
+        drops: true,
+        must_live: true,
+    };
+    const NONE: Self = Self {
+        lives: false,
+        borrows: false,
+        drops: false,
+        must_live: false,
+    };
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::ALL
+    }
+
+    /// Clear the range vectors of categories not in the set
+    pub fn retain(&self, decl: &mut MirDecl) {
+        let (MirDecl::User {
+            lives,
+            shared_borrow,
+            mutable_borrow,
+            drop_range,
+            must_live_at,
+            ..
+        }
+        | MirDecl::Other {
+            lives,
+            shared_borrow,
+            mutable_borrow,
+            drop_range,
+            must_live_at,
+            ..
+        }) = decl;
+        if !self.lives {
+            lives.clear();
+        }
+        if !self.borrows {
+            shared_borrow.clear();
+            mutable_borrow.clear();
+        }
+        if !self.drops {
+            drop_range.clear();
+        }
+        if !self.must_live {
+            must_live_at.clear();
+        }
+    }
+}
+
+impl Default for DecorationKinds {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl FromStr for DecorationKinds {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = Self::NONE;
+        for kind in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            match kind {
+                "all" => kinds = Self::ALL,
+                "lives" => kinds.lives = true,
+                "borrows" => kinds.borrows = true,
+                "drops" => kinds.drops = true,
+                "must_live" => kinds.must_live = true,
+                _ => {
+                    return Err(format!(
+                        "unknown decoration kind `{kind}`; expected one of all, lives, borrows, drops, must_live"
+                    ));
+                }
+            }
+        }
+        if kinds == Self::NONE {
+            return Err("no decoration kind is given".to_owned());
+        }
+        Ok(kinds)
+    }
+}
+
+/// Borrow checker the ranges are derived from, set by `RUSTOWL_BORROWCK`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BorrowckMode {
+    /// Precise, but Polonius is unstable and can break or be slow
+    #[default]
+    Polonius,
+    /// Approximate ranges from the standard NLL borrow checker;
+    /// see [`nll_analyzer`]
+    Nll,
+}
+
+impl BorrowckMode {
+    pub fn from_env(value: Option<&str>) -> Self {
+        match value.map(str::parse) {
+            None => Self::default(),
+            Some(Ok(mode)) => mode,
+            Some(Err(e)) => {
+                log::warn!("{e}; fall back to polonius");
+                Self::default()
+            }
+        }
+    }
+}
+
+impl FromStr for BorrowckMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "polonius" => Ok(Self::Polonius),
+            "nll" => Ok(Self::Nll),
+            _ => Err(format!(
+                "unknown borrow checker `{s}`; expected polonius or nll"
+            )),
+        }
+    }
+}
+
+/// Panic strategy of the crate, from `-C panic` (`panic` of a Cargo profile)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PanicStrategy {
+    #[default]
+    Unwind,
+    /// No unwinding, so drops on cleanup paths never run
+    Abort,
+}
+
+impl PanicStrategy {
+    /// From the name of the `-C panic` value, e.g. `abort`; the target default
+    /// if unset, which is to unwind on all targets we analyze
+    pub fn from_desc(desc: Option<&str>) -> Self {
+        match desc {
+            None | Some("unwind") => Self::Unwind,
+            // `abort` and `immediate-abort`
+            Some(_) => Self::Abort,
+        }
+    }
+}
+
+/// Cleanup blocks whose drops cannot run under the [`PanicStrategy`]
+#[derive(Clone, Debug, Default)]
+pub struct UnreachableCleanup(HashSet<u32>);
+
+impl UnreachableCleanup {
+    pub fn new(strategy: PanicStrategy, cleanup_blocks: impl IntoIterator<Item = u32>) -> Self {
+        match strategy {
+            PanicStrategy::Unwind => Self::default(),
+            PanicStrategy::Abort => Self(cleanup_blocks.into_iter().collect()),
+        }
+    }
+
+    /// Whether a drop in `block` is left out of `drop_range`
+    pub fn contains(&self, block: u32) -> bool {
+        self.0.contains(&block)
+    }
+}
+
+/// Deepest nesting of bodies (closures, async blocks, inline consts) that is
+/// analyzed, given by `RUSTOWL_MAX_NESTED_DEPTH`; unlimited if `None`
+pub fn parse_max_nested_depth(value: Option<&str>) -> Option<usize> {
+    let value = value?;
+    match value.trim().parse() {
+        Ok(depth) => Some(depth),
+        Err(_) => {
+            log::warn!("invalid RUSTOWL_MAX_NESTED_DEPTH `{value}`; nested bodies are not limited");
+            None
+        }
+    }
+}
+
+/// Whether a body nested `depth` levels deep is analyzed;
+/// the item containing it is at depth 0
+fn within_nested_depth(depth: usize, max_depth: Option<usize>) -> bool {
+    max_depth.is_none_or(|max_depth| depth <= max_depth)
+}
 
 pub type MirAnalyzeFuture = Pin<Box<dyn Future<Output = MirAnalyzer> + Send + Sync>>;
 
@@ -18,13 +214,25 @@ pub struct AnalyzeResult {
     pub file_hash: String,
     pub mir_hash: String,
     pub analyzed: Function,
+    /// Excluded from the output by `#[rustowl::ignore]`
+    pub ignored: bool,
 }
 
 pub enum MirAnalyzerInitResult {
     Cached(AnalyzeResult),
+    /// Borrowck produced no facts, so the function is marked unavailable
+    Unavailable(AnalyzeResult),
     Analyzer(MirAnalyzeFuture),
 }
 
+/// Clear the contents of `function`, which could not be analyzed, and flag it
+/// so that it is not mistaken for a function without any ownership facts
+fn mark_unavailable(function: &mut Function) {
+    function.basic_blocks.clear();
+    function.decls.clear();
+    function.unavailable = true;
+}
+
 pub struct MirAnalyzer {
     file_path: PathBuf,
     local_decls: IndexMap<LocalId, MirType>,
@@ -33,6 +241,12 @@ pub struct MirAnalyzer {
     basic_blocks: Vec<MirBasicBlock>,
     fn_id: DefId,
     name: String,
+    span: Option<Range>,
+    /// Char length of the source, which no range may exceed
+    source_len: Loc,
+    ignored: bool,
+    /// A `#[test]` function or a closure in it
+    test: bool,
     file_hash: String,
     mir_hash: String,
     accurate_live: HashMap<LocalId, Vec<Range>>,
@@ -43,13 +257,37 @@ pub struct MirAnalyzer {
     storage_range: HashMap<LocalId, Vec<Range>>,
     definitely_live_range: HashMap<LocalId, Vec<Range>>,
     maybe_init_range: HashMap<LocalId, Vec<Range>>,
+    /// Borrowck left the Polonius output empty
+    unavailable: bool,
 }
 impl MirAnalyzer {
     /// initialize analyzer
-    pub fn init(tcx: TyCtxt<'_>, fn_id: DefId) -> HashMap<DefId, MirAnalyzerInitResult> {
+    pub fn init(
+        tcx: TyCtxt<'_>,
+        fn_id: DefId,
+        options: &AnalysisOptions,
+    ) -> HashMap<DefId, MirAnalyzerInitResult> {
         let mut result = HashMap::new();
-
-        let facts = tcx.get_borrowck_facts(fn_id);
+        if tcx.is_test_harness_item(fn_id) {
+            return result;
+        }
+        // closures are ignored along with the function containing them
+        let ignored = tcx.is_ignored(fn_id);
+        let test = tcx.is_test(fn_id);
+
+        let mut facts = tcx.get_borrowck_facts(fn_id);
+        let count = facts.len();
+        facts.retain(|def_id, _| {
+            within_nested_depth(tcx.nesting_depth(*def_id), options.max_nested_depth)
+        });
+        if facts.len() < count {
+            log::info!(
+                "skipped {} nested bodies of {} deeper than {}",
+                count - facts.len(),
+                tcx.def_name(fn_id),
+                options.max_nested_depth.unwrap_or_default(),
+            );
+        }
         for (fn_id, mut facts) in facts {
             let source_info = if let Some(v) = tcx.source_info_from_span(facts.body().span()) {
                 v
@@ -57,6 +295,7 @@ impl MirAnalyzer {
                 continue;
             };
             let name = tcx.def_name(fn_id);
+            let span = tcx.def_range(fn_id, &source_info);
             log::debug!("facts of {fn_id:?} ({name}) prepared; start analyze...");
 
             let body = facts.body();
@@ -71,19 +310,31 @@ impl MirAnalyzer {
 
             // region variables should not be hashed (it results an error)
             // so we erase region variables and set 'static as new region
-            let mir_hash = tcx.get_hash(body.clone().erase_region_variables(tcx).as_rustc());
+            let mut mir_hash = tcx.get_hash(body.clone().erase_region_variables(tcx).as_rustc());
+            // drop ranges differ by the panic strategy for the same MIR
+            if options.panic_strategy == PanicStrategy::Abort {
+                mir_hash.push_str("-abort");
+            }
+            // so do the ranges of each borrow checker
+            if options.borrowck_mode == BorrowckMode::Nll {
+                mir_hash.push_str("-nll");
+            }
+            // and the ranges with macro-generated code dropped
+            if options.exclude_macros {
+                mir_hash.push_str("-no-macros");
+            }
             let file_hash = tcx.get_hash(source_info.source());
+            let source_len = Loc(source_info.cleaned_source().chars().count() as u32);
 
-            let mut cache = cache::CACHE.lock().unwrap();
+            let mut cache = utils::lock_or_recover(&cache::CACHE, "cache");
 
             // setup cache
-            if cache.is_none() {
-                *cache = cache::get_cache(&tcx.crate_name());
-            }
+            cache::load_cache(&mut cache, &tcx.crate_name());
             if let Some(cache) = cache.as_mut()
                 && let Some(analyzed) = cache.get_cache(&file_hash, &mir_hash)
             {
                 log::debug!("MIR cache hit: {fn_id:?}");
+                cache::STATS.record_hit();
                 result.insert(
                     fn_id,
                     MirAnalyzerInitResult::Cached(AnalyzeResult {
@@ -91,10 +342,14 @@ impl MirAnalyzer {
                         file_hash,
                         mir_hash,
                         analyzed: analyzed.clone(),
+                        ignored,
                     }),
                 );
                 continue;
             }
+            if cache.is_some() {
+                cache::STATS.record_miss();
+            }
             drop(cache);
 
             // collect user defined vars
@@ -106,56 +361,60 @@ impl MirAnalyzer {
 
             // build basic blocks map
             // this must be done in local thread
-            let basic_blocks =
-                tcx.collect_basic_blocks(fn_id, &body, &source_info, &location_ranges);
+            let borrow_reasons = tcx.borrow_reasons(fn_id);
+            let basic_blocks = tcx.collect_basic_blocks(
+                fn_id,
+                &body,
+                &source_info,
+                &location_ranges,
+                &borrow_reasons,
+            );
 
             // compute storage ranges based on StorageLive/StorageDead
             // this must be done in local thread as body cannot be sent across threads
             let storage_range = body.compute_storage_ranges(&source_info);
 
+            let unreachable_cleanup =
+                UnreachableCleanup::new(options.panic_strategy, body.cleanup_blocks());
+
             // collect borrow data
             // this must be done in local thread
             let borrow_data = facts.borrow_map();
 
-            let input = facts.polonius_input();
-            let location_table = facts.location_table();
-
-            let analyzer = Box::pin(async move {
-                log::debug!("start re-computing borrow check with dump: true");
-                // compute accurate region, which may eliminate invalid region
-                let output = input.compute();
-                log::debug!("second borrow check finished");
-
-                let accurate_live = polonius_analyzer::get_accurate_live(
-                    &output,
-                    &location_table,
-                    &location_ranges,
-                );
-
-                let must_live = polonius_analyzer::get_must_live(
-                    &output,
-                    &location_table,
-                    &borrow_data,
-                    &location_ranges,
-                );
-
-                let (shared_live, mutable_live) = polonius_analyzer::get_borrow_live(
-                    &output,
-                    &location_table,
-                    &borrow_data,
-                    &location_ranges,
-                );
-
-                let drop_range =
-                    polonius_analyzer::drop_range(&output, &location_table, &location_ranges);
-
-                let reference_local_live = polonius_analyzer::reference_local_live_range(
-                    &output,
-                    region_vids.into_iter(),
-                    &location_table,
-                    &location_ranges,
+            let (Some(input), Some(location_table)) =
+                (facts.polonius_input(), facts.location_table())
+            else {
+                log::warn!("no borrowck facts of {name}; its analysis is unavailable");
+                let mut analyzed = Function {
+                    fn_id: fn_id.as_u32(),
+                    name,
+                    span,
+                    test,
+                    unavailable: false,
+                    basic_blocks: Vec::new(),
+                    decls: Vec::new(),
+                };
+                mark_unavailable(&mut analyzed);
+                result.insert(
+                    fn_id,
+                    MirAnalyzerInitResult::Unavailable(AnalyzeResult {
+                        file_path,
+                        file_hash,
+                        mir_hash,
+                        analyzed,
+                        ignored,
+                    }),
                 );
+                continue;
+            };
 
+            let kinds = options.decoration_kinds;
+            // NLL borrow scopes are computed from the body, which cannot be sent
+            let nll_borrows = match options.borrowck_mode {
+                BorrowckMode::Polonius => None,
+                BorrowckMode::Nll => Some(facts.nll_borrow_locations()),
+            };
+            let analyzer = Box::pin(async move {
                 // CFG based liveness analysis
                 log::debug!("start CFG based liveness check");
                 let cfg_analysis_output = dataflow_analyzer::CfgAnalyzer::walk_cfg(
@@ -169,17 +428,115 @@ impl MirAnalyzer {
                     &cfg_analysis_output,
                     &location_ranges,
                 );
+                let mut unavailable = false;
+
+                let (accurate_live, must_live, shared_live, mutable_live, drop_range) =
+                    if let Some(nll_borrows) = nll_borrows {
+                        let (shared_live, mutable_live) =
+                            nll_analyzer::get_borrow_live(&nll_borrows, &location_ranges);
+                        let must_live = nll_analyzer::get_must_live(&shared_live, &mutable_live);
+                        let accurate_live =
+                            nll_analyzer::get_lives(&definitely_live_range, &maybe_init_range);
+                        let dropped: HashSet<_> = input
+                            .var_dropped_at()
+                            .into_iter()
+                            .filter(|(_, point)| {
+                                let location = location_table.get_rich_location(point).location();
+                                !unreachable_cleanup.contains(location.block())
+                            })
+                            .map(|v| v.0)
+                            .collect();
+                        let drop_range = accurate_live
+                            .iter()
+                            .filter(|(local, _)| dropped.contains(local))
+                            .map(|(local, ranges)| (*local, ranges.clone()))
+                            .collect();
+                        (
+                            accurate_live,
+                            must_live,
+                            shared_live,
+                            mutable_live,
+                            drop_range,
+                        )
+                    } else {
+                        log::debug!("start re-computing borrow check with dump: true");
+                        // compute accurate region, which may eliminate invalid region
+                        let output = input.compute();
+                        log::debug!("second borrow check finished");
+                        if polonius_analyzer::is_output_missing(&input, &output) {
+                            log::warn!("no Polonius output of {name}; its analysis is unavailable");
+                            unavailable = true;
+                        }
 
-                // overwrite live ranges by reference_local_live if the local is
-                // reference (lifetime of reference is differ from variable's lifetime)
-                for (local, ranges) in &mut maybe_init_range {
-                    if let Some(ref_ranges) = reference_local_live.get(local) {
-                        *ranges = ref_ranges.clone();
-                        if let Some(ranges) = definitely_live_range.get_mut(local) {
-                            *ranges = ref_ranges.clone();
+                        // skip the queries of categories which are not emitted
+                        let accurate_live = if kinds.lives {
+                            polonius_analyzer::get_accurate_live(
+                                &output,
+                                &location_table,
+                                &location_ranges,
+                            )
+                        } else {
+                            HashMap::new()
+                        };
+
+                        let must_live = if kinds.must_live {
+                            polonius_analyzer::get_must_live(
+                                &output,
+                                &location_table,
+                                &borrow_data,
+                                &location_ranges,
+                            )
+                        } else {
+                            HashMap::new()
+                        };
+
+                        let (shared_live, mutable_live) = if kinds.borrows {
+                            polonius_analyzer::get_borrow_live(
+                                &output,
+                                &location_table,
+                                &borrow_data,
+                                &location_ranges,
+                            )
+                        } else {
+                            (HashMap::new(), HashMap::new())
+                        };
+
+                        let drop_range = if kinds.drops {
+                            polonius_analyzer::drop_range(
+                                &output,
+                                &location_table,
+                                &location_ranges,
+                                &unreachable_cleanup,
+                            )
+                        } else {
+                            HashMap::new()
+                        };
+
+                        let reference_local_live = polonius_analyzer::reference_local_live_range(
+                            &output,
+                            region_vids.into_iter(),
+                            &location_table,
+                            &location_ranges,
+                        );
+
+                        // overwrite live ranges by reference_local_live if the local is
+                        // reference (lifetime of reference is differ from variable's lifetime)
+                        for (local, ranges) in &mut maybe_init_range {
+                            if let Some(ref_ranges) = reference_local_live.get(local) {
+                                *ranges = ref_ranges.clone();
+                                if let Some(ranges) = definitely_live_range.get_mut(local) {
+                                    *ranges = ref_ranges.clone();
+                                }
+                            }
                         }
-                    }
-                }
+                        (
+                            accurate_live,
+                            must_live,
+                            shared_live,
+                            mutable_live,
+                            drop_range,
+                        )
+                    };
 
                 MirAnalyzer {
                     file_path,
@@ -189,6 +546,10 @@ impl MirAnalyzer {
                     basic_blocks: basic_blocks.values().cloned().collect(),
                     fn_id,
                     name,
+                    span,
+                    source_len,
+                    ignored,
+                    test,
                     file_hash,
                     mir_hash,
                     accurate_live,
@@ -199,6 +560,7 @@ impl MirAnalyzer {
                     storage_range,
                     definitely_live_range,
                     maybe_init_range,
+                    unavailable,
                 }
             });
             result.insert(fn_id, MirAnalyzerInitResult::Analyzer(analyzer));
@@ -289,16 +651,216 @@ impl MirAnalyzer {
         let decls = self.collect_decls();
         let basic_blocks = self.basic_blocks;
 
+        let mut analyzed = Function {
+            fn_id: self.fn_id.as_u32(),
+            name: self.name,
+            span: self.span,
+            test: self.test,
+            unavailable: false,
+            basic_blocks,
+            decls,
+        };
+        if self.unavailable {
+            mark_unavailable(&mut analyzed);
+        }
+        // ranges past the end would be highlighted at impossible positions
+        let fixed = analyzed.clamp_ranges(self.source_len);
+        if 0 < fixed {
+            log::warn!(
+                "{fixed} ranges of {} exceed {}; clamped or dropped",
+                analyzed.name,
+                self.file_path.display()
+            );
+        }
+
         AnalyzeResult {
             file_path: self.file_path,
             file_hash: self.file_hash,
             mir_hash: self.mir_hash,
-            analyzed: Function {
-                fn_id: self.fn_id.as_u32(),
-                name: self.name,
-                basic_blocks,
-                decls,
+            analyzed,
+            ignored: self.ignored,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decl() -> MirDecl {
+        let ranges = || vec![Range::new(Loc(0), Loc(4)).unwrap()];
+        MirDecl::Other {
+            local: FnLocal::new(1, 0),
+            ty: MirType {
+                name: "i32".into(),
+                reference: None,
             },
+            lives: ranges(),
+            shared_borrow: ranges(),
+            mutable_borrow: ranges(),
+            drop: true,
+            drop_range: ranges(),
+            definitely_live_at: ranges(),
+            maybe_init_at: ranges(),
+            must_live_at: ranges(),
+            storage_range: ranges(),
         }
     }
+
+    #[test]
+    fn borrowck_mode_selection() {
+        assert_eq!(BorrowckMode::from_env(None), BorrowckMode::Polonius);
+        assert_eq!(BorrowckMode::from_env(Some("nll")), BorrowckMode::Nll);
+        assert_eq!(BorrowckMode::from_env(Some(" NLL ")), BorrowckMode::Nll);
+        assert_eq!(
+            BorrowckMode::from_env(Some("polonius")),
+            BorrowckMode::Polonius
+        );
+        assert_eq!(
+            BorrowckMode::from_env(Some("chalk")),
+            BorrowckMode::Polonius
+        );
+        assert!("chalk".parse::<BorrowckMode>().is_err());
+    }
+
+    #[test]
+    fn cleanup_drops_by_panic_strategy() {
+        assert_eq!(PanicStrategy::from_desc(None), PanicStrategy::Unwind);
+        assert_eq!(
+            PanicStrategy::from_desc(Some("unwind")),
+            PanicStrategy::Unwind
+        );
+        assert_eq!(
+            PanicStrategy::from_desc(Some("abort")),
+            PanicStrategy::Abort
+        );
+        assert_eq!(
+            PanicStrategy::from_desc(Some("immediate-abort")),
+            PanicStrategy::Abort
+        );
+
+        let cleanup_blocks = [2, 5];
+        let unwind = UnreachableCleanup::new(PanicStrategy::Unwind, cleanup_blocks);
+        assert!(cleanup_blocks.iter().all(|block| !unwind.contains(*block)));
+        let abort = UnreachableCleanup::new(PanicStrategy::Abort, cleanup_blocks);
+        assert!(abort.contains(2) && abort.contains(5));
+        assert!(!abort.contains(0) && !abort.contains(3));
+    }
+
+    #[test]
+    fn unavailable_without_polonius_output() {
+        use rustc_index::Idx;
+        type Facts = polonius_engine::AllFacts<rustc_borrowck::consumers::RustcFacts>;
+
+        let empty = PoloniusInput::from_rustc(Facts::default());
+        let mut facts = Facts::default();
+        facts.var_used_at.push((Idx::new(1), Idx::new(0)));
+        let used = PoloniusInput::from_rustc(facts);
+        // a body without uses has nothing live, like a trivial function
+        assert!(!polonius_analyzer::is_output_missing(
+            &empty,
+            &empty.compute()
+        ));
+        assert!(!polonius_analyzer::is_output_missing(
+            &used,
+            &used.compute()
+        ));
+        assert!(polonius_analyzer::is_output_missing(
+            &used,
+            &empty.compute()
+        ));
+
+        let mut function = Function {
+            fn_id: 1,
+            name: "transmute".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![MirBasicBlock {
+                statements: Vec::new(),
+                terminator: MirTerminator {
+                    kind: MirTerminatorKind::Return,
+                    range: None,
+                },
+                is_cleanup: false,
+            }],
+            decls: vec![decl()],
+        };
+        mark_unavailable(&mut function);
+        assert!(function.unavailable);
+        assert!(function.decls.is_empty() && function.basic_blocks.is_empty());
+        let json = serde_json::to_value(&function).unwrap();
+        assert_eq!(json["unavailable"], true);
+        function.unavailable = false;
+        let json = serde_json::to_value(&function).unwrap();
+        assert!(json.get("unavailable").is_none());
+    }
+
+    #[test]
+    fn nested_depth_limit() {
+        assert_eq!(parse_max_nested_depth(None), None);
+        assert_eq!(parse_max_nested_depth(Some(" 2 ")), Some(2));
+        assert_eq!(parse_max_nested_depth(Some("deep")), None);
+
+        assert!(within_nested_depth(0, Some(0)));
+        assert!(!within_nested_depth(1, Some(0)));
+        assert!(within_nested_depth(2, Some(2)));
+        assert!(!within_nested_depth(3, Some(2)));
+        assert!(within_nested_depth(1000, None));
+    }
+
+    #[test]
+    fn decoration_kinds_parsing() {
+        assert_eq!(
+            "borrows, drops".parse(),
+            Ok(DecorationKinds {
+                lives: false,
+                borrows: true,
+                drops: true,
+                must_live: false,
+            })
+        );
+        assert_eq!("all".parse(), Ok(DecorationKinds::ALL));
+        assert_eq!(
+            "lives,borrows,drops,must_live".parse(),
+            Ok(DecorationKinds::ALL)
+        );
+        assert!("borrows,moves".parse::<DecorationKinds>().is_err());
+        assert!(" , ".parse::<DecorationKinds>().is_err());
+    }
+
+    #[test]
+    fn decoration_kinds_filter() {
+        let mut filtered = decl();
+        "borrows"
+            .parse::<DecorationKinds>()
+            .unwrap()
+            .retain(&mut filtered);
+        let MirDecl::Other {
+            lives,
+            shared_borrow,
+            mutable_borrow,
+            drop,
+            drop_range,
+            must_live_at,
+            storage_range,
+            ..
+        } = &filtered
+        else {
+            unreachable!()
+        };
+        assert!(lives.is_empty() && drop_range.is_empty() && must_live_at.is_empty());
+        assert_eq!(shared_borrow.len(), 1);
+        assert_eq!(mutable_borrow.len(), 1);
+        // categories outside of the set are kept as they are
+        assert!(*drop);
+        assert_eq!(storage_range.len(), 1);
+
+        let mut all = decl();
+        DecorationKinds::ALL.retain(&mut all);
+        assert_eq!(
+            serde_json::to_value(all).unwrap(),
+            serde_json::to_value(decl()).unwrap()
+        );
+    }
 }
@@ -0,0 +1,66 @@
+//! Approximate analysis without Polonius, selected by `RUSTOWL_BORROWCK=nll`.
+//!
+//! Borrows are live where NLL keeps them in scope, computed from
+//! `rustc_borrowck::consumers` instead of the Polonius output. This is more
+//! robust, as Polonius is unstable, but less precise:
+//!
+//! - a borrow is live until it goes out of scope on every path, so its range
+//!   is often longer than Polonius' `loan_live_at`;
+//! - `must_live_at` of a local is every range where its borrows are live;
+//! - `lives` and `drop_range` come from the CFG-based analysis in
+//!   [`super::dataflow_analyzer`] instead of Polonius' liveness.
+
+use super::*;
+use rustowl::utils;
+use std::collections::HashMap;
+
+/// returns (shared, mutable)
+pub fn get_borrow_live(
+    borrow_locations: &[(BorrowData, Vec<Location>)],
+    location_ranges: &LocationRanges,
+) -> (HashMap<LocalId, Vec<Range>>, HashMap<LocalId, Vec<Range>>) {
+    let mut shared_borrows = HashMap::new();
+    let mut mutable_borrows = HashMap::new();
+    for (data, locations) in borrow_locations {
+        let (borrows, borrowed) = match data {
+            BorrowData::Shared { borrowed, .. } => (&mut shared_borrows, borrowed),
+            BorrowData::Mutable { borrowed, .. } => (&mut mutable_borrows, borrowed),
+        };
+        borrows
+            .entry(*borrowed)
+            .or_insert_with(Vec::new)
+            .extend(locations.iter().map(|v| RichLocation::Mid(*v)));
+    }
+    (
+        local_ranges(shared_borrows, location_ranges, utils::eliminated_ranges),
+        local_ranges(mutable_borrows, location_ranges, utils::eliminated_ranges),
+    )
+}
+
+/// A local must live wherever any of its borrows is live
+pub fn get_must_live(
+    shared_live: &HashMap<LocalId, Vec<Range>>,
+    mutable_live: &HashMap<LocalId, Vec<Range>>,
+) -> HashMap<LocalId, Vec<Range>> {
+    union_by_local(shared_live, mutable_live)
+}
+
+/// A local is live wherever it may be initialized
+pub fn get_lives(
+    definitely_live: &HashMap<LocalId, Vec<Range>>,
+    maybe_init: &HashMap<LocalId, Vec<Range>>,
+) -> HashMap<LocalId, Vec<Range>> {
+    union_by_local(definitely_live, maybe_init)
+}
+
+fn union_by_local(
+    ranges1: &HashMap<LocalId, Vec<Range>>,
+    ranges2: &HashMap<LocalId, Vec<Range>>,
+) -> HashMap<LocalId, Vec<Range>> {
+    let mut union = ranges1.clone();
+    for (local, ranges) in ranges2 {
+        let entry = union.entry(*local).or_default();
+        *entry = utils::union_ranges(std::mem::take(entry), ranges.clone());
+    }
+    union
+}
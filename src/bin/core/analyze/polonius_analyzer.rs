@@ -1,22 +1,38 @@
-use rayon::prelude::*;
 use rustowl::{models::*, utils};
 use std::collections::{HashMap, HashSet};
 
 use super::*;
 
+/// Largest gap between two live ranges that is still shown as one,
+/// which bridges the boundary between adjacent statements
+pub const LIVE_RANGE_GAP_TOLERANCE: u32 = 1;
+
+/// Log target of the intermediate maps below, enabled by `--trace-borrowck`
+pub const BORROWCK_TRACE_TARGET: &str = module_path!();
+
+/// Whether borrowck left `datafrog` empty although `input` uses locals,
+/// which happens for bodies rustc does not fully borrow-check; every range
+/// derived from it would be empty rather than correct
+pub fn is_output_missing(input: &PoloniusInput, datafrog: &PoloniusOutput) -> bool {
+    input.has_uses() && datafrog.is_empty()
+}
+
 pub fn get_accurate_live(
     datafrog: &PoloniusOutput,
     location_table: &PoloniusLocationTable,
     location_ranges: &LocationRanges,
 ) -> HashMap<LocalId, Vec<Range>> {
-    get_range(
+    let live = get_range(
         datafrog
             .var_live_on_entry()
             .iter()
             .map(|(p, v)| (*p, v.iter().copied())),
         location_table,
         location_ranges,
-    )
+        LIVE_RANGE_GAP_TOLERANCE,
+    );
+    log::trace!("accurate live: local -> ranges: {live:?}");
+    live
 }
 
 /// returns (shared, mutable)
@@ -49,26 +65,11 @@ pub fn get_borrow_live(
             }
         }
     }
-    (
-        shared_borrows
-            .into_par_iter()
-            .map(|(local, locations)| {
-                (
-                    local,
-                    utils::eliminated_ranges(rich_locations_to_ranges(location_ranges, &locations)),
-                )
-            })
-            .collect(),
-        mutable_borrows
-            .into_par_iter()
-            .map(|(local, locations)| {
-                (
-                    local,
-                    utils::eliminated_ranges(rich_locations_to_ranges(location_ranges, &locations)),
-                )
-            })
-            .collect(),
-    )
+    let shared = local_ranges(shared_borrows, location_ranges, utils::eliminated_ranges);
+    let mutable = local_ranges(mutable_borrows, location_ranges, utils::eliminated_ranges);
+    log::trace!("shared borrows: local -> ranges: {shared:?}");
+    log::trace!("mutable borrows: local -> ranges: {mutable:?}");
+    (shared, mutable)
 }
 
 pub fn get_must_live(
@@ -96,6 +97,8 @@ pub fn get_must_live(
         }
     }
 
+    log::trace!("region -> live locations: {region_locations:?}");
+
     // obtain a map that region -> locations where region must be live
     // For subset relation sup >= sub at point p:
     // - if sup is live at p, sup itself must be live at p (for borrows contained in sup)
@@ -125,6 +128,8 @@ pub fn get_must_live(
         }
     }
 
+    log::trace!("region -> must live locations: {region_must_locations:?}");
+
     // Build a map from borrow to all regions that ever contain it
     let mut borrow_regions = HashMap::new();
     for (_location, region_borrows) in output.origin_contains_loan_at().iter() {
@@ -154,7 +159,10 @@ pub fn get_must_live(
         }
     }
 
-    HashMap::from_iter(local_must_locations.iter().map(|(local, locations)| {
+    log::trace!("borrow -> regions: {borrow_regions:?}");
+    log::trace!("local -> must live locations: {local_must_locations:?}");
+
+    let must_live = HashMap::from_iter(local_must_locations.iter().map(|(local, locations)| {
         (
             *local,
             utils::eliminated_ranges(rich_locations_to_ranges(
@@ -165,23 +173,34 @@ pub fn get_must_live(
                     .collect::<Vec<_>>(),
             )),
         )
-    }))
+    }));
+    log::trace!("must live: local -> ranges: {must_live:?}");
+    must_live
 }
 
-/// obtain map from local id to living range
+/// obtain map from local id to living range,
+/// without the drops of `unreachable_cleanup` blocks
 pub fn drop_range(
     datafrog: &PoloniusOutput,
     location_table: &PoloniusLocationTable,
     location_ranges: &LocationRanges,
+    unreachable_cleanup: &UnreachableCleanup,
 ) -> HashMap<LocalId, Vec<Range>> {
-    get_range(
+    let drop = get_range(
         datafrog
             .var_drop_live_on_entry()
             .iter()
+            .filter(|(p, _)| {
+                let location = location_table.get_rich_location(p).location();
+                !unreachable_cleanup.contains(location.block())
+            })
             .map(|(p, v)| (*p, v.iter().copied())),
         location_table,
         location_ranges,
-    )
+        LIVE_RANGE_GAP_TOLERANCE,
+    );
+    log::trace!("drop live: local -> ranges: {drop:?}");
+    drop
 }
 
 pub fn reference_local_live_range(
@@ -221,6 +240,7 @@ pub fn get_range(
     live_on_entry: impl Iterator<Item = (Point, impl Iterator<Item = LocalId>)>,
     location_table: &PoloniusLocationTable,
     location_ranges: &LocationRanges,
+    gap_tolerance: u32,
 ) -> HashMap<LocalId, Vec<Range>> {
     let mut local_locs = HashMap::new();
     for (point, locals) in live_on_entry {
@@ -232,13 +252,7 @@ pub fn get_range(
                 .push(location);
         }
     }
-    local_locs
-        .into_par_iter()
-        .map(|(local, locations)| {
-            (
-                local,
-                utils::eliminated_ranges(rich_locations_to_ranges(location_ranges, &locations)),
-            )
-        })
-        .collect()
+    local_ranges(local_locs, location_ranges, |ranges| {
+        utils::coalesce_ranges(ranges, gap_tolerance)
+    })
 }
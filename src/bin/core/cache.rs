@@ -1,51 +1,486 @@
+use rustowl::cache::{
+    CACHE_SCHEMA_VERSION, CacheCompression, CacheEntries, decode_cache_file, encode_cache_file,
+    migrate_entries,
+};
 use rustowl::models::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::io::Write;
-use std::sync::{LazyLock, Mutex};
+use std::sync::{
+    LazyLock, Mutex,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
 
 pub static CACHE: LazyLock<Mutex<Option<CacheData>>> = LazyLock::new(|| Mutex::new(None));
+/// Set once the cache could not be loaded, so that later functions of this run
+/// do not wait for the lock again
+static CACHE_DISABLED: AtomicBool = AtomicBool::new(false);
+pub static STATS: CacheStats = CacheStats::new();
 
-/// Single file cache body
+#[derive(Default, Debug)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+}
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            inserts: AtomicU64::new(0),
+        }
+    }
+    fn snapshot(&self) -> CacheStatsSnapshot {
+        CacheStatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Cache hit, miss and insert counters
+///
+/// The per-run counters are zeroed by [`CacheStats::reset_stats`] when an analysis
+/// run starts, while the lifetime totals keep accumulating.
+#[derive(Default, Debug)]
+pub struct CacheStats {
+    run: Counters,
+    total: Counters,
+}
+impl CacheStats {
+    pub const fn new() -> Self {
+        Self {
+            run: Counters::new(),
+            total: Counters::new(),
+        }
+    }
+    fn record(&self, counter: impl Fn(&Counters) -> &AtomicU64) {
+        counter(&self.run).fetch_add(1, Ordering::Relaxed);
+        counter(&self.total).fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn record_hit(&self) {
+        self.record(|v| &v.hits);
+    }
+    pub fn record_miss(&self) {
+        self.record(|v| &v.misses);
+    }
+    pub fn record_insert(&self) {
+        self.record(|v| &v.inserts);
+    }
+    /// Zero the per-run counters
+    pub fn reset_stats(&self) {
+        self.run.hits.store(0, Ordering::Relaxed);
+        self.run.misses.store(0, Ordering::Relaxed);
+        self.run.inserts.store(0, Ordering::Relaxed);
+    }
+    /// Counters since the last [`CacheStats::reset_stats`]
+    pub fn run(&self) -> CacheStatsSnapshot {
+        self.run.snapshot()
+    }
+    /// Counters over the process lifetime
+    pub fn total(&self) -> CacheStatsSnapshot {
+        self.total.snapshot()
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CacheStatsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+}
+impl CacheStatsSnapshot {
+    /// Operations between `earlier` and this snapshot
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            inserts: self.inserts.saturating_sub(earlier.inserts),
+        }
+    }
+}
+impl CacheStatsSnapshot {
+    /// `hits / (hits + misses)`, or 0 without any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+impl std::fmt::Display for CacheStatsSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hit(s), {} miss(es), {} insert(s)",
+            self.hits, self.misses, self.inserts
+        )
+    }
+}
+
+/// Single crate cache body
+///
+/// this is a map: MIR body hash -> (file hash -> analyze result)
 ///
-/// this is a map: file hash -> (MIR body hash -> analyze result)
+/// The MIR body hash is the primary key: MIR is built after macro expansion
+/// and name resolution, so it also changes when something the function depends on
+/// changes (a type, a macro or an item in another module), even if its own file does not.
+/// The file hash only guards the source ranges in the result, which move whenever
+/// anything in the file is edited.
 ///
 /// Note: Cache can be utilized when neither
 /// the MIR body nor the entire file is modified.
+///
+/// The memory of an entry is the serialized size of its result. Past
+/// [`CacheData::max_memory_bytes`] the oldest entries are evicted.
+///
+/// The entries are kept oldest first, and so they are written and read back:
+/// an insert moves its MIR body hash and file hash to the end, so that the
+/// functions used by the latest runs are evicted last.
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 #[serde(transparent)]
-pub struct CacheData(HashMap<String, HashMap<String, Function>>);
+pub struct CacheData {
+    entries: CacheEntries,
+    #[serde(skip)]
+    memory_bytes: usize,
+    #[serde(skip)]
+    max_memory_bytes: Option<usize>,
+    /// Entries larger than `max_memory_bytes` on their own
+    #[serde(skip)]
+    oversized: usize,
+    /// Entries evicted to stay within `max_memory_bytes`
+    #[serde(skip)]
+    evictions: usize,
+}
 impl CacheData {
     pub fn get_cache(&self, file_hash: &str, mir_hash: &str) -> Option<Function> {
-        self.0.get(file_hash).and_then(|v| v.get(mir_hash)).cloned()
+        self.entries
+            .get(mir_hash)
+            .and_then(|v| v.get(file_hash))
+            .cloned()
     }
     pub fn insert_cache(&mut self, file_hash: String, mir_hash: String, analyzed: Function) {
-        self.0
-            .entry(file_hash)
-            .or_default()
-            .insert(mir_hash, analyzed);
+        let size = entry_size(&analyzed);
+        if self.max_memory_bytes.is_some_and(|max| size > max) {
+            log::warn!(
+                "cache entry of {} takes {size} bytes, more than the cache limit",
+                analyzed.name
+            );
+            self.oversized += 1;
+        }
+        let entry = self.entries.entry(mir_hash);
+        let index = entry.index();
+        let files = entry.or_default();
+        if let Some(replaced) = files.shift_remove(&file_hash) {
+            self.memory_bytes -= entry_size(&replaced);
+        }
+        files.insert(file_hash, analyzed);
+        self.entries.move_index(index, self.entries.len() - 1);
+        self.memory_bytes += size;
+        self.evict();
+    }
+
+    /// Limit the memory of the entries, evicting the oldest ones at once if it
+    /// is exceeded
+    pub fn set_max_memory_bytes(&mut self, max_memory_bytes: Option<usize>) {
+        self.max_memory_bytes = max_memory_bytes;
+        self.evict();
+    }
+
+    /// Sum of the serialized sizes of the entries
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    /// Measure the entries, which deserialization leaves unmeasured
+    fn recount(&mut self) {
+        self.memory_bytes = self
+            .entries
+            .values()
+            .flat_map(|files| files.values())
+            .map(entry_size)
+            .sum();
+    }
+
+    /// Evict the oldest entries until the memory is within the limit.
+    /// The newest entry is never evicted, so that an oversized one is kept
+    /// rather than evicted right after its insertion.
+    fn evict(&mut self) {
+        let Some(max) = self.max_memory_bytes else {
+            return;
+        };
+        let mut left = self.entry_count();
+        // MIR body hashes left without entries, drained at once
+        let mut emptied = 0;
+        for (mir_hash, files) in &mut self.entries {
+            while self.memory_bytes > max
+                && left > 1
+                && let Some((_, analyzed)) = files.shift_remove_index(0)
+            {
+                self.memory_bytes -= entry_size(&analyzed);
+                self.evictions += 1;
+                left -= 1;
+                log::debug!("cache entry evicted: {mir_hash}");
+            }
+            if !files.is_empty() {
+                break;
+            }
+            emptied += 1;
+        }
+        self.entries.drain(..emptied);
     }
 }
 
-/// Get cache data
+/// Memory of a cache entry, by the serialized size of its result
+fn entry_size(analyzed: &Function) -> usize {
+    serde_json::to_vec(analyzed).map_or(0, |v| v.len())
+}
+
+/// Cache statistics of a crate for tooling, written by [`write_stats`]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CacheStatsReport {
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub hit_rate: f64,
+    /// Number of cached functions
+    pub entries: usize,
+    /// Approximate memory of the cache, the sum of the serialized sizes of
+    /// the cached functions
+    pub memory_bytes: usize,
+    /// Functions larger than `RUSTOWL_CACHE_MAX_MEMORY` on their own
+    pub oversized_entries: usize,
+    /// Functions evicted to stay within `RUSTOWL_CACHE_MAX_MEMORY`, including
+    /// those evicted when the cache was read
+    pub evictions: usize,
+}
+
+impl CacheData {
+    /// Number of cached functions
+    pub fn entry_count(&self) -> usize {
+        self.entries.values().map(|v| v.len()).sum()
+    }
+    pub fn stats_report(&self, crate_name: &str, stats: CacheStatsSnapshot) -> CacheStatsReport {
+        CacheStatsReport {
+            crate_name: crate_name.to_owned(),
+            hits: stats.hits,
+            misses: stats.misses,
+            inserts: stats.inserts,
+            hit_rate: stats.hit_rate(),
+            entries: self.entry_count(),
+            memory_bytes: self.memory_bytes,
+            oversized_entries: self.oversized,
+            evictions: self.evictions,
+        }
+    }
+}
+
+/// Path of the cache statistics file, set by `RUSTOWL_CACHE_STATS_FILE`
+pub fn stats_file() -> Option<std::path::PathBuf> {
+    std::env::var_os("RUSTOWL_CACHE_STATS_FILE")
+        .filter(|v| !v.is_empty())
+        .map(std::path::PathBuf::from)
+}
+
+/// Append `report` to `path` as a JSON line
 ///
-/// If cache is not enabled, then return None.
-/// If file is not exists, it returns empty [`CacheData`].
-pub fn get_cache(krate: &str) -> Option<CacheData> {
-    if let Some(cache_path) = rustowl::cache::get_cache_path() {
-        let cache_path = cache_path.join(format!("{krate}.json"));
-        let s = match std::fs::read_to_string(&cache_path) {
+/// Each crate is compiled by its own process, so every crate adds a line.
+pub fn write_stats(path: &std::path::Path, report: &CacheStatsReport) {
+    let mut line = serde_json::to_string(report).unwrap();
+    line.push('\n');
+    let mut f = match std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("failed to open cache stats file: {e}");
+            return;
+        }
+    };
+    if let Err(e) = f.write_all(line.as_bytes()) {
+        log::warn!("failed to write cache stats file: {e}");
+    }
+}
+
+/// Converts a cache body of another schema version, if it can
+///
+/// The version is `None` for caches written before versioning.
+pub type CacheMigration = fn(Option<u32>, &str) -> Option<CacheData>;
+
+/// How cache files are read and written
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    /// Version written into cache files; files of other versions are migrated
+    /// or discarded
+    pub schema_version: u32,
+    pub migrate: CacheMigration,
+    /// Codec of written files; files are read with the codec they record
+    pub compression: CacheCompression,
+    /// Memory of the entries beyond which the oldest are evicted
+    /// (`RUSTOWL_CACHE_MAX_MEMORY`, in bytes); unlimited if `None`
+    pub max_memory_bytes: Option<usize>,
+}
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            schema_version: CACHE_SCHEMA_VERSION,
+            migrate: migrate_cache,
+            compression: CacheCompression::None,
+            max_memory_bytes: None,
+        }
+    }
+}
+
+/// Convert the caches [`migrate_entries`] knows, the same way as
+/// `rustowl cache migrate` does
+fn migrate_cache(version: Option<u32>, body: &str) -> Option<CacheData> {
+    let entries = migrate_entries(version, body)
+        .inspect_err(|e| log::debug!("{e}"))
+        .ok()?;
+    Some(CacheData {
+        entries,
+        ..CacheData::default()
+    })
+}
+
+impl CacheConfig {
+    /// The default configuration with the compression set by
+    /// `RUSTOWL_CACHE_COMPRESSION` and the limit by `RUSTOWL_CACHE_MAX_MEMORY`
+    pub fn from_env() -> Self {
+        Self {
+            compression: CacheCompression::from_env(|name| std::env::var(name).ok()),
+            max_memory_bytes: parse_max_memory_bytes(
+                std::env::var("RUSTOWL_CACHE_MAX_MEMORY").ok().as_deref(),
+            ),
+            ..Self::default()
+        }
+    }
+
+    /// Serialize and compress `cache` behind the header line
+    pub fn encode(&self, cache: &CacheData) -> std::io::Result<Vec<u8>> {
+        encode_cache_file(
+            self.schema_version,
+            self.compression,
+            serde_json::to_vec(cache)?,
+        )
+    }
+
+    /// Decompress and deserialize a cache file; caches of other versions go through
+    /// [`CacheConfig::migrate`] and are discarded unless migrated, as they would
+    /// not deserialize or, worse, deserialize into wrong data
+    pub fn decode(&self, bytes: &[u8]) -> CacheData {
+        let mut cache = self.decode_entries(bytes);
+        cache.recount();
+        cache.set_max_memory_bytes(self.max_memory_bytes);
+        cache
+    }
+
+    fn decode_entries(&self, bytes: &[u8]) -> CacheData {
+        let (version, body) = match decode_cache_file(bytes) {
             Ok(v) => v,
             Err(e) => {
-                log::warn!("failed to read incremental cache file: {e}");
-                return Some(CacheData::default());
+                log::warn!("discard broken incremental cache: {e}");
+                return CacheData::default();
+            }
+        };
+        if version == Some(self.schema_version) {
+            return serde_json::from_slice(&body).unwrap_or_else(|e| {
+                log::warn!("discard broken incremental cache: {e}");
+                CacheData::default()
+            });
+        }
+        let version_name = version.map_or("unversioned".to_owned(), |v| format!("version {v}"));
+        let migrated = std::str::from_utf8(&body)
+            .ok()
+            .and_then(|body| (self.migrate)(version, body));
+        match migrated {
+            Some(cache) => {
+                log::info!(
+                    "migrated incremental cache from {version_name} to version {}",
+                    self.schema_version
+                );
+                cache
+            }
+            None => {
+                log::info!(
+                    "discard incremental cache of {version_name}; this build uses version {}",
+                    self.schema_version
+                );
+                CacheData::default()
+            }
+        }
+    }
+
+    /// Read the cache file at `path`; empty if it does not exist
+    pub fn read_file(&self, path: &std::path::Path) -> CacheData {
+        match std::fs::read(path) {
+            Ok(bytes) => {
+                log::debug!("cache read: {}", path.display());
+                self.decode(&bytes)
+            }
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("failed to read incremental cache file: {e}");
+                }
+                let mut cache = CacheData::default();
+                cache.set_max_memory_bytes(self.max_memory_bytes);
+                cache
             }
+        }
+    }
+
+    /// Write `cache` to `path` through a temporary file renamed over it, so that
+    /// readers never see a partly written cache
+    pub fn write_file(&self, path: &std::path::Path, cache: &CacheData) -> std::io::Result<()> {
+        let dir = match path.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v,
+            _ => std::path::Path::new("."),
         };
-        let read = serde_json::from_str(&s).ok();
-        log::debug!("cache read: {}", cache_path.display());
-        read
-    } else {
-        None
+        let mut f = tempfile::NamedTempFile::new_in(dir)?;
+        f.write_all(&self.encode(cache)?)?;
+        f.persist(path)?;
+        Ok(())
+    }
+}
+
+fn parse_max_memory_bytes(value: Option<&str>) -> Option<usize> {
+    let value = value?;
+    match value.trim().parse() {
+        Ok(bytes) => Some(bytes),
+        Err(_) => {
+            log::warn!("invalid RUSTOWL_CACHE_MAX_MEMORY `{value}`; the cache is not limited");
+            None
+        }
+    }
+}
+
+/// Get cache data
+///
+/// If cache is not enabled, or another process holds the cache for too long, then return None.
+/// If file is not exists or of another schema version, it returns empty [`CacheData`].
+pub fn get_cache(krate: &str) -> Option<CacheData> {
+    let cache_path = rustowl::cache::get_cache_path()?.join(format!("{krate}.json"));
+    let _lock = rustowl::cache::lock_cache(&cache_path, false, rustowl::cache::CACHE_LOCK_TIMEOUT)?;
+    Some(CacheConfig::from_env().read_file(&cache_path))
+}
+
+/// Load the cache of `krate` into `cache` unless it is loaded already or could
+/// not be loaded earlier in this run
+pub fn load_cache(cache: &mut Option<CacheData>, krate: &str) {
+    if cache.is_some() || CACHE_DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    *cache = get_cache(krate);
+    if cache.is_none() {
+        log::debug!("incremental cache disabled for this run");
+        CACHE_DISABLED.store(true, Ordering::Relaxed);
     }
 }
 
@@ -56,22 +491,369 @@ pub fn write_cache(krate: &str, cache: &CacheData) {
             return;
         }
         let cache_path = cache_path.join(format!("{krate}.json"));
-        let s = serde_json::to_string(cache).unwrap();
-        let mut f = match std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&cache_path)
-        {
-            Ok(v) => v,
-            Err(e) => {
-                log::warn!("failed to open incremental cache file: {e}");
-                return;
-            }
+        let Some(_lock) =
+            rustowl::cache::lock_cache(&cache_path, true, rustowl::cache::CACHE_LOCK_TIMEOUT)
+        else {
+            return;
         };
-        if let Err(e) = f.write_all(s.as_bytes()) {
+        if let Err(e) = CacheConfig::from_env().write_file(&cache_path, cache) {
             log::warn!("failed to write incremental cache file: {e}");
+            return;
         }
         log::debug!("incremental cache saved: {}", cache_path.display());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustowl::cache::{CACHE_HEADER, MigrationOutcome, migrate_cache_dir};
+
+    fn function(fn_id: u32) -> Function {
+        Function {
+            fn_id,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn cache_is_keyed_by_mir_and_file_hash() {
+        let mut cache = CacheData::default();
+        cache.insert_cache("file".to_owned(), "mir".to_owned(), function(1));
+        assert_eq!(cache.get_cache("file", "mir").map(|v| v.fn_id), Some(1));
+
+        // a dependency changed: same file, different MIR
+        assert!(cache.get_cache("file", "mir2").is_none());
+        // the file was edited elsewhere: same MIR, moved ranges
+        assert!(cache.get_cache("file2", "mir").is_none());
+
+        cache.insert_cache("file".to_owned(), "mir2".to_owned(), function(2));
+        assert_eq!(cache.get_cache("file", "mir2").map(|v| v.fn_id), Some(2));
+        assert_eq!(cache.get_cache("file", "mir").map(|v| v.fn_id), Some(1));
+    }
+
+    #[test]
+    fn cache_file_is_replaced_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owl.json");
+        let config = CacheConfig::default();
+        let mut cache = CacheData::default();
+        cache.insert_cache("file".to_owned(), "mir".to_owned(), function(1));
+        config.write_file(&path, &cache).unwrap();
+        cache.insert_cache("file".to_owned(), "mir2".to_owned(), function(2));
+        config.write_file(&path, &cache).unwrap();
+
+        assert_eq!(config.read_file(&path).entry_count(), 2);
+        // no temporary file is left behind
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|v| v.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["owl.json"]);
+    }
+
+    #[test]
+    fn cache_of_other_schema_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owl.json");
+        let mut cache = CacheData::default();
+        cache.insert_cache("file".to_owned(), "mir".to_owned(), function(1));
+
+        let config = CacheConfig::default();
+        config.write_file(&path, &cache).unwrap();
+        let read = config.read_file(&path);
+        assert_eq!(read.get_cache("file", "mir").map(|v| v.fn_id), Some(1));
+
+        // written by a build with another model
+        let newer = CacheConfig {
+            schema_version: CACHE_SCHEMA_VERSION + 1,
+            ..config
+        };
+        assert_eq!(newer.read_file(&path).entry_count(), 0);
+        // written before versioning, in which the body alone was stored
+        std::fs::write(&path, serde_json::to_string(&cache).unwrap()).unwrap();
+        assert_eq!(config.read_file(&path).entry_count(), 0);
+        // written before borrows carried their reason
+        let body = serde_json::to_string(&cache).unwrap();
+        std::fs::write(&path, format!("{CACHE_HEADER}1\n{body}")).unwrap();
+        assert_eq!(config.read_file(&path).entry_count(), 0);
+        std::fs::write(&path, r#"{"mir": {"file": {"fn_id": "main"}}}"#).unwrap();
+        assert_eq!(config.read_file(&path).entry_count(), 0);
+        // not even JSON
+        std::fs::write(&path, format!("{CACHE_HEADER}{CACHE_SCHEMA_VERSION}\n{{")).unwrap();
+        assert_eq!(config.read_file(&path).entry_count(), 0);
+        assert_eq!(
+            config
+                .read_file(&dir.path().join("none.json"))
+                .entry_count(),
+            0
+        );
+
+        // a fresh cache replaces the discarded one
+        std::fs::write(&path, "rustowl-cache v0\n{}").unwrap();
+        let mut fresh = config.read_file(&path);
+        fresh.insert_cache("file".to_owned(), "mir".to_owned(), function(2));
+        config.write_file(&path, &fresh).unwrap();
+        let header = format!("{CACHE_HEADER}{CACHE_SCHEMA_VERSION}\n");
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with(&header));
+        let read = config.read_file(&path);
+        assert_eq!(read.get_cache("file", "mir").map(|v| v.fn_id), Some(2));
+
+        // the hook converts caches it knows
+        let migrating = CacheConfig {
+            migrate: |version, body| {
+                (version == Some(0)).then(|| serde_json::from_str(body).unwrap())
+            },
+            ..config
+        };
+        let old = format!(
+            "rustowl-cache v0\n{}",
+            serde_json::to_string(&cache).unwrap()
+        );
+        assert_eq!(migrating.decode(old.as_bytes()).entry_count(), 1);
+        assert_eq!(migrating.decode(b"rustowl-cache v7\n{}").entry_count(), 0);
+    }
+
+    #[test]
+    fn migrate_old_cache_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = CacheData::default();
+        cache.insert_cache("file".to_owned(), "mir".to_owned(), function(1));
+        let body = serde_json::to_string(&cache).unwrap();
+        // written before versioning, without the header
+        std::fs::write(dir.path().join("a.json"), &body).unwrap();
+        // a version no converter exists for
+        std::fs::write(
+            dir.path().join("b.json"),
+            format!("{CACHE_HEADER}0\n{body}"),
+        )
+        .unwrap();
+        let config = CacheConfig::default();
+        config
+            .write_file(&dir.path().join("c.json"), &cache)
+            .unwrap();
+        // other JSON files in a wrong directory
+        std::fs::write(dir.path().join("package.json"), r#"{"name": "owl"}"#).unwrap();
+        std::fs::write(dir.path().join("tsconfig.json"), "{").unwrap();
+
+        let report = migrate_cache_dir(dir.path(), CacheCompression::Zstd { level: 3 }).unwrap();
+        let outcomes: Vec<_> = report
+            .into_iter()
+            .map(|(path, outcome)| (path.file_name().unwrap().to_owned(), outcome.unwrap()))
+            .collect();
+        assert!(matches!(
+            &outcomes[0],
+            (name, MigrationOutcome::Discarded { reason })
+                if name == "a.json" && reason.contains("unversioned")
+        ));
+        assert!(matches!(
+            &outcomes[1],
+            (name, MigrationOutcome::Discarded { reason })
+                if name == "b.json" && reason.contains("version 0")
+        ));
+        assert_eq!(outcomes[2], ("c.json".into(), MigrationOutcome::Current));
+        assert_eq!(
+            outcomes[3..],
+            [
+                ("package.json".into(), MigrationOutcome::Skipped),
+                ("tsconfig.json".into(), MigrationOutcome::Skipped),
+            ]
+        );
+
+        assert!(!dir.path().join("a.json").exists());
+        assert!(!dir.path().join("b.json").exists());
+        let read = config.read_file(&dir.path().join("c.json"));
+        assert_eq!(read.get_cache("file", "mir").map(|v| v.fn_id), Some(1));
+        // left untouched, without lock files next to them
+        let package = std::fs::read_to_string(dir.path().join("package.json")).unwrap();
+        assert_eq!(package, r#"{"name": "owl"}"#);
+        assert!(dir.path().join("tsconfig.json").exists());
+        assert!(!dir.path().join("package.lock").exists());
+    }
+
+    #[test]
+    fn compressed_cache_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owl.json");
+        let mut cache = CacheData::default();
+        for fn_id in 0..100 {
+            cache.insert_cache("file".to_owned(), format!("mir{fn_id}"), function(fn_id));
+        }
+
+        let plain = CacheConfig::default();
+        let zstd = CacheConfig {
+            compression: CacheCompression::Zstd { level: 3 },
+            ..plain
+        };
+        zstd.write_file(&path, &cache).unwrap();
+        let written = std::fs::read(&path).unwrap();
+        let header = format!("{CACHE_HEADER}{CACHE_SCHEMA_VERSION} zstd\n");
+        assert!(written.starts_with(header.as_bytes()));
+        assert!(written.len() < plain.encode(&cache).unwrap().len());
+
+        // read back whatever the current setting is
+        for config in [zstd, plain] {
+            let read = config.read_file(&path);
+            assert_eq!(read.entry_count(), 100);
+            assert_eq!(read.get_cache("file", "mir42").map(|v| v.fn_id), Some(42));
+        }
+        plain.write_file(&path, &cache).unwrap();
+        assert_eq!(zstd.read_file(&path).entry_count(), 100);
+
+        // truncated body or unknown codec
+        let truncated = &written[..written.len() / 2];
+        assert_eq!(zstd.decode(truncated).entry_count(), 0);
+        assert_eq!(zstd.decode(b"rustowl-cache v1 lz4\n{}").entry_count(), 0);
+    }
+
+    #[test]
+    fn memory_accounting_and_eviction() {
+        let size = entry_size(&function(1));
+        assert_eq!(size, serde_json::to_vec(&function(1)).unwrap().len());
+
+        let mut cache = CacheData::default();
+        cache.set_max_memory_bytes(Some(3 * size));
+        for fn_id in 1..=3 {
+            cache.insert_cache("file".to_owned(), format!("mir{fn_id}"), function(fn_id));
+        }
+        assert_eq!(cache.memory_bytes(), 3 * size);
+        // replacing an entry does not count it twice
+        cache.insert_cache("file".to_owned(), "mir2".to_owned(), function(2));
+        assert_eq!((cache.entry_count(), cache.memory_bytes()), (3, 3 * size));
+
+        // the oldest entry makes room for a new one
+        cache.insert_cache("file".to_owned(), "mir4".to_owned(), function(4));
+        assert_eq!((cache.entry_count(), cache.memory_bytes()), (3, 3 * size));
+        assert!(cache.get_cache("file", "mir1").is_none());
+        assert!(cache.get_cache("file", "mir3").is_some());
+        assert_eq!(cache.evictions, 1);
+
+        // lowering the limit evicts at once
+        cache.set_max_memory_bytes(Some(size));
+        assert_eq!((cache.entry_count(), cache.memory_bytes()), (1, size));
+        assert!(cache.get_cache("file", "mir4").is_some());
+        assert_eq!(cache.evictions, 3);
+
+        // an entry over the limit on its own is kept and flagged
+        cache.set_max_memory_bytes(Some(size - 1));
+        assert_eq!(cache.entry_count(), 1);
+        cache.insert_cache("file".to_owned(), "mir5".to_owned(), function(5));
+        assert_eq!((cache.entry_count(), cache.memory_bytes()), (1, size));
+        assert!(cache.get_cache("file", "mir5").is_some());
+        let report = cache.stats_report("owl", CacheStatsSnapshot::default());
+        assert_eq!((report.memory_bytes, report.oversized_entries), (size, 1));
+        assert_eq!(report.evictions, 4);
+
+        // sizes are not stored, but measured again on read
+        let config = CacheConfig {
+            max_memory_bytes: Some(2 * size),
+            ..CacheConfig::default()
+        };
+        let mut full = CacheData::default();
+        for fn_id in 1..=5 {
+            full.insert_cache("file".to_owned(), format!("mir{fn_id}"), function(fn_id));
+        }
+        let read = config.decode(&config.encode(&full).unwrap());
+        assert_eq!((read.entry_count(), read.memory_bytes()), (2, 2 * size));
+        assert_eq!(read.evictions, 3);
+
+        assert_eq!(parse_max_memory_bytes(None), None);
+        assert_eq!(parse_max_memory_bytes(Some(" 1024 ")), Some(1024));
+        assert_eq!(parse_max_memory_bytes(Some("1G")), None);
+    }
+
+    #[test]
+    fn eviction_order_survives_reload() {
+        // room for the 10 newest entries
+        let newest: usize = [44, 45, 46, 47, 48, 49, 50, 1, 2, 2]
+            .map(|fn_id| entry_size(&function(fn_id)))
+            .iter()
+            .sum();
+        let config = CacheConfig {
+            max_memory_bytes: Some(newest),
+            ..CacheConfig::default()
+        };
+        let mut cache = CacheData::default();
+        for fn_id in 1..=50 {
+            cache.insert_cache("file".to_owned(), format!("mir{fn_id}"), function(fn_id));
+        }
+        // used again, so newer than the others
+        cache.insert_cache("file".to_owned(), "mir1".to_owned(), function(1));
+        cache.insert_cache("file2".to_owned(), "mir2".to_owned(), function(2));
+
+        let read = config.decode(&config.encode(&cache).unwrap());
+        assert_eq!(read.entry_count(), 10);
+        for fn_id in 44..=50 {
+            assert!(read.get_cache("file", &format!("mir{fn_id}")).is_some());
+        }
+        assert!(read.get_cache("file", "mir43").is_none());
+        assert!(read.get_cache("file", "mir1").is_some());
+        // the entries of a MIR body hash move together
+        assert!(read.get_cache("file", "mir2").is_some());
+        assert!(read.get_cache("file2", "mir2").is_some());
+    }
+
+    #[test]
+    fn stats_reset_per_run() {
+        let stats = CacheStats::new();
+        stats.record_hit();
+        stats.record_miss();
+        stats.record_insert();
+        let before = stats.total();
+
+        stats.reset_stats();
+        assert_eq!(stats.run(), CacheStatsSnapshot::default());
+        assert_eq!(stats.total(), before);
+
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        let delta = stats.total().since(&before);
+        assert_eq!(
+            delta,
+            CacheStatsSnapshot {
+                hits: 2,
+                misses: 1,
+                inserts: 0,
+            }
+        );
+        assert_eq!(stats.run(), delta);
+        assert_eq!(stats.total().hits, 3);
+    }
+
+    #[test]
+    fn stats_report() {
+        let snapshot = |hits, misses| CacheStatsSnapshot {
+            hits,
+            misses,
+            inserts: 0,
+        };
+        assert_eq!(snapshot(3, 1).hit_rate(), 0.75);
+        assert_eq!(snapshot(0, 4).hit_rate(), 0.0);
+        assert_eq!(snapshot(2, 0).hit_rate(), 1.0);
+        // no lookups
+        assert_eq!(snapshot(0, 0).hit_rate(), 0.0);
+
+        let mut cache = CacheData::default();
+        cache.insert_cache("file".to_owned(), "mir".to_owned(), function(1));
+        cache.insert_cache("file2".to_owned(), "mir".to_owned(), function(2));
+        cache.insert_cache("file".to_owned(), "mir2".to_owned(), function(3));
+        let report = cache.stats_report("owl", snapshot(1, 3));
+        assert_eq!(report.crate_name, "owl");
+        assert_eq!(report.hit_rate, 0.25);
+        assert_eq!(report.entries, 3);
+        let function_size = serde_json::to_string(&function(1)).unwrap().len();
+        assert_eq!(report.memory_bytes, 3 * function_size);
+        assert_eq!((report.oversized_entries, report.evictions), (0, 0));
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.contains(r#""crate":"owl""#));
+        let read: CacheStatsReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, report);
+    }
+}
@@ -41,12 +41,60 @@ pub use borrowck::*;
 pub use hash::Hasher;
 pub use transform::*;
 
+/// Convert a span into a range of the source file starting at `offset`.
+///
+/// Spans produced by macro expansion or inlining point into the macro or the
+/// inlined callee, possibly in another file. Such spans are resolved to the
+/// outermost call site in this file, which is where the user wrote the code;
+/// see [`lexical_frame`]. With `exclude_macros` of the options, spans generated by a macro
+/// have no range at all.
 fn range_from_span(source: &str, span: Span, offset: u32) -> Option<Range> {
-    let from = Loc::new(source, span.lo(), offset);
-    let until = Loc::new(source, span.hi(), offset);
+    if super::options::get().exclude_macros && is_macro_generated(span.expansion_kinds()) {
+        return None;
+    }
+    lexical_range(source, span, offset)
+}
+
+/// Same as [`range_from_span`], but macro-generated spans are always resolved
+fn lexical_range(source: &str, span: Span, offset: u32) -> Option<Range> {
+    let file = (offset, offset.saturating_add(source.len() as u32));
+    let (lo, hi) = lexical_frame(span.expansion_chain(), file)?;
+    let from = Loc::new(source, lo, offset);
+    let until = Loc::new(source, hi, offset);
     Range::new(from, until)
 }
 
+/// Select the outermost frame of an expansion chain lying in `file`.
+///
+/// `chain` is a span followed by the call sites of its expansions, innermost
+/// first, as `(lo, hi)` byte positions. `None` if no frame lies in the file.
+fn lexical_frame(
+    chain: impl IntoIterator<Item = (u32, u32)>,
+    file: (u32, u32),
+) -> Option<(u32, u32)> {
+    chain
+        .into_iter()
+        .filter(|(lo, hi)| file.0 <= *lo && *hi <= file.1)
+        .last()
+}
+
+/// What a span was expanded from
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpansionKind {
+    /// A bang, attribute or derive macro
+    Macro,
+    /// Compiler desugaring, such as `for` loops, `?` or `async`
+    Desugaring,
+    /// Anything else, such as inlining or the test harness
+    Other,
+}
+
+/// Whether a span was generated by a macro, given the kinds of its expansions,
+/// innermost first. Desugaring of code written by the user does not count.
+fn is_macro_generated(kinds: impl IntoIterator<Item = ExpansionKind>) -> bool {
+    kinds.into_iter().any(|kind| kind == ExpansionKind::Macro)
+}
+
 pub struct SourceInfo {
     offset: u32,
     path: PathBuf,
@@ -115,41 +163,36 @@ impl<'tcx> TyCtxt<'tcx> {
     }
 
     #[rustversion::since(1.94.0)]
-    pub fn source_info_from_span(&self, span: Span) -> Option<SourceInfo> {
+    fn embeddable_path(&self, file_name: rustc_span::FileName) -> Option<PathBuf> {
         let source_map = self.as_rustc().sess.source_map();
-        let file_name = source_map.span_to_filename(*span.as_rustc());
-        let source_file = source_map.get_source_file(&file_name)?;
-        let offset = source_file.start_pos.0;
-
         let file_name = source_map
             .path_mapping()
             .to_real_filename(source_map.working_dir(), file_name.into_local_path()?);
         let (_work_dir, path) =
             file_name.embeddable_name(rustc_span::RemapPathScopeComponents::DIAGNOSTICS);
-        let path = path.to_path_buf();
-        let source = std::fs::read_to_string(&path).unwrap();
-        let cleaned_source = utils::clean_source(&source);
-        Some(SourceInfo {
-            offset,
-            path,
-            source,
-            cleaned_source,
-        })
+        Some(path.to_path_buf())
     }
     #[rustversion::before(1.94.0)]
-    pub fn source_info_from_span(&self, span: Span) -> Option<SourceInfo> {
+    fn embeddable_path(&self, file_name: rustc_span::FileName) -> Option<PathBuf> {
         let source_map = self.as_rustc().sess.source_map();
-        let file_name = source_map.span_to_filename(*span.as_rustc());
-        let source_file = source_map.get_source_file(&file_name)?;
-        let offset = source_file.start_pos.0;
         let file_name = source_map.path_mapping().to_embeddable_absolute_path(
             rustc_span::RealFileName::LocalPath(file_name.into_local_path()?),
             &rustc_span::RealFileName::LocalPath(std::env::current_dir().unwrap()),
         );
-        let path = file_name
-            .to_path(rustc_span::FileNameDisplayPreference::Local)
-            .to_path_buf();
-        let source = std::fs::read_to_string(&path).unwrap();
+        Some(
+            file_name
+                .to_path(rustc_span::FileNameDisplayPreference::Local)
+                .to_path_buf(),
+        )
+    }
+
+    pub fn source_info_from_span(&self, span: Span) -> Option<SourceInfo> {
+        let source_map = self.as_rustc().sess.source_map();
+        let file_name = source_map.span_to_filename(*span.as_rustc());
+        let source_file = source_map.get_source_file(&file_name)?;
+        let offset = source_file.start_pos.0;
+        let path = self.embeddable_path(file_name)?;
+        let source = utils::read_source(&path).unwrap();
         let cleaned_source = utils::clean_source(&source);
         Some(SourceInfo {
             offset,
@@ -159,6 +202,24 @@ impl<'tcx> TyCtxt<'tcx> {
         })
     }
 
+    /// Paths of the source files of the currently-compiling crate,
+    /// in the same form as [`SourceInfo::path`]
+    pub fn local_source_paths(&self) -> Vec<PathBuf> {
+        let source_map = self.as_rustc().sess.source_map();
+        let files: Vec<_> = source_map
+            .files()
+            .iter()
+            .filter(|file| file.cnum == rustc_hir::def_id::LOCAL_CRATE)
+            .map(|file| file.name.clone())
+            .collect();
+        files
+            .into_iter()
+            .filter_map(|name| self.embeddable_path(name))
+            // `include_str!` and `include_bytes!` also load files into the source map
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+            .collect()
+    }
+
     pub fn crate_name(&self) -> String {
         self.as_rustc()
             .crate_name(rustc_hir::def_id::LOCAL_CRATE)
@@ -168,6 +229,143 @@ impl<'tcx> TyCtxt<'tcx> {
     pub fn def_name(&self, def_id: DefId) -> String {
         self.as_rustc().def_path_str(def_id.as_rustc().to_def_id())
     }
+
+    /// Whether the definition is excluded by `#[rustowl::ignore]` or its doc comment marker
+    pub fn is_ignored(&self, def_id: DefId) -> bool {
+        let tcx = *self.as_rustc();
+        let hir_id = tcx.local_def_id_to_hir_id(*def_id.as_rustc());
+        tcx.hir_attrs(hir_id).iter().any(|attr| {
+            let path = attr.path();
+            let path: Vec<_> = path.iter().map(|v| v.as_str()).collect();
+            let doc = attr.doc_str();
+            utils::is_ignore_attribute(&path, doc.as_ref().map(|v| v.as_str()))
+        })
+    }
+
+    /// Contexts of the expressions in the body of `def_id` which may be borrowed,
+    /// to tell the reason of each borrow in its MIR
+    pub fn borrow_reasons(&self, def_id: DefId) -> BorrowReasons {
+        use rustc_hir::intravisit::{self, Visitor};
+        use rustc_hir::{BorrowKind, Expr, ExprKind};
+        use rustc_middle::ty::adjustment::Adjust;
+
+        struct Collector<'tcx> {
+            typeck: &'tcx rustc_middle::ty::TypeckResults<'tcx>,
+            reasons: BorrowReasons,
+        }
+        impl<'tcx> Collector<'tcx> {
+            fn autoref(&self, expr: &Expr<'_>) -> bool {
+                self.typeck
+                    .expr_adjustments(expr)
+                    .iter()
+                    .any(|v| matches!(v.kind, Adjust::Borrow(_)))
+            }
+        }
+        // nested bodies are not visited; they have their own MIR
+        impl<'tcx> Visitor<'tcx> for Collector<'tcx> {
+            fn visit_expr(&mut self, expr: &'tcx Expr<'tcx>) {
+                let site = BorrowSite {
+                    explicit: matches!(expr.kind, ExprKind::AddrOf(BorrowKind::Ref, ..)),
+                    autoref: self.autoref(expr),
+                    ..Default::default()
+                };
+                self.reasons.insert(AsRustc::from_rustc(expr.span), site);
+                let (receiver, args) = match expr.kind {
+                    ExprKind::Call(_, args) => (None, args),
+                    ExprKind::MethodCall(_, receiver, args, _) => (Some(receiver), args),
+                    _ => (None, [].as_slice()),
+                };
+                if let Some(receiver) = receiver {
+                    let site = BorrowSite {
+                        receiver: true,
+                        ..Default::default()
+                    };
+                    self.reasons
+                        .insert(AsRustc::from_rustc(receiver.span), site);
+                }
+                for arg in args {
+                    let site = BorrowSite {
+                        argument: true,
+                        ..Default::default()
+                    };
+                    self.reasons.insert(AsRustc::from_rustc(arg.span), site);
+                }
+                intravisit::walk_expr(self, expr);
+            }
+        }
+
+        let tcx = *self.as_rustc();
+        let def_id = *def_id.as_rustc();
+        let Some(body) = tcx.hir_maybe_body_owned_by(def_id) else {
+            return BorrowReasons::default();
+        };
+        let mut collector = Collector {
+            typeck: tcx.typeck(def_id),
+            reasons: BorrowReasons::default(),
+        };
+        collector.visit_body(body);
+        collector.reasons
+    }
+
+    /// How many bodies (closures, async blocks, inline consts) the definition
+    /// is nested in; 0 for an item
+    pub fn nesting_depth(&self, def_id: DefId) -> usize {
+        let tcx = *self.as_rustc();
+        // walk up iteratively; pathological nesting must not overflow the stack
+        let mut current = def_id.as_rustc().to_def_id();
+        let mut depth = 0;
+        while tcx.is_typeck_child(current) {
+            depth += 1;
+            current = tcx.parent(current);
+        }
+        depth
+    }
+
+    /// Whether the function is a `#[test]`.
+    ///
+    /// The test harness keeps the function as is and generates a const of the
+    /// same name next to it, holding a `test::TestDescAndFn`.
+    pub fn is_test(&self, def_id: DefId) -> bool {
+        let tcx = *self.as_rustc();
+        let def_id = *def_id.as_rustc();
+        if !matches!(tcx.def_kind(def_id), rustc_hir::def::DefKind::Fn) {
+            return false;
+        }
+        let name = tcx.item_name(def_id.to_def_id());
+        let module = tcx.parent_module_from_def_id(def_id);
+        tcx.hir_module_items(module).definitions().any(|item| {
+            matches!(tcx.def_kind(item), rustc_hir::def::DefKind::Const { .. })
+                && tcx.item_name(item.to_def_id()) == name
+                && utils::is_test_descriptor_type(
+                    &tcx.type_of(item).instantiate_identity().to_string(),
+                )
+        })
+    }
+
+    /// Whether the definition is generated by the test harness: the
+    /// descriptor of a `#[test]` function or the `main` running the tests
+    pub fn is_test_harness_item(&self, def_id: DefId) -> bool {
+        let tcx = *self.as_rustc();
+        let def_id = *def_id.as_rustc();
+        let expn = tcx.def_span(def_id).ctxt().outer_expn_data();
+        if matches!(
+            expn.kind,
+            rustc_span::hygiene::ExpnKind::AstPass(rustc_span::hygiene::AstPass::TestHarness)
+        ) {
+            return true;
+        }
+        matches!(tcx.def_kind(def_id), rustc_hir::def::DefKind::Const { .. })
+            && utils::is_test_descriptor_type(
+                &tcx.type_of(def_id).instantiate_identity().to_string(),
+            )
+    }
+
+    /// Range of the whole definition, including its signature
+    pub fn def_range(&self, def_id: DefId, source_info: &SourceInfo) -> Option<Range> {
+        let span = Span::from_rustc(self.as_rustc().source_span(*def_id.as_rustc()));
+        // a function generated by a macro still needs its range
+        lexical_range(&source_info.source, span, source_info.offset)
+    }
 }
 
 impl_as_rustc!(
@@ -229,6 +427,16 @@ impl<'tcx> Body<'tcx> {
         AsRustc::from_rustc(self.0.span)
     }
 
+    /// Indices of the blocks only reached while unwinding
+    pub fn cleanup_blocks(&self) -> Vec<u32> {
+        self.as_rustc()
+            .basic_blocks
+            .iter_enumerated()
+            .filter(|(_, data)| data.is_cleanup)
+            .map(|(block, _)| block.as_u32())
+            .collect()
+    }
+
     pub fn get_location_ranges(&self, source_info: &SourceInfo) -> LocationRanges {
         LocationRanges::compute(self, source_info)
     }
@@ -322,9 +530,14 @@ impl_as_rustc!(
     rustc_middle::ty::Ty<'tcx>,
 );
 
+thread_local! {
+    /// Type names of this analysis run, shared among the declarations
+    static TYPE_NAMES: std::cell::RefCell<TypeInterner> = Default::default();
+}
+
 impl Ty<'_> {
     pub fn to_mir_type(&self) -> MirType {
-        let name = self.0.to_string();
+        let name = TYPE_NAMES.with_borrow_mut(|names| names.intern(&self.0.to_string()));
         let reference = if let rustc_middle::ty::TyKind::Ref(_r, ty, m) = self.0.kind() {
             Some(Box::new(MirRefType {
                 refer_to: Ty::from_rustc(*ty).to_mir_type(),
@@ -359,6 +572,33 @@ impl Span {
         use rustc_span::Pos;
         self.0.hi().to_u32()
     }
+    /// The span followed by the call sites of its macro expansions and inlining,
+    /// innermost first
+    pub fn expansion_chain(&self) -> Vec<(u32, u32)> {
+        let mut span = self.0;
+        let mut chain = vec![(Self(span).lo(), Self(span).hi())];
+        while span.from_expansion() {
+            span = span.ctxt().outer_expn_data().call_site;
+            chain.push((Self(span).lo(), Self(span).hi()));
+        }
+        chain
+    }
+    /// Kinds of the expansions the span went through, innermost first
+    pub fn expansion_kinds(&self) -> Vec<ExpansionKind> {
+        use rustc_span::hygiene::ExpnKind;
+        let mut span = self.0;
+        let mut kinds = Vec::new();
+        while span.from_expansion() {
+            let expn = span.ctxt().outer_expn_data();
+            kinds.push(match expn.kind {
+                ExpnKind::Macro(..) => ExpansionKind::Macro,
+                ExpnKind::Desugaring(_) => ExpansionKind::Desugaring,
+                _ => ExpansionKind::Other,
+            });
+            span = expn.call_site;
+        }
+        kinds
+    }
 }
 
 impl_as_rustc!(
@@ -386,3 +626,44 @@ impl LocalId {
         self.as_rustc().as_u32()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexical_frame_of_expansion() {
+        let file = (100, 200);
+        // not expanded
+        assert_eq!(lexical_frame([(110, 120)], file), Some((110, 120)));
+        // expanded from a macro defined in another file, invoked here
+        assert_eq!(
+            lexical_frame([(20, 30), (140, 160)], file),
+            Some((140, 160))
+        );
+        // nested macros defined in this file: the outermost invocation wins
+        assert_eq!(
+            lexical_frame([(110, 115), (130, 150), (170, 190)], file),
+            Some((170, 190))
+        );
+        // inlined into a caller in another file
+        assert_eq!(
+            lexical_frame([(120, 125), (300, 310)], file),
+            Some((120, 125))
+        );
+        assert_eq!(lexical_frame([(20, 30), (300, 310)], file), None);
+    }
+
+    #[test]
+    fn macro_generated_span() {
+        use ExpansionKind::*;
+        // written by the user
+        assert!(!is_macro_generated([]));
+        assert!(!is_macro_generated([Desugaring]));
+        assert!(!is_macro_generated([Other, Desugaring]));
+        // expanded from a macro, possibly desugared within it
+        assert!(is_macro_generated([Macro]));
+        assert!(is_macro_generated([Desugaring, Macro]));
+        assert!(is_macro_generated([Macro, Macro]));
+    }
+}
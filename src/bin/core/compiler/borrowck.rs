@@ -36,6 +36,11 @@ impl PoloniusInput {
         ))
     }
 
+    /// Whether any local is used, so that its output has live points
+    pub fn has_uses(&self) -> bool {
+        !self.as_rustc().var_used_at.is_empty()
+    }
+
     pub fn var_dropped_at(&self) -> Vec<(LocalId, Point)> {
         self.as_rustc()
             .var_dropped_at
@@ -48,6 +53,13 @@ impl PoloniusInput {
 impl_as_rustc!(PoloniusOutput, rustc_borrowck::consumers::PoloniusOutput);
 
 impl PoloniusOutput {
+    /// Whether nothing is live anywhere
+    pub fn is_empty(&self) -> bool {
+        let output = self.as_rustc();
+        output.var_live_on_entry.is_empty()
+            && output.origin_live_on_entry.is_empty()
+            && output.loan_live_at.is_empty()
+    }
     pub fn var_live_on_entry(&self) -> HashMap<Point, Vec<LocalId>> {
         self.as_rustc()
             .var_live_on_entry
@@ -159,10 +171,70 @@ impl<'tcx> BorrowckFacts<'tcx> {
         BorrowMap::new(&self.as_rustc().borrow_set)
     }
 
-    pub fn polonius_input(&mut self) -> PoloniusInput {
-        AsRustc::from_rustc(*self.mut_rustc().input_facts.take().unwrap())
+    /// Each borrow and the locations where it is in scope according to NLL,
+    /// which does not need Polonius
+    ///
+    /// A borrow is in scope from where it is created until it goes out of scope
+    /// along every path of the control-flow graph.
+    pub fn nll_borrow_locations(&self) -> Vec<(BorrowData, Vec<Location>)> {
+        let facts = self.as_rustc();
+        let body = &facts.body;
+        let mut out_of_scope: HashMap<usize, HashSet<rustc_middle::mir::Location>> = HashMap::new();
+        for (location, borrows) in
+            rustc_borrowck::consumers::calculate_borrows_out_of_scope_at_location(
+                body,
+                &facts.region_inference_context,
+                &facts.borrow_set,
+            )
+        {
+            for borrow in borrows {
+                out_of_scope
+                    .entry(borrow.as_usize())
+                    .or_default()
+                    .insert(location);
+            }
+        }
+
+        // BorrowIndex corresponds to Location index
+        facts
+            .borrow_set
+            .location_map()
+            .iter()
+            .enumerate()
+            .map(|(index, (reserve, data))| {
+                let killed = out_of_scope.get(&index);
+                let mut visited = HashSet::new();
+                let mut stack = vec![*reserve];
+                while let Some(location) = stack.pop() {
+                    let is_killed =
+                        location != *reserve && killed.is_some_and(|v| v.contains(&location));
+                    if is_killed || !visited.insert(location) {
+                        continue;
+                    }
+                    let block = &body.basic_blocks[location.block];
+                    if location.statement_index < block.statements.len() {
+                        stack.push(location.successor_within_block());
+                    } else {
+                        stack.extend(block.terminator().successors().map(|v| v.start_location()));
+                    }
+                }
+                (
+                    BorrowData::new(data),
+                    visited.into_iter().map(AsRustc::from_rustc).collect(),
+                )
+            })
+            .collect()
+    }
+
+    /// The Polonius facts, if rustc computed them for this body
+    pub fn polonius_input(&mut self) -> Option<PoloniusInput> {
+        let input = self.mut_rustc().input_facts.take()?;
+        Some(AsRustc::from_rustc(*input))
     }
-    pub fn location_table(&mut self) -> PoloniusLocationTable {
-        AsRustc::from_rustc(self.mut_rustc().location_table.take().unwrap())
+    pub fn location_table(&mut self) -> Option<PoloniusLocationTable> {
+        self.mut_rustc()
+            .location_table
+            .take()
+            .map(AsRustc::from_rustc)
     }
 }
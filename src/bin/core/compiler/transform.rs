@@ -2,6 +2,7 @@ use super::*;
 use rustowl::utils;
 
 use indexmap::IndexMap;
+use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
 
 /// RegionEraser to erase region variables from MIR body
@@ -39,6 +40,7 @@ impl<'tcx> TyCtxt<'tcx> {
         body: &Body<'tcx>,
         source_info: &SourceInfo,
         location_ranges: &LocationRanges,
+        borrow_reasons: &BorrowReasons,
     ) -> IndexMap<BasicBlockId, MirBasicBlock> {
         body.as_rustc()
             .basic_blocks
@@ -54,6 +56,7 @@ impl<'tcx> TyCtxt<'tcx> {
                             BasicBlockId(block.as_usize()),
                             statement_index,
                             location_ranges,
+                            borrow_reasons,
                         )
                     })
                     .collect();
@@ -69,6 +72,7 @@ impl<'tcx> TyCtxt<'tcx> {
                     MirBasicBlock {
                         statements,
                         terminator,
+                        is_cleanup: bb_data.is_cleanup,
                     },
                 )
             })
@@ -76,6 +80,56 @@ impl<'tcx> TyCtxt<'tcx> {
     }
 }
 
+/// HIR context of an expression, which a MIR borrow carries the span of
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct BorrowSite {
+    /// `&expr` or `&mut expr` in the source
+    pub explicit: bool,
+    /// Borrowed by the compiler, as an adjustment of the expression
+    pub autoref: bool,
+    /// The receiver of a method call
+    pub receiver: bool,
+    /// An argument of a call or a method call
+    pub argument: bool,
+}
+
+impl BorrowSite {
+    pub fn reason(&self) -> Option<BorrowReason> {
+        if self.receiver && self.autoref {
+            Some(BorrowReason::MethodCall)
+        } else if self.argument && (self.explicit || self.autoref) {
+            Some(BorrowReason::Argument)
+        } else if self.explicit {
+            Some(BorrowReason::Explicit)
+        } else {
+            None
+        }
+    }
+}
+
+/// [`BorrowSite`]s of a body by span, collected by [`TyCtxt::borrow_reasons`]
+#[derive(Clone, Default, Debug)]
+pub struct BorrowReasons(HashMap<Span, BorrowSite>);
+
+impl BorrowReasons {
+    /// Add the context `site` to the expression at `span`
+    pub fn insert(&mut self, span: Span, site: BorrowSite) {
+        if site == BorrowSite::default() {
+            return;
+        }
+        let merged = self.0.entry(span).or_default();
+        merged.explicit |= site.explicit;
+        merged.autoref |= site.autoref;
+        merged.receiver |= site.receiver;
+        merged.argument |= site.argument;
+    }
+
+    /// Reason of a borrow at `span`, if the source explains it
+    pub fn get(&self, span: Span) -> Option<BorrowReason> {
+        self.0.get(&span).and_then(|site| site.reason())
+    }
+}
+
 /// Our representation of `rustc_borrowck::consumers::BorrowData`
 #[derive(Clone, Debug)]
 pub enum BorrowData {
@@ -89,6 +143,18 @@ pub enum BorrowData {
     },
 }
 
+impl BorrowData {
+    pub fn new(data: &rustc_borrowck::consumers::BorrowData<'_>) -> Self {
+        let borrowed = AsRustc::from_rustc(data.borrowed_place().local);
+        let assigned = AsRustc::from_rustc(data.assigned_place().local);
+        if data.kind().mutability().is_mut() {
+            BorrowData::Mutable { borrowed, assigned }
+        } else {
+            BorrowData::Shared { borrowed, assigned }
+        }
+    }
+}
+
 impl_as_rustc!(
     #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
     Location,
@@ -129,6 +195,13 @@ pub enum RichLocation {
     Start(Location),
     Mid(Location),
 }
+impl RichLocation {
+    pub fn location(&self) -> Location {
+        match self {
+            Self::Start(l) | Self::Mid(l) => *l,
+        }
+    }
+}
 
 /// [`Location`] to [`Range`] map
 pub struct LocationRanges {
@@ -149,11 +222,11 @@ impl LocationRanges {
                     block,
                     statement_index,
                 };
-                // source_callsite is wide, for macro invocation
-                let span_callsite = body.as_rustc().source_info(location).span.source_callsite();
+                // macro invocations are resolved to their call site by `range_from_span`
+                let span = body.as_rustc().source_info(location).span;
                 let range = if let Some(v) = range_from_span(
                     &source_info.source,
-                    AsRustc::from_rustc(span_callsite),
+                    AsRustc::from_rustc(span),
                     source_info.offset,
                 ) {
                     v
@@ -219,6 +292,26 @@ pub fn rich_locations_to_ranges(
         .collect()
 }
 
+/// Ranges of each local from the locations where it holds, merged by `merge`
+///
+/// Shared by the Polonius and NLL analyzers, which only differ in how they
+/// find the locations.
+pub fn local_ranges(
+    local_locations: HashMap<LocalId, Vec<RichLocation>>,
+    location_ranges: &LocationRanges,
+    merge: impl Fn(Vec<Range>) -> Vec<Range> + Sync,
+) -> HashMap<LocalId, Vec<Range>> {
+    local_locations
+        .into_par_iter()
+        .map(|(local, locations)| {
+            (
+                local,
+                merge(rich_locations_to_ranges(location_ranges, &locations)),
+            )
+        })
+        .collect()
+}
+
 pub struct BorrowMap {
     location_map: HashMap<Borrow, (Location, BorrowData)>,
     local_map: HashMap<LocalId, HashSet<Borrow>>,
@@ -228,17 +321,7 @@ impl BorrowMap {
         let mut location_map = HashMap::new();
         // BorrowIndex corresponds to Location index
         for (location, data) in borrow_set.location_map().iter() {
-            let data = if data.kind().mutability().is_mut() {
-                BorrowData::Mutable {
-                    borrowed: AsRustc::from_rustc(data.borrowed_place().local),
-                    assigned: AsRustc::from_rustc(data.assigned_place().local),
-                }
-            } else {
-                BorrowData::Shared {
-                    borrowed: AsRustc::from_rustc(data.borrowed_place().local),
-                    assigned: AsRustc::from_rustc(data.assigned_place().local),
-                }
-            };
+            let data = BorrowData::new(data);
             if let Some(borrows) = borrow_set.activation_map().get(location) {
                 for borrow in borrows {
                     location_map.insert(
@@ -325,7 +408,7 @@ impl_as_rustc!(
     rustc_middle::mir::Rvalue<'tcx>,
 );
 impl Rvalue<'_> {
-    pub fn transform(&self, fn_id: DefId) -> MirRval {
+    pub fn transform(&self, fn_id: DefId, reason: Option<BorrowReason>) -> MirRval {
         use rustc_middle::mir::Rvalue;
         match &self.as_rustc() {
             Rvalue::Use(operand) => {
@@ -339,7 +422,11 @@ impl Rvalue<'_> {
             Rvalue::Ref(_region, kind, place) => {
                 let place = Place::from_rustc(*place).transform(fn_id);
                 let mutable = kind.mutability().is_mut();
-                MirRval::Ref { place, mutable }
+                MirRval::Ref {
+                    place,
+                    mutable,
+                    reason,
+                }
             }
             Rvalue::Cast(_kind, operand, _ty) => {
                 let operand = Operand::from_rustc(operand.clone()).transform(fn_id);
@@ -378,6 +465,7 @@ impl Statement<'_> {
         block: BasicBlockId,
         statement_index: usize,
         location_ranges: &LocationRanges,
+        borrow_reasons: &BorrowReasons,
     ) -> MirStatement {
         use rustc_middle::mir::StatementKind;
         let location = rustc_middle::mir::Location {
@@ -390,7 +478,8 @@ impl Statement<'_> {
         match &self.as_rustc().kind {
             StatementKind::Assign(boxed) => {
                 let place = Place::from_rustc((**boxed).0).transform(fn_id);
-                let rval = Rvalue::from_rustc((**boxed).1.clone()).transform(fn_id);
+                let reason = borrow_reasons.get(Span::from_rustc(self.as_rustc().source_info.span));
+                let rval = Rvalue::from_rustc((**boxed).1.clone()).transform(fn_id, reason);
                 let kind = MirStatementKind::Assign { place, rval };
                 MirStatement { kind, range }
             }
@@ -548,3 +637,96 @@ impl Terminator<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(block: u32, statement_index: usize) -> Location {
+        AsRustc::from_rustc(rustc_middle::mir::Location {
+            block: rustc_middle::mir::BasicBlock::from_u32(block),
+            statement_index,
+        })
+    }
+
+    #[test]
+    fn borrow_reason_classification() {
+        let site = |explicit, autoref, receiver, argument| BorrowSite {
+            explicit,
+            autoref,
+            receiver,
+            argument,
+        };
+        // `let r = &x;`
+        assert_eq!(
+            site(true, false, false, false).reason(),
+            Some(BorrowReason::Explicit)
+        );
+        // `f(&x)`, and `f(r)` reborrowing `r: &mut T`
+        assert_eq!(
+            site(true, false, false, true).reason(),
+            Some(BorrowReason::Argument)
+        );
+        assert_eq!(
+            site(false, true, false, true).reason(),
+            Some(BorrowReason::Argument)
+        );
+        // `v.push(1)`, but not a receiver which is a reference already
+        assert_eq!(
+            site(false, true, true, false).reason(),
+            Some(BorrowReason::MethodCall)
+        );
+        assert_eq!(site(false, false, true, false).reason(), None);
+        // an argument moved or copied
+        assert_eq!(site(false, false, false, true).reason(), None);
+
+        // contexts of the same expression are merged
+        let span = Span::from_rustc(rustc_span::DUMMY_SP);
+        let mut reasons = BorrowReasons::default();
+        assert_eq!(reasons.get(span), None);
+        reasons.insert(span, site(true, false, false, false));
+        assert_eq!(reasons.get(span), Some(BorrowReason::Explicit));
+        reasons.insert(span, site(false, false, false, true));
+        assert_eq!(reasons.get(span), Some(BorrowReason::Argument));
+    }
+
+    #[test]
+    fn local_ranges_from_polonius_and_nll_locations() {
+        let range = |from, until| Range::new(Loc(from), Loc(until)).unwrap();
+        let location_ranges = LocationRanges {
+            map: HashMap::from([
+                (location(0, 0), range(0, 5)),
+                (location(0, 1), range(5, 10)),
+                (location(1, 0), range(20, 30)),
+            ]),
+        };
+        let local = LocalId::from_rustc(rustc_middle::mir::Local::from_u32(1));
+
+        // Polonius points are at the start and the middle of each location
+        let polonius = HashMap::from([(
+            local,
+            vec![
+                RichLocation::Start(location(0, 0)),
+                RichLocation::Mid(location(0, 0)),
+                RichLocation::Start(location(0, 1)),
+                RichLocation::Mid(location(0, 1)),
+                RichLocation::Mid(location(1, 0)),
+            ],
+        )]);
+        // NLL scopes are plain locations
+        let nll = HashMap::from([(
+            local,
+            [location(1, 0), location(0, 1), location(0, 0)]
+                .into_iter()
+                .map(RichLocation::Mid)
+                .collect(),
+        )]);
+
+        let normalized =
+            |ranges: HashMap<LocalId, Vec<Range>>| utils::normalize_ranges(ranges[&local].clone());
+        let polonius = local_ranges(polonius, &location_ranges, utils::eliminated_ranges);
+        let nll = local_ranges(nll, &location_ranges, utils::eliminated_ranges);
+        assert_eq!(normalized(polonius), vec![range(0, 10), range(20, 30)]);
+        assert_eq!(normalized(nll), vec![range(0, 10), range(20, 30)]);
+    }
+}
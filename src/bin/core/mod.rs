@@ -1,16 +1,19 @@
 pub mod analyze;
 pub mod cache;
 pub mod compiler;
+pub mod options;
 
 use analyze::{AnalyzeResult, MirAnalyzer, MirAnalyzerInitResult};
 use compiler::AsRustc;
+use options::AnalysisOptions;
 use rustc_hir::def_id::{LOCAL_CRATE, LocalDefId};
 use rustc_interface::interface;
 use rustc_middle::{ty::TyCtxt, util::Providers};
 use rustc_session::config;
-use rustowl::models::*;
-use std::collections::HashMap;
+use rustowl::{lsp::decoration, models::*, toolchain, utils};
+use std::collections::HashSet;
 use std::env;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::sync::{LazyLock, Mutex, atomic::AtomicBool};
 use tokio::{
@@ -27,17 +30,53 @@ pub struct RustcCallback;
 impl rustc_driver::Callbacks for RustcCallback {}
 
 static ATOMIC_TRUE: AtomicBool = AtomicBool::new(true);
+/// Shared by all files so that placeholders are consistent within a crate
+static REDACTOR: LazyLock<Mutex<Redactor>> = LazyLock::new(|| Mutex::new(Redactor::new()));
+
+/// Whether `--trace-borrowck` or `RUSTOWL_TRACE_BORROWCK` asks for tracing the
+/// Polonius data extraction. Checked before the logger is set up, so this reads
+/// the arguments directly; [`run_compiler`] removes the flag.
+fn trace_borrowck(args: &[String], env_value: Option<&str>) -> bool {
+    args.iter().any(|v| v == "--trace-borrowck")
+        || env_value.is_some_and(|v| v == "1" || v == "true")
+}
+
+/// Log levels of specific targets on top of `RUST_LOG`
+pub fn log_target_filters(
+    args: &[String],
+    env_value: Option<&str>,
+) -> Vec<(&'static str, log::LevelFilter)> {
+    if trace_borrowck(args, env_value) {
+        vec![(analyze::BORROWCK_TRACE_TARGET, log::LevelFilter::Trace)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Crates of the standard library, which are skipped unless `include_std` is set
+const STD_CRATES: [&str; 3] = ["std", "core", "alloc"];
+
+fn is_std_crate(crate_name: &str) -> bool {
+    STD_CRATES.contains(&crate_name)
+}
+
+/// The value of `--crate-name` in rustc arguments
+fn crate_name_from_args(args: &[String]) -> Option<&str> {
+    args.iter()
+        .position(|v| v == "--crate-name")
+        .and_then(|i| args.get(i + 1))
+        .map(|v| v.as_str())
+}
+/// Source files which have at least one analyzed function in the output
+static ANALYZED_FILES: LazyLock<Mutex<HashSet<PathBuf>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
 static TASKS: LazyLock<Mutex<JoinSet<AnalyzeResult>>> =
     LazyLock::new(|| Mutex::new(JoinSet::new()));
 // make tokio runtime
 static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
-    let worker_threads = std::thread::available_parallelism()
-        .map(|n| (n.get() / 2).clamp(2, 8))
-        .unwrap_or(4);
-
     Builder::new_multi_thread()
         .enable_all()
-        .worker_threads(worker_threads)
+        .worker_threads(utils::worker_threads())
         .thread_stack_size(128 * 1024 * 1024)
         .build()
         .unwrap()
@@ -63,13 +102,20 @@ fn mir_borrowck(tcx: TyCtxt<'_>, def_id: LocalDefId) -> queries::mir_borrowck::P
     log::debug!("start borrowck of {def_id:?}");
 
     let default_borrowck_result = DEFAULT_MIR_BORROWCK(tcx, def_id);
-    let analyzers = MirAnalyzer::init(AsRustc::from_rustc(tcx), AsRustc::from_rustc(def_id));
+    // query providers are plain functions, so the options cannot be passed in
+    let options = options::get();
+    let analyzers = MirAnalyzer::init(
+        AsRustc::from_rustc(tcx),
+        AsRustc::from_rustc(def_id),
+        options,
+    );
     {
-        let mut tasks = TASKS.lock().unwrap();
+        let mut tasks = utils::lock_or_recover(&TASKS, "tasks");
         for (_, analyzer) in analyzers {
             match analyzer {
-                MirAnalyzerInitResult::Cached(cached) => {
-                    handle_analyzed_result(tcx, cached);
+                MirAnalyzerInitResult::Cached(result)
+                | MirAnalyzerInitResult::Unavailable(result) => {
+                    handle_analyzed_result(tcx, result, options);
                 }
                 MirAnalyzerInitResult::Analyzer(analyzer) => {
                     tasks.spawn_on(async move { analyzer.await.analyze() }, RUNTIME.handle());
@@ -80,19 +126,31 @@ fn mir_borrowck(tcx: TyCtxt<'_>, def_id: LocalDefId) -> queries::mir_borrowck::P
         log::debug!("there are {} tasks", tasks.len());
         while let Some(Ok(result)) = tasks.try_join_next() {
             log::debug!("one task joined");
-            handle_analyzed_result(tcx, result);
+            handle_analyzed_result(tcx, result, options);
         }
     }
 
     default_borrowck_result
 }
 
-pub struct AnalyzerCallback;
+pub struct AnalyzerCallback {
+    /// Completed with the session options and stored by [`Self::config`]
+    options: AnalysisOptions,
+}
 impl rustc_driver::Callbacks for AnalyzerCallback {
     fn config(&mut self, config: &mut interface::Config) {
+        self.options.panic_strategy =
+            analyze::PanicStrategy::from_desc(config.opts.cg.panic.map(|v| v.desc()));
+        if self.options.panic_strategy == analyze::PanicStrategy::Abort {
+            log::info!("panic=abort; drops on unwind paths are left out");
+        }
+        options::init(self.options.clone());
+
         config.using_internal_features = &ATOMIC_TRUE;
         config.opts.unstable_opts.mir_opt_level = Some(0);
-        config.opts.unstable_opts.polonius = config::Polonius::Next;
+        if self.options.borrowck_mode == analyze::BorrowckMode::Polonius {
+            config.opts.unstable_opts.polonius = config::Polonius::Next;
+        }
         config.opts.incremental = None;
         config.override_queries = Some(override_queries);
         config.make_codegen_backend = None;
@@ -102,7 +160,10 @@ impl rustc_driver::Callbacks for AnalyzerCallback {
         _compiler: &interface::Compiler,
         tcx: TyCtxt<'tcx>,
     ) -> rustc_driver::Compilation {
+        // stats of this run only; the totals span the whole process
+        cache::STATS.reset_stats();
         let result = rustc_driver::catch_fatal_errors(|| tcx.analysis(()));
+        let options = options::get();
 
         // join all tasks after all analysis finished
         //
@@ -110,15 +171,34 @@ impl rustc_driver::Callbacks for AnalyzerCallback {
         // for TASKS because block_on cannot be used in `mir_borrowck`.
         #[allow(clippy::await_holding_lock)]
         RUNTIME.block_on(async move {
-            while let Some(Ok(result)) = { TASKS.lock().unwrap().join_next().await } {
+            while let Some(Ok(result)) =
+                { utils::lock_or_recover(&TASKS, "tasks").join_next().await }
+            {
                 log::debug!("one task joined");
-                handle_analyzed_result(tcx, result);
+                handle_analyzed_result(tcx, result, options);
             }
-            if let Some(cache) = cache::CACHE.lock().unwrap().as_ref() {
-                cache::write_cache(&tcx.crate_name(LOCAL_CRATE).to_string(), cache);
+            if let Some(cache) = utils::lock_or_recover(&cache::CACHE, "cache").as_ref() {
+                log::info!(
+                    "cache stats: {} (total: {})",
+                    cache::STATS.run(),
+                    cache::STATS.total()
+                );
+                let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+                cache::write_cache(&crate_name, cache);
+                if let Some(path) = cache::stats_file() {
+                    cache::write_stats(&path, &cache.stats_report(&crate_name, cache::STATS.run()));
+                }
             }
         });
 
+        // emit files without any function (e.g. empty or comment-only ones) as empty,
+        // so that they are distinguishable from files not analyzed at all
+        let sources = compiler::TyCtxt::from_rustc(tcx).local_source_paths();
+        let analyzed = utils::lock_or_recover(&ANALYZED_FILES, "analyzed files");
+        for path in unanalyzed_files(sources, &analyzed) {
+            print_output(tcx, &path, Vec::new(), options);
+        }
+
         if result.is_ok() {
             rustc_driver::Compilation::Continue
         } else {
@@ -127,24 +207,109 @@ impl rustc_driver::Callbacks for AnalyzerCallback {
     }
 }
 
-pub fn handle_analyzed_result(tcx: TyCtxt<'_>, analyzed: AnalyzeResult) {
-    if let Some(cache) = cache::CACHE.lock().unwrap().as_mut() {
+pub fn handle_analyzed_result(
+    tcx: TyCtxt<'_>,
+    mut analyzed: AnalyzeResult,
+    options: &AnalysisOptions,
+) {
+    let kinds = options.decoration_kinds;
+    // results lacking some categories must not be reused by a later full analysis
+    if kinds.is_all()
+        && let Some(cache) = utils::lock_or_recover(&cache::CACHE, "cache").as_mut()
+    {
         cache.insert_cache(
             analyzed.file_hash.clone(),
             analyzed.mir_hash.clone(),
             analyzed.analyzed.clone(),
         );
+        cache::STATS.record_insert();
+    }
+    if analyzed.ignored {
+        log::debug!("skip ignored function: {}", analyzed.analyzed.name);
+        return;
+    }
+    // unavailable functions are empty, but kept for their marker
+    if options.skip_empty && analyzed.analyzed.is_empty() && !analyzed.analyzed.unavailable {
+        log::debug!("skip empty function: {}", analyzed.analyzed.name);
+        return;
     }
-    let krate = Crate(HashMap::from([(
-        analyzed.file_path.to_string_lossy().to_string(),
+    // cached results have all categories and whole type names
+    for decl in &mut analyzed.analyzed.decls {
+        kinds.retain(decl);
+        if let Some(max_len) = options.max_type_len {
+            decl.ty_mut().truncate(max_len);
+        }
+    }
+    utils::lock_or_recover(&ANALYZED_FILES, "analyzed files").insert(analyzed.file_path.clone());
+    print_output(tcx, &analyzed.file_path, vec![analyzed.analyzed], options);
+}
+
+/// Source files which have no analyzed function
+fn unanalyzed_files(
+    sources: impl IntoIterator<Item = PathBuf>,
+    analyzed: &HashSet<PathBuf>,
+) -> impl Iterator<Item = PathBuf> {
+    let mut seen = HashSet::new();
+    sources
+        .into_iter()
+        .filter(move |path| !analyzed.contains(path) && seen.insert(path.clone()))
+}
+
+/// Output of one file of the crate `crate_name`
+fn file_output(crate_name: &str, path: &Path, items: Vec<Function>) -> (String, File) {
+    // files generated by build scripts live under `OUT_DIR` or the target directory
+    let out_dir = env::var_os("OUT_DIR").map(PathBuf::from);
+    let target_dir = env::var_os("CARGO_TARGET_DIR").map(PathBuf::from);
+    let generated = utils::is_generated_file(path, out_dir.as_deref(), target_dir.as_deref());
+    (
+        path.to_string_lossy().to_string(),
         File {
-            items: vec![analyzed.analyzed],
+            items,
+            generated,
+            std: is_std_crate(crate_name),
         },
-    )]));
-    // get currently-compiling crate name
+    )
+}
+
+fn print_output(tcx: TyCtxt<'_>, path: &Path, items: Vec<Function>, options: &AnalysisOptions) {
+    // get currently-compiling crate name; the stable crate id differs between
+    // same-named crates and between targets of the same crate
     let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
-    let ws = Workspace(HashMap::from([(crate_name.clone(), krate)]));
-    println!("{}", serde_json::to_string(&ws).unwrap());
+    let krate = Crate(OutputMap::from_iter([file_output(
+        &crate_name,
+        path,
+        items,
+    )]));
+    let stable_crate_id = tcx.stable_crate_id(LOCAL_CRATE).as_u64();
+    let key = crate_key(&crate_name, &format!("{stable_crate_id:016x}"));
+    let mut ws = Workspace(OutputMap::from_iter([(key, krate)]));
+    if options.redact {
+        ws = utils::lock_or_recover(&REDACTOR, "redactor").redact(ws);
+    }
+    let pretty = options.pretty;
+    if options.decorations_only {
+        let decos = decoration::workspace_decorations(&ws);
+        let json = if options.byte_ranges {
+            // the same text the positions of transcoded sources are counted in
+            let decos = decoration::with_byte_ranges(decos, |path| {
+                utils::read_source(std::path::Path::new(path))
+            });
+            if pretty {
+                serde_json::to_string_pretty(&decos)
+            } else {
+                serde_json::to_string(&decos)
+            }
+        } else if pretty {
+            serde_json::to_string_pretty(&decos)
+        } else {
+            serde_json::to_string(&decos)
+        };
+        println!("{}", json.unwrap());
+    } else if options.unversioned_output {
+        println!("{}", ws.to_unversioned_json(pretty).unwrap());
+    } else {
+        println!("{}", ws.to_json(pretty).unwrap());
+    }
 }
 
 #[rustversion::since(1.95.0)]
@@ -158,12 +323,27 @@ fn handle_exit_code(code: i32) -> ExitCode {
 
 pub fn run_compiler() -> ExitCode {
     let mut args: Vec<String> = env::args().collect();
+    if args
+        .get(1)
+        .is_some_and(|v| v == toolchain::VERSION_PROBE_FLAG)
+    {
+        println!("{}", toolchain::rustowl_version());
+        return ExitCode::SUCCESS;
+    }
+    let options = AnalysisOptions::from_settings(&mut args, |var| env::var(var).ok());
     // by using `RUSTC_WORKSPACE_WRAPPER`, arguments will be as follows:
     // For dependencies: rustowlc [args...]
     // For user workspace: rustowlc rustowlc [args...]
     // So we skip analysis if currently-compiling crate is one of the dependencies
     if args.first() == args.get(1) {
         args = args.into_iter().skip(1).collect();
+    } else if options.include_std
+        && let Some(crate_name) = crate_name_from_args(&args)
+        && is_std_crate(crate_name)
+    {
+        log::warn!(
+            "analyzing standard library crate `{crate_name}`; this greatly increases output size and analysis time"
+        );
     } else {
         return handle_exit_code(rustc_driver::catch_with_exit_code(|| {
             rustc_driver::run_compiler(&args, &mut RustcCallback)
@@ -180,6 +360,73 @@ pub fn run_compiler() -> ExitCode {
     }
 
     handle_exit_code(rustc_driver::catch_with_exit_code(|| {
-        rustc_driver::run_compiler(&args, &mut AnalyzerCallback);
+        rustc_driver::run_compiler(&args, &mut AnalyzerCallback { options });
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn std_crate_detection() {
+        for name in ["std", "core", "alloc"] {
+            assert!(is_std_crate(name));
+        }
+        for name in ["rustowl", "stdx", "core_foundation", "proc_macro"] {
+            assert!(!is_std_crate(name));
+        }
+
+        let args = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            crate_name_from_args(&args(&[
+                "rustowlc",
+                "--crate-name",
+                "core",
+                "--edition=2024"
+            ])),
+            Some("core")
+        );
+        assert_eq!(crate_name_from_args(&args(&["rustowlc", "-vV"])), None);
+        assert_eq!(
+            crate_name_from_args(&args(&["rustowlc", "--crate-name"])),
+            None
+        );
+    }
+
+    #[test]
+    fn trace_borrowck_filters() {
+        let args = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let traced = vec![(analyze::BORROWCK_TRACE_TARGET, log::LevelFilter::Trace)];
+        assert!(analyze::BORROWCK_TRACE_TARGET.ends_with("::analyze::polonius_analyzer"));
+
+        assert_eq!(
+            log_target_filters(&args(&["rustowlc", "--trace-borrowck"]), None),
+            traced
+        );
+        assert_eq!(log_target_filters(&args(&["rustowlc"]), Some("1")), traced);
+        assert_eq!(log_target_filters(&args(&["rustowlc"]), Some("0")), []);
+        assert_eq!(log_target_filters(&args(&["rustowlc"]), None), []);
+    }
+
+    #[test]
+    fn empty_file_is_emitted() {
+        let lib = PathBuf::from("src/lib.rs");
+        let empty = PathBuf::from("src/empty.rs");
+        let analyzed = HashSet::from([lib.clone()]);
+        let sources = [lib, empty.clone(), empty.clone()];
+        let unanalyzed: Vec<_> = unanalyzed_files(sources, &analyzed).collect();
+        assert_eq!(unanalyzed, vec![empty.clone()]);
+
+        let krate = Crate(OutputMap::from_iter([file_output(
+            "empty",
+            &empty,
+            Vec::new(),
+        )]));
+        let ws = Workspace(OutputMap::from_iter([(crate_key("empty", "0"), krate)]));
+        let json = ws.to_json(false).unwrap();
+        let ws = Workspace::from_json_reader(json.as_bytes()).unwrap();
+        let file = &ws.0[&crate_key("empty", "0")].0["src/empty.rs"];
+        assert!(file.items.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use super::analyze::{BorrowckMode, DecorationKinds, PanicStrategy, parse_max_nested_depth};
+use std::sync::OnceLock;
+
+/// Options of the running analysis, resolved once by [`super::AnalyzerCallback`]
+static OPTIONS: OnceLock<AnalysisOptions> = OnceLock::new();
+
+/// Store the options of this process; later calls are ignored
+pub fn init(options: AnalysisOptions) -> &'static AnalysisOptions {
+    OPTIONS.get_or_init(|| options)
+}
+
+/// Options of this process, for places the callbacks cannot pass them to
+/// (e.g. the `mir_borrowck` query override); the defaults before [`init`]
+pub fn get() -> &'static AnalysisOptions {
+    static DEFAULT: OnceLock<AnalysisOptions> = OnceLock::new();
+    OPTIONS
+        .get()
+        .unwrap_or_else(|| DEFAULT.get_or_init(AnalysisOptions::default))
+}
+
+/// Settings of an analysis run, from the `rustowlc` flags and `RUSTOWL_*`
+/// environment variables
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnalysisOptions {
+    /// Pretty-print the output (`--pretty`), for debugging by humans
+    pub pretty: bool,
+    /// Emit only decorations instead of the full MIR (`--decorations-only`)
+    pub decorations_only: bool,
+    /// Add byte ranges to the decorations-only output (`--byte-ranges`)
+    pub byte_ranges: bool,
+    /// Replace identifiers with placeholders (`--redact`)
+    pub redact: bool,
+    /// Print the workspace without the version envelope, as before the output
+    /// was versioned (`--unversioned-output` or `RUSTOWL_UNVERSIONED_OUTPUT`)
+    pub unversioned_output: bool,
+    /// Leave out functions without declarations and statements
+    /// (`--skip-empty` or `RUSTOWL_SKIP_EMPTY`), which are mostly generated code
+    pub skip_empty: bool,
+    /// Analyze the standard library crates too (`--include-std` or
+    /// `RUSTOWL_INCLUDE_STD`), which are only built with `-Zbuild-std`
+    pub include_std: bool,
+    /// Drop the ranges of macro-generated code (`--exclude-macros` or
+    /// `RUSTOWL_EXCLUDE_MACROS`), keeping only source-written code
+    pub exclude_macros: bool,
+    /// Range categories emitted for each declaration (`--decorations=<kinds>`
+    /// or `RUSTOWL_DECORATIONS`); all of them by default
+    pub decoration_kinds: DecorationKinds,
+    /// Borrow checker the ranges are derived from (`RUSTOWL_BORROWCK`)
+    pub borrowck_mode: BorrowckMode,
+    /// Deepest nesting of bodies that is analyzed (`RUSTOWL_MAX_NESTED_DEPTH`)
+    pub max_nested_depth: Option<usize>,
+    /// Longest type name in the output, in chars (`RUSTOWL_MAX_TYPE_LEN`);
+    /// unlimited if `None`
+    pub max_type_len: Option<usize>,
+    /// Panic strategy of the crate, from the session options rather than our
+    /// settings; set by [`super::AnalyzerCallback`]
+    pub panic_strategy: PanicStrategy,
+}
+
+impl AnalysisOptions {
+    /// Resolve the options from `args` and the environment variables looked up
+    /// by `env`. Our own flags are removed from `args` as rustc must not see them;
+    /// a flag takes precedence over its environment variable.
+    pub fn from_settings(args: &mut Vec<String>, env: impl Fn(&str) -> Option<String>) -> Self {
+        let enabled = |var: &str| env(var).is_some_and(|v| v == "1" || v == "true");
+
+        let decorations = args
+            .iter()
+            .rev()
+            .find_map(|v| v.strip_prefix("--decorations="))
+            .map(|v| v.to_owned())
+            .or_else(|| env("RUSTOWL_DECORATIONS"));
+        args.retain(|v| !v.starts_with("--decorations="));
+        let decoration_kinds = match decorations {
+            None => DecorationKinds::default(),
+            Some(decorations) => decorations.parse().unwrap_or_else(|e| {
+                log::warn!("ignore decoration kinds `{decorations}`: {e}");
+                DecorationKinds::default()
+            }),
+        };
+        // `--trace-borrowck` has been applied to the logger already
+        take_flag(args, "--trace-borrowck");
+
+        let decorations_only = take_flag(args, "--decorations-only");
+        let byte_ranges = take_flag(args, "--byte-ranges");
+        if byte_ranges && !decorations_only {
+            log::warn!("`--byte-ranges` only applies with `--decorations-only`; ignored");
+        }
+
+        Self {
+            pretty: take_flag(args, "--pretty"),
+            decorations_only,
+            byte_ranges: byte_ranges && decorations_only,
+            redact: take_flag(args, "--redact"),
+            unversioned_output: take_flag(args, "--unversioned-output")
+                || enabled("RUSTOWL_UNVERSIONED_OUTPUT"),
+            skip_empty: take_flag(args, "--skip-empty") || enabled("RUSTOWL_SKIP_EMPTY"),
+            include_std: take_flag(args, "--include-std") || enabled("RUSTOWL_INCLUDE_STD"),
+            exclude_macros: take_flag(args, "--exclude-macros")
+                || enabled("RUSTOWL_EXCLUDE_MACROS"),
+            decoration_kinds,
+            borrowck_mode: BorrowckMode::from_env(env("RUSTOWL_BORROWCK").as_deref()),
+            max_nested_depth: parse_max_nested_depth(env("RUSTOWL_MAX_NESTED_DEPTH").as_deref()),
+            max_type_len: parse_max_type_len(env("RUSTOWL_MAX_TYPE_LEN").as_deref()),
+            panic_strategy: PanicStrategy::default(),
+        }
+    }
+}
+
+fn parse_max_type_len(value: Option<&str>) -> Option<usize> {
+    let value = value?;
+    match value.trim().parse() {
+        Ok(len) => Some(len),
+        Err(_) => {
+            log::warn!("invalid RUSTOWL_MAX_TYPE_LEN `{value}`; type names are not truncated");
+            None
+        }
+    }
+}
+
+/// Remove every `flag` from `args`, returning whether there was any
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|v| v != flag);
+    args.len() < len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn options_from_settings() {
+        let args = |v: &[&str]| v.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let env = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            move |var: &str| vars.get(var).cloned()
+        };
+
+        let mut rustc_args = args(&["rustowlc", "--edition=2024", "lib.rs"]);
+        let options = AnalysisOptions::from_settings(&mut rustc_args, env(&[]));
+        assert_eq!(options, AnalysisOptions::default());
+        assert_eq!(options.decoration_kinds, DecorationKinds::ALL);
+        assert_eq!(rustc_args, args(&["rustowlc", "--edition=2024", "lib.rs"]));
+
+        let mut rustc_args = args(&[
+            "rustowlc",
+            "--pretty",
+            "--decorations=borrows",
+            "--redact",
+            "--trace-borrowck",
+            "--skip-empty",
+            "lib.rs",
+            "--pretty",
+        ]);
+        let options = AnalysisOptions::from_settings(
+            &mut rustc_args,
+            env(&[
+                ("RUSTOWL_DECORATIONS", "drops"),
+                ("RUSTOWL_INCLUDE_STD", "1"),
+                ("RUSTOWL_EXCLUDE_MACROS", "0"),
+                ("RUSTOWL_BORROWCK", "nll"),
+                ("RUSTOWL_MAX_NESTED_DEPTH", "2"),
+                ("RUSTOWL_MAX_TYPE_LEN", "120"),
+                ("RUSTOWL_UNVERSIONED_OUTPUT", "1"),
+            ]),
+        );
+        assert_eq!(
+            options,
+            AnalysisOptions {
+                pretty: true,
+                decorations_only: false,
+                byte_ranges: false,
+                redact: true,
+                unversioned_output: true,
+                skip_empty: true,
+                include_std: true,
+                exclude_macros: false,
+                decoration_kinds: "borrows".parse().unwrap(),
+                borrowck_mode: BorrowckMode::Nll,
+                max_nested_depth: Some(2),
+                max_type_len: Some(120),
+                panic_strategy: PanicStrategy::Unwind,
+            }
+        );
+        assert_eq!(rustc_args, args(&["rustowlc", "lib.rs"]));
+
+        let options = AnalysisOptions::from_settings(
+            &mut args(&["rustowlc"]),
+            env(&[
+                ("RUSTOWL_DECORATIONS", "drops,lives"),
+                ("RUSTOWL_SKIP_EMPTY", "true"),
+                ("RUSTOWL_BORROWCK", "chalk"),
+                ("RUSTOWL_MAX_NESTED_DEPTH", "deep"),
+                ("RUSTOWL_MAX_TYPE_LEN", "-1"),
+            ]),
+        );
+        assert_eq!(
+            options.decoration_kinds,
+            "drops,lives".parse::<DecorationKinds>().unwrap()
+        );
+        assert!(options.skip_empty);
+        assert_eq!(options.borrowck_mode, BorrowckMode::Polonius);
+        assert_eq!(options.max_nested_depth, None);
+        assert_eq!(options.max_type_len, None);
+
+        // byte ranges only apply to the decorations-only output
+        let options =
+            AnalysisOptions::from_settings(&mut args(&["rustowlc", "--byte-ranges"]), env(&[]));
+        assert!(!options.byte_ranges);
+        let options = AnalysisOptions::from_settings(
+            &mut args(&["rustowlc", "--byte-ranges", "--decorations-only"]),
+            env(&[]),
+        );
+        assert!(options.byte_ranges && options.decorations_only);
+    }
+}
@@ -2,12 +2,11 @@
 //!
 //! An LSP server for visualizing ownership and lifetimes in Rust, designed for debugging and optimization.
 
-use clap::{CommandFactory, Parser};
-use clap_complete::generate;
+use clap::Parser;
 use rustowl::*;
 use std::env;
 use std::io;
-use tower_lsp::{LspService, Server};
+use tower_lsp::Server;
 
 use crate::cli::{Cli, Commands, ToolchainCommands};
 
@@ -44,19 +43,43 @@ fn set_log_level(default: log::LevelFilter) {
 async fn handle_command(command: Commands) {
     match command {
         Commands::Check(command_options) => {
-            let path = command_options.path.unwrap_or(env::current_dir().unwrap());
+            let path = command_options
+                .path
+                .clone()
+                .unwrap_or(env::current_dir().unwrap());
+            let config = config::Config::discover(&path)
+                .with_flags(command_options.all_targets, command_options.all_features);
 
-            if Backend::check_with_options(
+            if command_options.pretty
+                && !matches!(
+                    command_options.format,
+                    cli::CheckFormat::Json | cli::CheckFormat::Decorations
+                )
+            {
+                log::warn!(
+                    "`--pretty` only applies to `--format json` and `--format decorations`; ignored"
+                );
+            }
+            // the backend does not take the options of a single analyzer
+            if command_options.needs_analyzer() {
+                handle_output(&path, config, command_options).await;
+            }
+            match Backend::check_packages(
                 &path,
-                command_options.all_targets,
-                command_options.all_features,
+                config.all_targets,
+                config.all_features,
+                command_options.packages,
+                command_options.tests,
             )
             .await
             {
-                log::info!("Successfully analyzed");
-                std::process::exit(0);
+                Ok(true) => {
+                    log::info!("Successfully analyzed");
+                    std::process::exit(0);
+                }
+                Ok(false) => log::error!("Analyze failed"),
+                Err(e) => log::error!("{e}"),
             }
-            log::error!("Analyze failed");
             std::process::exit(1);
         }
         Commands::Clean => {
@@ -73,10 +96,10 @@ async fn handle_command(command: Commands) {
                         skip_rustowl_toolchain,
                     } => {
                         let path = path.unwrap_or(toolchain::FALLBACK_RUNTIME_DIR.clone());
-                        if toolchain::setup_toolchain(&path, skip_rustowl_toolchain)
-                            .await
-                            .is_err()
+                        if let Err(e) =
+                            toolchain::setup_toolchain(&path, skip_rustowl_toolchain).await
                         {
+                            log::error!("{e}");
                             std::process::exit(1);
                         }
                     }
@@ -88,13 +111,294 @@ async fn handle_command(command: Commands) {
         }
         Commands::Completions(command_options) => {
             set_log_level(log::LevelFilter::Off);
-            let shell = command_options.shell;
-            generate(shell, &mut Cli::command(), "rustowl", &mut io::stdout());
+            if let Some(cli::CompletionsCommands::List) = command_options.command {
+                let detected = shells::Shell::from_env()
+                    .map(|shell| (shell, shell.completion_install_path("rustowl")));
+                print!("{}", shells::completions_list("rustowl", detected));
+            } else if let Some(shell) = command_options.shell {
+                if let Some(reason) = shell.unsupported_reason() {
+                    eprintln!("{reason}");
+                    std::process::exit(1);
+                }
+                shells::generate_completion(shell, "rustowl", &mut io::stdout());
+            }
         }
         Commands::Show(command_options) => {
             handle_show_command(command_options).await;
         }
+        Commands::Validate(command_options) => {
+            handle_validate_command(command_options);
+        }
+        Commands::Serve(command_options) => {
+            handle_serve_command(command_options).await;
+        }
+        Commands::Cache(command_options) => match command_options.command {
+            cli::CacheCommands::Migrate { path } => handle_cache_migrate_command(path),
+        },
+    }
+}
+
+/// Handles `cache migrate`, reporting what became of each cache file.
+/// Exits with 1 if a file could not be migrated nor removed.
+fn handle_cache_migrate_command(path: Option<std::path::PathBuf>) {
+    let dir = path.or_else(cache::get_cache_path).or_else(|| {
+        cargo_metadata::MetadataCommand::new()
+            .exec()
+            .ok()
+            .map(|meta| meta.target_directory.join("owl").join("cache").into())
+    });
+    let Some(dir) = dir else {
+        log::error!("no cache directory found; pass its path");
+        std::process::exit(1);
+    };
+    let compression = cache::CacheCompression::from_env(|name| env::var(name).ok());
+    let report = match cache::migrate_cache_dir(&dir, compression) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("failed to read {}: {e}", dir.display());
+            std::process::exit(1);
+        }
+    };
+    let mut failed = false;
+    for (path, outcome) in &report {
+        let path = path.display();
+        match outcome {
+            Ok(cache::MigrationOutcome::Current) => log::debug!("{path} is up to date"),
+            Ok(cache::MigrationOutcome::Migrated { from: None }) => {
+                log::info!("{path} migrated from the unversioned format")
+            }
+            Ok(cache::MigrationOutcome::Migrated { from: Some(v) }) => {
+                log::info!("{path} migrated from version {v}")
+            }
+            Ok(cache::MigrationOutcome::Discarded { reason }) => {
+                log::warn!("{path} discarded: {reason}")
+            }
+            Ok(cache::MigrationOutcome::Skipped) => {
+                log::warn!("{path} skipped: not a RustOwl cache file")
+            }
+            Err(e) => {
+                log::error!("failed to migrate {path}: {e}");
+                failed = true;
+            }
+        }
+    }
+    log::info!(
+        "{} cache file(s) checked in {}",
+        report.len(),
+        dir.display()
+    );
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// Handles the serve command, running until the listener fails.
+async fn handle_serve_command(opts: cli::Serve) {
+    let result = match (opts.socket, opts.tcp) {
+        #[cfg(unix)]
+        (Some(path), _) => rustowl::serve::serve_unix(&path).await,
+        #[cfg(not(unix))]
+        (Some(_), _) => {
+            log::error!("`--socket` is only supported on Unix; use `--tcp` instead");
+            std::process::exit(1);
+        }
+        (None, Some(addr)) => rustowl::serve::serve_tcp(&addr, opts.allow_remote).await,
+        (None, None) => unreachable!("clap requires either `--socket` or `--tcp`"),
+    };
+    if let Err(e) = result {
+        log::error!("failed to serve: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Handles the validate command for checking a captured analysis output.
+fn handle_validate_command(opts: cli::Validate) {
+    let file = match std::fs::File::open(&opts.path) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("failed to open {}: {e}", opts.path.display());
+            std::process::exit(1);
+        }
+    };
+    let ws = match models::Workspace::from_json_reader(io::BufReader::new(file)) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("failed to parse {}: {e}", opts.path.display());
+            std::process::exit(1);
+        }
+    };
+    let errors = ws.validate();
+    if errors.is_empty() {
+        log::info!("{} is valid", opts.path.display());
+        return;
     }
+    for error in &errors {
+        log::error!("{error}");
+    }
+    log::error!("{} validation error(s) found", errors.len());
+    std::process::exit(1);
+}
+
+/// Analyzes the workspace, or only the file at `path` with `--single-file`,
+/// then outputs it in `--format` unless it is text and writes one file per crate
+/// into `--output-dir` if given.
+async fn handle_output(path: &std::path::Path, config: config::Config, opts: cli::Check) -> ! {
+    use rustowl::lsp::analyze::{Analyzer, AnalyzerEvent, FeatureSelection};
+    use rustowl::serve::{JsonLinesSink, ResultSink, SentCounts, ServeResponse};
+
+    if opts.format == cli::CheckFormat::Sqlite && cfg!(not(feature = "sqlite")) {
+        log::error!("`--format sqlite` requires rustowl built with the `sqlite` feature");
+        std::process::exit(1);
+    }
+    let single_file = opts.single_file;
+    let analyzer = if single_file {
+        match Analyzer::single_file(path) {
+            // the diagnostics tell whether external crates are missing
+            Ok(a) => a.capture_stderr(),
+            Err(e) => {
+                log::error!("{e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        match Analyzer::new(path).await {
+            Ok(a) => a,
+            Err(e) => {
+                log::error!("Failed to create analyzer: {e:?}");
+                std::process::exit(1);
+            }
+        }
+    };
+    let features = FeatureSelection {
+        features: opts.features,
+        no_default_features: opts.no_default_features,
+    };
+    let analyzer = match analyzer
+        .select_packages(opts.packages)
+        .and_then(|a| a.select_features(features))
+    {
+        Ok(a) => a
+            .analyze_tests(opts.tests)
+            .cargo_args(opts.cargo_args)
+            .cfgs(opts.cfgs),
+        Err(e) => {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let mut ws = models::Workspace::with_capacity(analyzer.expected_crates());
+    let mut iter = analyzer
+        .analyze(config.all_targets, config.all_features)
+        .await;
+    while let Some(event) = iter.next_event().await {
+        if let AnalyzerEvent::Analyzed(analyzed) = event {
+            ws.merge(analyzed);
+        }
+    }
+    let json = matches!(
+        opts.format,
+        cli::CheckFormat::Json | cli::CheckFormat::Decorations
+    );
+    let mut sink = json.then(|| JsonLinesSink {
+        writer: tokio::io::stdout(),
+        pretty: opts.pretty,
+    });
+    let (success, stderr) = iter.finish().await;
+    let failure = if !success {
+        Some(if single_file {
+            rustowl::analysis::AnalysisError::from_diagnostics(stderr, true).to_string()
+        } else {
+            // not captured unless requested, the compiler has printed it already
+            if !stderr.is_empty() {
+                eprint!("{stderr}");
+            }
+            "Analyze failed".to_owned()
+        })
+    } else if ws.0.is_empty() {
+        Some("Analyze failed".to_owned())
+    } else {
+        None
+    };
+    if let Some(message) = failure {
+        log::error!("{message}");
+        if let Some(sink) = &mut sink
+            && opts.format == cli::CheckFormat::Json
+        {
+            sink.send(&ServeResponse::Error { message }).await.ok();
+        }
+        std::process::exit(1);
+    }
+    if let Some(dir) = opts.output_dir.as_deref() {
+        match ws.write_crate_files(dir) {
+            Ok(written) => log::info!("wrote {} crates into {}", written.len(), dir.display()),
+            Err(e) => {
+                log::error!("failed to write into {}: {e}", dir.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    match opts.format {
+        cli::CheckFormat::Text => log::info!("Successfully analyzed"),
+        cli::CheckFormat::Stats => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&ws.statistics()).unwrap()
+            );
+        }
+        cli::CheckFormat::Lines => {
+            let lines = ws.fact_lines(|file| utils::read_source(std::path::Path::new(file)).ok());
+            for line in lines {
+                println!("{line}");
+            }
+        }
+        cli::CheckFormat::Sqlite => {
+            // `--output` is required by clap for this format
+            write_sqlite(&ws, &opts.output.unwrap());
+        }
+        cli::CheckFormat::Json => {
+            let sink = sink.as_mut().unwrap();
+            let mut counts = SentCounts::default();
+            counts.add(&ws);
+            let sent = match sink.send(&ServeResponse::Analyzed { workspace: ws }).await {
+                Ok(()) => sink.send(&counts.done()).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = sent {
+                log::error!("failed to write the result: {e}");
+                std::process::exit(1);
+            }
+        }
+        cli::CheckFormat::Decorations => {
+            let decos = rustowl::lsp::decoration::workspace_decorations(&ws);
+            if let Err(e) = sink.as_mut().unwrap().write(&decos).await {
+                log::error!("failed to write the result: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Writes `ws` into a new SQLite database at `path`, replacing the file.
+#[cfg(feature = "sqlite")]
+fn write_sqlite(ws: &models::Workspace, path: &std::path::Path) {
+    if let Err(e) = std::fs::remove_file(path)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        log::error!("failed to replace {}: {e}", path.display());
+        std::process::exit(1);
+    }
+    match rustowl::sqlite::export(ws, path) {
+        Ok(()) => log::info!("wrote the analysis into {}", path.display()),
+        Err(e) => {
+            log::error!("failed to write into {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+#[cfg(not(feature = "sqlite"))]
+fn write_sqlite(_ws: &models::Workspace, _path: &std::path::Path) {
+    unreachable!("rejected before the analysis")
 }
 
 /// Handles the show command for visualizing ownership and lifetimes.
@@ -120,7 +424,11 @@ async fn handle_show_command(opts: cli::Show) {
         }
     };
 
-    let mut iter = analyzer.analyze(opts.all_targets, opts.all_features).await;
+    let config =
+        rustowl::config::Config::discover(&path).with_flags(opts.all_targets, opts.all_features);
+    let mut iter = analyzer
+        .analyze(config.all_targets, config.all_features)
+        .await;
 
     // Collect analysis results
     let mut crate_data: Option<rustowl::models::Crate> = None;
@@ -180,6 +488,23 @@ async fn handle_no_command(args: Cli) {
     start_lsp_server().await;
 }
 
+/// Prints the configuration resolved for the command, with the source of each value
+fn print_config(command: Option<&Commands>) -> std::io::Result<()> {
+    let (path, all_targets, all_features) = match command {
+        Some(Commands::Check(check)) => (check.path.clone(), check.all_targets, check.all_features),
+        _ => (None, None, None),
+    };
+    let path = match path {
+        Some(path) => path,
+        None => env::current_dir()?,
+    };
+    let resolved = config::ResolvedConfig::resolve(&path, all_targets, all_features, |name| {
+        env::var(name).ok()
+    });
+    println!("{}", serde_json::to_string_pretty(&resolved)?);
+    Ok(())
+}
+
 /// Displays the version information
 fn display_version(show_prefix: bool) {
     if show_prefix {
@@ -197,10 +522,7 @@ async fn start_lsp_server() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::build(Backend::new)
-        .custom_method("rustowl/cursor", Backend::cursor)
-        .custom_method("rustowl/analyze", Backend::analyze)
-        .finish();
+    let (service, socket) = Backend::service();
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -209,7 +531,21 @@ async fn start_lsp_server() {
 async fn main() {
     initialize_logging();
 
-    let parsed_args = Cli::parse();
+    let parsed_args = Cli::parse_from(cli::cargo_subcommand_args(std::env::args_os().collect()));
+    if parsed_args.no_toolchain_download {
+        rustowl::toolchain::disable_toolchain_download();
+    }
+    if let Some(threads) = parsed_args.analysis_threads {
+        rustowl::toolchain::set_analysis_threads(threads);
+    }
+
+    if parsed_args.print_config {
+        if let Err(e) = print_config(parsed_args.command.as_ref()) {
+            eprintln!("failed to resolve the configuration: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
     match parsed_args.command {
         Some(command) => handle_command(command).await,
@@ -30,20 +30,19 @@ pub mod core;
 use tikv_jemalloc_sys as _;
 
 fn main() -> std::process::ExitCode {
-    simple_logger::SimpleLogger::new()
-        .env()
-        .with_colors(true)
-        .init()
-        .unwrap();
+    let args: Vec<String> = std::env::args().collect();
+    let trace_env = std::env::var("RUSTOWL_TRACE_BORROWCK").ok();
+    let mut logger = simple_logger::SimpleLogger::new().env().with_colors(true);
+    for (target, level) in core::log_target_filters(&args, trace_env.as_deref()) {
+        logger = logger.with_module_level(target, level);
+    }
+    logger.init().unwrap();
 
+    let rayon_pool = rayon::ThreadPoolBuilder::new().num_threads(rustowl::utils::worker_threads());
     // rayon panics without this only on Windows
     #[cfg(target_os = "windows")]
-    {
-        rayon::ThreadPoolBuilder::new()
-            .stack_size(4 * 1024 * 1024)
-            .build_global()
-            .unwrap();
-    }
+    let rayon_pool = rayon_pool.stack_size(4 * 1024 * 1024);
+    rayon_pool.build_global().unwrap();
 
     core::run_compiler()
 }
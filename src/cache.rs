@@ -1,7 +1,15 @@
+use crate::models::Function;
+use indexmap::IndexMap;
 use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::process::Command;
 
+/// How long to wait for another process holding a cache lock
+pub const CACHE_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const CACHE_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 pub fn is_cache() -> bool {
     !env::var("RUSTOWL_CACHE")
         .map(|v| v == "false" || v == "0")
@@ -13,5 +21,533 @@ pub fn set_cache_path(cmd: &mut Command, target_dir: impl AsRef<Path>) {
 }
 
 pub fn get_cache_path() -> Option<PathBuf> {
-    env::var("RUSTOWL_CACHE_DIR").map(PathBuf::from).ok()
+    env::var("RUSTOWL_CACHE_DIR")
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+        .map(|v| PathBuf::from(expand_path(&v, |name| env::var(name).ok())))
+        .and_then(usable_cache_dir)
+}
+
+/// `None` if `path` exists but is not a directory, in which case
+/// caching is disabled rather than failing on every cache access
+fn usable_cache_dir(path: PathBuf) -> Option<PathBuf> {
+    if path.exists() && !path.is_dir() {
+        log::warn!(
+            "RUSTOWL_CACHE_DIR `{}` is not a directory; cache is disabled",
+            path.display()
+        );
+        return None;
+    }
+    Some(path)
+}
+
+/// Lock on a cache file shared between processes, released on drop
+#[derive(Debug)]
+pub struct CacheLock(#[allow(unused)] fs::File);
+
+/// Lock `cache_file` through a `.lock` file next to it
+///
+/// Readers share the lock and a writer takes it exclusively.
+/// Returns `None` if the lock could not be taken within `timeout`,
+/// in which case the caller should skip the cache rather than wait forever.
+pub fn lock_cache(cache_file: &Path, exclusive: bool, timeout: Duration) -> Option<CacheLock> {
+    let lock_path = cache_file.with_extension("lock");
+    if let Some(parent) = lock_path.parent()
+        && let Err(e) = fs::create_dir_all(parent)
+    {
+        log::warn!("failed to create cache dir: {e}");
+        return None;
+    }
+    let file = match fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_path)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("failed to open cache lock file: {e}");
+            return None;
+        }
+    };
+    let start = Instant::now();
+    loop {
+        let locked = if exclusive {
+            file.try_lock()
+        } else {
+            file.try_lock_shared()
+        };
+        match locked {
+            Ok(()) => return Some(CacheLock(file)),
+            Err(fs::TryLockError::WouldBlock) if start.elapsed() < timeout => {
+                std::thread::sleep(CACHE_LOCK_POLL_INTERVAL);
+            }
+            Err(fs::TryLockError::WouldBlock) => {
+                log::warn!(
+                    "cache {} is locked by another process; skip caching",
+                    cache_file.display()
+                );
+                return None;
+            }
+            Err(fs::TryLockError::Error(e)) => {
+                log::warn!("failed to lock cache: {e}");
+                return None;
+            }
+        }
+    }
+}
+
+/// Default zstd level, a good balance of size and speed
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Codec of cache files, set by `RUSTOWL_CACHE_COMPRESSION=zstd|none`
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum CacheCompression {
+    #[default]
+    None,
+    /// At the level of `RUSTOWL_CACHE_COMPRESSION_LEVEL`, [`DEFAULT_ZSTD_LEVEL`] by default
+    Zstd { level: i32 },
+}
+
+impl CacheCompression {
+    pub fn from_env(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        match lookup("RUSTOWL_CACHE_COMPRESSION")
+            .as_deref()
+            .map(str::trim)
+        {
+            None | Some("" | "none") => Self::None,
+            Some("zstd") => {
+                let level = lookup("RUSTOWL_CACHE_COMPRESSION_LEVEL");
+                let level = match level.as_deref().map(|v| v.trim().parse()) {
+                    None => DEFAULT_ZSTD_LEVEL,
+                    Some(Ok(level)) if zstd::compression_level_range().contains(&level) => level,
+                    Some(_) => {
+                        log::warn!(
+                            "invalid RUSTOWL_CACHE_COMPRESSION_LEVEL `{}`; use {DEFAULT_ZSTD_LEVEL}",
+                            level.unwrap_or_default()
+                        );
+                        DEFAULT_ZSTD_LEVEL
+                    }
+                };
+                Self::Zstd { level }
+            }
+            Some(other) => {
+                log::warn!("unknown RUSTOWL_CACHE_COMPRESSION `{other}`; expected zstd or none");
+                Self::None
+            }
+        }
+    }
+
+    /// Name of the codec, which cache files record to be read back with any setting
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zstd { .. } => "zstd",
+        }
+    }
+
+    pub fn compress(&self, bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(bytes),
+            Self::Zstd { level } => zstd::encode_all(bytes.as_slice(), *level),
+        }
+    }
+
+    /// Decompress `bytes` compressed by the codec called `name`
+    pub fn decompress(name: &str, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match name {
+            "none" => Ok(bytes.to_vec()),
+            "zstd" => zstd::decode_all(bytes),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown cache compression `{name}`"),
+            )),
+        }
+    }
+}
+
+/// Version of the cache file format, to be bumped whenever a change of the
+/// models breaks reading caches written by earlier releases, or would let them
+/// deserialize with fields silently defaulted.
+///
+/// - 2: borrows carry the `reason` they are taken for
+pub const CACHE_SCHEMA_VERSION: u32 = 2;
+/// Start of the first line of a cache file, followed by the schema version
+/// and the compression of the body unless it is stored as is
+pub const CACHE_HEADER: &str = "rustowl-cache v";
+
+/// Body of a crate cache: MIR body hash -> (file hash -> analyze result).
+///
+/// The maps keep their order through serialization, which is the order the
+/// entries are evicted in: oldest first.
+pub type CacheEntries = IndexMap<String, IndexMap<String, Function>>;
+
+/// Cache file of schema `version` holding `body` compressed with `compression`
+pub fn encode_cache_file(
+    version: u32,
+    compression: CacheCompression,
+    body: Vec<u8>,
+) -> std::io::Result<Vec<u8>> {
+    let mut header = format!("{CACHE_HEADER}{version}");
+    if compression != CacheCompression::None {
+        header = format!("{header} {}", compression.name());
+    }
+    let body = compression.compress(body)?;
+    let mut encoded = Vec::with_capacity(header.len() + 1 + body.len());
+    encoded.extend_from_slice(header.as_bytes());
+    encoded.push(b'\n');
+    encoded.extend_from_slice(&body);
+    Ok(encoded)
+}
+
+/// Schema version and decompressed body of a cache file
+///
+/// The version is `None` for caches written before versioning, which are
+/// the body alone.
+pub fn decode_cache_file(bytes: &[u8]) -> std::io::Result<(Option<u32>, Vec<u8>)> {
+    let header = bytes
+        .iter()
+        .position(|v| *v == b'\n')
+        .and_then(|end| Some((std::str::from_utf8(&bytes[..end]).ok()?, end)))
+        .and_then(|(header, end)| Some((header.strip_prefix(CACHE_HEADER)?, end)));
+    let Some((header, end)) = header else {
+        return Ok((None, bytes.to_vec()));
+    };
+    let mut fields = header.split_whitespace();
+    let version = fields.next().and_then(|v| v.parse::<u32>().ok());
+    let codec = fields.next().unwrap_or(CacheCompression::None.name());
+    Ok((
+        version,
+        CacheCompression::decompress(codec, &bytes[end + 1..])?,
+    ))
+}
+
+/// Convert a cache body of an earlier schema `version` into the current one
+///
+/// No version can be converted yet. Caches written before versioning lack the
+/// fields added since, which would deserialize into their defaults and so into
+/// wrong data, and are discarded as well.
+pub fn migrate_entries(version: Option<u32>, _body: &str) -> Result<CacheEntries, String> {
+    match version {
+        None => Err("unversioned cache predates the current models".to_owned()),
+        Some(version) => Err(format!(
+            "version {version} cannot be converted to version {CACHE_SCHEMA_VERSION}"
+        )),
+    }
+}
+
+/// What [`migrate_cache_file`] did to a cache file
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MigrationOutcome {
+    /// Already in the current format; left as it is
+    Current,
+    /// Rewritten in the current format from `from`, `None` if unversioned
+    Migrated { from: Option<u32> },
+    /// Removed, as it could not be read or converted
+    Discarded { reason: String },
+    /// Not a cache file; left untouched
+    Skipped,
+}
+
+/// Whether `bytes` look like a cache file: a [`CACHE_HEADER`] line, or the bare
+/// non-empty [`CacheEntries`] written before versioning
+fn is_cache_file(bytes: &[u8]) -> bool {
+    bytes.starts_with(CACHE_HEADER.as_bytes())
+        || serde_json::from_slice::<CacheEntries>(bytes).is_ok_and(|v| !v.is_empty())
+}
+
+/// Rewrite the cache file at `path` in the current format, compressed with
+/// `compression`, or remove it if it cannot be converted.
+/// Files that are not caches are skipped, so that a wrong directory is harmless.
+pub fn migrate_cache_file(
+    path: &Path,
+    compression: CacheCompression,
+) -> std::io::Result<MigrationOutcome> {
+    if !is_cache_file(&fs::read(path)?) {
+        return Ok(MigrationOutcome::Skipped);
+    }
+    let Some(_lock) = lock_cache(path, true, CACHE_LOCK_TIMEOUT) else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            "the cache is locked by another process",
+        ));
+    };
+    let entries = decode_cache_file(&fs::read(path)?)
+        .map_err(|e| format!("broken cache: {e}"))
+        .and_then(|(version, body)| {
+            let body = String::from_utf8(body).map_err(|e| format!("broken cache: {e}"))?;
+            if version == Some(CACHE_SCHEMA_VERSION) {
+                serde_json::from_str::<CacheEntries>(&body)
+                    .map(|_| None)
+                    .map_err(|e| format!("broken cache: {e}"))
+            } else {
+                migrate_entries(version, &body).map(|entries| Some((version, entries)))
+            }
+        });
+    match entries {
+        Ok(None) => Ok(MigrationOutcome::Current),
+        Ok(Some((from, entries))) => {
+            let encoded = encode_cache_file(
+                CACHE_SCHEMA_VERSION,
+                compression,
+                serde_json::to_vec(&entries)?,
+            )?;
+            fs::write(path, encoded)?;
+            Ok(MigrationOutcome::Migrated { from })
+        }
+        Err(reason) => {
+            fs::remove_file(path)?;
+            Ok(MigrationOutcome::Discarded { reason })
+        }
+    }
+}
+
+/// Migrate every cache file in `dir` by [`migrate_cache_file`], in path order
+pub fn migrate_cache_dir(
+    dir: &Path,
+    compression: CacheCompression,
+) -> std::io::Result<Vec<(PathBuf, std::io::Result<MigrationOutcome>)>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|v| v == "json") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let outcome = migrate_cache_file(&path, compression);
+            (path, outcome)
+        })
+        .collect())
+}
+
+fn home_dir(lookup: &impl Fn(&str) -> Option<String>) -> Option<String> {
+    lookup("HOME").or_else(|| lookup("USERPROFILE"))
+}
+
+/// Expands a leading `~` and `$VAR`/`${VAR}` in `path`
+///
+/// `~user` and unset variables are left as they are.
+fn expand_path(path: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut path = path.to_owned();
+    if (path == "~" || path.starts_with("~/") || path.starts_with("~\\"))
+        && let Some(home) = home_dir(&lookup)
+    {
+        path.replace_range(..1, &home);
+    }
+
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path.as_str();
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[dollar..dollar + 1 + consumed]),
+        }
+        rest = &after[consumed..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/owl".to_owned()),
+            "XDG_CACHE_HOME" => Some("/home/owl/.cache".to_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn compression_settings() {
+        let env = |compression: &'static str, level: &'static str| {
+            move |name: &str| match name {
+                "RUSTOWL_CACHE_COMPRESSION" => Some(compression.to_owned()),
+                "RUSTOWL_CACHE_COMPRESSION_LEVEL" => {
+                    Some(level.to_owned()).filter(|v| !v.is_empty())
+                }
+                _ => None,
+            }
+        };
+        assert_eq!(CacheCompression::from_env(lookup), CacheCompression::None);
+        assert_eq!(
+            CacheCompression::from_env(env("none", "9")),
+            CacheCompression::None
+        );
+        assert_eq!(
+            CacheCompression::from_env(env("lz4", "")),
+            CacheCompression::None
+        );
+        assert_eq!(
+            CacheCompression::from_env(env("zstd", "")),
+            CacheCompression::Zstd {
+                level: DEFAULT_ZSTD_LEVEL
+            }
+        );
+        assert_eq!(
+            CacheCompression::from_env(env("zstd", "19")),
+            CacheCompression::Zstd { level: 19 }
+        );
+        assert_eq!(
+            CacheCompression::from_env(env("zstd", "fast")),
+            CacheCompression::Zstd {
+                level: DEFAULT_ZSTD_LEVEL
+            }
+        );
+    }
+
+    #[test]
+    fn workspace_compression_round_trip() {
+        use crate::models::*;
+
+        let range = |from, until| Range::new(Loc(from), Loc(until)).unwrap();
+        let items = (0..50)
+            .map(|fn_id| Function {
+                fn_id,
+                name: format!("f{fn_id}"),
+                span: Some(range(fn_id * 100, fn_id * 100 + 90)),
+                test: false,
+                unavailable: false,
+                basic_blocks: Vec::new(),
+                decls: (0..10)
+                    .map(|id| MirDecl::Other {
+                        local: FnLocal::new(id, fn_id),
+                        ty: MirType {
+                            name: "Vec<String>".into(),
+                            reference: None,
+                        },
+                        lives: vec![range(fn_id * 100, fn_id * 100 + id + 1)],
+                        shared_borrow: Vec::new(),
+                        mutable_borrow: Vec::new(),
+                        drop: true,
+                        drop_range: vec![range(fn_id * 100 + id, fn_id * 100 + 90)],
+                        must_live_at: Vec::new(),
+                        storage_range: Vec::new(),
+                        definitely_live_at: Vec::new(),
+                        maybe_init_at: Vec::new(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        let file = File {
+            items,
+            generated: false,
+            std: false,
+        };
+        let ws = Workspace(OutputMap::from_iter([(
+            crate_key("owl", "0"),
+            Crate(OutputMap::from_iter([("src/lib.rs".to_owned(), file)])),
+        )]));
+        let json = ws.to_json(false).unwrap().into_bytes();
+
+        let zstd = CacheCompression::Zstd {
+            level: DEFAULT_ZSTD_LEVEL,
+        };
+        let compressed = zstd.compress(json.clone()).unwrap();
+        assert!(compressed.len() * 5 < json.len());
+        let decompressed = CacheCompression::decompress(zstd.name(), &compressed).unwrap();
+        assert_eq!(decompressed, json);
+        let read = Workspace::from_json_reader(decompressed.as_slice()).unwrap();
+        assert_eq!(read.to_json(false).unwrap().into_bytes(), json);
+
+        let none = CacheCompression::None;
+        assert_eq!(none.compress(json.clone()).unwrap(), json);
+        assert_eq!(CacheCompression::decompress("none", &json).unwrap(), json);
+        assert!(CacheCompression::decompress("lz4", &json).is_err());
+        assert!(CacheCompression::decompress("zstd", &json).is_err());
+    }
+
+    #[test]
+    fn expand_tilde() {
+        assert_eq!(expand_path("~", lookup), "/home/owl");
+        assert_eq!(
+            expand_path("~/.cache/rustowl", lookup),
+            "/home/owl/.cache/rustowl"
+        );
+        assert_eq!(expand_path("~owl/cache", lookup), "~owl/cache");
+        assert_eq!(expand_path("/tmp/~/cache", lookup), "/tmp/~/cache");
+    }
+
+    #[test]
+    fn expand_env_vars() {
+        assert_eq!(expand_path("$HOME/sub", lookup), "/home/owl/sub");
+        assert_eq!(
+            expand_path("${XDG_CACHE_HOME}/rustowl", lookup),
+            "/home/owl/.cache/rustowl"
+        );
+        assert_eq!(expand_path("$UNSET/sub", lookup), "$UNSET/sub");
+        assert_eq!(expand_path("${UNCLOSED/sub", lookup), "${UNCLOSED/sub");
+        assert_eq!(expand_path("/tmp/cost$", lookup), "/tmp/cost$");
+    }
+
+    #[test]
+    fn contended_cache_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("owl.json");
+
+        let first = lock_cache(&cache_file, true, Duration::ZERO).unwrap();
+        // another process gives up after the timeout
+        assert!(lock_cache(&cache_file, true, Duration::from_millis(100)).is_none());
+        assert!(lock_cache(&cache_file, false, Duration::from_millis(100)).is_none());
+
+        // or waits until the lock is released
+        let waiter = {
+            let cache_file = cache_file.clone();
+            std::thread::spawn(move || lock_cache(&cache_file, true, Duration::from_secs(10)))
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        drop(first);
+        let second = waiter.join().unwrap();
+        assert!(second.is_some());
+        drop(second);
+
+        // readers do not block each other
+        let _read = lock_cache(&cache_file, false, Duration::ZERO).unwrap();
+        assert!(lock_cache(&cache_file, false, Duration::ZERO).is_some());
+    }
+
+    #[test]
+    fn cache_dir_is_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("cache");
+        fs::write(&file, "").unwrap();
+        assert_eq!(usable_cache_dir(file), None);
+
+        assert_eq!(
+            usable_cache_dir(dir.path().to_path_buf()),
+            Some(dir.path().to_path_buf())
+        );
+        // created on the first write
+        let missing = dir.path().join("missing");
+        assert_eq!(usable_cache_dir(missing.clone()), Some(missing));
+    }
+
+    #[test]
+    fn no_expansion_needed() {
+        assert_eq!(
+            expand_path("/var/cache/rustowl", lookup),
+            "/var/cache/rustowl"
+        );
+        assert_eq!(expand_path("relative/dir", lookup), "relative/dir");
+    }
 }
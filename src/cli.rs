@@ -15,6 +15,19 @@ pub struct Cli {
     #[arg(long)]
     pub stdio: bool,
 
+    /// Print the effective configuration with the source of each value.
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    /// Never download the toolchain; fail with install instructions if it is missing.
+    #[arg(long, global = true)]
+    pub no_toolchain_download: bool,
+
+    /// Worker threads of the analysis, at least 1 (default: half of the CPUs
+    /// within 2 to 8, or `RUSTOWL_ANALYSIS_THREADS`).
+    #[arg(long, global = true, value_name("n"))]
+    pub analysis_threads: Option<std::num::NonZeroUsize>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -35,6 +48,15 @@ pub enum Commands {
 
     /// Show ownership and lifetime visualization for a variable.
     Show(Show),
+
+    /// Validate a captured analysis output.
+    Validate(Validate),
+
+    /// Serve analysis results over a Unix socket or TCP, for IDE integrations.
+    Serve(Serve),
+
+    /// Manage the incremental cache.
+    Cache(CacheArgs),
 }
 
 #[derive(Args, Debug)]
@@ -43,25 +65,139 @@ pub struct Check {
     #[arg(value_name("path"), value_hint(ValueHint::AnyPath))]
     pub path: Option<std::path::PathBuf>,
 
-    /// Whether to check for all targets
-    /// (default: false).
+    /// Whether to check for all targets; `--all-targets=false` overrides
+    /// the configuration file (default: from `rustowl.toml`, else false).
     #[arg(
         long,
-        default_value_t = false,
-        value_name("all-targets"),
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name("bool"),
         help = "Run the check for all targets instead of current only"
     )]
-    pub all_targets: bool,
+    pub all_targets: Option<bool>,
 
-    /// Whether to check for all features
-    /// (default: false).
+    /// Whether to check for all features; `--all-features=false` overrides
+    /// the configuration file (default: from `rustowl.toml`, else false).
     #[arg(
         long,
-        default_value_t = false,
-        value_name("all-features"),
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name("bool"),
         help = "Run the check for all features instead of the current active ones only"
     )]
-    pub all_features: bool,
+    pub all_features: Option<bool>,
+
+    /// Whether to analyze `cfg(test)` code and test functions too
+    /// (default: false, or `RUSTOWL_ANALYZE_TESTS=1`).
+    #[arg(long, default_value_t = false)]
+    pub tests: bool,
+
+    /// Compile the given `.rs` file on its own with rustowlc, without cargo,
+    /// even if it belongs to a cargo project (default: false).
+    /// External crates are not available then.
+    #[arg(long, default_value_t = false)]
+    pub single_file: bool,
+
+    /// Packages of the workspace to check, like cargo's `--package`
+    /// (default: all members).
+    #[arg(short, long = "package", value_name("name"))]
+    pub packages: Vec<String>,
+
+    /// Features to enable, comma separated, like cargo's `--features`;
+    /// `<package>/<feature>` enables a feature of one package.
+    #[arg(short = 'F', long, value_name("features"), value_delimiter(','))]
+    pub features: Vec<String>,
+
+    /// Disable the default features, like cargo's `--no-default-features`
+    /// (default: false).
+    #[arg(long, default_value_t = false)]
+    pub no_default_features: bool,
+
+    /// Configuration to compile with, passed to rustc as `--cfg <spec>`,
+    /// e.g. `tokio_unstable` or `feature="serde"`; repeatable.
+    #[arg(long = "cfg", value_name("spec"))]
+    pub cfgs: Vec<String>,
+
+    /// The output format.
+    #[arg(long, value_enum, default_value_t = CheckFormat::Text)]
+    pub format: CheckFormat,
+
+    /// The file `--format sqlite` writes the database into; replaced if it exists.
+    #[arg(
+        long,
+        value_name("path"),
+        value_hint(ValueHint::FilePath),
+        required_if_eq("format", "sqlite")
+    )]
+    pub output: Option<std::path::PathBuf>,
+
+    /// Pretty-print the JSON of `--format json` and `--format decorations` for
+    /// humans to read; it then spans many lines, which line-based consumers
+    /// cannot read (default: false).
+    #[arg(long, default_value_t = false)]
+    pub pretty: bool,
+
+    /// Write the analysis result into `<dir>/<crate name>.json`, one file per crate.
+    #[arg(long, value_name("dir"), value_hint(ValueHint::DirPath))]
+    pub output_dir: Option<std::path::PathBuf>,
+
+    /// Further arguments of `cargo check`, like `--locked` or `--target <triple>`,
+    /// passed through untouched.
+    #[arg(last = true, value_name("cargo-args"))]
+    pub cargo_args: Vec<String>,
+}
+
+impl Check {
+    /// Whether the options are only honoured by a single analyzer, rather than by
+    /// the backend, which runs `cargo check` with its own arguments and prints no results
+    pub fn needs_analyzer(&self) -> bool {
+        self.format != CheckFormat::Text
+            || self.output_dir.is_some()
+            || self.single_file
+            || !self.features.is_empty()
+            || self.no_default_features
+            || !self.cfgs.is_empty()
+            || !self.cargo_args.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CheckFormat {
+    /// Only report whether the analysis succeeded.
+    Text,
+    /// Print workspace statistics as JSON.
+    Stats,
+    /// Print one ownership fact per line as `file:line:col-line:col kind local`.
+    Lines,
+    /// Write functions, declarations, ranges and moves into an SQLite database
+    /// given by `--output`; requires the `sqlite` feature.
+    Sqlite,
+    /// Print the analysis result as `rustowl serve` sends it, one JSON
+    /// document per line, followed by `{"type":"done"}`.
+    Json,
+    /// Print only the decorations of every local as one JSON document,
+    /// keyed by crate and then by file.
+    Decorations,
+}
+
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    pub command: CacheCommands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheCommands {
+    /// Rewrite cache files of earlier formats in the current one, removing
+    /// those that cannot be converted.
+    Migrate {
+        /// The cache directory (default: `RUSTOWL_CACHE_DIR`, or the one of
+        /// the cargo workspace in the current directory).
+        #[arg(value_name("path"), value_hint(ValueHint::DirPath))]
+        path: Option<std::path::PathBuf>,
+    },
 }
 
 #[derive(Args, Debug)]
@@ -94,10 +230,20 @@ pub enum ToolchainCommands {
 }
 
 #[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Completions {
+    #[command(subcommand)]
+    pub command: Option<CompletionsCommands>,
+
     /// The shell to generate completions for.
-    #[arg(value_enum)]
-    pub shell: crate::shells::Shell,
+    #[arg(value_enum, required = true)]
+    pub shell: Option<crate::shells::Shell>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CompletionsCommands {
+    /// List supported shells and where completions of the current shell are installed.
+    List,
 }
 
 #[derive(Args, Debug)]
@@ -116,11 +262,336 @@ pub struct Show {
     #[arg(value_name("variable"))]
     pub variable: String,
 
-    /// Check all targets.
-    #[arg(long, default_value_t = false)]
-    pub all_targets: bool,
+    /// Check all targets; `--all-targets=false` overrides the configuration file.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name("bool")
+    )]
+    pub all_targets: Option<bool>,
 
-    /// Check all features.
-    #[arg(long, default_value_t = false)]
-    pub all_features: bool,
+    /// Check all features; `--all-features=false` overrides the configuration file.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name("bool")
+    )]
+    pub all_features: Option<bool>,
+}
+
+#[derive(Args, Debug)]
+pub struct Validate {
+    /// The path of the JSON file written by rustowlc.
+    #[arg(value_name("path"), value_hint(ValueHint::FilePath))]
+    pub path: std::path::PathBuf,
+}
+
+#[derive(Args, Debug)]
+#[command(group(clap::ArgGroup::new("listen").required(true).args(["socket", "tcp"])))]
+pub struct Serve {
+    /// Listen on a Unix domain socket at this path.
+    #[arg(long, value_name("path"), value_hint(ValueHint::FilePath))]
+    pub socket: Option<std::path::PathBuf>,
+
+    /// Listen on a TCP address, e.g. `127.0.0.1:7878`.
+    #[arg(long, value_name("addr"))]
+    pub tcp: Option<String>,
+
+    /// Accept TCP connections on addresses other than loopback. Anyone who can
+    /// connect gets cargo, build scripts and proc macros run on this machine.
+    #[arg(long, requires("tcp"))]
+    pub allow_remote: bool,
+}
+
+/// File name cargo looks for to run `cargo rustowl`
+pub const CARGO_SUBCOMMAND: &str = "cargo-rustowl";
+
+/// Flags of `cargo check` taking the next argument as their value, which is thus
+/// not the path of `rustowl check`
+const CARGO_CHECK_VALUE_FLAGS: &[&str] = &[
+    "--artifact-dir",
+    "--bench",
+    "--bin",
+    "--color",
+    "--config",
+    "--example",
+    "--exclude",
+    "--jobs",
+    "--lockfile-path",
+    "--manifest-path",
+    "--message-format",
+    "--profile",
+    "--target",
+    "--target-dir",
+    "--test",
+    "-Z",
+    "-j",
+];
+
+/// The arguments `args` of the process as `rustowl` understands them.
+///
+/// Cargo runs `cargo-rustowl rustowl <args>` for `cargo rustowl <args>`, so the
+/// `rustowl` is removed. Of `cargo rustowl check`, the flags of `rustowl check`
+/// and the first bare argument, the path, are kept, and every other argument is
+/// moved behind `--`, to be passed through to `cargo check` untouched. A bare
+/// argument following a flag of `cargo check` that takes a value is that value.
+/// Other invocations are returned as is.
+pub fn cargo_subcommand_args(mut args: Vec<std::ffi::OsString>) -> Vec<std::ffi::OsString> {
+    let is_subcommand = args
+        .first()
+        .and_then(|v| std::path::Path::new(v).file_stem())
+        .is_some_and(|v| v == CARGO_SUBCOMMAND);
+    if !is_subcommand {
+        return args;
+    }
+    if args.get(1).is_some_and(|v| v == "rustowl") {
+        args.remove(1);
+    }
+    if args.get(1).is_none_or(|v| v != "check") {
+        return args;
+    }
+
+    let mut command = <Cli as clap::CommandFactory>::command();
+    command.build();
+    let check = command
+        .find_subcommand("check")
+        .expect("`check` is a subcommand");
+    // whether `arg` is a flag of `rustowl check` and takes the next argument
+    let flag = |arg: &str| {
+        let known = if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (long, false),
+            };
+            check
+                .get_arguments()
+                .find(|v| v.get_long() == Some(name))
+                .map(|v| (v, value))
+        } else if let Some(short) = arg.strip_prefix('-') {
+            let mut chars = short.chars();
+            chars.next().and_then(|c| {
+                check
+                    .get_arguments()
+                    .find(|v| v.get_short() == Some(c))
+                    .map(|v| (v, chars.next().is_some()))
+            })
+        } else {
+            None
+        };
+        // a value given with `=` only, like `--all-targets=false`, is never the next argument
+        known
+            .map(|(v, value)| !value && v.get_action().takes_values() && !v.is_require_equals_set())
+    };
+
+    let mut rest = args.split_off(2).into_iter();
+    let mut passthrough = Vec::new();
+    let mut path = None;
+    // whether the argument is the value of the `cargo check` flag before it
+    let mut cargo_value = false;
+    while let Some(arg) = rest.next() {
+        if arg == "--" {
+            passthrough.extend(rest.by_ref());
+            break;
+        }
+        let text = arg.to_str();
+        if let Some(takes_value) = text.and_then(flag) {
+            args.push(arg);
+            if takes_value {
+                args.extend(rest.next());
+            }
+            cargo_value = false;
+            continue;
+        }
+        let is_path = path.is_none() && !cargo_value && !arg.to_string_lossy().starts_with('-');
+        cargo_value = text.is_some_and(|v| CARGO_CHECK_VALUE_FLAGS.contains(&v));
+        if is_path {
+            path = Some(arg);
+        } else {
+            passthrough.push(arg);
+        }
+    }
+    args.extend(path);
+    if !passthrough.is_empty() {
+        args.push("--".into());
+        args.extend(passthrough);
+    }
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os_args(v: &[&str]) -> Vec<std::ffi::OsString> {
+        v.iter().map(|v| v.into()).collect()
+    }
+
+    #[test]
+    fn cargo_subcommand_detection() {
+        let args = os_args(&["rustowl", "check", "--locked"]);
+        assert_eq!(cargo_subcommand_args(args.clone()), args);
+
+        assert_eq!(
+            cargo_subcommand_args(os_args(&["/bin/cargo-rustowl", "rustowl", "clean"])),
+            os_args(&["/bin/cargo-rustowl", "clean"])
+        );
+        // run directly instead of by cargo
+        assert_eq!(
+            cargo_subcommand_args(os_args(&["cargo-rustowl.exe", "--stdio"])),
+            os_args(&["cargo-rustowl.exe", "--stdio"])
+        );
+        assert_eq!(
+            cargo_subcommand_args(os_args(&["cargo-rustowl", "rustowl"])),
+            os_args(&["cargo-rustowl"])
+        );
+    }
+
+    #[test]
+    fn cargo_check_passthrough() {
+        let args = cargo_subcommand_args(os_args(&[
+            "cargo-rustowl",
+            "rustowl",
+            "check",
+            "--all-features",
+            "-p",
+            "core",
+            "--locked",
+            "--target",
+            "x86_64-unknown-linux-gnu",
+            "--package=cli",
+            "-Zunstable-options",
+            "--format",
+            "stats",
+            "--no-toolchain-download",
+            "--",
+            "--all-features",
+        ]));
+        assert_eq!(
+            args,
+            os_args(&[
+                "cargo-rustowl",
+                "check",
+                "--all-features",
+                "-p",
+                "core",
+                "--package=cli",
+                "--format",
+                "stats",
+                "--no-toolchain-download",
+                "--",
+                "--locked",
+                "--target",
+                "x86_64-unknown-linux-gnu",
+                "-Zunstable-options",
+                "--all-features",
+            ])
+        );
+
+        let cli = <Cli as clap::Parser>::try_parse_from(args).unwrap();
+        let Some(Commands::Check(check)) = cli.command else {
+            panic!("expected `check`");
+        };
+        assert_eq!(check.all_features, Some(true));
+        assert_eq!(check.packages, ["core", "cli"]);
+        assert_eq!(check.format, CheckFormat::Stats);
+        assert_eq!(
+            check.cargo_args,
+            [
+                "--locked",
+                "--target",
+                "x86_64-unknown-linux-gnu",
+                "-Zunstable-options",
+                "--all-features"
+            ]
+        );
+        assert!(check.path.is_none());
+
+        assert_eq!(
+            cargo_subcommand_args(os_args(&["cargo-rustowl", "rustowl", "check", "-pcore"])),
+            os_args(&["cargo-rustowl", "check", "-pcore"])
+        );
+    }
+
+    #[test]
+    fn all_targets_flag_values() {
+        let check = |args: &[&str]| {
+            let cli = <Cli as clap::Parser>::try_parse_from(os_args(args)).unwrap();
+            let Some(Commands::Check(check)) = cli.command else {
+                panic!("expected `check`");
+            };
+            check
+        };
+        let given = check(&["rustowl", "check"]);
+        assert_eq!((given.all_targets, given.all_features), (None, None));
+        // a bare flag does not take the path as its value
+        let given = check(&["rustowl", "check", "--all-targets", "src"]);
+        assert_eq!(given.all_targets, Some(true));
+        assert_eq!(given.path.as_deref(), Some(std::path::Path::new("src")));
+        let given = check(&[
+            "rustowl",
+            "check",
+            "--all-targets=false",
+            "--all-features=true",
+        ]);
+        assert_eq!(
+            (given.all_targets, given.all_features),
+            (Some(false), Some(true))
+        );
+    }
+
+    #[test]
+    fn cargo_check_path() {
+        // the path is kept for `rustowl check`, values of cargo flags are not
+        let args = cargo_subcommand_args(os_args(&[
+            "cargo-rustowl",
+            "rustowl",
+            "check",
+            "--target-dir",
+            "target",
+            "--locked",
+            "crates/core",
+            "--bin",
+            "owl",
+        ]));
+        assert_eq!(
+            args,
+            os_args(&[
+                "cargo-rustowl",
+                "check",
+                "crates/core",
+                "--",
+                "--target-dir",
+                "target",
+                "--locked",
+                "--bin",
+                "owl",
+            ])
+        );
+        let cli = <Cli as clap::Parser>::try_parse_from(args).unwrap();
+        let Some(Commands::Check(check)) = cli.command else {
+            panic!("expected `check`");
+        };
+        assert_eq!(check.path.as_deref(), Some("crates/core".as_ref()));
+        // `--locked` is only honoured by a single analyzer
+        assert!(check.needs_analyzer());
+
+        let cli = <Cli as clap::Parser>::try_parse_from(["rustowl", "check", "."]).unwrap();
+        let Some(Commands::Check(check)) = cli.command else {
+            panic!("expected `check`");
+        };
+        assert!(!check.needs_analyzer());
+        for flag in [["-F", "owl"], ["--cfg", "owl"]] {
+            let cli =
+                <Cli as clap::Parser>::try_parse_from(["rustowl", "check"].iter().chain(&flag))
+                    .unwrap();
+            let Some(Commands::Check(check)) = cli.command else {
+                panic!("expected `check`");
+            };
+            assert!(check.needs_analyzer(), "{flag:?}");
+        }
+    }
 }
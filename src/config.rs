@@ -0,0 +1,306 @@
+//! Configuration file discovery
+//!
+//! RustOwl looks for a `rustowl.toml` in the analysis target directory and its
+//! ancestors, like cargo does for `Cargo.toml`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "rustowl.toml";
+
+/// Environment variables read by `rustowl` and `rustowlc` at run time
+pub const ENV_SETTINGS: &[&str] = &[
+    "RUSTOWL_ANALYSIS_THREADS",
+    "RUSTOWL_ANALYZE_TESTS",
+    "RUSTOWL_BORROWCK",
+    "RUSTOWL_CACHE_COMPRESSION",
+    "RUSTOWL_CACHE_COMPRESSION_LEVEL",
+    "RUSTOWL_CACHE_DIR",
+    "RUSTOWL_CACHE_MAX_MEMORY",
+    "RUSTOWL_CACHE_STATS_FILE",
+    "RUSTOWL_DECORATIONS",
+    "RUSTOWL_DETERMINISTIC_HASH",
+    "RUSTOWL_DOWNLOAD_RETRIES",
+    "RUSTOWL_EXCLUDE_MACROS",
+    "RUSTOWL_INCLUDE_STD",
+    "RUSTOWL_LOC_AS_STRING",
+    "RUSTOWL_MAX_NESTED_DEPTH",
+    "RUSTOWL_MAX_TYPE_LEN",
+    "RUSTOWL_PARALLELISM",
+    "RUSTOWL_RUSTFLAGS",
+    "RUSTOWL_SKIP_CHECKSUM",
+    "RUSTOWL_SKIP_EMPTY",
+    "RUSTOWL_SOURCE_ENCODING",
+    "RUSTOWL_TRACE_BORROWCK",
+    "RUSTOWL_UNVERSIONED_OUTPUT",
+];
+
+#[derive(Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Analyze all targets
+    pub all_targets: bool,
+    /// Analyze with all features enabled
+    pub all_features: bool,
+}
+
+impl Config {
+    /// Discover and load the configuration file from `start` upwards.
+    ///
+    /// If no configuration file is found, all options are default.
+    pub fn discover(start: impl AsRef<Path>) -> Self {
+        find_config_file(start.as_ref())
+            .and_then(|path| Self::load(&path))
+            .unwrap_or_default()
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        load_file::<Self>(path)
+    }
+
+    /// Merge command line flags, which take precedence over the file where
+    /// they are given, both to enable and to disable an option
+    pub fn with_flags(self, all_targets: Option<bool>, all_features: Option<bool>) -> Self {
+        Self {
+            all_targets: all_targets.unwrap_or(self.all_targets),
+            all_features: all_features.unwrap_or(self.all_features),
+        }
+    }
+}
+
+/// Options as written in the configuration file, `None` if absent
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+struct ConfigFile {
+    all_targets: Option<bool>,
+    all_features: Option<bool>,
+}
+
+fn load_file<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let s = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("failed to read {}: {e}", path.display());
+            return None;
+        }
+    };
+    match toml::from_str(&s) {
+        Ok(v) => {
+            log::debug!("config loaded: {}", path.display());
+            Some(v)
+        }
+        Err(e) => {
+            log::warn!("failed to parse {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Where the effective value of a setting comes from
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingSource {
+    Default,
+    File,
+    Env,
+    Flag,
+}
+
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: SettingSource,
+}
+
+impl<T> Setting<T> {
+    fn new(value: T, source: SettingSource) -> Self {
+        Self { value, source }
+    }
+}
+
+/// Effective settings with their sources, shown by `--print-config`
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResolvedConfig {
+    /// The configuration file in effect, if any
+    pub config_file: Option<PathBuf>,
+    pub all_targets: Setting<bool>,
+    pub all_features: Setting<bool>,
+    /// Incremental cache, disabled by `RUSTOWL_CACHE=false`
+    pub cache: Setting<bool>,
+    /// Raw value of each of [`ENV_SETTINGS`]; `None` where the built-in
+    /// default applies
+    pub env: BTreeMap<&'static str, Setting<Option<String>>>,
+}
+
+impl ResolvedConfig {
+    /// Resolve the settings the same way [`Config::discover`],
+    /// [`Config::with_flags`] and [`crate::cache::is_cache`] do.
+    ///
+    /// A given flag takes precedence over the file, which takes precedence
+    /// over the default.
+    pub fn resolve(
+        start: &Path,
+        all_targets: Option<bool>,
+        all_features: Option<bool>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Self {
+        let config_file = find_config_file(start);
+        let file = config_file
+            .as_deref()
+            .and_then(load_file::<ConfigFile>)
+            .unwrap_or_default();
+        let resolve_flag = |flag: Option<bool>, file: Option<bool>| match (flag, file) {
+            (Some(v), _) => Setting::new(v, SettingSource::Flag),
+            (None, Some(v)) => Setting::new(v, SettingSource::File),
+            (None, None) => Setting::new(false, SettingSource::Default),
+        };
+        let cache = match env("RUSTOWL_CACHE") {
+            Some(v) => Setting::new(!(v == "false" || v == "0"), SettingSource::Env),
+            None => Setting::new(true, SettingSource::Default),
+        };
+        let env = ENV_SETTINGS
+            .iter()
+            .map(|&name| {
+                let setting = match env(name) {
+                    Some(v) => Setting::new(Some(v), SettingSource::Env),
+                    None => Setting::new(None, SettingSource::Default),
+                };
+                (name, setting)
+            })
+            .collect();
+        Self {
+            config_file,
+            all_targets: resolve_flag(all_targets, file.all_targets),
+            all_features: resolve_flag(all_features, file.all_features),
+            cache,
+            env,
+        }
+    }
+}
+
+/// Find `rustowl.toml` in `start` or its nearest ancestor
+pub fn find_config_file(start: &Path) -> Option<PathBuf> {
+    let start = if start.is_file() {
+        start.parent()?
+    } else {
+        start
+    };
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_config_in_ancestor() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("a").join("b").join("c");
+        std::fs::create_dir_all(&nested).unwrap();
+        let config_path = root.path().join("a").join(CONFIG_FILE_NAME);
+        std::fs::write(&config_path, "all-targets = true\n").unwrap();
+
+        assert_eq!(find_config_file(&nested), Some(config_path.clone()));
+
+        let source = nested.join("main.rs");
+        std::fs::write(&source, "fn main() {}\n").unwrap();
+        assert_eq!(find_config_file(&source), Some(config_path));
+
+        let config = Config::discover(&nested);
+        assert!(config.all_targets);
+        assert!(!config.all_features);
+        assert!(config.clone().with_flags(None, Some(true)).all_features);
+        // a flag can disable what the file enables
+        assert!(!config.clone().with_flags(Some(false), None).all_targets);
+        assert!(config.with_flags(None, None).all_targets);
+    }
+
+    #[test]
+    fn nearest_config_wins() {
+        let root = tempfile::tempdir().unwrap();
+        let nested = root.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.path().join(CONFIG_FILE_NAME), "all-targets = true\n").unwrap();
+        std::fs::write(nested.join(CONFIG_FILE_NAME), "all-features = true\n").unwrap();
+
+        let config = Config::discover(&nested);
+        assert!(!config.all_targets);
+        assert!(config.all_features);
+    }
+
+    #[test]
+    fn resolved_config_sources() {
+        let root = tempfile::tempdir().unwrap();
+        let config_path = root.path().join(CONFIG_FILE_NAME);
+        std::fs::write(&config_path, "all-targets = true\nall-features = false\n").unwrap();
+        let no_env = |_: &str| None;
+
+        let resolved = ResolvedConfig::resolve(root.path(), None, None, no_env);
+        assert_eq!(resolved.config_file, Some(config_path));
+        assert_eq!(
+            resolved.all_targets,
+            Setting::new(true, SettingSource::File)
+        );
+        assert_eq!(
+            resolved.all_features,
+            Setting::new(false, SettingSource::File)
+        );
+        assert_eq!(resolved.cache, Setting::new(true, SettingSource::Default));
+        assert_eq!(resolved.env.len(), ENV_SETTINGS.len());
+        assert_eq!(
+            resolved.env["RUSTOWL_BORROWCK"],
+            Setting::new(None, SettingSource::Default)
+        );
+
+        // flags take precedence over the file, and env over the default
+        let env = |name: &str| match name {
+            "RUSTOWL_CACHE" => Some("false".to_owned()),
+            "RUSTOWL_BORROWCK" => Some("nll".to_owned()),
+            _ => None,
+        };
+        let resolved = ResolvedConfig::resolve(root.path(), Some(false), Some(true), env);
+        assert_eq!(
+            resolved.all_targets,
+            Setting::new(false, SettingSource::Flag)
+        );
+        assert_eq!(
+            resolved.all_features,
+            Setting::new(true, SettingSource::Flag)
+        );
+        assert_eq!(resolved.cache, Setting::new(false, SettingSource::Env));
+        assert_eq!(
+            resolved.env["RUSTOWL_BORROWCK"],
+            Setting::new(Some("nll".to_owned()), SettingSource::Env)
+        );
+        assert_eq!(
+            resolved.env["RUSTOWL_CACHE_DIR"],
+            Setting::new(None, SettingSource::Default)
+        );
+
+        // matches the configuration used for the analysis
+        let config = Config::discover(root.path()).with_flags(Some(false), Some(true));
+        let resolved = ResolvedConfig::resolve(root.path(), Some(false), Some(true), no_env);
+        assert_eq!(resolved.all_targets.value, config.all_targets);
+        assert_eq!(resolved.all_features.value, config.all_features);
+
+        let other = tempfile::tempdir().unwrap();
+        let resolved = ResolvedConfig::resolve(other.path(), None, None, no_env);
+        assert_eq!(resolved.config_file, None);
+        assert_eq!(
+            resolved.all_targets,
+            Setting::new(false, SettingSource::Default)
+        );
+    }
+
+    #[test]
+    fn defaults_without_config() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(find_config_file(root.path()), None);
+        assert_eq!(Config::discover(root.path()), Config::default());
+    }
+}
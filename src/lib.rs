@@ -2,15 +2,21 @@
 //!
 //! Libraries that used in RustOwl
 
+pub mod analysis;
 pub mod cache;
 pub mod cli;
+pub mod config;
 pub mod lsp;
 pub mod models;
+pub mod serve;
 pub mod shells;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 pub mod toolchain;
 pub mod utils;
 pub mod visualize;
 
+pub use cli::Cli;
 pub use lsp::backend::Backend;
 
 // Miri-specific memory safety tests
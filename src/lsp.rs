@@ -1,4 +1,7 @@
 pub mod analyze;
 pub mod backend;
 pub mod decoration;
+pub mod diagnostics;
+pub mod highlight;
+pub mod hover;
 pub mod progress;
@@ -1,4 +1,5 @@
 use crate::{cache::*, models::*, toolchain};
+use std::collections::{BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
@@ -8,6 +9,9 @@ use tokio::{
     sync::{Notify, mpsc},
 };
 
+/// Edition a single file is compiled with, as it has no manifest to tell
+const SINGLE_FILE_EDITION: &str = "2024";
+
 #[derive(serde::Deserialize, Clone, Debug)]
 pub struct CargoCheckMessageTarget {
     name: String,
@@ -29,16 +33,48 @@ pub enum AnalyzerEvent {
     Analyzed(Workspace),
 }
 
+/// Features to compile the packages with, like cargo's flags of the same names
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeatureSelection {
+    /// `--features`; `<package>/<feature>` enables a feature of one package
+    pub features: Vec<String>,
+    /// `--no-default-features`
+    pub no_default_features: bool,
+}
+
 #[derive(Clone)]
 pub struct Analyzer {
     path: PathBuf,
     metadata: Option<cargo_metadata::Metadata>,
+    /// Workspace members to analyze; all of them if empty
+    packages: Vec<String>,
+    /// Collect stderr of the compiler instead of passing it through
+    capture_stderr: bool,
+    /// Compile `cfg(test)` code too, so that tests are analyzed
+    tests: bool,
+    /// Features to enable or disable besides `--all-features`
+    features: FeatureSelection,
+    /// Further arguments of `cargo check`, passed through untouched
+    cargo_args: Vec<String>,
+    /// Specs passed to rustc as `--cfg <spec>`
+    cfgs: Vec<String>,
+    /// Workspace member whose results are emitted before those of the others
+    priority: Option<String>,
 }
 
 impl Analyzer {
     pub async fn new(path: impl AsRef<Path>) -> Result<Self, ()> {
         let path = path.as_ref().to_path_buf();
 
+        // a single file is compiled by `rustowlc` directly, without cargo
+        let single_file = is_rust_file(&path);
+        if let Err(e) = toolchain::verify_cargo().await {
+            log::error!("{e}");
+            if !single_file {
+                return Err(());
+            }
+        }
+
         let mut cargo_cmd = toolchain::setup_cargo_command().await;
 
         cargo_cmd
@@ -65,20 +101,227 @@ impl Analyzer {
         };
 
         if let Some(metadata) = metadata {
-            Ok(Self {
-                path: metadata.workspace_root.as_std_path().to_path_buf(),
-                metadata: Some(metadata),
-            })
-        } else if path.is_file() && path.extension().map(|v| v == "rs").unwrap_or(false) {
-            Ok(Self {
-                path,
-                metadata: None,
-            })
+            Ok(Self::with_metadata(metadata))
+        } else if single_file {
+            Self::single_file(path).map_err(|_| ())
         } else {
             log::warn!("Invalid analysis target: {}", path.display());
             Err(())
         }
     }
+    /// Analyze the whole workspace described by `metadata`
+    pub(crate) fn with_metadata(metadata: cargo_metadata::Metadata) -> Self {
+        Self {
+            path: metadata.workspace_root.as_std_path().to_path_buf(),
+            metadata: Some(metadata),
+            packages: Vec::new(),
+            capture_stderr: false,
+            tests: tests_from_env(),
+            features: FeatureSelection::default(),
+            cargo_args: Vec::new(),
+            cfgs: Vec::new(),
+            priority: None,
+        }
+    }
+    /// Analyze the `.rs` file at `path` on its own, compiling it with `rustowlc`
+    /// directly even if it belongs to a cargo project
+    pub fn single_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        if !is_rust_file(path) {
+            return Err(format!("{} is not a Rust source file", path.display()));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            metadata: None,
+            packages: Vec::new(),
+            capture_stderr: false,
+            tests: tests_from_env(),
+            features: FeatureSelection::default(),
+            cargo_args: Vec::new(),
+            cfgs: Vec::new(),
+            priority: None,
+        })
+    }
+    /// Analyze only the given workspace members, like cargo's `--package`
+    pub fn select_packages(mut self, packages: Vec<String>) -> Result<Self, String> {
+        if packages.is_empty() {
+            return Ok(self);
+        }
+        let Some(metadata) = &self.metadata else {
+            return Err(format!(
+                "`--package` requires a cargo workspace, but {} is not",
+                self.path.display()
+            ));
+        };
+        let members: Vec<_> = metadata
+            .workspace_packages()
+            .iter()
+            .map(|v| v.name.to_string())
+            .collect();
+        validate_packages(&members, &packages)?;
+        self.packages = packages;
+        Ok(self)
+    }
+    /// Compile with the given features, so that the output reflects the code
+    /// gated by them. Call it after [`Self::select_packages`]: a feature must
+    /// belong to a selected package.
+    pub fn select_features(mut self, features: FeatureSelection) -> Result<Self, String> {
+        if features == FeatureSelection::default() {
+            return Ok(self);
+        }
+        let Some(metadata) = &self.metadata else {
+            return Err(format!(
+                "`--features` requires a cargo workspace, but {} is not",
+                self.path.display()
+            ));
+        };
+        let members: Vec<_> = metadata
+            .workspace_packages()
+            .iter()
+            .map(|v| v.name.to_string())
+            .collect();
+        let selected: Vec<_> = metadata
+            .workspace_packages()
+            .into_iter()
+            .filter(|v| self.packages.is_empty() || self.packages.contains(&v.name))
+            .map(|v| {
+                // optional dependencies are implicit features
+                let optional = v
+                    .dependencies
+                    .iter()
+                    .filter(|dep| dep.optional)
+                    .map(|dep| dep.rename.clone().unwrap_or_else(|| dep.name.clone()));
+                let features = v.features.keys().cloned().chain(optional).collect();
+                (v.name.to_string(), features)
+            })
+            .collect();
+        validate_features(&members, &selected, &features.features)?;
+        self.features = features;
+        Ok(self)
+    }
+    /// Analyze `cfg(test)` code and test functions too, which is also enabled
+    /// by `RUSTOWL_ANALYZE_TESTS=1`
+    pub fn analyze_tests(mut self, tests: bool) -> Self {
+        self.tests |= tests;
+        self
+    }
+    /// Pass `args` on to `cargo check`; they are ignored for a single file
+    pub fn cargo_args(mut self, args: Vec<String>) -> Self {
+        self.cargo_args = args;
+        self
+    }
+    /// Compile with `--cfg <spec>` for each of `cfgs`, in every crate of the
+    /// workspace or the single file
+    pub fn cfgs(mut self, cfgs: Vec<String>) -> Self {
+        self.cfgs = cfgs;
+        self
+    }
+    /// Collect the compiler diagnostics, returned by [`AnalyzeEventIter::finish`]
+    pub fn capture_stderr(mut self) -> Self {
+        self.capture_stderr = true;
+        self
+    }
+    /// Whether `package` is a member of the analyzed workspace
+    pub fn has_package(&self, package: &str) -> bool {
+        self.metadata.as_ref().is_some_and(|metadata| {
+            metadata
+                .workspace_packages()
+                .iter()
+                .any(|v| v.name.as_str() == package)
+        })
+    }
+    /// Workspace members depending directly on each workspace member
+    pub fn workspace_dependents(&self) -> HashMap<String, BTreeSet<String>> {
+        let Some(metadata) = &self.metadata else {
+            return HashMap::new();
+        };
+        let packages = metadata.workspace_packages();
+        let members: BTreeSet<_> = packages.iter().map(|v| v.name.as_str()).collect();
+        let mut dependents: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for package in &packages {
+            for dependency in &package.dependencies {
+                if members.contains(dependency.name.as_str()) {
+                    dependents
+                        .entry(dependency.name.clone())
+                        .or_default()
+                        .insert(package.name.to_string());
+                }
+            }
+        }
+        dependents
+    }
+    /// The workspace member that has a target compiled as `crate_name`
+    pub fn crate_package(&self, crate_name: &str) -> Option<String> {
+        self.metadata
+            .as_ref()?
+            .workspace_packages()
+            .iter()
+            .find_map(|package| {
+                package
+                    .targets
+                    .iter()
+                    .any(|target| target.name.replace('-', "_") == crate_name)
+                    .then(|| package.name.to_string())
+            })
+    }
+    /// This analysis with the results of the package owning `file` emitted
+    /// before those of the other selected packages, so that the file is
+    /// highlighted early; `None` if `file` is not part of the analysis.
+    ///
+    /// The packages are still checked by a single cargo invocation, so that
+    /// features are unified and dependencies are built once.
+    pub fn prioritize(&self, file: &Path) -> Option<Self> {
+        let Some(metadata) = &self.metadata else {
+            return (self.path == file).then(|| self.clone());
+        };
+        let packages: Vec<_> = metadata
+            .workspace_packages()
+            .into_iter()
+            .filter(|v| self.packages.is_empty() || self.packages.contains(&v.name))
+            .filter_map(|v| {
+                let dir = v.manifest_path.parent()?.as_std_path().to_path_buf();
+                Some((v.name.to_string(), dir))
+            })
+            .collect();
+        let mut prioritized = self.clone();
+        prioritized.priority = Some(owning_package(&packages, file)?);
+        Some(prioritized)
+    }
+    /// Workspace member whose results are emitted first; see [`Self::prioritize`]
+    pub fn priority_package(&self) -> Option<&str> {
+        self.priority.as_deref()
+    }
+    /// Crate names of the targets of the prioritized package
+    fn priority_crates(&self) -> BTreeSet<String> {
+        let (Some(metadata), Some(priority)) = (&self.metadata, &self.priority) else {
+            return BTreeSet::new();
+        };
+        metadata
+            .workspace_packages()
+            .iter()
+            .filter(|package| package.name.as_str() == priority)
+            .flat_map(|package| &package.targets)
+            .map(|target| target.name.replace('-', "_"))
+            .collect()
+    }
+    /// Number of crates the analysis is expected to emit, from the targets of the
+    /// selected workspace members, to pre-size the merged [`Workspace`]
+    pub fn expected_crates(&self) -> usize {
+        let Some(metadata) = &self.metadata else {
+            return 1;
+        };
+        expected_crate_count(
+            metadata
+                .workspace_packages()
+                .iter()
+                .map(|package| (package.name.as_str(), package.targets.len())),
+            &self.packages,
+        )
+    }
+    /// Whether a single file is compiled without cargo
+    pub fn is_single_file(&self) -> bool {
+        self.metadata.is_none()
+    }
     pub fn target_path(&self) -> &Path {
         &self.path
     }
@@ -91,6 +334,7 @@ impl Analyzer {
     }
 
     pub async fn analyze(&self, all_targets: bool, all_features: bool) -> AnalyzeEventIter {
+        toolchain::verify_rustowlc().await;
         if let Some(metadata) = &self.metadata {
             self.analyze_package(metadata, all_targets, all_features)
                 .await
@@ -105,11 +349,15 @@ impl Analyzer {
         all_targets: bool,
         all_features: bool,
     ) -> AnalyzeEventIter {
-        let package_names: Vec<_> = metadata
-            .workspace_packages()
-            .iter()
-            .map(|v| v.name.to_string())
-            .collect();
+        let package_names: Vec<_> = if self.packages.is_empty() {
+            metadata
+                .workspace_packages()
+                .iter()
+                .map(|v| v.name.to_string())
+                .collect()
+        } else {
+            self.packages.clone()
+        };
         let target_dir = metadata.target_directory.as_std_path().join("owl");
         log::debug!("clear cargo cache");
         for package_name in &package_names {
@@ -123,19 +371,17 @@ impl Analyzer {
             command.spawn().unwrap().wait().await.ok();
         }
 
-        let mut command = toolchain::setup_cargo_command().await;
-
-        let mut args = vec!["check", "--workspace"];
-        if all_targets {
-            args.push("--all-targets");
-        }
-        if all_features {
-            args.push("--all-features");
-        }
-        args.extend_from_slice(&["--keep-going", "--message-format=json"]);
+        let mut command = toolchain::setup_cargo_command_with_cfgs(&self.cfgs).await;
 
         command
-            .args(args)
+            .args(cargo_check_args(
+                all_targets,
+                all_features,
+                &self.packages,
+                self.tests,
+                &self.features,
+                &self.cargo_args,
+            ))
             .env("CARGO_TARGET_DIR", &target_dir)
             .env_remove("RUSTC_WRAPPER")
             .current_dir(&self.path)
@@ -146,25 +392,23 @@ impl Analyzer {
             set_cache_path(&mut command, target_dir);
         }
 
-        if log::max_level()
-            .to_level()
-            .map(|v| v < log::Level::Info)
-            .unwrap_or(true)
-        {
-            command.stderr(std::process::Stdio::null());
-        }
+        self.set_stderr(&mut command);
 
         let package_count = metadata.packages.len();
+        let mut order = PriorityOrder::new(self.priority_crates());
 
         log::debug!("start analyzing package {package_names:?}");
         let mut child = command.spawn().unwrap();
         let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let stderr = child.stderr.take().map(collect_stderr);
 
         let (sender, receiver) = mpsc::channel(1024);
         let notify = Arc::new(Notify::new());
         let notify_c = notify.clone();
         let _handle = tokio::spawn(async move {
             // prevent command from dropped
+            // type names are shared by the crates of this run
+            let mut type_names = TypeInterner::default();
             while let Ok(Some(line)) = stdout.next_line().await {
                 if let Ok(CargoCheckMessage::CompilerArtifact { target }) =
                     serde_json::from_str(&line)
@@ -178,14 +422,19 @@ impl Analyzer {
                     };
                     let _ = sender.send(event).await;
                 }
-                if let Ok(ws) = serde_json::from_str::<Workspace>(&line) {
-                    let event = AnalyzerEvent::Analyzed(ws);
-                    let _ = sender.send(event).await;
+                if let Ok(mut ws) = Workspace::from_json_reader(line.as_bytes()) {
+                    ws.intern_types(&mut type_names);
+                    for ws in order.push(ws) {
+                        let _ = sender.send(AnalyzerEvent::Analyzed(ws)).await;
+                    }
                 }
                 if !line.is_empty() {
                     log::warn!("unknown format stdout from rustowlc");
                 }
             }
+            for ws in order.finish() {
+                let _ = sender.send(AnalyzerEvent::Analyzed(ws)).await;
+            }
             log::debug!("stdout closed");
             notify_c.notify_one();
         });
@@ -194,6 +443,7 @@ impl Analyzer {
             receiver,
             notify,
             child,
+            stderr,
         }
     }
 
@@ -205,7 +455,18 @@ impl Analyzer {
         command
             .arg(&rustowlc_path) // rustowlc triggers when first arg is the path of itself
             .arg(format!("--sysroot={}", sysroot.display()))
+            .arg(format!("--edition={SINGLE_FILE_EDITION}"))
+            .arg(format!("--crate-name={}", single_file_crate_name(path)))
             .arg("--crate-type=lib");
+        if self.tests {
+            command.arg("--test");
+        }
+        if let Ok(flags) = std::env::var("RUSTOWL_RUSTFLAGS") {
+            command.args(toolchain::rustowl_rustflags(&flags));
+        }
+        for cfg in &self.cfgs {
+            command.arg("--cfg").arg(cfg);
+        }
         #[cfg(unix)]
         command.arg("-o/dev/null");
         #[cfg(windows)]
@@ -217,25 +478,23 @@ impl Analyzer {
 
         toolchain::set_rustc_env(&mut command, &sysroot);
 
-        if log::max_level()
-            .to_level()
-            .map(|v| v < log::Level::Info)
-            .unwrap_or(true)
-        {
-            command.stderr(std::process::Stdio::null());
-        }
+        self.set_stderr(&mut command);
 
         log::debug!("start analyzing {}", path.display());
         let mut child = command.spawn().unwrap();
         let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let stderr = child.stderr.take().map(collect_stderr);
 
         let (sender, receiver) = mpsc::channel(1024);
         let notify = Arc::new(Notify::new());
         let notify_c = notify.clone();
         let _handle = tokio::spawn(async move {
             // prevent command from dropped
+            // type names are shared by the crates of this run
+            let mut type_names = TypeInterner::default();
             while let Ok(Some(line)) = stdout.next_line().await {
-                if let Ok(ws) = serde_json::from_str::<Workspace>(&line) {
+                if let Ok(mut ws) = Workspace::from_json_reader(line.as_bytes()) {
+                    ws.intern_types(&mut type_names);
                     let event = AnalyzerEvent::Analyzed(ws);
                     let _ = sender.send(event).await;
                 }
@@ -248,15 +507,222 @@ impl Analyzer {
             receiver,
             notify,
             child,
+            stderr,
         }
     }
+
+    fn set_stderr(&self, command: &mut process::Command) {
+        if self.capture_stderr {
+            command.stderr(Stdio::piped());
+        } else if log::max_level()
+            .to_level()
+            .map(|v| v < log::Level::Info)
+            .unwrap_or(true)
+        {
+            command.stderr(Stdio::null());
+        }
+    }
+}
+
+/// Read all of `stderr` in the background, so that the child never blocks on it
+fn collect_stderr(stderr: process::ChildStderr) -> tokio::task::JoinHandle<String> {
+    tokio::spawn(async move {
+        let mut collected = String::new();
+        let mut lines = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            collected.push_str(&line);
+            collected.push('\n');
+        }
+        collected
+    })
+}
+
+fn is_rust_file(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|v| v == "rs")
+}
+
+/// Crate name of a single file, from its stem with the characters not allowed
+/// in identifiers replaced by `_`, like cargo does for `-`
+fn single_file_crate_name(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+fn tests_from_env() -> bool {
+    std::env::var("RUSTOWL_ANALYZE_TESTS").is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Arguments of `cargo check` for the whole workspace or only `packages`,
+/// followed by the `extra` arguments of the user
+fn cargo_check_args(
+    all_targets: bool,
+    all_features: bool,
+    packages: &[String],
+    tests: bool,
+    features: &FeatureSelection,
+    extra: &[String],
+) -> Vec<String> {
+    let mut args = vec!["check".to_owned()];
+    if packages.is_empty() {
+        args.push("--workspace".to_owned());
+    }
+    for package in packages {
+        args.extend(["--package".to_owned(), package.clone()]);
+    }
+    if all_targets {
+        args.push("--all-targets".to_owned());
+    }
+    if all_features {
+        args.push("--all-features".to_owned());
+    }
+    if !features.features.is_empty() {
+        args.extend(["--features".to_owned(), features.features.join(",")]);
+    }
+    if features.no_default_features {
+        args.push("--no-default-features".to_owned());
+    }
+    // compiles library targets with `cfg(test)` too
+    if tests {
+        args.push("--tests".to_owned());
+    }
+    args.extend([
+        "--keep-going".to_owned(),
+        "--message-format=json".to_owned(),
+    ]);
+    args.extend_from_slice(extra);
+    args
+}
+
+/// Ensure that every requested package is a member of the workspace
+fn validate_packages(members: &[String], requested: &[String]) -> Result<(), String> {
+    let unknown: Vec<_> = requested
+        .iter()
+        .filter(|v| !members.contains(v))
+        .map(|v| format!("`{v}`"))
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "package(s) {} not found in workspace; members are: {}",
+            unknown.join(", "),
+            members.join(", ")
+        ))
+    }
+}
+
+/// Ensure that every plain feature is defined by one of the `selected` packages,
+/// given with their features, and that `<package>/<feature>` of a workspace
+/// member names a selected package defining it; other `<dependency>/<feature>`
+/// are left to cargo
+fn validate_features(
+    members: &[String],
+    selected: &[(String, Vec<String>)],
+    features: &[String],
+) -> Result<(), String> {
+    let defines =
+        |package: &(String, Vec<String>), feature: &str| package.1.iter().any(|v| v == feature);
+    for feature in features {
+        match feature.split_once('/') {
+            Some((package, name)) if members.iter().any(|v| v == package) => {
+                let Some(package) = selected.iter().find(|v| v.0 == package) else {
+                    return Err(format!(
+                        "feature `{feature}` is of package `{package}`, which is not selected by `--package`"
+                    ));
+                };
+                if !defines(package, name) {
+                    return Err(format!("package `{}` has no feature `{name}`", package.0));
+                }
+            }
+            Some(_) => {}
+            None => {
+                if !selected.iter().any(|v| defines(v, feature)) {
+                    let names: Vec<_> = selected.iter().map(|v| format!("`{}`", v.0)).collect();
+                    return Err(format!(
+                        "none of the packages {} has feature `{feature}`",
+                        names.join(", ")
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the package whose directory, given with the names, most closely
+/// contains `file`
+fn owning_package(packages: &[(String, PathBuf)], file: &Path) -> Option<String> {
+    packages
+        .iter()
+        .filter(|(_, dir)| file.starts_with(dir))
+        .max_by_key(|(_, dir)| dir.components().count())
+        .map(|(name, _)| name.clone())
+}
+
+/// Sum of the target counts of `members` that are `selected`, or of all of them
+/// if none is selected; at least 1
+fn expected_crate_count<'a>(
+    members: impl IntoIterator<Item = (&'a str, usize)>,
+    selected: &[String],
+) -> usize {
+    members
+        .into_iter()
+        .filter(|(name, _)| selected.is_empty() || selected.iter().any(|v| v == name))
+        .map(|(_, targets)| targets)
+        .sum::<usize>()
+        .max(1)
+}
+
+/// Order of the results of a run: those of the other crates are held back
+/// until a crate of the prioritized package is emitted
+struct PriorityOrder {
+    priority: BTreeSet<String>,
+    /// Results held back; `None` once they are released
+    deferred: Option<Vec<Workspace>>,
+}
+impl PriorityOrder {
+    /// Order by the crate names `priority`; nothing is held back if it is empty
+    fn new(priority: BTreeSet<String>) -> Self {
+        let deferred = (!priority.is_empty()).then(Vec::new);
+        Self { priority, deferred }
+    }
+
+    /// Results to emit now, in order, on receiving `ws`
+    fn push(&mut self, ws: Workspace) -> Vec<Workspace> {
+        let Some(deferred) = &mut self.deferred else {
+            return vec![ws];
+        };
+        let prioritized =
+            ws.0.keys()
+                .any(|key| self.priority.contains(crate_display_name(key)));
+        if !prioritized {
+            deferred.push(ws);
+            return Vec::new();
+        }
+        let mut released = vec![ws];
+        released.extend(self.deferred.take().unwrap_or_default());
+        released
+    }
+
+    /// Results still held back at the end of the run, e.g. as the prioritized
+    /// package failed to compile
+    fn finish(&mut self) -> Vec<Workspace> {
+        self.deferred.take().unwrap_or_default()
+    }
 }
 
 pub struct AnalyzeEventIter {
     receiver: mpsc::Receiver<AnalyzerEvent>,
     notify: Arc<Notify>,
-    #[allow(unused)]
     child: process::Child,
+    stderr: Option<tokio::task::JoinHandle<String>>,
 }
 impl AnalyzeEventIter {
     pub async fn next_event(&mut self) -> Option<AnalyzerEvent> {
@@ -266,4 +732,216 @@ impl AnalyzeEventIter {
             _ = self.notify.notified() => None,
         }
     }
+
+    /// Wait for the compiler to exit, returning its success and the
+    /// diagnostics collected with [`Analyzer::capture_stderr`]
+    pub async fn finish(mut self) -> (bool, String) {
+        let success = self.child.wait().await.is_ok_and(|v| v.success());
+        let stderr = match self.stderr.take() {
+            Some(handle) => handle.await.unwrap_or_default(),
+            None => String::new(),
+        };
+        (success, stderr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn cargo_check_args_for_packages() {
+        assert_eq!(
+            cargo_check_args(false, false, &[], false, &FeatureSelection::default(), &[]),
+            strings(&[
+                "check",
+                "--workspace",
+                "--keep-going",
+                "--message-format=json"
+            ])
+        );
+        assert_eq!(
+            cargo_check_args(
+                true,
+                false,
+                &strings(&["core", "cli"]),
+                false,
+                &FeatureSelection::default(),
+                &[]
+            ),
+            strings(&[
+                "check",
+                "--package",
+                "core",
+                "--package",
+                "cli",
+                "--all-targets",
+                "--keep-going",
+                "--message-format=json"
+            ])
+        );
+        assert_eq!(
+            cargo_check_args(false, false, &[], true, &FeatureSelection::default(), &[]),
+            strings(&[
+                "check",
+                "--workspace",
+                "--tests",
+                "--keep-going",
+                "--message-format=json"
+            ])
+        );
+        assert_eq!(
+            cargo_check_args(
+                false,
+                true,
+                &[],
+                false,
+                &FeatureSelection::default(),
+                &strings(&["--locked", "-Zunstable"])
+            ),
+            strings(&[
+                "check",
+                "--workspace",
+                "--all-features",
+                "--keep-going",
+                "--message-format=json",
+                "--locked",
+                "-Zunstable"
+            ])
+        );
+    }
+
+    #[test]
+    fn package_validation() {
+        let members = strings(&["core", "cli"]);
+        assert!(validate_packages(&members, &[]).is_ok());
+        assert!(validate_packages(&members, &strings(&["cli"])).is_ok());
+        let error = validate_packages(&members, &strings(&["cli", "serde"])).unwrap_err();
+        assert!(error.contains("`serde`"));
+        assert!(!error.contains("`cli`"));
+    }
+
+    #[test]
+    fn feature_args_and_validation() {
+        let features = FeatureSelection {
+            features: strings(&["serde", "cli/color"]),
+            no_default_features: true,
+        };
+        assert_eq!(
+            cargo_check_args(false, false, &strings(&["cli"]), false, &features, &[]),
+            strings(&[
+                "check",
+                "--package",
+                "cli",
+                "--features",
+                "serde,cli/color",
+                "--no-default-features",
+                "--keep-going",
+                "--message-format=json"
+            ])
+        );
+
+        let members = strings(&["core", "cli"]);
+        let core = ("core".to_owned(), strings(&["serde"]));
+        let cli = ("cli".to_owned(), strings(&["color", "serde"]));
+        let all = [core.clone(), cli.clone()];
+        assert!(validate_features(&members, &all, &strings(&["serde", "cli/color"])).is_ok());
+        // a plain feature needs only one of the selected packages to define it
+        assert!(validate_features(&members, &all, &strings(&["color"])).is_ok());
+        // features of dependencies are checked by cargo
+        assert!(validate_features(&members, &all, &strings(&["tokio/full"])).is_ok());
+
+        // `-p core` leaves `cli` and its features out
+        let only_core = [core];
+        let error = validate_features(&members, &only_core, &strings(&["cli/color"])).unwrap_err();
+        assert!(error.contains("`cli`") && error.contains("--package"));
+        let error = validate_features(&members, &only_core, &strings(&["color"])).unwrap_err();
+        assert!(error.contains("`color`"));
+        let error = validate_features(&members, &[cli], &strings(&["cli/json"])).unwrap_err();
+        assert!(error.contains("`json`"));
+    }
+
+    #[test]
+    fn package_owning_file() {
+        let packages = [
+            ("app".to_owned(), PathBuf::from("/ws")),
+            ("core".to_owned(), PathBuf::from("/ws/crates/core")),
+            (
+                "core-macros".to_owned(),
+                PathBuf::from("/ws/crates/core/macros"),
+            ),
+        ];
+        let owner = |file: &str| owning_package(&packages, Path::new(file));
+        assert_eq!(owner("/ws/src/main.rs").as_deref(), Some("app"));
+        assert_eq!(owner("/ws/crates/core/src/lib.rs").as_deref(), Some("core"));
+        assert_eq!(
+            owner("/ws/crates/core/macros/src/lib.rs").as_deref(),
+            Some("core-macros")
+        );
+        // not a prefix by path components
+        assert_eq!(
+            owner("/ws/crates/core-extra/lib.rs").as_deref(),
+            Some("app")
+        );
+        assert_eq!(owner("/elsewhere/lib.rs"), None);
+    }
+
+    #[test]
+    fn crate_name_of_single_file() {
+        let name = |path: &str| single_file_crate_name(Path::new(path));
+        assert_eq!(name("snippets/main.rs"), "main");
+        assert_eq!(name("my-snippet.rs"), "my_snippet");
+        assert_eq!(name("/tmp/2024 demo.rs"), "_2024_demo");
+        assert_eq!(name("ünïcode.rs"), "_n_code");
+        assert_eq!(name(".rs"), "_rs");
+    }
+
+    #[test]
+    fn crate_count_of_members() {
+        let members = [("core", 1), ("cli", 3), ("macros", 2)];
+        assert_eq!(expected_crate_count(members, &[]), 6);
+        assert_eq!(expected_crate_count(members, &strings(&["cli"])), 3);
+        assert_eq!(
+            expected_crate_count(members, &strings(&["core", "macros"])),
+            3
+        );
+        // never zero, so that the workspace has room for a result
+        assert_eq!(expected_crate_count([], &[]), 1);
+        assert_eq!(expected_crate_count([("core", 0)], &[]), 1);
+    }
+
+    #[test]
+    fn prioritized_crates_are_emitted_first() {
+        let ws = |name: &str| {
+            let mut ws = Workspace::with_capacity(1);
+            ws.0.insert(name.to_owned(), Crate(OutputMap::default()));
+            ws
+        };
+        let names = |emitted: Vec<Workspace>| {
+            emitted
+                .into_iter()
+                .flat_map(|ws| ws.0.into_keys())
+                .collect::<Vec<_>>()
+        };
+
+        let mut order = PriorityOrder::new(BTreeSet::from(["app".to_owned()]));
+        assert!(order.push(ws("core")).is_empty());
+        assert!(order.push(ws("cli")).is_empty());
+        assert_eq!(names(order.push(ws("app"))), ["app", "core", "cli"]);
+        assert_eq!(names(order.push(ws("server"))), ["server"]);
+        assert!(order.finish().is_empty());
+
+        // released at the end if the prioritized crate is never emitted
+        let mut order = PriorityOrder::new(BTreeSet::from(["app".to_owned()]));
+        assert!(order.push(ws("core")).is_empty());
+        assert_eq!(names(order.finish()), ["core"]);
+
+        // nothing is held back without priority
+        let mut order = PriorityOrder::new(BTreeSet::new());
+        assert_eq!(names(order.push(ws("core"))), ["core"]);
+    }
 }
@@ -1,13 +1,15 @@
 use super::analyze::*;
 use crate::{lsp::*, models::*, utils};
-use std::collections::BTreeMap;
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::{sync::RwLock, task::JoinSet};
 use tokio_util::sync::CancellationToken;
 use tower_lsp::jsonrpc;
 use tower_lsp::lsp_types;
-use tower_lsp::{Client, LanguageServer, LspService};
+use tower_lsp::{Client, ClientSocket, LanguageServer, LspService};
 
 #[derive(serde::Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -15,16 +17,204 @@ pub struct AnalyzeRequest {}
 #[derive(serde::Serialize, Clone, Debug)]
 pub struct AnalyzeResponse {}
 
+/// A file edited since it was analyzed
+#[derive(Clone, Debug)]
+struct EditedFile {
+    /// Hash of the analyzed content; `None` if the file did not exist
+    hash: Option<u64>,
+    /// The analysis result, restored if the file is saved unchanged
+    analyzed: Option<File>,
+}
+
+/// What to analyze again after edits
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Reanalysis {
+    /// Every edited file is back to its analyzed content
+    Unchanged,
+    /// Only the packages owning the edited files
+    Packages(BTreeSet<String>),
+    /// Everything, when an edited file is not known to belong to any package
+    Full,
+}
+
+/// Decide what to analyze again from the edited files, their current content
+/// hashes, the packages each file belongs to and the workspace members depending
+/// on each package, whose results may change with the packages they depend on
+fn plan_reanalysis(
+    edited: &HashMap<String, EditedFile>,
+    current_hash: impl Fn(&str) -> Option<u64>,
+    file_packages: &HashMap<String, BTreeSet<String>>,
+    package_dependents: &HashMap<String, BTreeSet<String>>,
+) -> Reanalysis {
+    if edited.is_empty() {
+        return Reanalysis::Full;
+    }
+    let mut packages = BTreeSet::new();
+    for (path, file) in edited {
+        if file.hash.is_some() && current_hash(path) == file.hash {
+            continue;
+        }
+        // a file belonging to several targets needs all of them analyzed
+        match file_packages.get(path) {
+            Some(owners) if !owners.is_empty() => packages.extend(owners.iter().cloned()),
+            // added files are not known to any package yet
+            _ => return Reanalysis::Full,
+        }
+    }
+    if packages.is_empty() {
+        return Reanalysis::Unchanged;
+    }
+    let mut pending: Vec<_> = packages.iter().cloned().collect();
+    while let Some(package) = pending.pop() {
+        for dependent in package_dependents.get(&package).into_iter().flatten() {
+            if packages.insert(dependent.clone()) {
+                pending.push(dependent.clone());
+            }
+        }
+    }
+    Reanalysis::Packages(packages)
+}
+
+/// Forget that the files belong to `packages`, which are analyzed again and
+/// emit their files anew, so that files moved to other packages are not kept
+fn prune_file_packages(
+    file_packages: &mut HashMap<String, BTreeSet<String>>,
+    packages: &BTreeSet<String>,
+) {
+    for owners in file_packages.values_mut() {
+        owners.retain(|v| !packages.contains(v));
+    }
+    file_packages.retain(|_, owners| !owners.is_empty());
+}
+
+fn file_hash(path: &str) -> Option<u64> {
+    let content = std::fs::read(path).ok()?;
+    let mut hasher = std::hash::DefaultHasher::new();
+    content.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Order `analyzers` to be started, each running concurrently with the
+/// others. The first analysis that `prioritize` accepts, i.e. that of the
+/// focused file, is started first and emits the file's package first.
+fn schedule_analyses<T>(analyzers: Vec<T>, prioritize: impl Fn(&T) -> Option<T>) -> Vec<T> {
+    let mut scheduled = Vec::with_capacity(analyzers.len());
+    let mut prioritized = None;
+    for analyzer in analyzers {
+        match prioritized {
+            None => match prioritize(&analyzer) {
+                Some(analyzer) => prioritized = Some(analyzer),
+                None => scheduled.push(analyzer),
+            },
+            Some(_) => scheduled.push(analyzer),
+        }
+    }
+    if let Some(analyzer) = prioritized {
+        scheduled.insert(0, analyzer);
+    }
+    scheduled
+}
+
+/// Merge the results `krate` of a run into `analyzed`.
+///
+/// Files first merged by this run, as recorded in `merged_files`, replace the
+/// results of earlier runs, which are stale. Files merged already by this run,
+/// e.g. by another target of the package, keep the richer function.
+fn merge_analyzed(analyzed: &mut Crate, krate: Crate, merged_files: &mut BTreeSet<String>) {
+    let (same_run, fresh): (OutputMap<_, _>, OutputMap<_, _>) = krate
+        .0
+        .into_iter()
+        .partition(|(path, _)| merged_files.contains(path));
+    merged_files.extend(fresh.keys().cloned());
+    analyzed.merge_with(Crate(fresh), true);
+    analyzed.merge(Crate(same_run));
+}
+
+/// Number of selected locals whose decorations are kept in [`DecoCache`]
+const DECO_CACHE_CAPACITY: usize = 64;
+
+/// Cached decorations of a local of a file at an analysis version
+type DecoCacheEntry = ((String, u64, FnLocal), Arc<Vec<decoration::Deco>>);
+
+/// Decorations of recently selected locals, keyed by file, analysis version and
+/// local, computed only for the locals the cursor selects.
+///
+/// The least recently used local is evicted once the capacity is reached.
+#[derive(Debug)]
+struct DecoCache {
+    capacity: usize,
+    /// Most recently used last
+    entries: VecDeque<DecoCacheEntry>,
+}
+
+impl DecoCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn get_or_insert_with(
+        &mut self,
+        file: &str,
+        version: u64,
+        local: FnLocal,
+        build: impl FnOnce() -> Vec<decoration::Deco>,
+    ) -> Arc<Vec<decoration::Deco>> {
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|((path, v, l), _)| path == file && *v == version && *l == local)
+        {
+            let entry = self.entries.remove(index).unwrap();
+            let decos = entry.1.clone();
+            self.entries.push_back(entry);
+            return decos;
+        }
+        // entries of older analyses are never queried again
+        self.entries
+            .retain(|((path, v, _), _)| path != file || *v == version);
+        while self.capacity <= self.entries.len() {
+            self.entries.pop_front();
+        }
+        let decos = Arc::new(build());
+        if 0 < self.capacity {
+            self.entries
+                .push_back(((file.to_owned(), version, local), decos.clone()));
+        }
+        decos
+    }
+
+    /// Drop every entry older than `version`
+    fn invalidate(&mut self, version: u64) {
+        self.entries.retain(|((_, v, _), _)| version <= *v);
+    }
+}
+
 /// RustOwl LSP server backend
 pub struct Backend {
     #[allow(unused)]
     client: Client,
     analyzers: Arc<RwLock<Vec<Analyzer>>>,
     status: Arc<RwLock<progress::AnalysisStatus>>,
+    /// Analysis result of every file, kept whole as hovers, highlights and
+    /// diagnostics need the MIR; decorations are computed from it on request
     analyzed: Arc<RwLock<Option<Crate>>>,
     processes: Arc<RwLock<JoinSet<()>>>,
     process_tokens: Arc<RwLock<BTreeMap<usize, CancellationToken>>>,
     work_done_progress: Arc<RwLock<bool>>,
+    /// Packages owning each analyzed file, to re-analyze only them after edits
+    file_packages: Arc<RwLock<HashMap<String, BTreeSet<String>>>>,
+    edited: Arc<RwLock<HashMap<String, EditedFile>>>,
+    /// Bumped whenever `analyzed` changes, while its write lock is held
+    analysis_version: Arc<AtomicU64>,
+    deco_cache: Arc<Mutex<DecoCache>>,
+    position_encoding: Arc<RwLock<decoration::PositionEncoding>>,
+    /// The file last opened or queried, whose package is analyzed first
+    focused_file: Arc<RwLock<Option<PathBuf>>>,
+    /// Publish lifetime conflicts as diagnostics (`rustowl.diagnostics.enable`)
+    diagnostics_enabled: Arc<RwLock<bool>>,
 }
 
 impl Backend {
@@ -37,6 +227,13 @@ impl Backend {
             processes: Arc::new(RwLock::new(JoinSet::new())),
             process_tokens: Arc::new(RwLock::new(BTreeMap::new())),
             work_done_progress: Arc::new(RwLock::new(false)),
+            file_packages: Arc::new(RwLock::new(HashMap::new())),
+            edited: Arc::new(RwLock::new(HashMap::new())),
+            analysis_version: Arc::new(AtomicU64::new(0)),
+            deco_cache: Arc::new(Mutex::new(DecoCache::new(DECO_CACHE_CAPACITY))),
+            position_encoding: Arc::new(RwLock::new(decoration::PositionEncoding::default())),
+            focused_file: Arc::new(RwLock::new(None)),
+            diagnostics_enabled: Arc::new(RwLock::new(false)),
         }
     }
 
@@ -55,6 +252,14 @@ impl Backend {
         }
     }
 
+    /// LSP service with RustOwl's custom methods, to be served over any transport
+    pub fn service() -> (LspService<Self>, ClientSocket) {
+        LspService::build(Backend::new)
+            .custom_method("rustowl/cursor", Backend::cursor)
+            .custom_method("rustowl/analyze", Backend::analyze)
+            .finish()
+    }
+
     pub async fn analyze(&self, _params: AnalyzeRequest) -> jsonrpc::Result<AnalyzeResponse> {
         log::info!("rustowl/analyze request received");
         self.do_analyze().await;
@@ -62,10 +267,94 @@ impl Backend {
     }
     async fn do_analyze(&self) {
         self.shutdown_subprocesses().await;
-        self.analyze_with_options(false, false).await;
+        let edited = std::mem::take(&mut *self.edited.write().await);
+        let package_dependents = {
+            let mut dependents: HashMap<String, BTreeSet<String>> = HashMap::new();
+            for analyzer in self.analyzers.read().await.iter() {
+                for (package, v) in analyzer.workspace_dependents() {
+                    dependents.entry(package).or_default().extend(v);
+                }
+            }
+            dependents
+        };
+        let plan = plan_reanalysis(
+            &edited,
+            file_hash,
+            &*self.file_packages.read().await,
+            &package_dependents,
+        );
+        match plan {
+            Reanalysis::Unchanged => {
+                log::info!("edited files are unchanged; reuse the analysis");
+                let mut analyzed = self.analyzed.write().await;
+                for (path, file) in edited {
+                    if let Some(file) = file.analyzed {
+                        analyzed
+                            .get_or_insert_with(|| Crate(OutputMap::default()))
+                            .0
+                            .insert(path, file);
+                    }
+                }
+                self.analysis_version.fetch_add(1, Ordering::Relaxed);
+            }
+            Reanalysis::Packages(packages) => {
+                log::info!("re-analyze packages {packages:?}");
+                // files only in these packages are emitted again; removed files vanish
+                {
+                    let mut file_packages = self.file_packages.write().await;
+                    if let Some(analyzed) = &mut *self.analyzed.write().await {
+                        analyzed.0.retain(|path, _| {
+                            !file_packages
+                                .get(path)
+                                .is_some_and(|owners| owners.is_subset(&packages))
+                        });
+                        self.analysis_version.fetch_add(1, Ordering::Relaxed);
+                    }
+                    prune_file_packages(&mut file_packages, &packages);
+                }
+                let analyzers = self
+                    .analyzers
+                    .read()
+                    .await
+                    .iter()
+                    .filter_map(|analyzer| {
+                        let selected: Vec<_> = packages
+                            .iter()
+                            .filter(|v| analyzer.has_package(v))
+                            .cloned()
+                            .collect();
+                        if selected.is_empty() {
+                            return None;
+                        }
+                        analyzer.clone().select_packages(selected).ok()
+                    })
+                    .collect();
+                self.spawn_analyses(analyzers, false, false).await;
+            }
+            Reanalysis::Full => {
+                if !edited.is_empty() {
+                    let mut analyzed = self.analyzed.write().await;
+                    *analyzed = None;
+                    self.file_packages.write().await.clear();
+                    self.analysis_version.fetch_add(1, Ordering::Relaxed);
+                }
+                self.analyze_with_options(false, false).await;
+            }
+        }
     }
 
     async fn analyze_with_options(&self, all_targets: bool, all_features: bool) {
+        let analyzers = { self.analyzers.read().await.clone() };
+        self.spawn_analyses(analyzers, all_targets, all_features)
+            .await;
+    }
+
+    async fn spawn_analyses(
+        &self,
+        analyzers: Vec<Analyzer>,
+        all_targets: bool,
+        all_features: bool,
+    ) {
         log::info!("wait 100ms for rust-analyzer");
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
 
@@ -76,13 +365,20 @@ impl Backend {
         {
             *self.status.write().await = progress::AnalysisStatus::Analyzing;
         }
-        let analyzers = { self.analyzers.read().await.clone() };
 
         log::info!("analyze {} workspace(s)...", analyzers.len());
+        let focused = self.focused_file.read().await.clone();
+        let analyzers = schedule_analyses(analyzers, |analyzer| {
+            analyzer.prioritize(focused.as_deref()?)
+        });
         for analyzer in analyzers {
             let analyzed = self.analyzed.clone();
+            let analysis_version = self.analysis_version.clone();
+            let file_packages = self.file_packages.clone();
             let client = self.client.clone();
             let work_done_progress = self.work_done_progress.clone();
+            let diagnostics_enabled = self.diagnostics_enabled.clone();
+            let position_encoding = self.position_encoding.clone();
             let cancellation_token = CancellationToken::new();
 
             let cancellation_token_key = {
@@ -99,13 +395,19 @@ impl Backend {
 
             let process_tokens = self.process_tokens.clone();
             self.processes.write().await.spawn(async move {
+                // files merged by this run, whose earlier results are stale
+                let mut merged_files = BTreeSet::new();
                 let mut progress_token = None;
                 if *work_done_progress.read().await {
                     progress_token =
-                        Some(progress::ProgressToken::begin(client, None::<&str>).await)
+                        Some(progress::ProgressToken::begin(client.clone(), None::<&str>).await)
                 };
 
-                let mut iter = analyzer.analyze(all_targets, all_features).await;
+                let config = crate::config::Config::discover(analyzer.target_path())
+                    .with_flags(all_targets.then_some(true), all_features.then_some(true));
+                let mut iter = analyzer
+                    .analyze(config.all_targets, config.all_features)
+                    .await;
                 let mut analyzed_package_count = 0;
                 while let Some(event) = tokio::select! {
                     _ = cancellation_token.cancelled() => None,
@@ -129,23 +431,56 @@ impl Backend {
                             }
                         }
                         AnalyzerEvent::Analyzed(ws) => {
-                            let write = &mut *analyzed.write().await;
-                            for krate in ws.0.into_values() {
-                                if let Some(write) = write {
-                                    write.merge(krate);
-                                } else {
-                                    *write = Some(krate);
+                            {
+                                let mut file_packages = file_packages.write().await;
+                                for (key, krate) in &ws.0 {
+                                    let Some(package) =
+                                        analyzer.crate_package(crate_display_name(key))
+                                    else {
+                                        continue;
+                                    };
+                                    for path in krate.0.keys() {
+                                        file_packages
+                                            .entry(path.clone())
+                                            .or_default()
+                                            .insert(package.clone());
+                                    }
                                 }
                             }
+                            let paths: BTreeSet<_> =
+                                ws.0.values().flat_map(|v| v.0.keys().cloned()).collect();
+                            let files: Vec<_> = {
+                                let write = &mut *analyzed.write().await;
+                                for krate in ws.0.into_values() {
+                                    if let Some(write) = write {
+                                        merge_analyzed(write, krate, &mut merged_files);
+                                    } else {
+                                        merged_files.extend(krate.0.keys().cloned());
+                                        *write = Some(krate);
+                                    }
+                                }
+                                analysis_version.fetch_add(1, Ordering::Relaxed);
+                                // merged with the other crates of the files
+                                match write {
+                                    Some(krate) if *diagnostics_enabled.read().await => paths
+                                        .iter()
+                                        .filter_map(|v| Some((v, krate.0.get(v)?.clone())))
+                                        .collect(),
+                                    _ => Vec::new(),
+                                }
+                            };
+                            let encoding = *position_encoding.read().await;
+                            for (path, file) in files {
+                                diagnostics::publish(&client, path, &file, encoding, true).await;
+                            }
                         }
                     }
                 }
-                // remove cancellation token from list
-                process_tokens.write().await.remove(&cancellation_token_key);
-
                 if let Some(progress_token) = progress_token {
                     progress_token.finish().await;
                 }
+                // remove cancellation token from list
+                process_tokens.write().await.remove(&cancellation_token_key);
             });
         }
 
@@ -166,37 +501,57 @@ impl Backend {
         });
     }
 
+    /// Publish the diagnostics of every analyzed file again, or clear them
+    /// if they are disabled
+    async fn publish_diagnostics(&self) {
+        let enabled = *self.diagnostics_enabled.read().await;
+        let encoding = *self.position_encoding.read().await;
+        let files: Vec<_> = match &*self.analyzed.read().await {
+            Some(analyzed) => analyzed
+                .0
+                .iter()
+                .map(|(path, file)| (path.clone(), file.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+        for (path, file) in files {
+            diagnostics::publish(&self.client, &path, &file, encoding, enabled).await;
+        }
+    }
+
     async fn decos(
         &self,
         filepath: &Path,
         position: Loc,
     ) -> Result<Vec<decoration::Deco>, progress::AnalysisStatus> {
-        let mut selected = decoration::SelectLocal::new(position);
         let mut error = progress::AnalysisStatus::Error;
         if let Some(analyzed) = &*self.analyzed.read().await {
-            for (filename, file) in analyzed.0.iter() {
-                if &filepath.to_string_lossy() == filename {
-                    if !file.items.is_empty() {
-                        error = progress::AnalysisStatus::Finished;
-                    }
-                    for item in &file.items {
-                        utils::mir_visit(item, &mut selected);
-                    }
-                }
+            let filepath = filepath.to_string_lossy();
+            // read under the lock, so the version matches `analyzed`
+            let version = self.analysis_version.load(Ordering::Relaxed);
+            let files: Vec<_> = analyzed
+                .0
+                .iter()
+                .filter(|(filename, _)| filepath == **filename)
+                .map(|(_, file)| file)
+                .collect();
+            // a present but empty file has been analyzed and has nothing to show
+            if !files.is_empty() {
+                error = progress::AnalysisStatus::Finished;
             }
-
-            let mut calc = decoration::CalcDecos::new(selected.selected().iter().copied());
-            for (filename, file) in analyzed.0.iter() {
-                if &filepath.to_string_lossy() == filename {
-                    for item in &file.items {
-                        utils::mir_visit(item, &mut calc);
-                    }
-                }
-            }
-            calc.handle_overlapping();
-            let decos = calc.decorations();
+            let items = files.iter().flat_map(|file| &file.items);
+            let Some(local) = decoration::selected_local(items.clone(), position) else {
+                return Err(error);
+            };
+            let decos = {
+                let mut cache = utils::lock_or_recover(&self.deco_cache, "decoration cache");
+                cache.invalidate(version);
+                cache.get_or_insert_with(&filepath, version, local, || {
+                    decoration::local_decorations(items, local)
+                })
+            };
             if !decos.is_empty() {
-                Ok(decos)
+                Ok(decos.to_vec())
             } else {
                 Err(error)
             }
@@ -211,15 +566,11 @@ impl Backend {
     ) -> jsonrpc::Result<decoration::Decorations> {
         let is_analyzed = self.analyzed.read().await.is_some();
         let status = *self.status.read().await;
-        if let Some(path) = params.path()
-            && let Ok(text) = std::fs::read_to_string(&path)
-        {
-            let position = params.position();
-            let pos = Loc(utils::line_char_to_index(
-                &text,
-                position.line,
-                position.character,
-            ));
+        let position = lsp_types::TextDocumentPositionParams {
+            text_document: params.document,
+            position: params.position,
+        };
+        if let Some((path, text, pos, encoding)) = self.text_position(position).await {
             let (decos, status) = match self.decos(&path, pos).await {
                 Ok(v) => (v, status),
                 Err(e) => (
@@ -231,7 +582,10 @@ impl Backend {
                     },
                 ),
             };
-            let decorations = decos.into_iter().map(|v| v.to_lsp_range(&text)).collect();
+            let decorations = decos
+                .into_iter()
+                .map(|v| v.to_lsp_range(&text, encoding))
+                .collect();
             return Ok(decoration::Decorations {
                 is_analyzed,
                 status,
@@ -247,6 +601,21 @@ impl Backend {
         })
     }
 
+    /// The file, its source and the position a request points at, in the
+    /// negotiated encoding; the file becomes the focused one
+    async fn text_position(
+        &self,
+        params: lsp_types::TextDocumentPositionParams,
+    ) -> Option<(PathBuf, String, Loc, decoration::PositionEncoding)> {
+        let path = params.text_document.uri.to_file_path().ok()?;
+        let text = utils::read_source(&path).ok()?;
+        *self.focused_file.write().await = Some(path.clone());
+        let encoding = *self.position_encoding.read().await;
+        let position = params.position;
+        let loc = Loc(encoding.line_col_to_index(&text, position.line, position.character));
+        Some((path, text, loc, encoding))
+    }
+
     pub async fn check(path: impl AsRef<Path>) -> bool {
         Self::check_with_options(path, false, false).await
     }
@@ -256,25 +625,43 @@ impl Backend {
         all_targets: bool,
         all_features: bool,
     ) -> bool {
+        Self::check_packages(path, all_targets, all_features, Vec::new(), false)
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Check only the given workspace members; all of them if `packages` is empty.
+    /// With `tests`, `cfg(test)` code and test functions are analyzed too.
+    pub async fn check_packages(
+        path: impl AsRef<Path>,
+        all_targets: bool,
+        all_features: bool,
+        packages: Vec<String>,
+        tests: bool,
+    ) -> Result<bool, String> {
         let path = path.as_ref();
         let (service, _) = LspService::build(Backend::new).finish();
         let backend = service.inner();
 
-        if backend.add_analyze_target(path).await {
-            backend
-                .analyze_with_options(all_targets, all_features)
-                .await;
-            while backend.processes.write().await.join_next().await.is_some() {}
-            backend
-                .analyzed
-                .read()
-                .await
-                .as_ref()
-                .map(|v| !v.0.is_empty())
-                .unwrap_or(false)
-        } else {
-            false
-        }
+        let Ok(analyzer) = Analyzer::new(path).await else {
+            return Ok(false);
+        };
+        backend
+            .analyzers
+            .write()
+            .await
+            .push(analyzer.select_packages(packages)?.analyze_tests(tests));
+        backend
+            .analyze_with_options(all_targets, all_features)
+            .await;
+        while backend.processes.write().await.join_next().await.is_some() {}
+        Ok(backend
+            .analyzed
+            .read()
+            .await
+            .as_ref()
+            .map(|v| !v.0.is_empty())
+            .unwrap_or(false))
     }
 
     pub async fn shutdown_subprocesses(&self) {
@@ -321,9 +708,27 @@ impl LanguageServer for Backend {
             }),
             ..Default::default()
         };
+        let encoding = decoration::PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|v| v.position_encodings.as_deref()),
+        );
+        *self.position_encoding.write().await = encoding;
+        if let Some(enable) = params
+            .initialization_options
+            .as_ref()
+            .and_then(diagnostics::enabled_in)
+        {
+            *self.diagnostics_enabled.write().await = enable;
+        }
         let server_cap = lsp_types::ServerCapabilities {
+            position_encoding: Some(encoding.kind()),
             text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Options(sync_options)),
             workspace: Some(workspace_cap),
+            hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
+            document_highlight_provider: Some(lsp_types::OneOf::Left(true)),
             ..Default::default()
         };
         let init_res = lsp_types::InitializeResult {
@@ -365,18 +770,91 @@ impl LanguageServer for Backend {
         }
     }
 
+    async fn did_change_configuration(&self, params: lsp_types::DidChangeConfigurationParams) {
+        let Some(enable) = diagnostics::enabled_in(&params.settings) else {
+            return;
+        };
+        let changed = {
+            let mut enabled = self.diagnostics_enabled.write().await;
+            std::mem::replace(&mut *enabled, enable) != enable
+        };
+        if changed {
+            self.publish_diagnostics().await;
+        }
+    }
+
     async fn did_open(&self, params: lsp_types::DidOpenTextDocumentParams) {
         if let Ok(path) = params.text_document.uri.to_file_path()
             && path.is_file()
             && params.text_document.language_id == "rust"
-            && self.add_analyze_target(&path).await
         {
-            self.do_analyze().await;
+            *self.focused_file.write().await = Some(path.clone());
+            if self.add_analyze_target(&path).await {
+                self.do_analyze().await;
+            }
         }
     }
 
-    async fn did_change(&self, _params: lsp_types::DidChangeTextDocumentParams) {
-        *self.analyzed.write().await = None;
+    async fn hover(
+        &self,
+        params: lsp_types::HoverParams,
+    ) -> jsonrpc::Result<Option<lsp_types::Hover>> {
+        let Some((path, text, loc, encoding)) = self
+            .text_position(params.text_document_position_params)
+            .await
+        else {
+            return Ok(None);
+        };
+        let analyzed = self.analyzed.read().await;
+        let file = analyzed
+            .as_ref()
+            .and_then(|v| v.0.get(path.to_string_lossy().as_ref()));
+        Ok(file.and_then(|file| hover::hover(file, &text, loc, encoding)))
+    }
+
+    async fn document_highlight(
+        &self,
+        params: lsp_types::DocumentHighlightParams,
+    ) -> jsonrpc::Result<Option<Vec<lsp_types::DocumentHighlight>>> {
+        let Some((path, text, loc, encoding)) = self
+            .text_position(params.text_document_position_params)
+            .await
+        else {
+            return Ok(None);
+        };
+        let analyzed = self.analyzed.read().await;
+        let decl = analyzed
+            .as_ref()
+            .and_then(|v| v.0.get(path.to_string_lossy().as_ref()))
+            .and_then(|file| highlight::selected_decl(file, loc));
+        Ok(decl.map(|decl| highlight::document_highlights(decl, &text, encoding)))
+    }
+
+    async fn did_change(&self, params: lsp_types::DidChangeTextDocumentParams) {
+        // only the edited file is stale; keep the analysis of the others
+        if let Ok(path) = params.text_document.uri.to_file_path() {
+            let path = path.to_string_lossy().to_string();
+            let mut edited = self.edited.write().await;
+            if !edited.contains_key(&path) {
+                // the file on disk is still the analyzed content until saved
+                let analyzed = {
+                    let mut analyzed = self.analyzed.write().await;
+                    self.analysis_version.fetch_add(1, Ordering::Relaxed);
+                    analyzed.as_mut().and_then(|v| v.0.remove(&path))
+                };
+                edited.insert(
+                    path.clone(),
+                    EditedFile {
+                        hash: file_hash(&path),
+                        analyzed,
+                    },
+                );
+            }
+        } else {
+            let mut analyzed = self.analyzed.write().await;
+            *analyzed = None;
+            self.analysis_version.fetch_add(1, Ordering::Relaxed);
+        }
         self.shutdown_subprocesses().await;
     }
 
@@ -385,3 +863,624 @@ impl LanguageServer for Backend {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    /// Drives a [`Backend`] served in-process over an in-memory stream
+    struct TestClient {
+        reader: BufReader<DuplexStream>,
+        writer: DuplexStream,
+        analyzed: Arc<RwLock<Option<Crate>>>,
+        analysis_version: Arc<AtomicU64>,
+        next_id: u64,
+    }
+
+    impl TestClient {
+        fn start() -> Self {
+            let (service, socket) = Backend::service();
+            let analyzed = service.inner().analyzed.clone();
+            let analysis_version = service.inner().analysis_version.clone();
+            let (client_read, server_write) = tokio::io::duplex(1 << 16);
+            let (server_read, client_write) = tokio::io::duplex(1 << 16);
+            tokio::spawn(tower_lsp::Server::new(server_read, server_write, socket).serve(service));
+            Self {
+                reader: BufReader::new(client_read),
+                writer: client_write,
+                analyzed,
+                analysis_version,
+                next_id: 0,
+            }
+        }
+
+        /// Replace the analysis result, as if it came from `rustowlc`
+        async fn set_analyzed(&self, krate: Crate) {
+            let mut analyzed = self.analyzed.write().await;
+            *analyzed = Some(krate);
+            self.analysis_version.fetch_add(1, Ordering::Relaxed);
+        }
+
+        async fn send(&mut self, message: Value) {
+            let body = message.to_string();
+            let framed = format!("Content-Length: {}\r\n\r\n{body}", body.len());
+            self.writer.write_all(framed.as_bytes()).await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Value {
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                self.reader.read_line(&mut line).await.unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(v) = line.strip_prefix("Content-Length: ") {
+                    length = v.parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            self.reader.read_exact(&mut body).await.unwrap();
+            serde_json::from_slice(&body).unwrap()
+        }
+
+        async fn notify(&mut self, method: &str, params: Value) {
+            self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+                .await;
+        }
+
+        /// Send a request and wait for its response, skipping server notifications
+        async fn request(&mut self, method: &str, params: Value) -> Value {
+            self.next_id += 1;
+            let id = self.next_id;
+            self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+                .await;
+            loop {
+                let message = self.receive().await;
+                if message["id"] == id && message.get("method").is_none() {
+                    return message;
+                }
+            }
+        }
+
+        async fn initialize(&mut self) -> Value {
+            self.initialize_with(json!({})).await
+        }
+
+        async fn initialize_with(&mut self, capabilities: Value) -> Value {
+            let response = self
+                .request("initialize", json!({ "capabilities": capabilities }))
+                .await;
+            self.notify("initialized", json!({})).await;
+            response
+        }
+    }
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    /// `s` declared at `s_span`, living in `lives`
+    fn function(s_span: Range, lives: Range) -> Function {
+        Function {
+            fn_id: 1,
+            name: "main".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![MirDecl::User {
+                local: FnLocal::new(1, 1),
+                name: "s".to_owned(),
+                span: s_span,
+                ty: MirType {
+                    name: "String".into(),
+                    reference: None,
+                },
+                lives: vec![lives],
+                shared_borrow: Vec::new(),
+                mutable_borrow: Vec::new(),
+                drop: true,
+                drop_range: vec![lives],
+                definitely_live_at: Vec::new(),
+                maybe_init_at: Vec::new(),
+                must_live_at: Vec::new(),
+                storage_range: vec![lives],
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn cursor_on_opened_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        let source = "fn main() {\n    let s = String::new();\n    drop(s);\n}\n";
+        std::fs::write(&path, source).unwrap();
+        let uri = lsp_types::Url::from_file_path(&path).unwrap();
+
+        let mut client = TestClient::start();
+        let response = client.initialize().await;
+        assert!(response["result"]["capabilities"]["textDocumentSync"].is_object());
+        // the client does not advertise encodings, so UTF-16 is used
+        assert_eq!(
+            response["result"]["capabilities"]["positionEncoding"],
+            "utf-16"
+        );
+
+        // not opened as `rust` so that no real analysis is started
+        client
+            .notify(
+                "textDocument/didOpen",
+                json!({ "textDocument": {
+                    "uri": uri,
+                    "languageId": "plaintext",
+                    "version": 1,
+                    "text": source,
+                } }),
+            )
+            .await;
+
+        let s = source.find("s =").unwrap() as u32;
+        let drop_end = source.find("s);").unwrap() as u32 + 1;
+        client
+            .set_analyzed(Crate(OutputMap::from_iter([(
+                path.to_string_lossy().to_string(),
+                File {
+                    items: vec![function(range(s, s + 1), range(s, drop_end))],
+                    generated: false,
+                    std: false,
+                },
+            )])))
+            .await;
+
+        let response = client
+            .request(
+                "rustowl/cursor",
+                json!({
+                    "position": { "line": 1, "character": 8 },
+                    "document": { "uri": uri },
+                }),
+            )
+            .await;
+        let result = &response["result"];
+        assert_eq!(result["is_analyzed"], true);
+        assert_eq!(result["path"], json!(path));
+        assert_eq!(
+            result["decorations"],
+            json!([{
+                "type": "lifetime",
+                "local": { "id": 1, "fn_id": 1 },
+                "range": {
+                    "start": { "line": 1, "character": 8 },
+                    "end": { "line": 2, "character": 10 },
+                },
+                "hover_text": "lifetime of variable `s`",
+                "overlapped": false,
+            }])
+        );
+
+        // nothing is selected outside of the variable
+        let response = client
+            .request(
+                "rustowl/cursor",
+                json!({
+                    "position": { "line": 3, "character": 0 },
+                    "document": { "uri": uri },
+                }),
+            )
+            .await;
+        assert_eq!(response["result"]["decorations"], json!([]));
+    }
+
+    #[tokio::test]
+    async fn hover_over_variable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        let source = "fn main() {\n    let s = String::new();\n    drop(s);\n}\n";
+        std::fs::write(&path, source).unwrap();
+        let uri = lsp_types::Url::from_file_path(&path).unwrap();
+
+        let mut client = TestClient::start();
+        let response = client.initialize().await;
+        assert_eq!(response["result"]["capabilities"]["hoverProvider"], true);
+
+        let s = source.find("s =").unwrap() as u32;
+        let drop_end = source.find("s);").unwrap() as u32 + 1;
+        let scope_end = source.rfind('}').unwrap() as u32;
+        let mut func = function(range(s, s + 1), range(s, drop_end));
+        func.basic_blocks.push(MirBasicBlock {
+            statements: Vec::new(),
+            terminator: MirTerminator {
+                kind: MirTerminatorKind::Drop {
+                    place: MirPlace {
+                        local: FnLocal::new(1, 1),
+                        projection: Vec::new(),
+                    },
+                    target: BasicBlockId(1),
+                },
+                range: Some(range(scope_end, scope_end + 1)),
+            },
+            is_cleanup: false,
+        });
+        client
+            .set_analyzed(Crate(OutputMap::from_iter([(
+                path.to_string_lossy().to_string(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )])))
+            .await;
+
+        let hover = |line, character| {
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            })
+        };
+        let response = client.request("textDocument/hover", hover(1, 8)).await;
+        let result = &response["result"];
+        assert_eq!(result["contents"]["kind"], "markdown");
+        assert_eq!(
+            result["contents"]["value"],
+            "**`s`**: `String`\n\n\
+             - live: 2:9-3:11\n\
+             - shared borrows: nowhere\n\
+             - mutable borrows: nowhere\n\
+             - kept alive for its drop: 2:9-3:11\n\
+             - dropped at: 4:1\n"
+        );
+        assert_eq!(
+            result["range"],
+            json!({
+                "start": { "line": 1, "character": 8 },
+                "end": { "line": 1, "character": 9 },
+            })
+        );
+
+        // no variable is declared at the use of `s`
+        let response = client.request("textDocument/hover", hover(2, 9)).await;
+        assert_eq!(response["result"], Value::Null);
+    }
+
+    #[test]
+    fn reanalysis_of_edited_files() {
+        let owners = |packages: &[&str]| packages.iter().map(|v| v.to_string()).collect();
+        let file_packages = HashMap::from([
+            ("src/lib.rs".to_owned(), owners(&["core"])),
+            ("src/shared.rs".to_owned(), owners(&["core", "cli"])),
+        ]);
+        let edited = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| {
+                    let file = EditedFile {
+                        hash: Some(1),
+                        analyzed: None,
+                    };
+                    (path.to_string(), file)
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let changed = |_: &str| Some(2);
+        let unchanged = |_: &str| Some(1);
+        let dependents = HashMap::new();
+
+        // nothing known to be edited
+        assert_eq!(
+            plan_reanalysis(&HashMap::new(), changed, &file_packages, &dependents),
+            Reanalysis::Full
+        );
+        // saved back to the analyzed content
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/lib.rs"]),
+                unchanged,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Unchanged
+        );
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/lib.rs"]),
+                changed,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Packages(owners(&["core"]))
+        );
+        // a file in several targets
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/shared.rs"]),
+                changed,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Packages(owners(&["cli", "core"]))
+        );
+        // an added file
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/new.rs"]),
+                changed,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Full
+        );
+        // a removed file belongs to its packages until they are analyzed again
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/lib.rs"]),
+                |_| None,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Packages(owners(&["core"]))
+        );
+
+        // the dependents of an edited package, transitively
+        let dependents = HashMap::from([
+            ("core".to_owned(), owners(&["cli", "server"])),
+            ("server".to_owned(), owners(&["app"])),
+        ]);
+        assert_eq!(
+            plan_reanalysis(
+                &edited(&["src/lib.rs"]),
+                changed,
+                &file_packages,
+                &dependents
+            ),
+            Reanalysis::Packages(owners(&["app", "cli", "core", "server"]))
+        );
+
+        // files re-emitted by other packages are no longer owned by the analyzed ones
+        let mut file_packages = file_packages;
+        prune_file_packages(&mut file_packages, &owners(&["core"]));
+        assert_eq!(
+            file_packages,
+            HashMap::from([("src/shared.rs".to_owned(), owners(&["cli"]))])
+        );
+    }
+
+    /// Analyzer of a workspace at `root` with a library package per member
+    fn workspace_analyzer(root: &Path, members: &[&str]) -> Analyzer {
+        let packages: Vec<_> = members
+            .iter()
+            .map(|name| {
+                let dir = root.join(name);
+                json!({
+                    "name": name,
+                    "version": "0.1.0",
+                    "id": format!("path+file://{}#0.1.0", dir.display()),
+                    "dependencies": [],
+                    "targets": [{
+                        "name": name,
+                        "kind": ["lib"],
+                        "src_path": dir.join("src/lib.rs"),
+                    }],
+                    "features": {},
+                    "manifest_path": dir.join("Cargo.toml"),
+                })
+            })
+            .collect();
+        let ids: Vec<_> = packages.iter().map(|v| v["id"].clone()).collect();
+        let metadata = json!({
+            "packages": packages,
+            "workspace_members": ids,
+            "workspace_root": root,
+            "target_directory": root.join("target"),
+            "version": 1,
+        });
+        Analyzer::with_metadata(serde_json::from_value(metadata).unwrap())
+    }
+
+    #[test]
+    fn focused_package_is_scheduled_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let single = dir.path().join("single.rs");
+        std::fs::write(&single, "fn main() {}").unwrap();
+        let analyzers = || {
+            vec![
+                workspace_analyzer(&dir.path().join("ws1"), &["lib"]),
+                workspace_analyzer(&dir.path().join("ws2"), &["core", "app"]),
+                Analyzer::single_file(&single).unwrap(),
+            ]
+        };
+        let order = |analyzers: &[Analyzer]| {
+            analyzers
+                .iter()
+                .map(|v| {
+                    let priority = v.priority_package().map(str::to_owned);
+                    (v.target_path().to_path_buf(), priority)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // the workspace owning the focused file is analyzed first, as a whole
+        let focused = dir.path().join("ws2/app/src/lib.rs");
+        let scheduled = schedule_analyses(analyzers(), |v| v.prioritize(&focused));
+        assert_eq!(
+            order(&scheduled),
+            vec![
+                (dir.path().join("ws2"), Some("app".to_owned())),
+                (dir.path().join("ws1"), None),
+                (single.clone(), None),
+            ]
+        );
+
+        let scheduled = schedule_analyses(analyzers(), |v| v.prioritize(&single));
+        assert_eq!(scheduled[0].target_path(), single);
+
+        // without a focused file, the order is kept
+        let scheduled = schedule_analyses(analyzers(), |_| None);
+        assert_eq!(order(&scheduled), order(&analyzers()));
+        let outside = dir.path().join("other/src/lib.rs");
+        let scheduled = schedule_analyses(analyzers(), |v| v.prioritize(&outside));
+        assert_eq!(order(&scheduled), order(&analyzers()));
+    }
+
+    #[test]
+    fn reanalysis_replaces_stale_functions() {
+        let func = |decls: usize| Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: (0..decls)
+                .map(|i| MirDecl::Other {
+                    local: FnLocal::new(i as u32, 1),
+                    ty: MirType {
+                        name: "u8".into(),
+                        reference: None,
+                    },
+                    lives: Vec::new(),
+                    shared_borrow: Vec::new(),
+                    mutable_borrow: Vec::new(),
+                    drop: false,
+                    drop_range: Vec::new(),
+                    must_live_at: Vec::new(),
+                    storage_range: Vec::new(),
+                    definitely_live_at: Vec::new(),
+                    maybe_init_at: Vec::new(),
+                })
+                .collect(),
+        };
+        let krate = |decls: usize| {
+            Crate(OutputMap::from_iter([(
+                "lib.rs".to_owned(),
+                File {
+                    items: vec![func(decls)],
+                    generated: false,
+                    std: false,
+                },
+            )]))
+        };
+        let decls = |krate: &Crate| krate.0["lib.rs"].items[0].decls.len();
+
+        // an earlier run found more, but the edit removed some
+        let mut analyzed = krate(3);
+        let mut merged_files = BTreeSet::new();
+        merge_analyzed(&mut analyzed, krate(1), &mut merged_files);
+        assert_eq!(decls(&analyzed), 1);
+        // another target of the same run keeps the richer one
+        merge_analyzed(&mut analyzed, krate(0), &mut merged_files);
+        assert_eq!(decls(&analyzed), 1);
+        merge_analyzed(&mut analyzed, krate(2), &mut merged_files);
+        assert_eq!(decls(&analyzed), 2);
+    }
+
+    #[test]
+    fn deco_cache_by_file_version_and_local() {
+        let mut cache = DecoCache::new(2);
+        let builds = std::cell::Cell::new(0);
+        let local = FnLocal::new(1, 1);
+        let query = |cache: &mut DecoCache, file: &str, version: u64, local: FnLocal| {
+            cache.get_or_insert_with(file, version, local, || {
+                builds.set(builds.get() + 1);
+                Vec::new()
+            });
+        };
+
+        query(&mut cache, "a.rs", 1, local);
+        query(&mut cache, "a.rs", 1, local);
+        query(&mut cache, "b.rs", 1, local);
+        query(&mut cache, "a.rs", 1, local);
+        assert_eq!(builds.get(), 2);
+        // only the selected local is computed
+        query(&mut cache, "a.rs", 1, FnLocal::new(2, 1));
+        assert_eq!(builds.get(), 3);
+
+        // a new analysis invalidates the entries
+        cache.invalidate(2);
+        assert!(cache.entries.is_empty());
+        query(&mut cache, "a.rs", 2, local);
+        assert_eq!(builds.get(), 4);
+
+        // the least recently used local is evicted
+        query(&mut cache, "b.rs", 2, local);
+        query(&mut cache, "a.rs", 2, local);
+        query(&mut cache, "c.rs", 2, local);
+        query(&mut cache, "a.rs", 2, local);
+        assert_eq!(builds.get(), 6);
+        query(&mut cache, "b.rs", 2, local);
+        assert_eq!(builds.get(), 7);
+    }
+
+    #[tokio::test]
+    async fn position_encoding_negotiation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("main.rs");
+        // the crab is 1 char, 2 UTF-16 code units and 4 UTF-8 bytes
+        let source = "fn main() {\n    /*\u{1f980}*/let s = String::new();\n}\n";
+        std::fs::write(&path, source).unwrap();
+        let uri = lsp_types::Url::from_file_path(&path).unwrap();
+        let s = source.chars().position(|c| c == 's').unwrap() as u32;
+        let krate = Crate(OutputMap::from_iter([(
+            path.to_string_lossy().to_string(),
+            File {
+                items: vec![function(range(s, s + 1), range(s, s + 1))],
+                generated: false,
+                std: false,
+            },
+        )]));
+
+        // offered encodings, the chosen one and the column of `s` in it
+        let cases = [
+            (None, "utf-16", 14),
+            (Some(vec![]), "utf-16", 14),
+            (Some(vec!["utf-16"]), "utf-16", 14),
+            (Some(vec!["utf-8"]), "utf-8", 16),
+            (Some(vec!["utf-32"]), "utf-32", 13),
+            (Some(vec!["utf-16", "utf-8"]), "utf-8", 16),
+            (Some(vec!["utf-32", "utf-16"]), "utf-32", 13),
+            (Some(vec!["utf-16", "utf-32"]), "utf-16", 14),
+            (Some(vec!["utf-32", "utf-8", "utf-16"]), "utf-8", 16),
+            // the crab is a single grapheme too
+            (
+                Some(vec!["utf-8", "rustowl-grapheme"]),
+                "rustowl-grapheme",
+                13,
+            ),
+        ];
+        for (offered, chosen, column) in cases {
+            let mut client = TestClient::start();
+            let capabilities = match &offered {
+                Some(offered) => json!({ "general": { "positionEncodings": offered } }),
+                None => json!({}),
+            };
+            let response = client.initialize_with(capabilities).await;
+            assert_eq!(
+                response["result"]["capabilities"]["positionEncoding"], chosen,
+                "{offered:?}"
+            );
+            client.set_analyzed(krate.clone()).await;
+
+            let response = client
+                .request(
+                    "rustowl/cursor",
+                    json!({
+                        "position": { "line": 1, "character": column },
+                        "document": { "uri": uri },
+                    }),
+                )
+                .await;
+            assert_eq!(
+                response["result"]["decorations"][0]["range"],
+                json!({
+                    "start": { "line": 1, "character": column },
+                    "end": { "line": 1, "character": column + 1 },
+                }),
+                "{offered:?}"
+            );
+        }
+    }
+}
@@ -69,8 +69,110 @@ pub enum Deco<R = Range> {
         overlapped: bool,
     },
 }
+impl<R> Deco<R> {
+    /// Convert the range, keeping everything else
+    pub fn map_range<S>(self, f: impl FnOnce(R) -> S) -> Deco<S> {
+        macro_rules! map_range {
+            ($($variant:ident),*) => {
+                match self {
+                    $(Deco::$variant {
+                        local,
+                        range,
+                        hover_text,
+                        overlapped,
+                    } => Deco::$variant {
+                        local,
+                        range: f(range),
+                        hover_text,
+                        overlapped,
+                    },)*
+                }
+            };
+        }
+        map_range!(
+            Lifetime,
+            ImmBorrow,
+            MutBorrow,
+            Move,
+            Call,
+            SharedMut,
+            Outlive,
+            DefinitelyLive,
+            MaybeInitialized
+        )
+    }
+}
+
+/// How `Position.character` is counted, negotiated with the client
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// UTF-8 bytes, preferred when offered
+    Utf8,
+    /// UTF-16 code units, which every client supports
+    #[default]
+    Utf16,
+    /// Unicode scalar values, which is how [`Loc`] counts
+    Utf32,
+    /// Extended grapheme clusters, for clients moving the cursor by them;
+    /// only chosen if the client offers [`GRAPHEME_ENCODING`]
+    Grapheme,
+}
+
+/// Position encoding a client opts in to grapheme columns with
+pub const GRAPHEME_ENCODING: &str = "rustowl-grapheme";
+
+impl PositionEncoding {
+    /// Grapheme clusters or UTF-8 if the client offers them, otherwise the first
+    /// encoding we support in the client's order of preference
+    pub fn negotiate(client: Option<&[lsp_types::PositionEncodingKind]>) -> Self {
+        let client = client.unwrap_or_default();
+        if client.iter().any(|v| v.as_str() == GRAPHEME_ENCODING) {
+            return Self::Grapheme;
+        }
+        if client.contains(&lsp_types::PositionEncodingKind::UTF8) {
+            return Self::Utf8;
+        }
+        client
+            .iter()
+            .find_map(|kind| {
+                if *kind == lsp_types::PositionEncodingKind::UTF16 {
+                    Some(Self::Utf16)
+                } else if *kind == lsp_types::PositionEncodingKind::UTF32 {
+                    Some(Self::Utf32)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or_default()
+    }
+    pub fn kind(self) -> lsp_types::PositionEncodingKind {
+        match self {
+            Self::Utf8 => lsp_types::PositionEncodingKind::UTF8,
+            Self::Utf16 => lsp_types::PositionEncodingKind::UTF16,
+            Self::Utf32 => lsp_types::PositionEncodingKind::UTF32,
+            Self::Grapheme => lsp_types::PositionEncodingKind::new(GRAPHEME_ENCODING),
+        }
+    }
+    pub fn index_to_line_col(self, s: &str, idx: Loc) -> (u32, u32) {
+        match self {
+            Self::Utf8 => utils::index_to_line_utf8(s, idx),
+            Self::Utf16 => utils::index_to_line_utf16(s, idx),
+            Self::Utf32 => utils::index_to_line_char(s, idx),
+            Self::Grapheme => utils::index_to_line_grapheme(s, idx),
+        }
+    }
+    pub fn line_col_to_index(self, s: &str, line: u32, col: u32) -> u32 {
+        match self {
+            Self::Utf8 => utils::line_utf8_to_index(s, line, col),
+            Self::Utf16 => utils::line_utf16_to_index(s, line, col),
+            Self::Utf32 => utils::line_char_to_index(s, line, col),
+            Self::Grapheme => utils::line_grapheme_to_index(s, line, col),
+        }
+    }
+}
+
 impl Deco<Range> {
-    pub fn to_lsp_range(&self, s: &str) -> Deco<lsp_types::Range> {
+    pub fn to_lsp_range(&self, s: &str, encoding: PositionEncoding) -> Deco<lsp_types::Range> {
         match self.clone() {
             Deco::Lifetime {
                 local,
@@ -78,8 +180,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -101,8 +203,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -124,8 +226,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -147,8 +249,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -170,8 +272,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -193,8 +295,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -217,8 +319,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -241,8 +343,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -264,8 +366,8 @@ impl Deco<Range> {
                 hover_text,
                 overlapped,
             } => {
-                let start = utils::index_to_line_char(s, range.from());
-                let end = utils::index_to_line_char(s, range.until());
+                let start = encoding.index_to_line_col(s, range.from());
+                let end = encoding.index_to_line_col(s, range.until());
                 let start = lsp_types::Position {
                     line: start.0,
                     character: start.1,
@@ -369,82 +471,112 @@ impl SelectLocal {
         }
     }
 }
+impl SelectSink for SelectLocal {
+    fn push_candidate(&mut self, local: FnLocal) {
+        self.candidate_local_decls.push(local);
+    }
+    fn push_select(&mut self, reason: SelectReason, local: FnLocal, range: Range) {
+        self.select(reason, local, range);
+    }
+}
 impl utils::MirVisitor for SelectLocal {
     fn visit_decl(&mut self, decl: &MirDecl) {
-        let (local, ty) = match decl {
-            MirDecl::User { local, ty, .. } => (local, ty),
-            MirDecl::Other { local, ty, .. } => (local, ty),
-        };
-        if ASYNC_RESUME_TY.contains(&ty.name.as_str()) {
-            return;
-        }
-        self.candidate_local_decls.push(*local);
-        if let MirDecl::User { local, span, .. } = decl {
-            self.select(SelectReason::Var, *local, *span);
-        }
+        select_decl(self, decl);
     }
     fn visit_stmt(&mut self, stmt: &MirStatement) {
-        if let Some(range) = stmt.range
-            && let MirStatementKind::Assign { rval, .. } = &stmt.kind
-        {
-            match rval {
-                MirRval::Use { operand }
-                | MirRval::Repeat { operand }
-                | MirRval::Cast { operand }
-                | MirRval::UnaryOp { operand } => {
-                    self.select_operand(operand, range);
-                }
-                MirRval::BinaryOp { left, right } => {
-                    self.select_operand(left, range);
-                    self.select_operand(right, range);
-                }
-                MirRval::Ref { place, .. } => {
-                    self.select(SelectReason::Borrow, place.local, range);
-                }
-                MirRval::Aggregate { fields } => {
-                    for field in fields {
-                        self.select_operand(field, range);
-                    }
+        select_stmt(self, stmt);
+    }
+    fn visit_term(&mut self, term: &MirTerminator) {
+        select_term(self, term);
+    }
+}
+
+/// Receives the locals and ranges [`SelectLocal`] is interested in,
+/// so that they can be either applied directly or recorded for later
+trait SelectSink {
+    fn push_candidate(&mut self, local: FnLocal);
+    fn push_select(&mut self, reason: SelectReason, local: FnLocal, range: Range);
+    fn push_operand(&mut self, operand: &MirOperand, range: Range) {
+        if let MirOperand::Move { place } = operand {
+            self.push_select(SelectReason::Move, place.local, range);
+        }
+    }
+}
+fn select_decl(sink: &mut impl SelectSink, decl: &MirDecl) {
+    let (local, ty) = match decl {
+        MirDecl::User { local, ty, .. } => (local, ty),
+        MirDecl::Other { local, ty, .. } => (local, ty),
+    };
+    if ASYNC_RESUME_TY.contains(&ty.name.as_str()) {
+        return;
+    }
+    sink.push_candidate(*local);
+    if let MirDecl::User { local, span, .. } = decl {
+        sink.push_select(SelectReason::Var, *local, *span);
+    }
+}
+fn select_stmt(sink: &mut impl SelectSink, stmt: &MirStatement) {
+    if let Some(range) = stmt.range
+        && let MirStatementKind::Assign { rval, .. } = &stmt.kind
+    {
+        match rval {
+            MirRval::Use { operand }
+            | MirRval::Repeat { operand }
+            | MirRval::Cast { operand }
+            | MirRval::UnaryOp { operand } => {
+                sink.push_operand(operand, range);
+            }
+            MirRval::BinaryOp { left, right } => {
+                sink.push_operand(left, range);
+                sink.push_operand(right, range);
+            }
+            MirRval::Ref { place, .. } => {
+                sink.push_select(SelectReason::Borrow, place.local, range);
+            }
+            MirRval::Aggregate { fields } => {
+                for field in fields {
+                    sink.push_operand(field, range);
                 }
-                MirRval::Other => {}
             }
+            MirRval::Other => {}
         }
     }
-    fn visit_term(&mut self, term: &MirTerminator) {
-        if let Some(range) = term.range {
-            match &term.kind {
-                MirTerminatorKind::Call {
-                    destination,
-                    fn_range,
-                    ..
-                } => {
-                    // ignore args to ensure the destination local is selected
-                    if let Some(fn_range) = fn_range {
-                        self.select(SelectReason::Call, destination.local, *fn_range);
-                    }
+}
+fn select_term(sink: &mut impl SelectSink, term: &MirTerminator) {
+    if let Some(range) = term.range {
+        match &term.kind {
+            MirTerminatorKind::Call {
+                destination,
+                fn_range,
+                ..
+            } => {
+                // ignore args to ensure the destination local is selected
+                if let Some(fn_range) = fn_range {
+                    sink.push_select(SelectReason::Call, destination.local, *fn_range);
                 }
-                MirTerminatorKind::TailCall { args, fn_range, .. } => {
-                    if let Some(fn_range) = fn_range {
-                        for arg in args {
-                            self.select_operand(arg, *fn_range);
-                        }
+            }
+            MirTerminatorKind::TailCall { args, fn_range, .. } => {
+                if let Some(fn_range) = fn_range {
+                    for arg in args {
+                        sink.push_operand(arg, *fn_range);
                     }
                 }
-                MirTerminatorKind::Assert { cond, .. } => {
-                    self.select_operand(cond, range);
-                }
-                MirTerminatorKind::Drop { place, .. } => {
-                    self.select(SelectReason::Drop, place.local, range);
-                }
-                MirTerminatorKind::Goto { .. }
-                | MirTerminatorKind::SwitchInt { .. }
-                | MirTerminatorKind::Return
-                | MirTerminatorKind::Unreachable
-                | MirTerminatorKind::Other { .. } => {}
             }
+            MirTerminatorKind::Assert { cond, .. } => {
+                sink.push_operand(cond, range);
+            }
+            MirTerminatorKind::Drop { place, .. } => {
+                sink.push_select(SelectReason::Drop, place.local, range);
+            }
+            MirTerminatorKind::Goto { .. }
+            | MirTerminatorKind::SwitchInt { .. }
+            | MirTerminatorKind::Return
+            | MirTerminatorKind::Unreachable
+            | MirTerminatorKind::Other { .. } => {}
         }
     }
 }
+
 #[derive(Clone, Debug)]
 pub struct CalcDecos {
     locals: HashSet<FnLocal>,
@@ -712,10 +844,7 @@ impl utils::MirVisitor for CalcDecos {
         let (
             local,
             lives,
-            shared_borrow,
-            mutable_borrow,
             drop_range,
-            must_live_at,
             storage_range,
             name,
             drop,
@@ -726,10 +855,7 @@ impl utils::MirVisitor for CalcDecos {
                 local,
                 name,
                 lives,
-                shared_borrow,
-                mutable_borrow,
                 drop_range,
-                must_live_at,
                 storage_range,
                 drop,
                 definitely_live_at,
@@ -738,10 +864,7 @@ impl utils::MirVisitor for CalcDecos {
             } => (
                 *local,
                 lives,
-                shared_borrow,
-                mutable_borrow,
                 drop_range,
-                must_live_at,
                 storage_range,
                 Some(name),
                 drop,
@@ -751,10 +874,7 @@ impl utils::MirVisitor for CalcDecos {
             MirDecl::Other {
                 local,
                 lives,
-                shared_borrow,
-                mutable_borrow,
                 drop_range,
-                must_live_at,
                 storage_range,
                 drop,
                 definitely_live_at,
@@ -763,10 +883,7 @@ impl utils::MirVisitor for CalcDecos {
             } => (
                 *local,
                 lives,
-                shared_borrow,
-                mutable_borrow,
                 drop_range,
-                must_live_at,
                 storage_range,
                 None,
                 drop,
@@ -798,10 +915,7 @@ impl utils::MirVisitor for CalcDecos {
                     overlapped: false,
                 });
             }
-            let mut borrow_ranges = shared_borrow.clone();
-            borrow_ranges.extend_from_slice(mutable_borrow);
-            let shared_mut = utils::common_ranges(&borrow_ranges);
-            for range in shared_mut {
+            for range in decl.borrow_conflicts() {
                 self.decorations.push(Deco::SharedMut {
                     local,
                     range,
@@ -809,8 +923,7 @@ impl utils::MirVisitor for CalcDecos {
                     overlapped: false,
                 });
             }
-            let outlive = utils::exclude_ranges(must_live_at.clone(), definitely_live_at.clone());
-            for range in outlive {
+            for range in decl.outlive_ranges() {
                 self.decorations.push(Deco::Outlive {
                     local,
                     range,
@@ -853,20 +966,29 @@ impl utils::MirVisitor for CalcDecos {
                     self.visit_operand(left, range);
                     self.visit_operand(right, range);
                 }
-                MirRval::Ref { place, mutable } => {
+                MirRval::Ref {
+                    place,
+                    mutable,
+                    reason,
+                } => {
                     if self.locals.contains(&place.local) {
+                        let kind = if *mutable { "mutable" } else { "immutable" };
+                        let hover_text = match reason {
+                            Some(reason) => format!("{kind} borrow {}", reason.describe(*mutable)),
+                            None => format!("{kind} borrow"),
+                        };
                         if *mutable {
                             self.decorations.push(Deco::MutBorrow {
                                 local: place.local,
                                 range,
-                                hover_text: "mutable borrow".to_string(),
+                                hover_text,
                                 overlapped: false,
                             });
                         } else {
                             self.decorations.push(Deco::ImmBorrow {
                                 local: place.local,
                                 range,
-                                hover_text: "immutable borrow".to_string(),
+                                hover_text,
                                 overlapped: false,
                             });
                         }
@@ -921,4 +1043,383 @@ impl utils::MirVisitor for CalcDecos {
     }
 }
 
+/// Decorations for the local selected at `pos`, computed from whole functions
+pub fn decorations_at<'a>(
+    items: impl Iterator<Item = &'a Function> + Clone,
+    pos: Loc,
+) -> Vec<Deco> {
+    match selected_local(items.clone(), pos) {
+        Some(local) => local_decorations(items, local),
+        None => Vec::new(),
+    }
+}
+
+/// The local selected at `pos` among `items`
+pub fn selected_local<'a>(
+    items: impl IntoIterator<Item = &'a Function>,
+    pos: Loc,
+) -> Option<FnLocal> {
+    let mut selected = SelectLocal::new(pos);
+    for item in items {
+        utils::mir_visit(item, &mut selected);
+    }
+    selected.selected()
+}
+
+/// Decorations of `local`, visiting only the function of `items` it belongs to
+pub fn local_decorations<'a>(
+    items: impl IntoIterator<Item = &'a Function>,
+    local: FnLocal,
+) -> Vec<Deco> {
+    let mut calc = CalcDecos::new([local]);
+    for item in items.into_iter().filter(|v| v.fn_id == local.fn_id) {
+        utils::mir_visit(item, &mut calc);
+    }
+    calc.handle_overlapping();
+    calc.decorations()
+}
+
+/// Locals that can be selected, in declaration order
+#[derive(Default)]
+struct CandidateLocals(Vec<FnLocal>);
+impl SelectSink for CandidateLocals {
+    fn push_candidate(&mut self, local: FnLocal) {
+        self.0.push(local);
+    }
+    fn push_select(&mut self, _reason: SelectReason, _local: FnLocal, _range: Range) {}
+}
+impl utils::MirVisitor for CandidateLocals {
+    fn visit_decl(&mut self, decl: &MirDecl) {
+        select_decl(self, decl);
+    }
+}
+
+/// Decorations of a single function, computed for every local at once,
+/// as [`workspace_decorations`] outputs them.
+///
+/// The body is visited once per local; for the local under the cursor alone,
+/// [`local_decorations`] visits it once.
+#[derive(Clone, Debug)]
+pub struct FunctionDecos {
+    fn_id: u32,
+    /// Decorations of each local, in declaration order
+    decorations: Vec<(FnLocal, Vec<Deco>)>,
+}
+impl FunctionDecos {
+    pub fn new(func: &Function) -> Self {
+        let mut candidates = CandidateLocals::default();
+        utils::mir_visit(func, &mut candidates);
+        let decorations = candidates
+            .0
+            .into_iter()
+            .map(|local| {
+                let mut calc = CalcDecos::new([local]);
+                utils::mir_visit(func, &mut calc);
+                (local, calc.decorations())
+            })
+            .collect();
+        Self {
+            fn_id: func.fn_id,
+            decorations,
+        }
+    }
+
+    pub fn fn_id(&self) -> u32 {
+        self.fn_id
+    }
+
+    /// Decorations of every local, in declaration order
+    pub fn into_decorations(self) -> Vec<Deco> {
+        self.decorations
+            .into_iter()
+            .flat_map(|(_, decos)| decos)
+            .collect()
+    }
+}
+
+/// Decorations of every local, keyed by crate and then by file
+pub type WorkspaceDecorations = OutputMap<String, OutputMap<String, Vec<Deco>>>;
+
+/// Lowers the whole analysis result to its decorations,
+/// dropping the MIR that frontends usually do not need
+pub fn workspace_decorations(ws: &Workspace) -> WorkspaceDecorations {
+    ws.0.iter()
+        .map(|(crate_name, krate)| {
+            let files = krate
+                .0
+                .iter()
+                .map(|(file_name, file)| {
+                    let decos = file
+                        .items
+                        .iter()
+                        .flat_map(|item| FunctionDecos::new(item).into_decorations())
+                        .collect();
+                    (file_name.clone(), decos)
+                })
+                .collect();
+            (crate_name.clone(), files)
+        })
+        .collect()
+}
+
+/// [`WorkspaceDecorations`] with byte ranges alongside char ranges
+pub type WorkspaceByteDecorations = OutputMap<String, OutputMap<String, Vec<Deco<DualRange>>>>;
+
+/// Add byte ranges to the decorations, reading each file once with `read_source`.
+/// Files which cannot be read are logged and keep their char ranges only,
+/// with `bytes` of `null`.
+pub fn with_byte_ranges(
+    decos: WorkspaceDecorations,
+    read_source: impl Fn(&str) -> std::io::Result<String>,
+) -> WorkspaceByteDecorations {
+    decos
+        .into_iter()
+        .map(|(crate_name, files)| {
+            let files = files
+                .into_iter()
+                .map(|(file_name, decos)| {
+                    let index = match read_source(&file_name) {
+                        Ok(source) => Some(utils::ByteIndex::new(&source)),
+                        Err(e) => {
+                            log::warn!(
+                                "failed to read {file_name}: {e}; its byte ranges are left out"
+                            );
+                            None
+                        }
+                    };
+                    let decos = decos
+                        .into_iter()
+                        .map(|deco| {
+                            deco.map_range(|range| match &index {
+                                Some(index) => index.dual_range(range),
+                                None => DualRange { range, bytes: None },
+                            })
+                        })
+                        .collect();
+                    (file_name, decos)
+                })
+                .collect();
+            (crate_name, files)
+        })
+        .collect()
+}
+
 // TODO: new test
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    fn place(id: u32, fn_id: u32) -> MirPlace {
+        MirPlace {
+            local: FnLocal::new(id, fn_id),
+            projection: Vec::new(),
+        }
+    }
+
+    fn user_decl(id: u32, fn_id: u32, name: &str, span: Range, lives: Vec<Range>) -> MirDecl {
+        MirDecl::User {
+            local: FnLocal::new(id, fn_id),
+            name: name.to_owned(),
+            span,
+            ty: MirType {
+                name: "String".into(),
+                reference: None,
+            },
+            lives: lives.clone(),
+            shared_borrow: Vec::new(),
+            mutable_borrow: Vec::new(),
+            drop: true,
+            drop_range: lives.clone(),
+            definitely_live_at: lives.clone(),
+            maybe_init_at: Vec::new(),
+            must_live_at: lives.clone(),
+            storage_range: lives,
+        }
+    }
+
+    /// `let a = ..; let b = &a; let c = f(b);` starting at `base`
+    fn function(fn_id: u32, base: u32) -> Function {
+        let at = |from, until| range(base + from, base + until);
+        Function {
+            fn_id,
+            name: format!("f{fn_id}"),
+            span: None,
+            test: false,
+            unavailable: false,
+            decls: vec![
+                user_decl(1, fn_id, "a", at(4, 5), vec![at(4, 30)]),
+                user_decl(2, fn_id, "b", at(14, 15), vec![at(14, 25)]),
+                user_decl(3, fn_id, "c", at(24, 25), vec![at(24, 30)]),
+            ],
+            basic_blocks: vec![MirBasicBlock {
+                statements: vec![MirStatement {
+                    kind: MirStatementKind::Assign {
+                        place: place(2, fn_id),
+                        rval: MirRval::Ref {
+                            place: place(1, fn_id),
+                            mutable: false,
+                            reason: Some(BorrowReason::Explicit),
+                        },
+                    },
+                    range: Some(at(18, 20)),
+                }],
+                terminator: MirTerminator {
+                    kind: MirTerminatorKind::Call {
+                        func: MirOperand::Other,
+                        args: vec![MirOperand::Move {
+                            place: place(2, fn_id),
+                        }],
+                        destination: place(3, fn_id),
+                        target: None,
+                        fn_range: Some(at(26, 29)),
+                    },
+                    range: Some(at(26, 29)),
+                },
+                is_cleanup: false,
+            }],
+        }
+    }
+
+    #[test]
+    fn selected_decorations_match_whole_file() {
+        let functions = [function(1, 0), function(2, 40)];
+
+        let mut non_empty = 0;
+        for pos in 0..80 {
+            // visiting every function, not only the one of the selected local
+            let mut calc = CalcDecos::new(selected_local(&functions, Loc(pos)));
+            for func in &functions {
+                utils::mir_visit(func, &mut calc);
+            }
+            calc.handle_overlapping();
+            let expected = calc.decorations();
+            assert_eq!(
+                decorations_at(functions.iter(), Loc(pos)),
+                expected,
+                "at {pos}"
+            );
+            if !expected.is_empty() {
+                non_empty += 1;
+            }
+        }
+        assert!(non_empty > 0);
+    }
+
+    #[test]
+    fn decorations_only_output() {
+        let ws = Workspace(OutputMap::from_iter([(
+            "owl".to_owned(),
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![function(1, 0), function(2, 40)],
+                    generated: false,
+                    std: false,
+                },
+            )])),
+        )]));
+        let full = ws.to_json(false).unwrap();
+        let slim = serde_json::to_string(&workspace_decorations(&ws)).unwrap();
+        assert!(slim.len() < full.len());
+
+        // decorations extracted from the full output are the same
+        let parsed = Workspace::from_json_reader(full.as_bytes()).unwrap();
+        let decos = &workspace_decorations(&parsed)["owl"]["src/lib.rs"];
+        let mut expected = Vec::new();
+        for func in &parsed.0["owl"].0["src/lib.rs"].items {
+            for decl in &func.decls {
+                let mut calc = CalcDecos::new([decl.local()]);
+                utils::mir_visit(func, &mut calc);
+                expected.extend(calc.decorations());
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(decos, &expected);
+        assert_eq!(
+            serde_json::to_value(decos).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&slim).unwrap()["owl"]["src/lib.rs"]
+        );
+
+        // files which cannot be read keep their char ranges only
+        let mut decos = workspace_decorations(&parsed);
+        decos
+            .get_mut("owl")
+            .unwrap()
+            .insert("src/gone.rs".to_owned(), expected.clone());
+        let source = "x".repeat(100);
+        let bytes = with_byte_ranges(decos, |path| {
+            if path == "src/lib.rs" {
+                Ok(source.clone())
+            } else {
+                Err(std::io::ErrorKind::NotFound.into())
+            }
+        });
+        assert_eq!(bytes["owl"].len(), 2);
+        let ranges = |path: &str| {
+            bytes["owl"][path]
+                .iter()
+                .map(|deco| serde_json::to_value(deco).unwrap()["range"].clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ranges("src/lib.rs").len(), expected.len());
+        assert!(ranges("src/lib.rs").iter().all(|v| v["bytes"].is_object()));
+        assert!(ranges("src/gone.rs").iter().all(|v| v["bytes"].is_null()));
+        assert_eq!(
+            ranges("src/gone.rs")
+                .iter()
+                .map(|v| serde_json::from_value::<Range>(v.clone()).unwrap())
+                .collect::<Vec<_>>(),
+            ranges("src/lib.rs")
+                .iter()
+                .map(|v| serde_json::from_value::<Range>(v.clone()).unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn position_encoding_negotiation() {
+        use lsp_types::PositionEncodingKind as Kind;
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[Kind::UTF32, Kind::UTF16])),
+            PositionEncoding::Utf32
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[Kind::UTF16, Kind::UTF32])),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[Kind::UTF16, Kind::UTF8])),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&["ascii".to_owned().into()])),
+            PositionEncoding::Utf16
+        );
+
+        let source = "let a\u{1f980} = 1;";
+        let deco = Deco::Lifetime {
+            local: FnLocal::new(1, 1),
+            range: range(6, 7),
+            hover_text: String::new(),
+            overlapped: false,
+        };
+        let Deco::Lifetime { range: utf16, .. } =
+            deco.to_lsp_range(source, PositionEncoding::Utf16)
+        else {
+            unreachable!()
+        };
+        assert_eq!((utf16.start.character, utf16.end.character), (7, 8));
+        let Deco::Lifetime { range: utf32, .. } =
+            deco.to_lsp_range(source, PositionEncoding::Utf32)
+        else {
+            unreachable!()
+        };
+        assert_eq!((utf32.start.character, utf32.end.character), (6, 7));
+    }
+}
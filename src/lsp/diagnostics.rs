@@ -0,0 +1,217 @@
+//! Warnings where a variable must live past its drop, published as LSP
+//! diagnostics when the client enables `rustowl.diagnostics.enable`
+
+use super::decoration::PositionEncoding;
+use crate::{models::*, utils};
+use std::path::Path;
+use tower_lsp::{Client, lsp_types};
+
+/// Name of the diagnostics' source shown by editors
+pub const SOURCE: &str = "rustowl";
+
+/// Whether `settings` enable the diagnostics; `None` if they do not tell.
+///
+/// Both the settings of `workspace/didChangeConfiguration`, holding
+/// `{"rustowl": {"diagnostics": {"enable": true}}}`, and the initialization
+/// options, holding `{"diagnostics": {"enable": true}}`, are understood.
+pub fn enabled_in(settings: &serde_json::Value) -> Option<bool> {
+    let settings = settings.get("rustowl").unwrap_or(settings);
+    settings.get("diagnostics")?.get("enable")?.as_bool()
+}
+
+/// A user variable required to live where it is already dropped
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LifetimeConflict {
+    pub local: FnLocal,
+    pub name: String,
+    pub range: Range,
+}
+
+/// Conflicts of the user variables of `func`; temporaries are left out
+pub fn lifetime_conflicts(func: &Function) -> Vec<LifetimeConflict> {
+    let mut conflicts = Vec::new();
+    for decl in &func.decls {
+        let MirDecl::User { local, name, .. } = decl else {
+            continue;
+        };
+        for range in decl.lifetime_conflicts() {
+            conflicts.push(LifetimeConflict {
+                local: *local,
+                name: name.clone(),
+                range,
+            });
+        }
+    }
+    conflicts
+}
+
+impl LifetimeConflict {
+    /// Warning at the conflict, positioned in `text` by `encoding`
+    pub fn to_diagnostic(&self, text: &str, encoding: PositionEncoding) -> lsp_types::Diagnostic {
+        let position = |idx| {
+            let (line, character) = encoding.index_to_line_col(text, idx);
+            lsp_types::Position { line, character }
+        };
+        lsp_types::Diagnostic {
+            range: lsp_types::Range {
+                start: position(self.range.from()),
+                end: position(self.range.until()),
+            },
+            severity: Some(lsp_types::DiagnosticSeverity::WARNING),
+            source: Some(SOURCE.to_owned()),
+            message: format!(
+                "variable `{}` is required to live here, but it is already dropped",
+                self.name
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+/// Diagnostics of every function in `file`, whose source is `text`
+pub fn file_diagnostics(
+    file: &File,
+    text: &str,
+    encoding: PositionEncoding,
+) -> Vec<lsp_types::Diagnostic> {
+    file.items
+        .iter()
+        .flat_map(lifetime_conflicts)
+        .map(|v| v.to_diagnostic(text, encoding))
+        .collect()
+}
+
+/// Publish the diagnostics of `file` at `path`, replacing those published
+/// before; with `enabled` false, the previous ones are only cleared
+pub async fn publish(
+    client: &Client,
+    path: &str,
+    file: &File,
+    encoding: PositionEncoding,
+    enabled: bool,
+) {
+    let Ok(uri) = lsp_types::Url::from_file_path(path) else {
+        return;
+    };
+    let diagnostics = match utils::read_source(Path::new(path)) {
+        Ok(text) if enabled => file_diagnostics(file, &text, encoding),
+        _ => Vec::new(),
+    };
+    client.publish_diagnostics(uri, diagnostics, None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    #[test]
+    fn settings() {
+        let enable = json!({"rustowl": {"diagnostics": {"enable": true}}});
+        assert_eq!(enabled_in(&enable), Some(true));
+        let options = json!({"diagnostics": {"enable": false}});
+        assert_eq!(enabled_in(&options), Some(false));
+        assert_eq!(enabled_in(&json!({"rustowl": {}})), None);
+        assert_eq!(enabled_in(&json!(null)), None);
+    }
+
+    #[test]
+    fn borrow_outliving_its_referent() {
+        // `s` is dropped at the end of the block while `r` still borrows it
+        let text = "fn main() {\n    let r;\n    {\n        let s = String::new();\n        r = &s;\n    }\n    println!(\"{r}\");\n}\n";
+        let index = |pat: &str| text.find(pat).unwrap() as u32;
+        let s_span = index("s = String");
+        let block_end = index("    }\n") + 5;
+        let print_end = index("println!(\"{r}\");") + 15;
+        let decl = MirDecl::User {
+            local: FnLocal::new(2, 1),
+            name: "s".to_owned(),
+            span: range(s_span, s_span + 1),
+            ty: MirType {
+                name: "String".into(),
+                reference: None,
+            },
+            lives: vec![range(s_span, block_end)],
+            shared_borrow: vec![range(index("&s"), print_end)],
+            mutable_borrow: Vec::new(),
+            drop: true,
+            drop_range: vec![range(s_span, block_end)],
+            must_live_at: vec![range(index("&s"), print_end)],
+            storage_range: vec![range(s_span, block_end)],
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+        };
+        let file = File {
+            items: vec![Function {
+                fn_id: 1,
+                name: "main".to_owned(),
+                span: None,
+                test: false,
+                unavailable: false,
+                basic_blocks: Vec::new(),
+                decls: vec![decl],
+            }],
+            generated: false,
+            std: false,
+        };
+        assert_eq!(
+            lifetime_conflicts(&file.items[0]),
+            vec![LifetimeConflict {
+                local: FnLocal::new(2, 1),
+                name: "s".to_owned(),
+                // excluded ranges are cut with one position beside them
+                range: range(block_end + 1, print_end),
+            }]
+        );
+
+        let diagnostics = file_diagnostics(&file, text, PositionEncoding::Utf16);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(
+            diagnostic.range,
+            lsp_types::Range {
+                start: lsp_types::Position {
+                    line: 6,
+                    character: 0
+                },
+                end: lsp_types::Position {
+                    line: 6,
+                    character: 19
+                },
+            }
+        );
+        assert_eq!(
+            diagnostic.severity,
+            Some(lsp_types::DiagnosticSeverity::WARNING)
+        );
+        assert!(diagnostic.message.contains("`s`"));
+    }
+
+    #[test]
+    fn no_conflict_within_lifetime() {
+        let decl = |drop| MirDecl::Other {
+            local: FnLocal::new(1, 1),
+            ty: MirType {
+                name: "i32".into(),
+                reference: None,
+            },
+            lives: vec![range(0, 10)],
+            shared_borrow: Vec::new(),
+            mutable_borrow: Vec::new(),
+            drop,
+            drop_range: vec![range(0, 20)],
+            must_live_at: vec![range(5, 20)],
+            storage_range: Vec::new(),
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+        };
+        // within the drop range of a value with drop glue
+        assert!(decl(true).lifetime_conflicts().is_empty());
+        // past the lives of a value without
+        assert_eq!(decl(false).lifetime_conflicts(), vec![range(11, 20)]);
+    }
+}
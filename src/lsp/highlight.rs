@@ -0,0 +1,205 @@
+//! Ranges of the selected local, shown on `textDocument/documentHighlight`
+
+use super::decoration::{PositionEncoding, SelectLocal};
+use crate::{models::*, utils};
+use tower_lsp::lsp_types;
+
+/// How a range uses the local; where ranges overlap, the greater kind wins
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum HighlightKind {
+    /// The local is live
+    Live,
+    /// The local is borrowed shared
+    Read,
+    /// The local is borrowed mutably
+    Write,
+}
+
+impl HighlightKind {
+    pub fn to_lsp(self) -> lsp_types::DocumentHighlightKind {
+        match self {
+            Self::Live => lsp_types::DocumentHighlightKind::TEXT,
+            Self::Read => lsp_types::DocumentHighlightKind::READ,
+            Self::Write => lsp_types::DocumentHighlightKind::WRITE,
+        }
+    }
+}
+
+/// Declaration of the local selected at `loc`, the same way as the cursor
+/// decorations select it
+pub fn selected_decl(file: &File, loc: Loc) -> Option<&MirDecl> {
+    file.items.iter().find_map(|func| {
+        let mut select = SelectLocal::new(loc);
+        utils::mir_visit(func, &mut select);
+        let local = select.selected()?;
+        func.decls.iter().find(|decl| decl.local() == local)
+    })
+}
+
+/// Lives and borrows of `decl` as disjoint ranges, ordered by position.
+///
+/// Overlapping ranges are split so that each part keeps the strongest kind
+/// covering it, and adjacent parts of the same kind are joined.
+pub fn highlight_ranges(decl: &MirDecl) -> Vec<(Range, HighlightKind)> {
+    let (shared, mutable) = match decl {
+        MirDecl::User {
+            shared_borrow,
+            mutable_borrow,
+            ..
+        }
+        | MirDecl::Other {
+            shared_borrow,
+            mutable_borrow,
+            ..
+        } => (shared_borrow, mutable_borrow),
+    };
+    let ranges: Vec<_> = decl
+        .lives()
+        .iter()
+        .map(|v| (*v, HighlightKind::Live))
+        .chain(shared.iter().map(|v| (*v, HighlightKind::Read)))
+        .chain(mutable.iter().map(|v| (*v, HighlightKind::Write)))
+        .collect();
+
+    // sweep the bounds in order, counting the ranges of each kind that cover
+    // the part from a bound to the next one
+    let mut bounds: Vec<_> = ranges
+        .iter()
+        .flat_map(|(range, kind)| [(range.from(), *kind, true), (range.until(), *kind, false)])
+        .collect();
+    bounds.sort_by_key(|(loc, _, _)| *loc);
+    let mut covering = [0usize; 3];
+    let mut highlights: Vec<(Range, HighlightKind)> = Vec::new();
+    for (i, (loc, kind, opens)) in bounds.iter().enumerate() {
+        if *opens {
+            covering[*kind as usize] += 1;
+        } else {
+            covering[*kind as usize] -= 1;
+        }
+        // empty until the last bound at `loc`
+        let Some(part) = bounds.get(i + 1).and_then(|next| Range::new(*loc, next.0)) else {
+            continue;
+        };
+        let Some(kind) = [
+            HighlightKind::Write,
+            HighlightKind::Read,
+            HighlightKind::Live,
+        ]
+        .into_iter()
+        .find(|v| covering[*v as usize] > 0) else {
+            continue;
+        };
+        match highlights.last_mut() {
+            Some((last, last_kind)) if *last_kind == kind && last.until() == part.from() => {
+                *last = Range::new(last.from(), part.until()).unwrap();
+            }
+            _ => highlights.push((part, kind)),
+        }
+    }
+    highlights
+}
+
+/// Highlights of `decl`, positioned in `text` by `encoding`
+pub fn document_highlights(
+    decl: &MirDecl,
+    text: &str,
+    encoding: PositionEncoding,
+) -> Vec<lsp_types::DocumentHighlight> {
+    let position = |idx| {
+        let (line, character) = encoding.index_to_line_col(text, idx);
+        lsp_types::Position { line, character }
+    };
+    highlight_ranges(decl)
+        .into_iter()
+        .map(|(range, kind)| lsp_types::DocumentHighlight {
+            range: lsp_types::Range {
+                start: position(range.from()),
+                end: position(range.until()),
+            },
+            kind: Some(kind.to_lsp()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    fn decl(lives: Vec<Range>, shared: Vec<Range>, mutable: Vec<Range>) -> MirDecl {
+        MirDecl::Other {
+            local: FnLocal::new(1, 1),
+            ty: MirType {
+                name: "Vec<u8>".into(),
+                reference: None,
+            },
+            lives,
+            shared_borrow: shared,
+            mutable_borrow: mutable,
+            drop: true,
+            drop_range: Vec::new(),
+            must_live_at: Vec::new(),
+            storage_range: Vec::new(),
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn highlight_kinds() {
+        use HighlightKind::*;
+
+        // borrows split the lives they are in
+        assert_eq!(
+            highlight_ranges(&decl(
+                vec![range(0, 30)],
+                vec![range(5, 10)],
+                vec![range(20, 25)]
+            )),
+            vec![
+                (range(0, 5), Live),
+                (range(5, 10), Read),
+                (range(10, 20), Live),
+                (range(20, 25), Write),
+                (range(25, 30), Live),
+            ]
+        );
+
+        // the mutable borrow wins where it overlaps a shared one
+        assert_eq!(
+            highlight_ranges(&decl(
+                Vec::new(),
+                vec![range(0, 10), range(12, 14)],
+                vec![range(5, 15)]
+            )),
+            vec![(range(0, 5), Read), (range(5, 15), Write)]
+        );
+
+        // equal kinds are joined, gaps are kept
+        assert_eq!(
+            highlight_ranges(&decl(
+                vec![range(0, 4), range(2, 6), range(8, 9)],
+                Vec::new(),
+                Vec::new()
+            )),
+            vec![(range(0, 6), Live), (range(8, 9), Live)]
+        );
+        assert!(highlight_ranges(&decl(Vec::new(), Vec::new(), Vec::new())).is_empty());
+
+        // a range ending inside another of the same kind does not end the kind
+        assert_eq!(
+            highlight_ranges(&decl(
+                vec![range(0, 20)],
+                vec![range(0, 10), range(2, 4), range(10, 12)],
+                Vec::new()
+            )),
+            vec![(range(0, 12), Read), (range(12, 20), Live)]
+        );
+
+        assert_eq!(Write.to_lsp(), lsp_types::DocumentHighlightKind::WRITE);
+        assert_eq!(Read.to_lsp(), lsp_types::DocumentHighlightKind::READ);
+    }
+}
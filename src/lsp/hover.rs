@@ -0,0 +1,107 @@
+//! Summary of a variable's lifetime, shown on `textDocument/hover`
+
+use super::decoration::PositionEncoding;
+use crate::models::*;
+use std::fmt::Write;
+use tower_lsp::lsp_types;
+
+/// The user variable of `file` whose declaration span contains `loc`, with
+/// the function declaring it
+pub fn decl_at(file: &File, loc: Loc) -> Option<(&Function, &MirDecl)> {
+    file.items.iter().find_map(|func| {
+        let decl = func
+            .decls
+            .iter()
+            .find(|decl| matches!(decl, MirDecl::User { span, .. } if span.contains(loc)))?;
+        Some((func, decl))
+    })
+}
+
+/// Markdown summary of where the user variable `decl` of `func` lives, is
+/// borrowed and is dropped; positions are 1-based `line:col` in `text`,
+/// counted by `encoding`
+pub fn summary(
+    func: &Function,
+    decl: &MirDecl,
+    text: &str,
+    encoding: PositionEncoding,
+) -> Option<String> {
+    let MirDecl::User {
+        local,
+        name,
+        ty,
+        lives,
+        shared_borrow,
+        mutable_borrow,
+        drop,
+        drop_range,
+        ..
+    } = decl
+    else {
+        return None;
+    };
+    let position = |loc| {
+        let (line, col) = encoding.index_to_line_col(text, loc);
+        format!("{}:{}", line + 1, col + 1)
+    };
+    let ranges = |ranges: &[Range]| {
+        if ranges.is_empty() {
+            return "nowhere".to_owned();
+        }
+        ranges
+            .iter()
+            .map(|v| format!("{}-{}", position(v.from()), position(v.until())))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut summary = format!("**`{name}`**: `{}`\n\n", ty.name);
+    let _ = writeln!(summary, "- live: {}", ranges(lives));
+    let _ = writeln!(summary, "- shared borrows: {}", ranges(shared_borrow));
+    let _ = writeln!(summary, "- mutable borrows: {}", ranges(mutable_borrow));
+    if *drop {
+        let drops: Vec<_> = func
+            .drop_sequence()
+            .into_iter()
+            .filter(|(dropped, _)| dropped == local)
+            .map(|(_, range)| position(range.from()))
+            .collect();
+        let drops = if drops.is_empty() {
+            "nowhere".to_owned()
+        } else {
+            drops.join(", ")
+        };
+        let _ = writeln!(summary, "- kept alive for its drop: {}", ranges(drop_range));
+        let _ = writeln!(summary, "- dropped at: {drops}");
+    } else {
+        let _ = writeln!(summary, "- dropped: never, `{}` has no drop glue", ty.name);
+    }
+    Some(summary)
+}
+
+/// Hover over the variable declared at `loc`; `None` if there is none
+pub fn hover(
+    file: &File,
+    text: &str,
+    loc: Loc,
+    encoding: PositionEncoding,
+) -> Option<lsp_types::Hover> {
+    let (func, decl) = decl_at(file, loc)?;
+    let MirDecl::User { span, .. } = decl else {
+        return None;
+    };
+    let position = |idx| {
+        let (line, character) = encoding.index_to_line_col(text, idx);
+        lsp_types::Position { line, character }
+    };
+    Some(lsp_types::Hover {
+        contents: lsp_types::HoverContents::Markup(lsp_types::MarkupContent {
+            kind: lsp_types::MarkupKind::Markdown,
+            value: summary(func, decl, text, encoding)?,
+        }),
+        range: Some(lsp_types::Range {
+            start: position(span.from()),
+            end: position(span.until()),
+        }),
+    })
+}
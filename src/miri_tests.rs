@@ -120,7 +120,11 @@ mod miri_memory_safety_tests {
     #[test]
     fn test_file_model_operations() {
         // Test File model with various operations
-        let mut file = File { items: Vec::new() };
+        let mut file = File {
+            items: Vec::new(),
+            generated: false,
+            std: false,
+        };
 
         // Test vector operations
         assert_eq!(file.items.len(), 0);
@@ -138,21 +142,36 @@ mod miri_memory_safety_tests {
     #[test]
     fn test_workspace_operations() {
         // Test Workspace and Crate models
-        let mut workspace = Workspace(HashMap::new());
-        let mut crate1 = Crate(HashMap::new());
-        let mut crate2 = Crate(HashMap::new());
+        let mut workspace = Workspace(OutputMap::default());
+        let mut crate1 = Crate(OutputMap::default());
+        let mut crate2 = Crate(OutputMap::default());
 
         // Add some files to crates
-        crate1
-            .0
-            .insert("lib.rs".to_string(), File { items: Vec::new() });
-        crate1
-            .0
-            .insert("main.rs".to_string(), File { items: Vec::new() });
+        crate1.0.insert(
+            "lib.rs".to_string(),
+            File {
+                items: Vec::new(),
+                generated: false,
+                std: false,
+            },
+        );
+        crate1.0.insert(
+            "main.rs".to_string(),
+            File {
+                items: Vec::new(),
+                generated: false,
+                std: false,
+            },
+        );
 
-        crate2
-            .0
-            .insert("helper.rs".to_string(), File { items: Vec::new() });
+        crate2.0.insert(
+            "helper.rs".to_string(),
+            File {
+                items: Vec::new(),
+                generated: false,
+                std: false,
+            },
+        );
 
         // Add crates to workspace
         workspace.0.insert("crate1".to_string(), crate1);
@@ -163,8 +182,8 @@ mod miri_memory_safety_tests {
         assert!(workspace.0.contains_key("crate2"));
 
         // Test workspace merging
-        let mut other_workspace = Workspace(HashMap::new());
-        let crate3 = Crate(HashMap::new());
+        let mut other_workspace = Workspace(OutputMap::default());
+        let crate3 = Crate(OutputMap::default());
         other_workspace.0.insert("crate3".to_string(), crate3);
 
         workspace.merge(other_workspace);
@@ -221,6 +240,9 @@ mod miri_memory_safety_tests {
         let function = Function {
             fn_id: 42,
             name: String::from("test_function"),
+            span: None,
+            test: false,
+            unavailable: false,
             basic_blocks: Vec::new(),
             decls: Vec::new(),
         };
@@ -244,6 +266,9 @@ mod miri_memory_safety_tests {
             functions.push(Function {
                 fn_id: i,
                 name: format!("function_{i}"),
+                span: None,
+                test: false,
+                unavailable: false,
                 basic_blocks: Vec::new(),
                 decls: Vec::new(),
             });
@@ -256,6 +281,9 @@ mod miri_memory_safety_tests {
         let large_function = Function {
             fn_id: 999,
             name: String::from("large_function"),
+            span: None,
+            test: false,
+            unavailable: false,
             basic_blocks: Vec::with_capacity(1000),
             decls: Vec::with_capacity(500),
         };
@@ -286,7 +314,11 @@ mod miri_memory_safety_tests {
 
         // Test unicode handling
         let unicode_string = "🦀 Rust 🔥 Memory Safety 🛡️".to_string();
-        let _file = File { items: Vec::new() };
+        let _file = File {
+            items: Vec::new(),
+            generated: false,
+            std: false,
+        };
 
         // Ensure unicode doesn't cause memory issues
         assert!(unicode_string.len() > unicode_string.chars().count());
@@ -1,7 +1,52 @@
 #![allow(unused)]
 
+use crate::utils;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, DefaultHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+
+/// Whether `RUSTOWL_DETERMINISTIC_HASH` is set to make output maps
+/// iterate in a reproducible order
+static DETERMINISTIC_HASH: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("RUSTOWL_DETERMINISTIC_HASH")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+});
+
+/// Hasher for maps in the analysis output.
+///
+/// By default this is randomly seeded like [`RandomState`]. When
+/// `RUSTOWL_DETERMINISTIC_HASH=1`, a fixed seed is used so that the iteration
+/// order, and thus the serialized output, is the same across runs.
+#[derive(Clone, Debug)]
+pub enum OutputHashState {
+    Random(RandomState),
+    Fixed,
+}
+
+impl Default for OutputHashState {
+    fn default() -> Self {
+        if *DETERMINISTIC_HASH {
+            Self::Fixed
+        } else {
+            Self::Random(RandomState::new())
+        }
+    }
+}
+
+impl BuildHasher for OutputHashState {
+    type Hasher = DefaultHasher;
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            Self::Random(state) => state.build_hasher(),
+            Self::Fixed => DefaultHasher::new(),
+        }
+    }
+}
+
+pub type OutputMap<K, V> = HashMap<K, V, OutputHashState>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct FnLocal {
@@ -15,9 +60,66 @@ impl FnLocal {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
-#[serde(transparent)]
+/// Whether `RUSTOWL_LOC_AS_STRING` is set to serialize [`Loc`]s as strings
+static LOC_AS_STRING: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("RUSTOWL_LOC_AS_STRING")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false)
+});
+
+/// Character position in a source file.
+///
+/// Serialized as a number, or as a string like `"12345"` when
+/// `RUSTOWL_LOC_AS_STRING=1` for consumers that require it.
+/// Both forms are accepted when deserializing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Loc(pub u32);
+
+fn serialize_loc<S: serde::Serializer>(
+    loc: Loc,
+    as_string: bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if as_string {
+        serializer.collect_str(&loc.0)
+    } else {
+        serializer.serialize_u32(loc.0)
+    }
+}
+
+impl Serialize for Loc {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_loc(*self, *LOC_AS_STRING, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Loc {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct LocVisitor;
+        impl serde::de::Visitor<'_> for LocVisitor {
+            type Value = Loc;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a u32 position as a number or a string")
+            }
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Loc, E> {
+                u32::try_from(v)
+                    .map(Loc)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Unsigned(v), &self))
+            }
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Loc, E> {
+                u32::try_from(v)
+                    .map(Loc)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+            }
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Loc, E> {
+                v.parse()
+                    .map(Loc)
+                    .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+            }
+        }
+        deserializer.deserialize_any(LocVisitor)
+    }
+}
 impl Loc {
     pub fn new(source: &str, byte_pos: u32, offset: u32) -> Self {
         let byte_pos = byte_pos.saturating_sub(offset);
@@ -38,30 +140,47 @@ impl Loc {
             None => Self(source_clean.chars().count() as u32),
         }
     }
+
+    /// Byte offset of this position in `source`; the inverse of
+    /// `Loc::new(source, offset, 0)` for sources without CR.
+    ///
+    /// CRs are skipped when counting chars, like [`Loc::new`] does, but
+    /// counted in the offset, so that it indexes `source` itself.
+    /// Positions past the end map to the length.
+    /// To convert many positions of a source, build a [`utils::ByteIndex`] once.
+    pub fn to_byte_offset(&self, source: &str) -> usize {
+        utils::ByteIndex::new(source).byte_offset(*self) as usize
+    }
 }
 
+/// Saturates at `0` and `u32::MAX`
 impl std::ops::Add<i32> for Loc {
     type Output = Loc;
     fn add(self, rhs: i32) -> Self::Output {
-        if rhs < 0 && (self.0 as i32) < -rhs {
-            Loc(0)
-        } else {
-            Loc(self.0 + rhs as u32)
-        }
+        Loc(self.0.saturating_add_signed(rhs))
     }
 }
 
+/// Saturates at `0` and `u32::MAX`
 impl std::ops::Sub<i32> for Loc {
     type Output = Loc;
     fn sub(self, rhs: i32) -> Self::Output {
-        if 0 < rhs && (self.0 as i32) < rhs {
-            Loc(0)
+        if rhs < 0 {
+            Loc(self.0.saturating_add(rhs.unsigned_abs()))
         } else {
-            Loc(self.0 - rhs as u32)
+            Loc(self.0.saturating_sub(rhs as u32))
         }
     }
 }
 
+/// Signed distance from `rhs` to `self`, negative when `self` comes first
+impl std::ops::Sub<Loc> for Loc {
+    type Output = i64;
+    fn sub(self, rhs: Loc) -> Self::Output {
+        i64::from(self.0) - i64::from(rhs.0)
+    }
+}
+
 impl From<u32> for Loc {
     fn from(value: u32) -> Self {
         Self(value)
@@ -97,8 +216,204 @@ impl Range {
     pub fn size(&self) -> u32 {
         self.until.0 - self.from.0
     }
+
+    /// Whether the range fits in a source of `source_len` chars
+    pub fn within(&self, source_len: Loc) -> bool {
+        self.until <= source_len
+    }
+
+    /// The part of the range that fits in a source of `source_len` chars,
+    /// `None` if the range starts past the end
+    pub fn clamp(&self, source_len: Loc) -> Option<Range> {
+        Range::new(self.from, self.until.min(source_len))
+    }
+
+    /// Extend the range by `left` before and `right` after, saturating at `0` and `u32::MAX`
+    pub fn grow(&self, left: u32, right: u32) -> Range {
+        Range {
+            from: Loc(self.from.0.saturating_sub(left)),
+            until: Loc(self.until.0.saturating_add(right)),
+        }
+    }
+
+    /// Cut `left` from the start and `right` from the end, `None` if nothing is left
+    pub fn shrink(&self, left: u32, right: u32) -> Option<Range> {
+        Range::new(
+            Loc(self.from.0.saturating_add(left)),
+            Loc(self.until.0.saturating_sub(right)),
+        )
+    }
+
+    /// Whether `loc` is in the half-open range `[from, until)`
+    pub fn contains(&self, loc: Loc) -> bool {
+        self.from <= loc && loc < self.until
+    }
+
+    /// Whether the half-open ranges share a position; adjacent ranges do not
+    pub fn overlaps(&self, other: Range) -> bool {
+        self.from < other.until && other.from < self.until
+    }
+
+    /// Get the text covered by this range.
+    ///
+    /// As [`Loc`] ignores CR, CRs inside the range are kept in the returned slice.
+    /// Returns `None` if the range exceeds the source.
+    pub fn text<'a>(&self, source: &'a str) -> Option<&'a str> {
+        let index = utils::ByteIndex::new(source);
+        let start = index.checked_byte_offset(self.from)? as usize;
+        // end at the last char, so that a CR following the range is left out
+        let last = index.checked_byte_offset(self.until - 1)? as usize;
+        let end = last + source[last..].chars().next()?.len_utf8();
+        source.get(start..end)
+    }
+}
+
+/// Set of positions as sorted ranges, where overlapping and adjacent ranges are merged
+///
+/// This keeps the form [`utils::eliminated_ranges`] produces, sorted, on every insertion.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct RangeSet(Vec<Range>);
+
+impl RangeSet {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn insert(&mut self, range: Range) {
+        // ranges ending before `range` starts, without touching it
+        let start = self.0.partition_point(|v| v.until() < range.from());
+        // ranges starting at or before `range` ends, so touching ones are merged
+        let end = self.0.partition_point(|v| v.from() <= range.until());
+        let merged = self.0[start..end].iter().fold(range, |acc, v| Range {
+            from: acc.from().min(v.from()),
+            until: acc.until().max(v.until()),
+        });
+        self.0.splice(start..end, [merged]);
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut union = self.clone();
+        for range in other {
+            union.insert(*range);
+        }
+        union
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut intersection = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.0.len() && j < other.0.len() {
+            let (a, b) = (self.0[i], other.0[j]);
+            if let Some(common) = Range::new(a.from().max(b.from()), a.until().min(b.until())) {
+                intersection.push(common);
+            }
+            if a.until() < b.until() {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        // parts of separate ranges are separate, but may touch each other
+        intersection.into_iter().collect()
+    }
+
+    /// Positions in `self` but not in `other`
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut difference = Vec::new();
+        let mut j = 0;
+        for range in &self.0 {
+            let mut from = range.from();
+            // skip ranges of `other` ending before this one
+            while j < other.0.len() && other.0[j].until() <= from {
+                j += 1;
+            }
+            let mut k = j;
+            while k < other.0.len() && other.0[k].from() < range.until() {
+                if let Some(part) = Range::new(from, other.0[k].from()) {
+                    difference.push(part);
+                }
+                from = from.max(other.0[k].until());
+                k += 1;
+            }
+            if let Some(part) = Range::new(from, range.until()) {
+                difference.push(part);
+            }
+        }
+        Self(difference)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Range> {
+        self.0.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn as_slice(&self) -> &[Range] {
+        &self.0
+    }
+}
+
+impl From<Vec<Range>> for RangeSet {
+    fn from(ranges: Vec<Range>) -> Self {
+        ranges.into_iter().collect()
+    }
+}
+
+impl FromIterator<Range> for RangeSet {
+    fn from_iter<I: IntoIterator<Item = Range>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for range in iter {
+            set.insert(range);
+        }
+        set
+    }
+}
+
+impl From<RangeSet> for Vec<Range> {
+    fn from(set: RangeSet) -> Self {
+        set.0
+    }
+}
+
+impl IntoIterator for RangeSet {
+    type Item = Range;
+    type IntoIter = std::vec::IntoIter<Range>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a RangeSet {
+    type Item = &'a Range;
+    type IntoIter = std::slice::Iter<'a, Range>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
 }
 
+/// Byte offsets of a [`Range`] in the source file, where CRs are counted
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ByteRange {
+    pub from: u32,
+    pub until: u32,
+}
+
+/// [`Range`] along with its [`ByteRange`], for byte-oriented consumers
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DualRange {
+    #[serde(flatten)]
+    pub range: Range,
+    /// `None` if the source could not be read
+    pub bytes: Option<ByteRange>,
+}
+
+/// Coarse summary of a variable: one range where it lives and one where it is dead.
+///
+/// The analyzer emits the richer [`MirDecl`]s instead; [`MirVariables::into_decls`]
+/// converts a summary into them.
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum MirVariable {
@@ -116,7 +431,7 @@ pub enum MirVariable {
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 #[serde(transparent)]
-pub struct MirVariables(HashMap<u32, MirVariable>);
+pub struct MirVariables(OutputMap<u32, MirVariable>);
 
 impl Default for MirVariables {
     fn default() -> Self {
@@ -126,7 +441,7 @@ impl Default for MirVariables {
 
 impl MirVariables {
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self(OutputMap::default())
     }
 
     pub fn push(&mut self, var: MirVariable) {
@@ -147,6 +462,54 @@ impl MirVariables {
     pub fn to_vec(self) -> Vec<MirVariable> {
         self.0.into_values().collect()
     }
+
+    /// Convert into [`MirDecl`]s of the function `fn_id`, ordered by index.
+    ///
+    /// `live` becomes `lives` and `dead` becomes `drop_range`. A variable does not
+    /// carry its name, type or declaration span, so `name` and `ty` are left empty
+    /// and `span` is the `live` range.
+    pub fn into_decls(self, fn_id: u32) -> Vec<MirDecl> {
+        let mut vars = self.to_vec();
+        vars.sort_by_key(|var| match var {
+            MirVariable::User { index, .. } | MirVariable::Other { index, .. } => *index,
+        });
+        let ty = || MirType {
+            name: TypeName::default(),
+            reference: None,
+        };
+        vars.into_iter()
+            .map(|var| match var {
+                MirVariable::User { index, live, dead } => MirDecl::User {
+                    local: FnLocal::new(index, fn_id),
+                    name: String::new(),
+                    span: live,
+                    ty: ty(),
+                    lives: vec![live],
+                    shared_borrow: Vec::new(),
+                    mutable_borrow: Vec::new(),
+                    drop: true,
+                    drop_range: vec![dead],
+                    definitely_live_at: Vec::new(),
+                    maybe_init_at: Vec::new(),
+                    must_live_at: Vec::new(),
+                    storage_range: Vec::new(),
+                },
+                MirVariable::Other { index, live, dead } => MirDecl::Other {
+                    local: FnLocal::new(index, fn_id),
+                    ty: ty(),
+                    lives: vec![live],
+                    shared_borrow: Vec::new(),
+                    mutable_borrow: Vec::new(),
+                    drop: true,
+                    drop_range: vec![dead],
+                    definitely_live_at: Vec::new(),
+                    maybe_init_at: Vec::new(),
+                    must_live_at: Vec::new(),
+                    storage_range: Vec::new(),
+                },
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -158,13 +521,391 @@ pub enum Item {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct File {
     pub items: Vec<Function>,
+    /// Whether the file is generated at build time (e.g. `include!`'d from
+    /// `OUT_DIR`) and therefore cannot be opened as a user source file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub generated: bool,
+    /// Whether the file belongs to `std`, `core` or `alloc`,
+    /// which are only analyzed with `RUSTOWL_INCLUDE_STD`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub std: bool,
+}
+
+impl File {
+    /// Union of every range in the file, from declarations, statements and
+    /// terminators of all functions, as sorted disjoint ranges;
+    /// for a file-level overview of ownership activity
+    pub fn activity_spans(&self) -> Vec<Range> {
+        let mut ranges = Vec::new();
+        for func in &self.items {
+            for decl in &func.decls {
+                if let MirDecl::User { span, .. } = decl {
+                    ranges.push(*span);
+                }
+                ranges.extend(decl.ranges().copied());
+            }
+            for bb in &func.basic_blocks {
+                ranges.extend(bb.statements.iter().filter_map(|stmt| stmt.range));
+                ranges.extend(bb.terminator.range);
+            }
+        }
+        let mut spans = utils::eliminated_ranges(ranges);
+        spans.sort_by_key(|v| v.from());
+        spans
+    }
+}
+
+/// Version of the output format written by `rustowlc`, as `MAJOR.MINOR`.
+///
+/// The minor version is bumped for backward compatible changes, such as adding
+/// optional fields; the major version for incompatible ones.
+pub const OUTPUT_VERSION: &str = "1.3";
+
+/// Output version assumed when the output has no version (before versioning)
+const UNVERSIONED_OUTPUT_VERSION: &str = "1.0";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VersionCompatibility {
+    /// Written by the same or an older compatible version
+    Compatible,
+    /// Written by a newer compatible version; unknown data is ignored
+    Newer,
+    /// Written by an incompatible version
+    Incompatible,
+}
+
+/// Check whether output of `version` can be read by this version
+pub fn output_version_compatibility(version: &str) -> VersionCompatibility {
+    fn parse(version: &str) -> Option<(u32, u32)> {
+        let (major, minor) = version.trim().split_once('.')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    }
+    let (Some(current), Some(other)) = (parse(OUTPUT_VERSION), parse(version)) else {
+        return VersionCompatibility::Incompatible;
+    };
+    if current.0 != other.0 {
+        VersionCompatibility::Incompatible
+    } else if current.1 < other.1 {
+        VersionCompatibility::Newer
+    } else {
+        VersionCompatibility::Compatible
+    }
+}
+
+#[derive(Serialize)]
+struct VersionedOutputRef<'a, W: Serialize> {
+    version: &'a str,
+    workspace: &'a W,
+}
+
+/// Workspace of the single crate `krate` under `key`, serialized as a
+/// [`Workspace`] is without cloning the crate
+struct CrateWorkspaceRef<'a> {
+    key: &'a str,
+    krate: &'a Crate,
+}
+impl Serialize for CrateWorkspaceRef<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(self.key, self.krate)?;
+        map.end()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionedOutput {
+    Versioned {
+        version: String,
+        workspace: Workspace,
+    },
+    Unversioned(Workspace),
+}
+
+/// Aggregate numbers of a [`Workspace`], for tracking code health
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct WorkspaceStats {
+    pub functions: usize,
+    pub user_variables: usize,
+    /// Ranges where shared and mutable borrows of a variable overlap
+    pub borrow_conflicts: usize,
+    /// Ranges where a variable is required to live but is not definitely live
+    pub outlive_violations: usize,
+    /// Average [`Function::live_coverage`] of the functions in each file
+    pub live_coverage: BTreeMap<String, f64>,
+}
+
+/// Separates the crate name from its disambiguator in [`Workspace`] keys
+const CRATE_KEY_SEPARATOR: char = '#';
+
+/// Key of a crate in [`Workspace`].
+///
+/// Crates may share a name, e.g. a dependency and a workspace member, or the
+/// same crate compiled for several targets. `disambiguator` tells them apart so
+/// that their results are not merged together.
+pub fn crate_key(name: &str, disambiguator: &str) -> String {
+    format!("{name}{CRATE_KEY_SEPARATOR}{disambiguator}")
+}
+
+/// Replace characters that are unsafe in file names with `_`
+fn sanitize_file_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "_".to_owned()
+    } else {
+        name
+    }
+}
+
+/// Crate name to show for a [`Workspace`] key
+///
+/// Keys written before disambiguation was introduced are plain crate names.
+pub fn crate_display_name(key: &str) -> &str {
+    key.split_once(CRATE_KEY_SEPARATOR)
+        .map(|(name, _)| name)
+        .unwrap_or(key)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(transparent)]
-pub struct Workspace(pub HashMap<String, Crate>);
+pub struct Workspace(pub OutputMap<String, Crate>);
 
 impl Workspace {
+    /// Empty workspace with room for `crates` crates
+    pub fn with_capacity(crates: usize) -> Self {
+        Self(OutputMap::with_capacity_and_hasher(
+            crates,
+            OutputHashState::default(),
+        ))
+    }
+
+    /// Serialize into JSON with the output version.
+    ///
+    /// Compact output fits in a single line, which is what the analyzer expects
+    /// when reading `rustowlc` output line by line.
+    pub fn to_json(&self, pretty: bool) -> serde_json::Result<String> {
+        let output = VersionedOutputRef {
+            version: OUTPUT_VERSION,
+            workspace: self,
+        };
+        if pretty {
+            serde_json::to_string_pretty(&output)
+        } else {
+            serde_json::to_string(&output)
+        }
+    }
+
+    /// Serialize into JSON without the version envelope, in the shape written
+    /// before the output was versioned, for consumers that do not read it yet
+    pub fn to_unversioned_json(&self, pretty: bool) -> serde_json::Result<String> {
+        if pretty {
+            serde_json::to_string_pretty(self)
+        } else {
+            serde_json::to_string(self)
+        }
+    }
+
+    /// Read a workspace from JSON.
+    ///
+    /// `rustowlc` writes one workspace per line, so all the JSON values in
+    /// the reader are merged. Output without version is read as version 1.0.
+    /// Input without any JSON value is an error.
+    pub fn from_json_reader(reader: impl std::io::Read) -> serde_json::Result<Self> {
+        let mut ws = Workspace(OutputMap::default());
+        let mut empty = true;
+        for value in serde_json::Deserializer::from_reader(reader).into_iter::<VersionedOutput>() {
+            empty = false;
+            let (version, workspace) = match value? {
+                VersionedOutput::Versioned { version, workspace } => (version, workspace),
+                VersionedOutput::Unversioned(workspace) => {
+                    (UNVERSIONED_OUTPUT_VERSION.to_owned(), workspace)
+                }
+            };
+            match output_version_compatibility(&version) {
+                VersionCompatibility::Compatible => {}
+                VersionCompatibility::Newer => {
+                    log::warn!(
+                        "output version {version} is newer than {OUTPUT_VERSION}; some data may be ignored"
+                    );
+                }
+                VersionCompatibility::Incompatible => {
+                    return Err(serde::de::Error::custom(format!(
+                        "incompatible output version {version} (supported: {OUTPUT_VERSION})"
+                    )));
+                }
+            }
+            ws.merge(workspace);
+        }
+        if empty {
+            return Err(serde::de::Error::custom("no output to read"));
+        }
+        Ok(ws)
+    }
+
+    /// Make the equal type names of all the declarations share storage
+    /// through `interner`; see [`TypeInterner`]
+    pub fn intern_types(&mut self, interner: &mut TypeInterner) {
+        for func in self
+            .0
+            .values_mut()
+            .flat_map(|krate| krate.0.values_mut())
+            .flat_map(|file| file.items.iter_mut())
+        {
+            for decl in &mut func.decls {
+                let (MirDecl::User { ty, .. } | MirDecl::Other { ty, .. }) = decl;
+                interner.intern_type(ty);
+            }
+        }
+    }
+
+    /// Check internal consistency of the analysis result
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        for (crate_name, krate) in &self.0 {
+            for (file_name, file) in &krate.0 {
+                let mut fn_ids = std::collections::HashSet::new();
+                for func in &file.items {
+                    let mut error = |message: String| {
+                        errors.push(ValidationError {
+                            crate_name: crate_name.clone(),
+                            file_name: file_name.clone(),
+                            function: Some(func.name.clone()),
+                            message,
+                        })
+                    };
+                    if !fn_ids.insert(func.fn_id) {
+                        error(format!("duplicated function id {}", func.fn_id));
+                    }
+                    func.validate(&mut error);
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn statistics(&self) -> WorkspaceStats {
+        let mut stats = WorkspaceStats::default();
+        for (file_name, file) in self.0.values().flat_map(|krate| &krate.0) {
+            let mut coverages = Vec::new();
+            for func in &file.items {
+                stats.functions += 1;
+                for decl in &func.decls {
+                    if let MirDecl::User { .. } = decl {
+                        stats.user_variables += 1;
+                    }
+                    stats.borrow_conflicts += decl.borrow_conflicts().len();
+                    stats.outlive_violations += decl.outlive_ranges().len();
+                }
+                coverages.extend(func.live_coverage());
+            }
+            if !coverages.is_empty() {
+                stats.live_coverage.insert(
+                    file_name.clone(),
+                    coverages.iter().sum::<f64>() / coverages.len() as f64,
+                );
+            }
+        }
+        stats
+    }
+
+    /// One ownership fact per line as `file:line:col-line:col kind local`, for shell pipelines.
+    ///
+    /// Lines and columns are 1-based. Kinds are those of [`MirDecl::fact_ranges`];
+    /// user variables are named by their name and others by `_<local id>`.
+    /// `read_source` gives the source of a file; files without it are skipped.
+    pub fn fact_lines(&self, read_source: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut files: Vec<_> = self.0.values().flat_map(|krate| &krate.0).collect();
+        files.sort_by_key(|(name, _)| *name);
+        let mut lines = Vec::new();
+        for (file_name, file) in files {
+            let Some(source) = read_source(file_name) else {
+                log::warn!("failed to read {file_name}; skip its facts");
+                continue;
+            };
+            let index = utils::LineIndex::new(&source);
+            let position = |loc| {
+                let (line, col) = index.line_char(loc);
+                format!("{}:{}", line + 1, col + 1)
+            };
+            for decl in file.items.iter().flat_map(|func| &func.decls) {
+                let local = match decl {
+                    MirDecl::User { name, .. } => name.clone(),
+                    MirDecl::Other { local, .. } => format!("_{}", local.id),
+                };
+                for (kind, ranges) in decl.fact_ranges() {
+                    for range in ranges {
+                        lines.push(format!(
+                            "{file_name}:{}-{} {kind} {local}",
+                            position(range.from()),
+                            position(range.until()),
+                        ));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// Serialize each crate into a workspace of its own, like
+    /// [`Workspace::to_json`] in compact form, sorted by crate key.
+    ///
+    /// Crates are independent of each other, so with `parallel` they are
+    /// serialized on the rayon thread pool instead of one by one.
+    pub fn to_crate_json(&self, parallel: bool) -> serde_json::Result<Vec<(String, String)>> {
+        use rayon::prelude::*;
+
+        let mut keys: Vec<_> = self.0.keys().collect();
+        keys.sort();
+        let serialize = |key: &&String| {
+            let output = VersionedOutputRef {
+                version: OUTPUT_VERSION,
+                workspace: &CrateWorkspaceRef {
+                    key,
+                    krate: &self.0[*key],
+                },
+            };
+            serde_json::to_string(&output).map(|json| ((*key).clone(), json))
+        };
+        if parallel {
+            keys.par_iter().map(serialize).collect()
+        } else {
+            keys.iter().map(serialize).collect()
+        }
+    }
+
+    /// Write each crate into `<dir>/<crate name>.json`, creating `dir`.
+    ///
+    /// Each file holds a workspace of that crate only, so it can be read back
+    /// with [`Workspace::from_json_reader`]. Crate names are sanitized into
+    /// file names; same-named crates are told apart by their disambiguator.
+    /// Returns the written paths.
+    pub fn write_crate_files(&self, dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(dir)?;
+        let mut name_counts = HashMap::new();
+        for key in self.0.keys() {
+            *name_counts.entry(crate_display_name(key)).or_insert(0) += 1;
+        }
+        let mut written = Vec::new();
+        for (key, json) in self.to_crate_json(true)? {
+            let name = crate_display_name(&key);
+            let stem = if name_counts[name] == 1 { name } else { &key };
+            let path = dir.join(format!("{}.json", sanitize_file_name(stem)));
+            std::fs::write(&path, json)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
     pub fn merge(&mut self, other: Self) {
         let Workspace(crates) = other;
         for (name, krate) in crates {
@@ -179,15 +920,42 @@ impl Workspace {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(transparent)]
-pub struct Crate(pub HashMap<String, File>);
+pub struct Crate(pub OutputMap<String, File>);
 
 impl Crate {
     pub fn merge(&mut self, other: Self) {
+        self.merge_with(other, false);
+    }
+
+    /// Merge `other` into `self`.
+    ///
+    /// Functions sharing a `fn_id` are replaced by the incoming ones if
+    /// `prefer_incoming` is set, e.g. by freshly re-analyzed functions.
+    /// Otherwise the richer one is kept.
+    pub fn merge_with(&mut self, other: Self, prefer_incoming: bool) {
         let Crate(files) = other;
         for (file, mir) in files {
             if let Some(insert) = self.0.get_mut(&file) {
-                insert.items.extend_from_slice(&mir.items);
-                insert.items.dedup_by(|a, b| a.fn_id == b.fn_id);
+                // on conflicts, keep the richer result, e.g. a fresh one over a coarse cache
+                let mut index: HashMap<_, _> = insert
+                    .items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, func)| (func.fn_id, i))
+                    .collect();
+                for func in mir.items {
+                    match index.get(&func.fn_id) {
+                        Some(&i) => {
+                            if prefer_incoming || insert.items[i].richness() < func.richness() {
+                                insert.items[i] = func;
+                            }
+                        }
+                        None => {
+                            index.insert(func.fn_id, insert.items.len());
+                            insert.items.push(func);
+                        }
+                    }
+                }
             } else {
                 self.0.insert(file, mir);
             }
@@ -219,16 +987,61 @@ pub enum MirOperand {
     Other,
 }
 
+/// Why a borrow is taken, as far as the source shows it
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BorrowReason {
+    /// An explicit `&` or `&mut`, e.g. `let r = &x;`
+    Explicit,
+    /// Passed as an argument, explicitly (`f(&x)`) or by an implicit reborrow
+    Argument,
+    /// The receiver of a method taking `&self` or `&mut self`, e.g. `v.push(1)`
+    MethodCall,
+}
+
+impl BorrowReason {
+    /// How the borrow came about, completing "mutable borrow ..."
+    pub fn describe(&self, mutable: bool) -> &'static str {
+        match (self, mutable) {
+            (Self::Explicit, false) => "by `&`",
+            (Self::Explicit, true) => "by `&mut`",
+            (Self::Argument, _) => "to pass an argument",
+            (Self::MethodCall, false) => "by a method call on `&self`",
+            (Self::MethodCall, true) => "by a method call on `&mut self`",
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case", tag = "type")]
 pub enum MirRval {
-    Use { operand: MirOperand },
-    Repeat { operand: MirOperand },
-    Ref { place: MirPlace, mutable: bool },
-    Cast { operand: MirOperand },
-    BinaryOp { left: MirOperand, right: MirOperand },
-    UnaryOp { operand: MirOperand },
-    Aggregate { fields: Vec<MirOperand> },
+    Use {
+        operand: MirOperand,
+    },
+    Repeat {
+        operand: MirOperand,
+    },
+    Ref {
+        place: MirPlace,
+        mutable: bool,
+        /// Absent for borrows the source does not explain, such as reborrows
+        /// and `ref` bindings, and in output of earlier versions
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<BorrowReason>,
+    },
+    Cast {
+        operand: MirOperand,
+    },
+    BinaryOp {
+        left: MirOperand,
+        right: MirOperand,
+    },
+    UnaryOp {
+        operand: MirOperand,
+    },
+    Aggregate {
+        fields: Vec<MirOperand>,
+    },
     // TODO: ThreadLocalRef, RawPtr, Discriminant, CopyForDeref, WrapUnsafeBinder, Reborrow
     Other,
 }
@@ -319,6 +1132,9 @@ pub struct BasicBlockId(pub usize);
 pub struct MirBasicBlock {
     pub statements: Vec<MirStatement>,
     pub terminator: MirTerminator,
+    /// Run only while unwinding from a panic
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_cleanup: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
@@ -329,49 +1145,2081 @@ pub struct MirRefType {
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct MirType {
-    pub name: String,
+    pub name: TypeName,
     pub reference: Option<Box<MirRefType>>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum MirDecl {
-    User {
-        local: FnLocal,
-        name: String,
-        span: Range,
-        ty: MirType,
-        lives: Vec<Range>,
-        shared_borrow: Vec<Range>,
-        mutable_borrow: Vec<Range>,
-        drop: bool,
-        drop_range: Vec<Range>,
-        definitely_live_at: Vec<Range>,
-        maybe_init_at: Vec<Range>,
-        must_live_at: Vec<Range>,
-        /// Range from StorageLive to StorageDead for this variable
-        storage_range: Vec<Range>,
-    },
-    Other {
-        local: FnLocal,
-        ty: MirType,
-        lives: Vec<Range>,
-        shared_borrow: Vec<Range>,
-        mutable_borrow: Vec<Range>,
-        drop: bool,
-        drop_range: Vec<Range>,
-        definitely_live_at: Vec<Range>,
-        maybe_init_at: Vec<Range>,
-        must_live_at: Vec<Range>,
-        /// Range from StorageLive to StorageDead for this variable
-        storage_range: Vec<Range>,
-    },
+/// Name of a type, cheap to clone; equal names interned by a [`TypeInterner`]
+/// share one allocation.
+///
+/// Serialized as a plain string.
+#[derive(Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Default)]
+pub struct TypeName(Arc<str>);
+
+impl TypeName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether `self` and `other` share their storage
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct Function {
-    pub fn_id: u32,
-    pub name: String,
-    pub basic_blocks: Vec<MirBasicBlock>,
-    pub decls: Vec<MirDecl>,
+impl std::ops::Deref for TypeName {
+    type Target = str;
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<str> for TypeName {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for TypeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for TypeName {
+    fn from(name: &str) -> Self {
+        Self(name.into())
+    }
+}
+
+impl From<String> for TypeName {
+    fn from(name: String) -> Self {
+        Self(name.into())
+    }
+}
+
+impl PartialEq<str> for TypeName {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for TypeName {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl Serialize for TypeName {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for TypeName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// Deduplication of type names within a run.
+///
+/// The same types, e.g. `std::string::String` or long generic instantiations,
+/// appear in thousands of declarations across a workspace; interned, each
+/// distinct name is allocated once.
+#[derive(Default, Debug)]
+pub struct TypeInterner(indexmap::IndexSet<TypeName>);
+
+impl TypeInterner {
+    /// Handle of `name`, shared with every earlier handle of an equal name
+    pub fn intern(&mut self, name: &str) -> TypeName {
+        if let Some(interned) = self.0.get(name) {
+            return interned.clone();
+        }
+        let interned = TypeName::from(name);
+        self.0.insert(interned.clone());
+        interned
+    }
+
+    /// Replace the names of `ty` and the types it refers to by interned ones
+    pub fn intern_type(&mut self, ty: &mut MirType) {
+        ty.name = self.intern(&ty.name);
+        if let Some(reference) = &mut ty.reference {
+            self.intern_type(&mut reference.refer_to);
+        }
+    }
+
+    /// Number of distinct names
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Marker ending a type name cut by [`MirType::truncate`]
+pub const TYPE_ELLIPSIS: char = '…';
+
+impl MirType {
+    /// Cut names longer than `max_len` chars to their head followed by
+    /// [`TYPE_ELLIPSIS`], `max_len` chars in total, as recursive types and large
+    /// generic instantiations can be thousands of chars long.
+    /// The referenced types are cut too; returns whether any name is cut.
+    pub fn truncate(&mut self, max_len: usize) -> bool {
+        let mut cut = false;
+        if self.name.char_indices().nth(max_len).is_some() {
+            let head = self
+                .name
+                .char_indices()
+                .nth(max_len.saturating_sub(1))
+                .map_or(self.name.len(), |(i, _)| i);
+            let mut name = self.name[..head].to_owned();
+            name.push(TYPE_ELLIPSIS);
+            self.name = name.into();
+            cut = true;
+        }
+        if let Some(reference) = &mut self.reference {
+            cut |= reference.refer_to.truncate(max_len);
+        }
+        cut
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MirDecl {
+    User {
+        local: FnLocal,
+        name: String,
+        span: Range,
+        ty: MirType,
+        lives: Vec<Range>,
+        shared_borrow: Vec<Range>,
+        mutable_borrow: Vec<Range>,
+        drop: bool,
+        drop_range: Vec<Range>,
+        definitely_live_at: Vec<Range>,
+        maybe_init_at: Vec<Range>,
+        must_live_at: Vec<Range>,
+        /// Range from StorageLive to StorageDead for this variable
+        storage_range: Vec<Range>,
+    },
+    Other {
+        local: FnLocal,
+        ty: MirType,
+        lives: Vec<Range>,
+        shared_borrow: Vec<Range>,
+        mutable_borrow: Vec<Range>,
+        drop: bool,
+        drop_range: Vec<Range>,
+        definitely_live_at: Vec<Range>,
+        maybe_init_at: Vec<Range>,
+        must_live_at: Vec<Range>,
+        /// Range from StorageLive to StorageDead for this variable
+        storage_range: Vec<Range>,
+    },
+}
+
+impl MirDecl {
+    pub fn local(&self) -> FnLocal {
+        match self {
+            MirDecl::User { local, .. } | MirDecl::Other { local, .. } => *local,
+        }
+    }
+    pub fn lives(&self) -> &[Range] {
+        match self {
+            MirDecl::User { lives, .. } | MirDecl::Other { lives, .. } => lives,
+        }
+    }
+    pub fn ty_mut(&mut self) -> &mut MirType {
+        match self {
+            MirDecl::User { ty, .. } | MirDecl::Other { ty, .. } => ty,
+        }
+    }
+
+    fn range_vecs_mut(&mut self) -> [&mut Vec<Range>; 8] {
+        match self {
+            MirDecl::User {
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+                ..
+            }
+            | MirDecl::Other {
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+                ..
+            } => [
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+            ],
+        }
+    }
+
+    fn range_vecs(&self) -> [&Vec<Range>; 8] {
+        match self {
+            MirDecl::User {
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+                ..
+            }
+            | MirDecl::Other {
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+                ..
+            } => [
+                lives,
+                shared_borrow,
+                mutable_borrow,
+                drop_range,
+                definitely_live_at,
+                maybe_init_at,
+                must_live_at,
+                storage_range,
+            ],
+        }
+    }
+
+    /// Ranges of the kinds listed by [`Workspace::fact_lines`], with their kind names
+    pub fn fact_ranges(&self) -> [(&'static str, &[Range]); 5] {
+        let [
+            lives,
+            shared_borrow,
+            mutable_borrow,
+            drop_range,
+            _,
+            _,
+            must_live_at,
+            _,
+        ] = self.range_vecs();
+        [
+            ("lives", lives),
+            ("shared_borrow", shared_borrow),
+            ("mutable_borrow", mutable_borrow),
+            ("drop", drop_range),
+            ("must_live_at", must_live_at),
+        ]
+    }
+
+    /// Ranges of all kinds
+    pub fn ranges(&self) -> impl Iterator<Item = &Range> {
+        self.range_vecs().into_iter().flatten()
+    }
+
+    /// Number of ranges of all kinds
+    pub fn range_count(&self) -> usize {
+        self.range_vecs().iter().map(|v| v.len()).sum()
+    }
+
+    /// Where shared and mutable borrows of the variable overlap
+    pub fn borrow_conflicts(&self) -> Vec<Range> {
+        let (shared_borrow, mutable_borrow) = match self {
+            MirDecl::User {
+                shared_borrow,
+                mutable_borrow,
+                ..
+            }
+            | MirDecl::Other {
+                shared_borrow,
+                mutable_borrow,
+                ..
+            } => (shared_borrow, mutable_borrow),
+        };
+        let mut borrows = shared_borrow.clone();
+        borrows.extend_from_slice(mutable_borrow);
+        utils::common_ranges(&borrows)
+    }
+
+    /// Where the variable is required to live but is not definitely live
+    pub fn outlive_ranges(&self) -> Vec<Range> {
+        let (must_live_at, definitely_live_at) = match self {
+            MirDecl::User {
+                must_live_at,
+                definitely_live_at,
+                ..
+            }
+            | MirDecl::Other {
+                must_live_at,
+                definitely_live_at,
+                ..
+            } => (must_live_at, definitely_live_at),
+        };
+        utils::exclude_ranges(must_live_at.clone(), definitely_live_at.clone())
+    }
+
+    /// Where the variable is required to live after it is gone: `must_live_at`
+    /// outside of its drop range, or of its lives if it has no drop glue
+    pub fn lifetime_conflicts(&self) -> Vec<Range> {
+        let (must_live_at, available) = match self {
+            MirDecl::User {
+                must_live_at,
+                drop,
+                drop_range,
+                lives,
+                ..
+            }
+            | MirDecl::Other {
+                must_live_at,
+                drop,
+                drop_range,
+                lives,
+                ..
+            } => (must_live_at, if *drop { drop_range } else { lives }),
+        };
+        utils::exclude_ranges(must_live_at.clone(), available.clone())
+    }
+
+    /// The earliest position where the variable is live
+    pub fn first_use(&self) -> Option<Loc> {
+        self.lives().iter().map(|v| v.from()).min()
+    }
+    /// The latest position where the variable is live
+    pub fn last_use(&self) -> Option<Loc> {
+        self.lives().iter().map(|v| v.until()).max()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Function {
+    pub fn_id: u32,
+    pub name: String,
+    /// Range of the whole definition, including its signature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Range>,
+    /// A `#[test]` function or a closure in it, only analyzed with `--tests`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub test: bool,
+    /// The borrow checker produced no output for it, e.g. for some intrinsics;
+    /// `basic_blocks` and `decls` are empty as there is nothing to show
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unavailable: bool,
+    pub basic_blocks: Vec<MirBasicBlock>,
+    pub decls: Vec<MirDecl>,
+}
+
+/// An inconsistency found by [`Workspace::validate`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ValidationError {
+    pub crate_name: String,
+    pub file_name: String,
+    pub function: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.crate_name, self.file_name)?;
+        if let Some(function) = &self.function {
+            write!(f, ": {function}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// First and last use of a user variable
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct VariableUses {
+    pub local: FnLocal,
+    pub name: String,
+    pub first_use: Option<Loc>,
+    pub last_use: Option<Loc>,
+}
+
+/// A function call and the local initialized by its result
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CallInfo {
+    pub destination: FnLocal,
+    pub span: Range,
+}
+
+impl Function {
+    fn validate(&self, error: &mut impl FnMut(String)) {
+        let check_range = |error: &mut dyn FnMut(String), what: &str, range: &Range| {
+            if range.until() <= range.from() {
+                error(format!(
+                    "empty or inverted {what} range {}..{}",
+                    range.from().0,
+                    range.until().0
+                ));
+            }
+        };
+
+        let mut locals = std::collections::HashSet::new();
+        for decl in &self.decls {
+            let local = decl.local();
+            if local.fn_id != self.fn_id {
+                error(format!(
+                    "local {} belongs to function id {}, expected {}",
+                    local.id, local.fn_id, self.fn_id
+                ));
+            }
+            if !locals.insert(local.id) {
+                error(format!("duplicated local {}", local.id));
+            }
+            for range in decl.lives() {
+                check_range(error, "live", range);
+            }
+            if let MirDecl::User { span, .. } = decl {
+                check_range(error, "declaration", span);
+            }
+        }
+
+        let block_count = self.basic_blocks.len();
+        for (index, bb) in self.basic_blocks.iter().enumerate() {
+            for stmt in &bb.statements {
+                if let Some(range) = &stmt.range {
+                    check_range(error, "statement", range);
+                }
+            }
+            if let Some(range) = &bb.terminator.range {
+                check_range(error, "terminator", range);
+            }
+            for successor in bb.terminator.successors() {
+                if block_count <= successor.0 {
+                    error(format!(
+                        "block {index} jumps to nonexistent block {}",
+                        successor.0
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Collect function calls with the local that receives the result
+    pub fn calls(&self) -> Vec<CallInfo> {
+        self.basic_blocks
+            .iter()
+            .filter_map(|bb| match &bb.terminator.kind {
+                MirTerminatorKind::Call {
+                    destination,
+                    fn_range: Some(span),
+                    ..
+                } => Some(CallInfo {
+                    destination: destination.local,
+                    span: *span,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Dropped locals in the order of their drop positions in the source.
+    /// Drops at the same position, such as at the end of a scope, keep
+    /// the order of their basic blocks, which is the order they run in.
+    /// Drops of cleanup blocks, which repeat them for unwinding, are left out.
+    pub fn drop_sequence(&self) -> Vec<(FnLocal, Range)> {
+        let mut drops: Vec<_> = self
+            .basic_blocks
+            .iter()
+            .filter(|bb| !bb.is_cleanup)
+            .filter_map(|bb| match (&bb.terminator.kind, bb.terminator.range) {
+                (MirTerminatorKind::Drop { place, .. }, Some(range)) => Some((place.local, range)),
+                _ => None,
+            })
+            .collect();
+        drops.sort_by_key(|(_, range)| range.from());
+        drops
+    }
+
+    /// Range covered by the function: its definition span if known,
+    /// otherwise the envelope of its statements and terminators
+    pub fn extent(&self) -> Option<Range> {
+        if self.span.is_some() {
+            return self.span;
+        }
+        let ranges = self.basic_blocks.iter().flat_map(|bb| {
+            bb.statements
+                .iter()
+                .filter_map(|stmt| stmt.range)
+                .chain(bb.terminator.range)
+        });
+        let from = ranges.clone().map(|v| v.from()).min()?;
+        let until = ranges.map(|v| v.until()).max()?;
+        Range::new(from, until)
+    }
+
+    /// Fraction of [`Function::extent`] where some user variable is live
+    pub fn live_coverage(&self) -> Option<f64> {
+        let extent = self.extent()?;
+        let lives = self
+            .decls
+            .iter()
+            .filter(|decl| matches!(decl, MirDecl::User { .. }))
+            .flat_map(|decl| decl.lives().iter().copied())
+            .collect();
+        let covered: u32 = utils::intersect_ranges(utils::eliminated_ranges(lives), vec![extent])
+            .iter()
+            .map(|v| v.size())
+            .sum();
+        Some(covered as f64 / extent.size() as f64)
+    }
+
+    /// How much analysis data the function has, as (declarations, ranges);
+    /// used to pick one of two results of the same function
+    pub fn richness(&self) -> (usize, usize) {
+        let ranges = self.decls.iter().map(|decl| decl.range_count()).sum();
+        (self.decls.len(), ranges)
+    }
+
+    /// Clamp ranges exceeding a source of `source_len` chars, and drop the
+    /// ones starting past its end. Returns how many ranges were out of bounds.
+    ///
+    /// Declaration spans cannot be dropped, so they are kept if out of bounds.
+    pub fn clamp_ranges(&mut self, source_len: Loc) -> usize {
+        let mut fixed = 0;
+        let mut clamp_option = |range: &mut Option<Range>| {
+            if let Some(r) = *range
+                && !r.within(source_len)
+            {
+                fixed += 1;
+                *range = r.clamp(source_len);
+            }
+        };
+        clamp_option(&mut self.span);
+        for bb in &mut self.basic_blocks {
+            for stmt in &mut bb.statements {
+                clamp_option(&mut stmt.range);
+            }
+            clamp_option(&mut bb.terminator.range);
+            if let MirTerminatorKind::Call { fn_range, .. } = &mut bb.terminator.kind {
+                clamp_option(fn_range);
+            }
+        }
+        for decl in &mut self.decls {
+            if let MirDecl::User { span, .. } = decl
+                && !span.within(source_len)
+            {
+                fixed += 1;
+                if let Some(clamped) = span.clamp(source_len) {
+                    *span = clamped;
+                }
+            }
+            for ranges in decl.range_vecs_mut() {
+                ranges.retain_mut(|r| {
+                    if r.within(source_len) {
+                        return true;
+                    }
+                    fixed += 1;
+                    match r.clamp(source_len) {
+                        Some(v) => {
+                            *r = v;
+                            true
+                        }
+                        None => false,
+                    }
+                });
+            }
+        }
+        fixed
+    }
+
+    /// Whether the function has neither declarations nor statements,
+    /// such as a trivial function or an analysis artifact
+    pub fn is_empty(&self) -> bool {
+        self.decls.is_empty() && self.basic_blocks.iter().all(|bb| bb.statements.is_empty())
+    }
+
+    /// Edges of the control-flow graph as `(from, to)` basic block indices
+    pub fn cfg_edges(&self) -> Vec<(usize, usize)> {
+        self.basic_blocks
+            .iter()
+            .enumerate()
+            .flat_map(|(from, bb)| {
+                bb.terminator
+                    .successors()
+                    .into_iter()
+                    .map(move |to| (from, to.0))
+            })
+            .collect()
+    }
+
+    /// Collect first and last use positions of each user variable
+    pub fn variable_uses(&self) -> Vec<VariableUses> {
+        self.decls
+            .iter()
+            .filter_map(|decl| match decl {
+                MirDecl::User { local, name, .. } => Some(VariableUses {
+                    local: *local,
+                    name: name.clone(),
+                    first_use: decl.first_use(),
+                    last_use: decl.last_use(),
+                }),
+                MirDecl::Other { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// Replaces identifiers in the analysis output with placeholders, so that
+/// the structure (ranges, categories and `fn_id`s) can be shared without
+/// leaking the source.
+///
+/// Variable names become `var1`, `var2`, ..., types `Type1`, `Type2`, ...,
+/// function names `fn1`, `fn2`, ..., crate names `crate1`, `crate2`, ...
+/// and file paths `file1.rs`, `file2.rs`, ..., keeping their extension.
+/// The disambiguator of a crate key is kept. The same identifier always gets
+/// the same placeholder across all workspaces passed to one `Redactor`.
+#[derive(Default, Clone, Debug)]
+pub struct Redactor {
+    names: HashMap<String, String>,
+    types: HashMap<String, String>,
+    functions: HashMap<String, String>,
+    crates: HashMap<String, String>,
+    files: HashMap<String, String>,
+}
+
+impl Redactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn placeholder(map: &mut HashMap<String, String>, prefix: &str, original: &str) -> String {
+        let next = map.len() + 1;
+        map.entry(original.to_owned())
+            .or_insert_with(|| format!("{prefix}{next}"))
+            .clone()
+    }
+
+    fn redact_type(&mut self, ty: &mut MirType) {
+        ty.name = Self::placeholder(&mut self.types, "Type", &ty.name).into();
+        if let Some(reference) = &mut ty.reference {
+            self.redact_type(&mut reference.refer_to);
+        }
+    }
+
+    fn redact_crate_key(&mut self, key: &str) -> String {
+        match key.split_once(CRATE_KEY_SEPARATOR) {
+            Some((name, disambiguator)) => crate_key(
+                &Self::placeholder(&mut self.crates, "crate", name),
+                disambiguator,
+            ),
+            None => Self::placeholder(&mut self.crates, "crate", key),
+        }
+    }
+
+    fn redact_path(&mut self, path: &str) -> String {
+        let placeholder = Self::placeholder(&mut self.files, "file", path);
+        match Path::new(path).extension() {
+            Some(ext) => format!("{placeholder}.{}", ext.to_string_lossy()),
+            None => placeholder,
+        }
+    }
+
+    pub fn redact(&mut self, ws: Workspace) -> Workspace {
+        let mut ws = Workspace(
+            ws.0.into_iter()
+                .map(|(key, krate)| {
+                    let files = krate
+                        .0
+                        .into_iter()
+                        .map(|(path, file)| (self.redact_path(&path), file))
+                        .collect();
+                    (self.redact_crate_key(&key), Crate(files))
+                })
+                .collect(),
+        );
+        for func in
+            ws.0.values_mut()
+                .flat_map(|krate| krate.0.values_mut())
+                .flat_map(|file| file.items.iter_mut())
+        {
+            func.name = Self::placeholder(&mut self.functions, "fn", &func.name);
+            for decl in &mut func.decls {
+                match decl {
+                    MirDecl::User { name, ty, .. } => {
+                        *name = Self::placeholder(&mut self.names, "var", name);
+                        self.redact_type(ty);
+                    }
+                    MirDecl::Other { ty, .. } => self.redact_type(ty),
+                }
+            }
+        }
+        ws
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    fn user_decl(id: u32, name: &str, lives: Vec<Range>) -> MirDecl {
+        MirDecl::User {
+            local: FnLocal::new(id, 1),
+            name: name.to_owned(),
+            span: range(0, 1),
+            ty: MirType {
+                name: "i32".into(),
+                reference: None,
+            },
+            lives,
+            shared_borrow: Vec::new(),
+            mutable_borrow: Vec::new(),
+            drop: false,
+            drop_range: Vec::new(),
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+            must_live_at: Vec::new(),
+            storage_range: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn mir_variables_into_decls() {
+        let mut vars = MirVariables::new();
+        vars.push(MirVariable::Other {
+            index: 2,
+            live: range(5, 15),
+            dead: range(15, 25),
+        });
+        vars.push(MirVariable::User {
+            index: 1,
+            live: range(0, 10),
+            dead: range(10, 20),
+        });
+        // the first one pushed wins
+        vars.push(MirVariable::User {
+            index: 1,
+            live: range(30, 40),
+            dead: range(40, 50),
+        });
+
+        let decls = vars.into_decls(7);
+        assert_eq!(decls.len(), 2);
+        match &decls[0] {
+            MirDecl::User {
+                local,
+                span,
+                lives,
+                drop,
+                drop_range,
+                ..
+            } => {
+                assert_eq!(*local, FnLocal::new(1, 7));
+                assert_eq!(*span, range(0, 10));
+                assert_eq!(lives, &[range(0, 10)]);
+                assert!(*drop);
+                assert_eq!(drop_range, &[range(10, 20)]);
+            }
+            other => panic!("expected a user variable, got {other:?}"),
+        }
+        match &decls[1] {
+            MirDecl::Other {
+                local,
+                lives,
+                drop_range,
+                ..
+            } => {
+                assert_eq!(*local, FnLocal::new(2, 7));
+                assert_eq!(lives, &[range(5, 15)]);
+                assert_eq!(drop_range, &[range(15, 25)]);
+            }
+            other => panic!("expected an other variable, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn fact_lines() {
+        let source = "fn f() {\r\n    let v = vec![1];\r\n    let r = &v;\r\n    drop(v);\r\n}\r\n";
+        let mut v = user_decl(1, "v", vec![range(17, 56)]);
+        if let MirDecl::User {
+            shared_borrow,
+            drop_range,
+            ..
+        } = &mut v
+        {
+            shared_borrow.push(range(42, 44));
+            drop_range.push(range(55, 56));
+        }
+        let mut r = user_decl(2, "r", vec![range(38, 44)]);
+        if let MirDecl::User { must_live_at, .. } = &mut r {
+            must_live_at.push(range(42, 44));
+        }
+        let tmp = MirDecl::Other {
+            local: FnLocal::new(3, 1),
+            ty: MirType {
+                name: "Vec<i32>".into(),
+                reference: None,
+            },
+            lives: vec![range(21, 28)],
+            shared_borrow: Vec::new(),
+            mutable_borrow: vec![range(21, 28)],
+            drop: false,
+            drop_range: Vec::new(),
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+            must_live_at: Vec::new(),
+            storage_range: Vec::new(),
+        };
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![v, r, tmp],
+        };
+        let file = |items| File {
+            items,
+            generated: false,
+            std: false,
+        };
+        let ws = Workspace(OutputMap::from_iter([(
+            crate_key("owl", "0"),
+            Crate(OutputMap::from_iter([
+                ("src/lib.rs".to_owned(), file(vec![func])),
+                ("src/missing.rs".to_owned(), file(Vec::new())),
+            ])),
+        )]));
+        let lines = ws.fact_lines(|name| (name == "src/lib.rs").then(|| source.to_owned()));
+        insta::assert_snapshot!(lines.join("\n"));
+    }
+
+    #[test]
+    fn range_set_matches_eliminated_ranges() {
+        // xorshift, to cover many shapes of overlapping and adjacent ranges
+        let mut state = 0x2545_f491_u32;
+        let mut next = |bound: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % bound
+        };
+        for _ in 0..500 {
+            let len = next(12) as usize;
+            let ranges: Vec<_> = (0..len)
+                .map(|_| {
+                    let from = next(40);
+                    range(from, from + 1 + next(8))
+                })
+                .collect();
+            let mut eliminated = utils::eliminated_ranges(ranges.clone());
+            eliminated.sort_by_key(|v| v.from());
+            let set = RangeSet::from(ranges.clone());
+            assert_eq!(set.iter().copied().collect::<Vec<_>>(), eliminated);
+
+            // set operations agree with the existing helpers on covered positions
+            let other = RangeSet::from(
+                (0..next(6))
+                    .map(|_| {
+                        let from = next(40);
+                        range(from, from + 1 + next(8))
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            let covered = |set: &RangeSet| -> Vec<u32> {
+                (0..60)
+                    .filter(|i| set.iter().any(|v| v.contains(Loc(*i))))
+                    .collect()
+            };
+            let (a, b) = (covered(&set), covered(&other));
+            assert_eq!(
+                covered(&set.union(&other)),
+                (0..60)
+                    .filter(|i| a.contains(i) || b.contains(i))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                covered(&set.intersection(&other)),
+                a.iter()
+                    .filter(|i| b.contains(i))
+                    .copied()
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(
+                covered(&set.difference(&other)),
+                a.iter()
+                    .filter(|i| !b.contains(i))
+                    .copied()
+                    .collect::<Vec<_>>()
+            );
+            for result in [
+                set.union(&other),
+                set.intersection(&other),
+                set.difference(&other),
+            ] {
+                assert_eq!(RangeSet::from(Vec::from(result.clone())), result);
+            }
+        }
+    }
+
+    #[test]
+    fn range_set_operations() {
+        let mut set = RangeSet::new();
+        set.insert(range(10, 20));
+        set.insert(range(0, 5));
+        // adjacent ranges are merged
+        set.insert(range(5, 8));
+        assert_eq!(set.as_slice(), [range(0, 8), range(10, 20)]);
+        set.insert(range(7, 11));
+        assert_eq!(set.as_slice(), [range(0, 20)]);
+
+        let other = RangeSet::from(vec![range(3, 6), range(15, 25)]);
+        assert_eq!(
+            set.difference(&other).as_slice(),
+            [range(0, 3), range(6, 15)]
+        );
+        assert_eq!(
+            set.intersection(&other).as_slice(),
+            [range(3, 6), range(15, 20)]
+        );
+        assert_eq!(set.union(&other).as_slice(), [range(0, 25)]);
+        assert!(set.difference(&set).is_empty());
+        assert_eq!(set.difference(&RangeSet::new()), set);
+    }
+
+    #[test]
+    fn range_grow_and_shrink() {
+        let r = range(5, 10);
+        assert_eq!(r.grow(2, 3), range(3, 13));
+        assert_eq!(r.grow(0, 0), r);
+        // saturating at the boundaries
+        assert_eq!(r.grow(10, 0), range(0, 10));
+        assert_eq!(range(0, 1).grow(1, 0), range(0, 1));
+        assert_eq!(range(1, u32::MAX - 1).grow(0, 5), range(1, u32::MAX));
+
+        assert_eq!(r.shrink(1, 2), Some(range(6, 8)));
+        assert_eq!(r.shrink(4, 0), Some(range(9, 10)));
+        assert_eq!(r.shrink(0, 4), Some(range(5, 6)));
+        // collapsed
+        assert_eq!(r.shrink(5, 0), None);
+        assert_eq!(r.shrink(3, 2), None);
+        assert_eq!(r.shrink(u32::MAX, u32::MAX), None);
+
+        assert_eq!(r.grow(1, 4).shrink(1, 4), Some(r));
+    }
+
+    #[test]
+    fn range_containment_and_overlap() {
+        let r = range(0, 5);
+        assert!(r.contains(Loc(0)));
+        assert!(r.contains(Loc(4)));
+        assert!(!r.contains(Loc(5)));
+
+        // adjacent ranges do not overlap
+        assert!(!r.overlaps(range(5, 10)));
+        assert!(!range(5, 10).overlaps(r));
+        assert!(r.overlaps(range(4, 10)));
+        assert!(range(4, 10).overlaps(r));
+        // starting at zero
+        assert!(range(0, 1).overlaps(range(0, 5)));
+        assert!(!range(0, 1).overlaps(range(1, 2)));
+        // nested and identical
+        assert!(r.overlaps(range(1, 2)));
+        assert!(r.overlaps(r));
+    }
+
+    #[test]
+    fn range_within_source() {
+        assert!(range(0, 10).within(Loc(10)));
+        assert!(!range(0, 11).within(Loc(10)));
+        assert_eq!(range(5, 15).clamp(Loc(10)), Some(range(5, 10)));
+        assert_eq!(range(10, 15).clamp(Loc(10)), None);
+        assert_eq!(range(2, 4).clamp(Loc(10)), Some(range(2, 4)));
+
+        let mut func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: Some(range(0, 12)),
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![user_decl(
+                1,
+                "v",
+                vec![range(0, 4), range(8, 12), range(10, 14)],
+            )],
+        };
+        assert_eq!(func.clamp_ranges(Loc(10)), 3);
+        assert_eq!(func.span, Some(range(0, 10)));
+        // clamped at the end, dropped past it
+        assert_eq!(func.decls[0].lives(), [range(0, 4), range(8, 10)]);
+        assert_eq!(func.clamp_ranges(Loc(10)), 0);
+    }
+
+    #[test]
+    fn loc_byte_offset_round_trip() {
+        let source = "a🦀b";
+        let offsets: Vec<_> = (0..4).map(|i| Loc(i).to_byte_offset(source)).collect();
+        assert_eq!(offsets, [0, 1, 5, 6]);
+        for (i, offset) in offsets.into_iter().enumerate() {
+            assert_eq!(Loc::new(source, offset as u32, 0), Loc(i as u32));
+        }
+        assert_eq!(Loc(10).to_byte_offset(source), source.len());
+
+        // CRs are skipped like `Loc::new` does, and the offset indexes the source
+        let source = "a\r\n🦀\r\nb";
+        let cleaned = source.replace('\r', "");
+        for (i, c) in cleaned.chars().enumerate() {
+            let offset = Loc(i as u32).to_byte_offset(source);
+            assert_eq!(source[offset..].chars().next(), Some(c));
+        }
+        assert_eq!(Loc(1).to_byte_offset(source), 2);
+    }
+
+    #[test]
+    fn loc_json_encodings() {
+        let loc = Loc(12345);
+        let numeric = serialize_loc(loc, false, serde_json::value::Serializer).unwrap();
+        assert_eq!(numeric, serde_json::json!(12345));
+        let string = serialize_loc(loc, true, serde_json::value::Serializer).unwrap();
+        assert_eq!(string, serde_json::json!("12345"));
+
+        // both forms round-trip
+        assert_eq!(serde_json::from_value::<Loc>(numeric).unwrap(), loc);
+        assert_eq!(serde_json::from_value::<Loc>(string).unwrap(), loc);
+        let range: Range = serde_json::from_str(r#"{"from":"3","until":7}"#).unwrap();
+        assert_eq!(range, Range::new(Loc(3), Loc(7)).unwrap());
+
+        assert!(serde_json::from_str::<Loc>("-1").is_err());
+        assert!(serde_json::from_str::<Loc>("4294967296").is_err());
+        assert!(serde_json::from_str::<Loc>(r#""12a""#).is_err());
+        assert!(serde_json::from_str::<Loc>("1.5").is_err());
+    }
+
+    #[test]
+    fn loc_distance() {
+        assert_eq!(Loc(10) - Loc(3), 7);
+        assert_eq!(Loc(3) - Loc(10), -7);
+        assert_eq!(Loc(5) - Loc(5), 0);
+        assert_eq!(Loc(u32::MAX) - Loc(0), i64::from(u32::MAX));
+        assert_eq!(Loc(0) - Loc(u32::MAX), -i64::from(u32::MAX));
+        assert_eq!(Loc(u32::MAX) - Loc(u32::MAX - 1), 1);
+        // distinct from the saturating `Loc - i32`
+        assert_eq!(Loc(3) - 10, Loc(0));
+    }
+
+    #[test]
+    fn range_text_extraction() {
+        let source = "fn main() {\r\n    let s = \"🦀owl\";\r\n}";
+        // `Loc`s are char indices with CRs ignored
+        let cleaned = source.replace('\r', "");
+        let loc_of = |needle: &str| {
+            let byte = cleaned.find(needle).unwrap();
+            Loc(cleaned[..byte].chars().count() as u32)
+        };
+
+        let from = loc_of("🦀");
+        let owl = Range::new(from, from + 4).unwrap();
+        assert_eq!(owl.text(source), Some("🦀owl"));
+
+        let from = loc_of("{");
+        let multiline = Range::new(from, loc_of("let") + 3).unwrap();
+        assert_eq!(multiline.text(source), Some("{\r\n    let"));
+
+        let len = cleaned.chars().count() as u32;
+        let last = Range::new(Loc(len - 1), Loc(len)).unwrap();
+        assert_eq!(last.text(source), Some("}"));
+
+        let out_of_bounds = Range::new(Loc(len - 1), Loc(len + 1)).unwrap();
+        assert_eq!(out_of_bounds.text(source), None);
+    }
+
+    #[test]
+    fn redaction_is_consistent() {
+        let func = |fn_id, name: &str, decls| Function {
+            fn_id,
+            name: name.to_owned(),
+            span: Some(range(0, 40)),
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls,
+        };
+        let items = vec![
+            func(
+                1,
+                "secret::f",
+                vec![
+                    user_decl(1, "password", vec![range(5, 8)]),
+                    user_decl(2, "token", vec![range(10, 12)]),
+                ],
+            ),
+            func(
+                2,
+                "secret::g",
+                vec![user_decl(1, "password", vec![range(20, 30)])],
+            ),
+        ];
+        let ws = Workspace(OutputMap::from_iter([(
+            crate_key("secret_crate", "0"),
+            Crate(OutputMap::from_iter([(
+                "/home/alice/secret/src/lib.rs".to_owned(),
+                File {
+                    items: items.clone(),
+                    generated: false,
+                    std: false,
+                },
+            )])),
+        )]));
+
+        let mut redactor = Redactor::new();
+        let redacted = redactor.redact(ws);
+        let redacted = &redacted.0[&crate_key("crate1", "0")].0["file1.rs"].items;
+        let names: Vec<_> = redacted
+            .iter()
+            .flat_map(|func| &func.decls)
+            .map(|decl| match decl {
+                MirDecl::User { name, ty, .. } => (name.as_str(), ty.name.as_str()),
+                MirDecl::Other { .. } => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [("var1", "Type1"), ("var2", "Type1"), ("var1", "Type1")]
+        );
+        assert_eq!(redacted[0].name, "fn1");
+        assert_eq!(redacted[1].name, "fn2");
+
+        // crate names and paths are replaced too, the same way in later workspaces
+        let file = |path: &str| {
+            (
+                path.to_owned(),
+                File {
+                    items: Vec::new(),
+                    generated: false,
+                    std: false,
+                },
+            )
+        };
+        let later = redactor.redact(Workspace(OutputMap::from_iter([
+            (
+                crate_key("secret_crate", "1"),
+                Crate(OutputMap::from_iter([
+                    file("/home/alice/secret/src/lib.rs"),
+                    file("/home/alice/secret/build"),
+                ])),
+            ),
+            (
+                "other".to_owned(),
+                Crate(OutputMap::from_iter([file("src/main.rs")])),
+            ),
+        ])));
+        // the crate and file seen before keep their placeholders
+        let secret = &later.0[&crate_key("crate1", "1")];
+        assert!(secret.0.contains_key("file1.rs"));
+        let names: Vec<_> = later
+            .0
+            .iter()
+            .flat_map(|(key, krate)| krate.0.keys().chain([key]))
+            .collect();
+        assert_eq!(names.len(), 5);
+        for name in names {
+            assert!(
+                ["crate", "file"].iter().any(|v| name.starts_with(v)),
+                "{name}"
+            );
+        }
+
+        // structure is untouched
+        for (original, redacted) in items.iter().zip(redacted) {
+            assert_eq!(original.fn_id, redacted.fn_id);
+            assert_eq!(original.span, redacted.span);
+            for (original, redacted) in original.decls.iter().zip(&redacted.decls) {
+                assert_eq!(original.local(), redacted.local());
+                assert_eq!(original.lives(), redacted.lives());
+            }
+        }
+    }
+
+    #[test]
+    fn crate_files_per_crate() {
+        let krate = |fn_id| {
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![Function {
+                        fn_id,
+                        name: "f".to_owned(),
+                        span: None,
+                        test: false,
+                        unavailable: false,
+                        basic_blocks: Vec::new(),
+                        decls: Vec::new(),
+                    }],
+                    generated: false,
+                    std: false,
+                },
+            )]))
+        };
+        let ws = Workspace(OutputMap::from_iter([
+            (crate_key("owl", "0123"), krate(1)),
+            (crate_key("owl_utils", "4567"), krate(2)),
+        ]));
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+
+        let written = ws.write_crate_files(&out).unwrap();
+        assert_eq!(written, [out.join("owl.json"), out.join("owl_utils.json")]);
+        for (path, fn_id) in written.iter().zip([1, 2]) {
+            let read = Workspace::from_json_reader(std::fs::File::open(path).unwrap()).unwrap();
+            assert_eq!(read.0.len(), 1);
+            let (_, krate) = read.0.iter().next().unwrap();
+            assert_eq!(krate.0["src/lib.rs"].items[0].fn_id, fn_id);
+        }
+
+        assert_eq!(sanitize_file_name("owl#0123"), "owl_0123");
+        assert_eq!(sanitize_file_name("../etc"), "___etc");
+    }
+
+    #[test]
+    fn merge_keeps_richer_function() {
+        let func = |decls| Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls,
+        };
+        let krate = |func| {
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )]))
+        };
+        let coarse = func(vec![user_decl(1, "v", vec![range(0, 9)])]);
+        let precise = func(vec![
+            user_decl(1, "v", vec![range(0, 3), range(5, 9)]),
+            user_decl(2, "w", vec![range(4, 6)]),
+        ]);
+        let richness_of = |krate: &Crate| {
+            let items = &krate.0["src/lib.rs"].items;
+            assert_eq!(items.len(), 1);
+            items[0].richness()
+        };
+
+        // the richer one survives in either order
+        let mut merged = krate(coarse.clone());
+        merged.merge(krate(precise.clone()));
+        assert_eq!(richness_of(&merged), (2, 3));
+
+        let mut merged = krate(precise);
+        merged.merge(krate(coarse));
+        assert_eq!(richness_of(&merged), (2, 3));
+    }
+
+    #[test]
+    fn type_name_truncation() {
+        let ty = |name: &str| MirType {
+            name: name.into(),
+            reference: None,
+        };
+
+        let mut below = ty("Vec<u8>");
+        assert!(!below.truncate(8));
+        assert_eq!(below.name, "Vec<u8>");
+        let mut at = ty("Vec<u8>");
+        assert!(!at.truncate(7));
+        assert_eq!(at.name, "Vec<u8>");
+        let mut above = ty("Vec<u8>");
+        assert!(above.truncate(6));
+        assert_eq!(above.name, "Vec<u…");
+        assert_eq!(above.name.chars().count(), 6);
+
+        // cut at char boundaries, not bytes
+        let mut multibyte = ty("Wrapper<日本語Σ>");
+        assert!(multibyte.truncate(10));
+        assert_eq!(multibyte.name, "Wrapper<日…");
+        let mut emoji = ty("🦀🦀🦀");
+        assert!(!emoji.truncate(3));
+        assert!(emoji.truncate(2));
+        assert_eq!(emoji.name, "🦀…");
+
+        let mut reference = MirType {
+            name: "&Option<Box<Node>>".into(),
+            reference: Some(Box::new(MirRefType {
+                refer_to: ty("Option<Box<Node>>"),
+                mutable: false,
+            })),
+        };
+        assert!(reference.truncate(8));
+        assert_eq!(reference.name, "&Option…");
+        assert_eq!(reference.reference.unwrap().refer_to.name, "Option<…");
+    }
+
+    #[test]
+    fn type_name_interning() {
+        let long = "std::collections::HashMap<std::string::String, std::vec::Vec<u8>>";
+        let mut interner = TypeInterner::default();
+        let first = interner.intern(long);
+        // an equal name in a separate allocation
+        let second = interner.intern(&long.chars().collect::<String>());
+        assert!(first.ptr_eq(&second));
+        let other = interner.intern("std::string::String");
+        assert!(!first.ptr_eq(&other));
+        assert_ne!(first, other);
+        assert_eq!(interner.len(), 2);
+
+        // the declarations and the types they refer to share the handles
+        let ty = |name: &str, refer_to: Option<MirType>| MirType {
+            name: name.into(),
+            reference: refer_to.map(|refer_to| {
+                Box::new(MirRefType {
+                    refer_to,
+                    mutable: false,
+                })
+            }),
+        };
+        let decl = |id, ty| MirDecl::Other {
+            local: FnLocal::new(id, 1),
+            ty,
+            lives: Vec::new(),
+            shared_borrow: Vec::new(),
+            mutable_borrow: Vec::new(),
+            drop: false,
+            drop_range: Vec::new(),
+            must_live_at: Vec::new(),
+            storage_range: Vec::new(),
+            definitely_live_at: Vec::new(),
+            maybe_init_at: Vec::new(),
+        };
+        let mut ws = Workspace(OutputMap::default());
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![
+                decl(1, ty(long, None)),
+                decl(
+                    2,
+                    ty(
+                        "&std::string::String",
+                        Some(ty("std::string::String", None)),
+                    ),
+                ),
+            ],
+        };
+        ws.0.insert(
+            crate_key("a", "0"),
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )])),
+        );
+        ws.intern_types(&mut interner);
+        assert_eq!(interner.len(), 3);
+        let decls = &ws.0[&crate_key("a", "0")].0["src/lib.rs"].items[0].decls;
+        let (MirDecl::Other { ty: first_ty, .. }, MirDecl::Other { ty: ref_ty, .. }) =
+            (&decls[0], &decls[1])
+        else {
+            unreachable!()
+        };
+        assert!(first_ty.name.ptr_eq(&first));
+        let refer_to = &ref_ty.reference.as_ref().unwrap().refer_to;
+        assert!(refer_to.name.ptr_eq(&other));
+        // serialized as plain strings
+        assert_eq!(
+            serde_json::to_value(&refer_to.name).unwrap(),
+            serde_json::json!("std::string::String")
+        );
+    }
+
+    #[test]
+    fn merge_with_prefers_incoming_function() {
+        let func = |terminator: Range| Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![MirBasicBlock {
+                statements: Vec::new(),
+                terminator: MirTerminator {
+                    kind: MirTerminatorKind::Return,
+                    range: Some(terminator),
+                },
+                is_cleanup: false,
+            }],
+            decls: vec![user_decl(1, "v", vec![range(0, 9)])],
+        };
+        let krate = |func| {
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )]))
+        };
+        let terminator_of = |krate: &Crate| {
+            let items = &krate.0["src/lib.rs"].items;
+            assert_eq!(items.len(), 1);
+            items[0].basic_blocks[0].terminator.range
+        };
+        let stale = func(range(0, 5));
+        let fresh = func(range(2, 7));
+
+        let mut merged = krate(stale.clone());
+        merged.merge_with(krate(fresh.clone()), true);
+        assert_eq!(terminator_of(&merged), Some(range(2, 7)));
+
+        // the first seen function is kept on equal richness otherwise
+        let mut merged = krate(stale);
+        merged.merge_with(krate(fresh), false);
+        assert_eq!(terminator_of(&merged), Some(range(0, 5)));
+    }
+
+    #[test]
+    fn parallel_crate_serialization() {
+        let ws = Workspace(OutputMap::from_iter((0..8).map(|i| {
+            let func = Function {
+                fn_id: i,
+                name: format!("f{i}"),
+                span: None,
+                test: false,
+                unavailable: false,
+                basic_blocks: Vec::new(),
+                decls: vec![user_decl(1, "v", vec![range(i, i + 3)])],
+            };
+            (
+                crate_key(&format!("krate{i}"), "0"),
+                Crate(OutputMap::from_iter([(
+                    "src/lib.rs".to_owned(),
+                    File {
+                        items: vec![func],
+                        generated: false,
+                        std: false,
+                    },
+                )])),
+            )
+        })));
+
+        let serial = ws.to_crate_json(false).unwrap();
+        let parallel = ws.to_crate_json(true).unwrap();
+        assert_eq!(serial.len(), 8);
+        // compare parsed values; map order within a crate may differ
+        let parse = |lines: &[(String, String)]| {
+            lines
+                .iter()
+                .map(|(key, json)| {
+                    let value: serde_json::Value = serde_json::from_str(json).unwrap();
+                    (key.clone(), value)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(parse(&serial), parse(&parallel));
+        // each line reads back as the workspace of its crate alone
+        for (key, json) in &serial {
+            let single = Workspace(OutputMap::from_iter([(key.clone(), ws.0[key].clone())]));
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(json).unwrap(),
+                serde_json::from_str::<serde_json::Value>(&single.to_json(false).unwrap()).unwrap()
+            );
+        }
+
+        let read = Workspace::from_json_reader(
+            parallel
+                .iter()
+                .map(|(_, json)| json.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(read.0.len(), 8);
+    }
+
+    #[test]
+    fn pretty_and_compact_json_are_equivalent() {
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![user_decl(1, "v", vec![range(5, 8)])],
+        };
+        let ws = Workspace(OutputMap::from_iter([(
+            "krate".to_owned(),
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )])),
+        )]));
+
+        let compact = ws.to_json(false).unwrap();
+        let pretty = ws.to_json(true).unwrap();
+        assert!(!compact.contains('\n'));
+        assert!(pretty.contains('\n'));
+
+        let compact = Workspace::from_json_reader(compact.as_bytes()).unwrap();
+        let pretty = Workspace::from_json_reader(pretty.as_bytes()).unwrap();
+        assert_eq!(
+            serde_json::to_value(&compact).unwrap(),
+            serde_json::to_value(&pretty).unwrap()
+        );
+    }
+
+    #[test]
+    fn output_version_check() {
+        assert_eq!(
+            output_version_compatibility(OUTPUT_VERSION),
+            VersionCompatibility::Compatible
+        );
+        assert_eq!(
+            output_version_compatibility("0.9"),
+            VersionCompatibility::Incompatible
+        );
+        assert_eq!(
+            output_version_compatibility("1.7"),
+            VersionCompatibility::Newer
+        );
+        assert_eq!(
+            output_version_compatibility("2.0"),
+            VersionCompatibility::Incompatible
+        );
+        assert_eq!(
+            output_version_compatibility("latest"),
+            VersionCompatibility::Incompatible
+        );
+    }
+
+    #[test]
+    fn read_versioned_and_unversioned_output() {
+        let unversioned = r#"{"krate":{"src/lib.rs":{"items":[]}}}"#;
+        let ws = Workspace::from_json_reader(unversioned.as_bytes()).unwrap();
+        assert!(ws.0["krate"].0.contains_key("src/lib.rs"));
+
+        let versioned = ws.to_json(false).unwrap();
+        assert!(versioned.contains(&format!(r#""version":"{OUTPUT_VERSION}""#)));
+        let ws = Workspace::from_json_reader(versioned.as_bytes()).unwrap();
+        assert!(ws.0["krate"].0.contains_key("src/lib.rs"));
+
+        let incompatible = r#"{"version":"2.0","workspace":{}}"#;
+        assert!(Workspace::from_json_reader(incompatible.as_bytes()).is_err());
+
+        // the shape written before versioning, read back the same
+        let bare = ws.to_unversioned_json(false).unwrap();
+        assert!(!bare.contains(r#""version""#));
+        let ws = Workspace::from_json_reader(bare.as_bytes()).unwrap();
+        assert!(ws.0["krate"].0.contains_key("src/lib.rs"));
+
+        // no output at all is not an empty workspace
+        assert!(Workspace::from_json_reader("".as_bytes()).is_err());
+        assert!(Workspace::from_json_reader(" \n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn function_span_serialization() {
+        let mut func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: Vec::new(),
+        };
+        let json = serde_json::to_value(&func).unwrap();
+        assert!(json.get("span").is_none());
+        let read: Function = serde_json::from_value(json).unwrap();
+        assert_eq!(read.span, None);
+
+        func.span = Some(range(3, 42));
+        let json = serde_json::to_value(&func).unwrap();
+        assert_eq!(json["span"], serde_json::json!({ "from": 3, "until": 42 }));
+        let read: Function = serde_json::from_value(json).unwrap();
+        assert_eq!(read.span, Some(range(3, 42)));
+    }
+
+    #[test]
+    fn same_named_crates_are_kept_apart() {
+        let output = |disambiguator: &str, path: &str| {
+            let file = File {
+                items: Vec::new(),
+                generated: false,
+                std: false,
+            };
+            Workspace(OutputMap::from_iter([(
+                crate_key("owl", disambiguator),
+                Crate(OutputMap::from_iter([(path.to_owned(), file)])),
+            )]))
+            .to_json(false)
+            .unwrap()
+        };
+        let lines = [
+            output("0123456789abcdef", "/deps/owl-0.1.0/src/lib.rs"),
+            output("fedcba9876543210", "/work/owl/src/lib.rs"),
+        ]
+        .join("\n");
+        let ws = Workspace::from_json_reader(lines.as_bytes()).unwrap();
+        assert_eq!(ws.0.len(), 2);
+        assert!(ws.0.keys().all(|key| crate_display_name(key) == "owl"));
+        assert!(
+            ws.0[&crate_key("owl", "0123456789abcdef")]
+                .0
+                .contains_key("/deps/owl-0.1.0/src/lib.rs")
+        );
+        assert!(
+            ws.0[&crate_key("owl", "fedcba9876543210")]
+                .0
+                .contains_key("/work/owl/src/lib.rs")
+        );
+        assert_eq!(crate_display_name("owl"), "owl");
+    }
+
+    #[test]
+    fn fixed_hash_state_iterates_deterministically() {
+        let keys: Vec<_> = (0..64).map(|i| format!("src/file_{i}.rs")).collect();
+        let collect_keys = || {
+            let mut map = OutputMap::with_hasher(OutputHashState::Fixed);
+            for key in &keys {
+                map.insert(key.clone(), ());
+            }
+            map.into_keys().collect::<Vec<_>>()
+        };
+        assert_eq!(collect_keys(), collect_keys());
+    }
+
+    #[test]
+    fn validate_workspace_json() {
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![call_block(2, Some(range(10, 20)), 0)],
+            decls: vec![user_decl(1, "v", vec![range(5, 8)])],
+        };
+        let ws = Workspace(OutputMap::from_iter([(
+            "krate".to_owned(),
+            Crate(OutputMap::from_iter([(
+                "src/lib.rs".to_owned(),
+                File {
+                    items: vec![func],
+                    generated: false,
+                    std: false,
+                },
+            )])),
+        )]));
+        let valid = ws.to_json(false).unwrap();
+        let ws = Workspace::from_json_reader(valid.as_bytes()).unwrap();
+        assert_eq!(ws.validate(), Vec::new());
+
+        // jump to a nonexistent block, and an inverted live range
+        let corrupted = valid.replace(r#""target":0"#, r#""target":7"#).replace(
+            r#""lives":[{"from":5,"until":8}]"#,
+            r#""lives":[{"from":8,"until":5}]"#,
+        );
+        let ws = Workspace::from_json_reader(corrupted.as_bytes()).unwrap();
+        let errors = ws.validate();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| e.function.as_deref() == Some("f")));
+        assert!(
+            errors
+                .iter()
+                .any(|e| e.message.contains("nonexistent block 7"))
+        );
+        assert!(errors.iter().any(|e| e.message.contains("live range 8..5")));
+    }
+
+    #[test]
+    fn first_and_last_use_of_disjoint_lives() {
+        let decl = user_decl(1, "v", vec![range(40, 52), range(3, 10), range(20, 25)]);
+        assert_eq!(decl.first_use(), Some(Loc(3)));
+        assert_eq!(decl.last_use(), Some(Loc(52)));
+
+        let empty = user_decl(2, "w", Vec::new());
+        assert_eq!(empty.first_use(), None);
+        assert_eq!(empty.last_use(), None);
+    }
+
+    fn call_block(destination: u32, fn_range: Option<Range>, target: usize) -> MirBasicBlock {
+        MirBasicBlock {
+            statements: Vec::new(),
+            terminator: MirTerminator {
+                kind: MirTerminatorKind::Call {
+                    func: MirOperand::Other,
+                    args: Vec::new(),
+                    destination: MirPlace {
+                        local: FnLocal::new(destination, 1),
+                        projection: Vec::new(),
+                    },
+                    target: Some(BasicBlockId(target)),
+                    fn_range,
+                },
+                range: fn_range,
+            },
+            is_cleanup: false,
+        }
+    }
+
+    #[test]
+    fn calls_of_function() {
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![
+                call_block(2, Some(range(10, 20)), 1),
+                MirBasicBlock {
+                    statements: Vec::new(),
+                    terminator: MirTerminator {
+                        kind: MirTerminatorKind::Goto {
+                            target: BasicBlockId(2),
+                        },
+                        range: None,
+                    },
+                    is_cleanup: false,
+                },
+                call_block(3, None, 3),
+                call_block(4, Some(range(30, 45)), 4),
+            ],
+            decls: Vec::new(),
+        };
+        assert_eq!(
+            func.calls(),
+            vec![
+                CallInfo {
+                    destination: FnLocal::new(2, 1),
+                    span: range(10, 20),
+                },
+                CallInfo {
+                    destination: FnLocal::new(4, 1),
+                    span: range(30, 45),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_sequence_of_function() {
+        let drop_block = |local: u32, range: Option<Range>, target: usize| MirBasicBlock {
+            statements: Vec::new(),
+            terminator: MirTerminator {
+                kind: MirTerminatorKind::Drop {
+                    place: MirPlace {
+                        local: FnLocal::new(local, 1),
+                        projection: Vec::new(),
+                    },
+                    target: BasicBlockId(target),
+                },
+                range,
+            },
+            is_cleanup: false,
+        };
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![
+                // an early drop in a later block
+                drop_block(4, Some(range(50, 51)), 1),
+                call_block(5, Some(range(20, 30)), 2),
+                drop_block(1, Some(range(10, 11)), 3),
+                drop_block(3, None, 4),
+                // the end of a scope, in reverse declaration order
+                drop_block(2, Some(range(60, 61)), 5),
+                drop_block(1, Some(range(60, 61)), 6),
+                // the same drops repeated for unwinding
+                MirBasicBlock {
+                    is_cleanup: true,
+                    ..drop_block(1, Some(range(60, 61)), 7)
+                },
+                MirBasicBlock {
+                    is_cleanup: true,
+                    ..drop_block(4, Some(range(50, 51)), 8)
+                },
+            ],
+            decls: Vec::new(),
+        };
+        assert_eq!(
+            func.drop_sequence(),
+            vec![
+                (FnLocal::new(1, 1), range(10, 11)),
+                (FnLocal::new(4, 1), range(50, 51)),
+                (FnLocal::new(2, 1), range(60, 61)),
+                (FnLocal::new(1, 1), range(60, 61)),
+            ]
+        );
+    }
+
+    #[test]
+    fn activity_spans_of_file() {
+        let stmt = |range: Range| MirStatement {
+            kind: MirStatementKind::Other,
+            range: Some(range),
+        };
+        let mut lived = user_decl(1, "a", vec![range(10, 20)]);
+        if let MirDecl::User {
+            span,
+            shared_borrow,
+            ..
+        } = &mut lived
+        {
+            *span = range(8, 9);
+            shared_borrow.push(range(18, 25));
+        }
+        let file = File {
+            items: vec![
+                Function {
+                    fn_id: 1,
+                    name: "f".to_owned(),
+                    span: Some(range(0, 100)),
+                    test: false,
+                    unavailable: false,
+                    basic_blocks: vec![MirBasicBlock {
+                        statements: vec![stmt(range(25, 30)), stmt(range(40, 45))],
+                        terminator: MirTerminator {
+                            kind: MirTerminatorKind::Return,
+                            range: Some(range(60, 61)),
+                        },
+                        is_cleanup: false,
+                    }],
+                    decls: vec![lived],
+                },
+                Function {
+                    fn_id: 2,
+                    name: "g".to_owned(),
+                    span: None,
+                    test: false,
+                    unavailable: false,
+                    basic_blocks: vec![call_block(1, Some(range(42, 50)), 1)],
+                    decls: vec![user_decl(2, "b", vec![range(75, 90)])],
+                },
+            ],
+            generated: false,
+            std: false,
+        };
+        // the function spans themselves are not activity
+        assert_eq!(
+            file.activity_spans(),
+            vec![
+                // declarations of `b` and `a`
+                range(0, 1),
+                range(8, 9),
+                range(10, 30),
+                range(40, 50),
+                range(60, 61),
+                range(75, 90),
+            ]
+        );
+        let empty = File {
+            items: Vec::new(),
+            generated: false,
+            std: false,
+        };
+        assert_eq!(empty.activity_spans(), Vec::new());
+    }
+
+    #[test]
+    fn workspace_statistics() {
+        let mut borrowed = user_decl(1, "v", vec![range(10, 30)]);
+        if let MirDecl::User {
+            shared_borrow,
+            mutable_borrow,
+            must_live_at,
+            definitely_live_at,
+            ..
+        } = &mut borrowed
+        {
+            *shared_borrow = vec![range(12, 20)];
+            *mutable_borrow = vec![range(15, 25)];
+            *must_live_at = vec![range(10, 40)];
+            *definitely_live_at = vec![range(10, 30)];
+        }
+        let func = |fn_id, span, decls| Function {
+            fn_id,
+            name: format!("f{fn_id}"),
+            span: Some(span),
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls,
+        };
+        let ws = Workspace(OutputMap::from_iter([(
+            "owl".to_owned(),
+            Crate(OutputMap::from_iter([
+                (
+                    "src/lib.rs".to_owned(),
+                    File {
+                        items: vec![
+                            func(1, range(0, 40), vec![borrowed]),
+                            func(
+                                2,
+                                range(50, 60),
+                                vec![user_decl(2, "w", vec![range(50, 60)])],
+                            ),
+                        ],
+                        generated: false,
+                        std: false,
+                    },
+                ),
+                (
+                    "src/main.rs".to_owned(),
+                    File {
+                        items: vec![Function {
+                            fn_id: 3,
+                            name: "main".to_owned(),
+                            span: None,
+                            test: false,
+                            unavailable: false,
+                            basic_blocks: Vec::new(),
+                            decls: Vec::new(),
+                        }],
+                        generated: false,
+                        std: false,
+                    },
+                ),
+            ])),
+        )]));
+
+        let stats = ws.statistics();
+        assert_eq!(stats.functions, 3);
+        assert_eq!(stats.user_variables, 2);
+        assert_eq!(stats.borrow_conflicts, 1);
+        assert_eq!(stats.outlive_violations, 1);
+        // (20/40 + 10/10) / 2; main.rs has no extent
+        assert_eq!(
+            stats.live_coverage,
+            BTreeMap::from([("src/lib.rs".to_owned(), 0.75)])
+        );
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["functions"], 3);
+    }
+
+    #[test]
+    fn cfg_edges_of_function() {
+        let block = |kind| MirBasicBlock {
+            statements: Vec::new(),
+            terminator: MirTerminator { kind, range: None },
+            is_cleanup: false,
+        };
+        // 0 -> {1, 2} -> 3, with a call in 1
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![
+                block(MirTerminatorKind::SwitchInt {
+                    discr: MirOperand::Other,
+                    targets: vec![BasicBlockId(1), BasicBlockId(2)],
+                }),
+                call_block(2, Some(range(10, 20)), 3),
+                block(MirTerminatorKind::Goto {
+                    target: BasicBlockId(3),
+                }),
+                block(MirTerminatorKind::Return),
+            ],
+            decls: Vec::new(),
+        };
+        assert_eq!(func.cfg_edges(), vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+    }
+
+    #[test]
+    fn empty_function() {
+        let mut func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: Vec::new(),
+        };
+        assert!(func.is_empty());
+        func.basic_blocks.push(MirBasicBlock {
+            statements: Vec::new(),
+            terminator: MirTerminator {
+                kind: MirTerminatorKind::Return,
+                range: None,
+            },
+            is_cleanup: false,
+        });
+        assert!(func.is_empty());
+
+        let mut with_decls = func.clone();
+        with_decls.decls.push(user_decl(1, "x", vec![range(0, 5)]));
+        assert!(!with_decls.is_empty());
+
+        func.basic_blocks[0].statements.push(MirStatement {
+            kind: MirStatementKind::Nop,
+            range: Some(range(0, 5)),
+        });
+        assert!(!func.is_empty());
+    }
+
+    #[test]
+    fn variable_uses_of_function() {
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: Vec::new(),
+            decls: vec![
+                user_decl(1, "v", vec![range(5, 8), range(12, 30)]),
+                user_decl(2, "w", Vec::new()),
+            ],
+        };
+        let uses = func.variable_uses();
+        assert_eq!(uses.len(), 2);
+        assert_eq!(uses[0].name, "v");
+        assert_eq!(uses[0].first_use, Some(Loc(5)));
+        assert_eq!(uses[0].last_use, Some(Loc(30)));
+        assert_eq!(uses[1].first_use, None);
+        assert_eq!(uses[1].last_use, None);
+    }
 }
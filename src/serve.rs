@@ -0,0 +1,401 @@
+//! Long-lived analysis server for IDE integrations
+//!
+//! A client connects over a Unix socket or TCP and sends requests, each naming
+//! a project or file to analyze. The results are streamed back as they arrive.
+//! Every message is a JSON document prefixed with its length as a 4-byte
+//! big-endian integer. A connection can carry any number of requests, which
+//! are handled one after another; connections are served concurrently.
+
+use crate::analysis::AnalysisError;
+use crate::config::Config;
+use crate::lsp::analyze::{Analyzer, AnalyzerEvent};
+use crate::models::Workspace;
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Requests are only paths, so a longer one is not a request at all
+const MAX_REQUEST_LEN: u32 = 1024 * 1024;
+
+/// Analyze the project or `.rs` file at `path`
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ServeRequest {
+    pub path: PathBuf,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServeResponse {
+    /// Results of some crates; a request has any number of them
+    Analyzed { workspace: Workspace },
+    /// The request finished successfully, after the compiler exited without
+    /// errors; holds how much was sent in the results before it
+    Done { crates: usize, functions: usize },
+    /// The request failed; no more results follow for it
+    Error { message: String },
+}
+
+/// Counts the crates and functions sent for a request, to report them in
+/// [`ServeResponse::Done`]
+#[derive(Default, Debug)]
+pub struct SentCounts {
+    crates: HashSet<String>,
+    functions: usize,
+}
+
+impl SentCounts {
+    pub fn add(&mut self, workspace: &Workspace) {
+        for (key, krate) in &workspace.0 {
+            // a crate arrives in pieces, one per file
+            self.crates.insert(key.clone());
+            self.functions += krate.0.values().map(|file| file.items.len()).sum::<usize>();
+        }
+    }
+
+    pub fn done(&self) -> ServeResponse {
+        ServeResponse::Done {
+            crates: self.crates.len(),
+            functions: self.functions,
+        }
+    }
+}
+
+/// Destination of the results of a request
+pub trait ResultSink {
+    fn send(&mut self, response: &ServeResponse) -> impl Future<Output = io::Result<()>>;
+}
+
+/// Writes each response as a length-prefixed frame
+pub struct FramedSink<W>(pub W);
+
+impl<W: AsyncWrite + Unpin> ResultSink for FramedSink<W> {
+    async fn send(&mut self, response: &ServeResponse) -> io::Result<()> {
+        write_frame(&mut self.0, &serde_json::to_vec(response)?).await
+    }
+}
+
+/// Writes each response as JSON on a line of its own, or pretty-printed over
+/// many lines for humans to read
+pub struct JsonLinesSink<W> {
+    pub writer: W,
+    pub pretty: bool,
+}
+
+impl<W: AsyncWrite + Unpin> JsonLinesSink<W> {
+    /// Write any document the same way as the responses
+    pub async fn write(&mut self, value: &impl serde::Serialize) -> io::Result<()> {
+        let mut json = if self.pretty {
+            serde_json::to_vec_pretty(value)?
+        } else {
+            serde_json::to_vec(value)?
+        };
+        json.push(b'\n');
+        self.writer.write_all(&json).await?;
+        self.writer.flush().await
+    }
+}
+
+impl<W: AsyncWrite + Unpin> ResultSink for JsonLinesSink<W> {
+    async fn send(&mut self, response: &ServeResponse) -> io::Result<()> {
+        self.write(response).await
+    }
+}
+
+/// Read one frame; `None` if the peer closed the connection between frames
+pub async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len);
+    if len > MAX_REQUEST_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {len} bytes exceeds the limit of {MAX_REQUEST_LEN}"),
+        ));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+pub async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), payload: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(payload).await?;
+    writer.flush().await
+}
+
+/// Handle the requests of one connection until the peer closes it,
+/// running `analyze` for each of them
+pub async fn serve_connection<S, F>(stream: S, mut analyze: F) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: AsyncFnMut(PathBuf, &mut FramedSink<tokio::io::WriteHalf<S>>) -> io::Result<()>,
+{
+    let (mut reader, writer) = tokio::io::split(stream);
+    let mut sink = FramedSink(writer);
+    while let Some(payload) = read_frame(&mut reader).await? {
+        match serde_json::from_slice::<ServeRequest>(&payload) {
+            Ok(request) => {
+                log::info!("analysis of {} requested", request.path.display());
+                analyze(request.path, &mut sink).await?;
+            }
+            Err(e) => {
+                let message = format!("invalid request: {e}");
+                sink.send(&ServeResponse::Error { message }).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Analyze the project or file at `path` with the options of its
+/// `rustowl.toml`, sending the results of each crate as they arrive
+pub async fn analyze_into(path: PathBuf, sink: &mut impl ResultSink) -> io::Result<()> {
+    let config = Config::discover(&path);
+    let Ok(analyzer) = Analyzer::new(&path).await else {
+        let message = AnalysisError::InvalidTarget(path).to_string();
+        return sink.send(&ServeResponse::Error { message }).await;
+    };
+    let analyzer = analyzer.capture_stderr();
+    let single_file = analyzer.is_single_file();
+    let mut iter = analyzer
+        .analyze(config.all_targets, config.all_features)
+        .await;
+    let mut sent = SentCounts::default();
+    while let Some(event) = iter.next_event().await {
+        if let AnalyzerEvent::Analyzed(workspace) = event {
+            sent.add(&workspace);
+            sink.send(&ServeResponse::Analyzed { workspace }).await?;
+        }
+    }
+    let (success, stderr) = iter.finish().await;
+    if success {
+        sink.send(&sent.done()).await
+    } else {
+        let message = AnalysisError::from_diagnostics(stderr, single_file).to_string();
+        sink.send(&ServeResponse::Error { message }).await
+    }
+}
+
+/// Serve connections accepted on `addr`, each in its own task.
+///
+/// Every request runs cargo, and with it build scripts and proc macros, for
+/// whoever connects without any authentication, so only loopback addresses
+/// are accepted unless `allow_remote` is set.
+pub async fn serve_tcp(addr: &str, allow_remote: bool) -> io::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let local = listener.local_addr()?;
+    if !allow_remote && !local.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "refusing to listen on {local}, which is not a loopback address; \
+                 pass `--allow-remote` to accept connections from other hosts"
+            ),
+        ));
+    }
+    log::info!("listening on {local}");
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        log::info!("connection from {peer}");
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, analyze_into).await {
+                log::warn!("connection from {peer} failed: {e}");
+            }
+        });
+    }
+}
+
+/// Serve connections accepted on the Unix socket at `path`, each in its own
+/// task. A stale socket left by a previous server is replaced, but any other
+/// file at `path` is an error.
+#[cfg(unix)]
+pub async fn serve_unix(path: &std::path::Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    log::info!("listening on {}", path.display());
+    loop {
+        let (stream, _) = listener.accept().await?;
+        log::info!("connection accepted");
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, analyze_into).await {
+                log::warn!("connection failed: {e}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Crate, File, Function, OutputMap, crate_key};
+
+    async fn response(reader: &mut (impl AsyncRead + Unpin)) -> ServeResponse {
+        let payload = read_frame(reader).await.unwrap().expect("no response");
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn framed_requests_over_duplex() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve_connection(
+            server,
+            async |path: PathBuf, sink: &mut FramedSink<_>| {
+                let key = crate_key(&path.to_string_lossy(), "0");
+                let workspace =
+                    Workspace(OutputMap::from_iter([(key, Crate(OutputMap::default()))]));
+                let mut sent = SentCounts::default();
+                sent.add(&workspace);
+                sink.send(&ServeResponse::Analyzed { workspace }).await?;
+                sink.send(&sent.done()).await
+            },
+        ));
+
+        let (mut reader, mut writer) = tokio::io::split(client);
+        for name in ["first", "second"] {
+            let request = ServeRequest { path: name.into() };
+            write_frame(&mut writer, &serde_json::to_vec(&request).unwrap())
+                .await
+                .unwrap();
+            let ServeResponse::Analyzed { workspace } = response(&mut reader).await else {
+                panic!("expected results");
+            };
+            assert!(workspace.0.contains_key(&crate_key(name, "0")));
+            assert!(matches!(
+                response(&mut reader).await,
+                ServeResponse::Done { crates: 1, .. }
+            ));
+        }
+
+        write_frame(&mut writer, b"{\"file\": 1}").await.unwrap();
+        let ServeResponse::Error { message } = response(&mut reader).await else {
+            panic!("expected an error");
+        };
+        assert!(message.starts_with("invalid request"));
+
+        drop(writer);
+        drop(reader);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_lines_pretty_and_compact() {
+        let key = crate_key("owl", "0");
+        let workspace = Workspace(OutputMap::from_iter([(key, Crate(OutputMap::default()))]));
+        let mut written = Vec::new();
+        for pretty in [false, true] {
+            let mut sink = JsonLinesSink {
+                writer: Vec::new(),
+                pretty,
+            };
+            sink.send(&ServeResponse::Analyzed {
+                workspace: workspace.clone(),
+            })
+            .await
+            .unwrap();
+            sink.send(&ServeResponse::Done {
+                crates: 1,
+                functions: 0,
+            })
+            .await
+            .unwrap();
+            written.push(String::from_utf8(sink.writer).unwrap());
+        }
+        let [compact, pretty] = &written[..] else {
+            unreachable!()
+        };
+        assert_eq!(compact.lines().count(), 2);
+        assert!(pretty.lines().count() > 2);
+
+        // both hold the same documents
+        let parse = |json: &str| {
+            serde_json::Deserializer::from_str(json)
+                .into_iter::<serde_json::Value>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        assert_eq!(parse(compact), parse(pretty));
+        assert_eq!(
+            parse(compact)[1],
+            serde_json::json!({"type": "done", "crates": 1, "functions": 0})
+        );
+    }
+
+    #[test]
+    fn counts_crates_sent_in_pieces() {
+        let piece = |name: &str, path: &str, functions: u32| {
+            let items = (0..functions)
+                .map(|fn_id| Function {
+                    fn_id,
+                    name: format!("f{fn_id}"),
+                    span: None,
+                    test: false,
+                    unavailable: false,
+                    basic_blocks: Vec::new(),
+                    decls: Vec::new(),
+                })
+                .collect();
+            let file = File {
+                items,
+                generated: false,
+                std: false,
+            };
+            Workspace(OutputMap::from_iter([(
+                crate_key(name, "0"),
+                Crate(OutputMap::from_iter([(path.to_owned(), file)])),
+            )]))
+        };
+        let mut sent = SentCounts::default();
+        sent.add(&piece("owl", "src/lib.rs", 2));
+        sent.add(&piece("owl", "src/main.rs", 1));
+        sent.add(&piece("hoot", "src/lib.rs", 3));
+        assert_eq!(
+            serde_json::to_value(sent.done()).unwrap(),
+            serde_json::json!({"type": "done", "crates": 2, "functions": 6})
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_tcp_needs_opt_in() {
+        let err = serve_tcp("0.0.0.0:0", false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn socket_path_of_other_file_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owl.sock");
+        std::fs::write(&path, "data").unwrap();
+        let err = serve_unix(&path).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected() {
+        let mut frame = (MAX_REQUEST_LEN + 1).to_be_bytes().to_vec();
+        frame.extend_from_slice(b"{}");
+        let err = read_frame(&mut frame.as_slice()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(read_frame(&mut [].as_slice()).await.unwrap().is_none());
+    }
+}
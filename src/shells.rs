@@ -1,7 +1,7 @@
 use clap_complete_nushell::Nushell;
 
 use std::fmt::Display;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use clap::ValueEnum;
@@ -26,8 +26,16 @@ pub enum Shell {
     Zsh,
     /// Nushell
     Nushell,
+    /// Windows Command Prompt (cmd.exe), which has no programmable completion
+    Cmd,
+    /// Xonsh, which loads bash completions
+    Xonsh,
 }
 
+/// Why there is no completion script for [`Shell::Cmd`]
+const CMD_UNSUPPORTED: &str =
+    "cmd.exe has no programmable completion; use PowerShell, or clink which extends cmd.exe";
+
 impl Display for Shell {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.to_possible_value()
@@ -59,6 +67,8 @@ impl Generator for Shell {
             Shell::PowerShell => shells::PowerShell.file_name(name),
             Shell::Zsh => shells::Zsh.file_name(name),
             Shell::Nushell => Nushell.file_name(name),
+            Shell::Cmd => format!("{name}.cmd"),
+            Shell::Xonsh => shells::Bash.file_name(name),
         }
     }
 
@@ -70,6 +80,11 @@ impl Generator for Shell {
             Shell::PowerShell => shells::PowerShell.generate(cmd, buf),
             Shell::Zsh => shells::Zsh.generate(cmd, buf),
             Shell::Nushell => Nushell.generate(cmd, buf),
+            // a comment, so that the file is harmless if run
+            Shell::Cmd => {
+                writeln!(buf, "rem {CMD_UNSUPPORTED}").expect("failed to write completion file")
+            }
+            Shell::Xonsh => shells::Bash.generate(cmd, buf),
         }
     }
 }
@@ -95,8 +110,9 @@ impl Shell {
     /// This will read the SHELL environment variable and try to determine which shell is in use
     /// from that.
     ///
-    /// If SHELL is not set, then on windows, it will default to powershell, and on
-    /// other operating systems it will return `None`.
+    /// If SHELL is not set, then the name of the parent process is tried instead,
+    /// on Linux and macOS. Failing that, on windows, it will default to powershell,
+    /// and on other operating systems it will return `None`.
     ///
     /// If SHELL is set, but contains a value that doesn't correspond to one of the supported shell
     /// types, then return `None`.
@@ -115,6 +131,9 @@ impl Shell {
     pub fn from_env() -> Option<Shell> {
         if let Some(env_shell) = std::env::var_os("SHELL") {
             Shell::from_shell_path(env_shell)
+        } else if let Some(shell) = parent_process_name().and_then(|v| shell_from_process_name(&v))
+        {
+            Some(shell)
         } else if cfg!(windows) {
             Some(Shell::PowerShell)
         } else {
@@ -123,9 +142,155 @@ impl Shell {
     }
 }
 
+impl Shell {
+    /// Why completions cannot be generated for this shell, if they cannot
+    pub fn unsupported_reason(&self) -> Option<&'static str> {
+        match self {
+            Shell::Cmd => Some(CMD_UNSUPPORTED),
+            _ => None,
+        }
+    }
+
+    /// Where the completion script is picked up by the shell without further setup
+    ///
+    /// Returns `None` for shells without such a location (PowerShell),
+    /// or if the home directory is unknown.
+    pub fn completion_install_path(&self, bin_name: &str) -> Option<PathBuf> {
+        self.completion_install_path_with(bin_name, |name| std::env::var_os(name))
+    }
+
+    fn completion_install_path_with(
+        &self,
+        bin_name: &str,
+        lookup: impl Fn(&str) -> Option<std::ffi::OsString>,
+    ) -> Option<PathBuf> {
+        let home = lookup("HOME").map(PathBuf::from);
+        let xdg_dir = |name: &str, default: &str| {
+            lookup(name)
+                .map(PathBuf::from)
+                .or_else(|| home.as_ref().map(|v| v.join(default)))
+        };
+        let config = || xdg_dir("XDG_CONFIG_HOME", ".config");
+        let data = || xdg_dir("XDG_DATA_HOME", ".local/share");
+        match self {
+            // bash-completion looks up completions by command name,
+            // and so does the bash completer of xonsh
+            Shell::Bash | Shell::Xonsh => {
+                Some(data()?.join("bash-completion/completions").join(bin_name))
+            }
+            Shell::Elvish => Some(config()?.join("elvish/lib").join(self.file_name(bin_name))),
+            Shell::Fish => Some(
+                config()?
+                    .join("fish/completions")
+                    .join(self.file_name(bin_name)),
+            ),
+            // needs `fpath+=~/.zfunc` before `compinit` in `.zshrc`
+            Shell::Zsh => Some(home?.join(".zfunc").join(self.file_name(bin_name))),
+            Shell::Nushell => Some(
+                data()?
+                    .join("nushell/vendor/autoload")
+                    .join(self.file_name(bin_name)),
+            ),
+            // completions are sourced from `$PROFILE`
+            Shell::PowerShell => None,
+            Shell::Cmd => None,
+        }
+    }
+}
+
+/// List the supported shells, and where the completions of `detected` are installed
+pub fn completions_list(bin_name: &str, detected: Option<(Shell, Option<PathBuf>)>) -> String {
+    let mut list = String::new();
+    for shell in Shell::value_variants() {
+        list.push_str(&shell.to_string());
+        if detected.as_ref().is_some_and(|(v, _)| v == shell) {
+            list.push_str(" (detected)");
+        }
+        list.push('\n');
+    }
+    match detected {
+        Some((shell, Some(path))) => {
+            list.push_str(&format!(
+                "\ncompletions for {shell} are loaded from {}\n",
+                path.display()
+            ));
+            if path.is_file() {
+                list.push_str("installed\n");
+            } else {
+                list.push_str(&format!(
+                    "not installed; run `{bin_name} completions {shell} > {}`\n",
+                    path.display()
+                ));
+            }
+        }
+        Some((shell, None)) if let Some(reason) = shell.unsupported_reason() => {
+            list.push_str(&format!("\n{reason}\n"));
+        }
+        Some((shell, None)) => {
+            list.push_str(&format!(
+                "\nno standard completion path for {shell}; \
+                 source the output of `{bin_name} completions {shell}` from your shell profile\n"
+            ));
+        }
+        None => list.push_str("\nno shell detected from $SHELL or the parent process\n"),
+    }
+    list
+}
+
+/// Write the completion script of the `rustowl` command line for `shell` to `out`
+pub fn generate_completion(shell: Shell, bin_name: &str, out: &mut dyn std::io::Write) {
+    use clap::CommandFactory;
+    clap_complete::generate(shell, &mut crate::Cli::command(), bin_name, out);
+}
+
+/// Name of the parent process, which is usually the shell running us
+#[cfg(target_os = "linux")]
+fn parent_process_name() -> Option<String> {
+    std::fs::read_to_string(format!(
+        "/proc/{}/comm",
+        std::os::unix::process::parent_id()
+    ))
+    .ok()
+}
+
+/// Name of the parent process, which is usually the shell running us
+#[cfg(target_os = "macos")]
+fn parent_process_name() -> Option<String> {
+    // `ps` reads the process table through sysctl
+    let output = std::process::Command::new("ps")
+        .args(["-o", "comm=", "-p"])
+        .arg(std::os::unix::process::parent_id().to_string())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn parent_process_name() -> Option<String> {
+    None
+}
+
+/// Shell of a process name, which is a path on macOS and starts with `-`
+/// for a login shell
+fn shell_from_process_name(name: &str) -> Option<Shell> {
+    let name = name.trim();
+    let name = name.strip_prefix('-').unwrap_or(name);
+    if name.is_empty() {
+        return None;
+    }
+    Shell::from_shell_path(name)
+}
+
 // use a separate function to avoid having to monomorphize the entire function due
 // to from_shell_path being generic
 fn parse_shell_from_path(path: &Path) -> Option<Shell> {
+    // `xon.sh` is an entry point of xonsh
+    if path.file_name()?.to_str()? == "xon.sh" {
+        return Some(Shell::Xonsh);
+    }
     let name = path.file_stem()?.to_str()?;
     match name {
         "bash" => Some(Shell::Bash),
@@ -134,6 +299,133 @@ fn parse_shell_from_path(path: &Path) -> Option<Shell> {
         "elvish" => Some(Shell::Elvish),
         "powershell" | "powershell_ise" => Some(Shell::PowerShell),
         "nushell" => Some(Shell::Nushell),
+        "cmd" => Some(Shell::Cmd),
+        "xonsh" => Some(Shell::Xonsh),
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    fn lookup(name: &str) -> Option<OsString> {
+        match name {
+            "HOME" => Some("/home/owl".into()),
+            "XDG_CONFIG_HOME" => Some("/home/owl/.xdg".into()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn completion_install_paths() {
+        let path = |shell: Shell| shell.completion_install_path_with("rustowl", lookup);
+        assert_eq!(
+            path(Shell::Bash),
+            Some(PathBuf::from(
+                "/home/owl/.local/share/bash-completion/completions/rustowl"
+            ))
+        );
+        assert_eq!(
+            path(Shell::Fish),
+            Some(PathBuf::from(
+                "/home/owl/.xdg/fish/completions/rustowl.fish"
+            ))
+        );
+        assert_eq!(
+            path(Shell::Zsh),
+            Some(PathBuf::from("/home/owl/.zfunc/_rustowl"))
+        );
+        assert_eq!(path(Shell::Xonsh), path(Shell::Bash));
+        assert_eq!(path(Shell::PowerShell), None);
+        assert_eq!(path(Shell::Cmd), None);
+        assert_eq!(
+            Shell::Fish.completion_install_path_with("rustowl", |_| None),
+            None
+        );
+    }
+
+    #[test]
+    fn list_completions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rustowl.fish");
+        let list = completions_list("rustowl", Some((Shell::Fish, Some(path.clone()))));
+        for shell in Shell::value_variants() {
+            assert!(
+                list.lines()
+                    .any(|line| line.starts_with(&shell.to_string()))
+            );
+        }
+        assert!(list.contains("fish (detected)"));
+        assert!(list.contains(&path.display().to_string()));
+        assert!(list.contains("not installed"));
+
+        std::fs::write(&path, "").unwrap();
+        let list = completions_list("rustowl", Some((Shell::Fish, Some(path))));
+        assert!(list.contains("\ninstalled\n"));
+
+        let list = completions_list("rustowl", None);
+        assert!(!list.contains("(detected)"));
+    }
+
+    #[test]
+    fn shells_by_name_and_path() {
+        assert_eq!(Shell::value_variants().len(), 8);
+        for shell in Shell::value_variants() {
+            assert_eq!(shell.to_string().parse::<Shell>(), Ok(*shell));
+        }
+        assert_eq!("cmd".parse::<Shell>(), Ok(Shell::Cmd));
+        assert_eq!("xonsh".parse::<Shell>(), Ok(Shell::Xonsh));
+
+        for (path, shell) in [
+            ("/bin/bash", Shell::Bash),
+            ("C:\\Windows\\System32\\cmd.exe", Shell::Cmd),
+            ("cmd", Shell::Cmd),
+            ("/usr/bin/xonsh", Shell::Xonsh),
+            ("/usr/local/bin/xon.sh", Shell::Xonsh),
+        ] {
+            let path = path.replace('\\', std::path::MAIN_SEPARATOR_STR);
+            assert_eq!(Shell::from_shell_path(&path), Some(shell), "{path}");
+        }
+        assert_eq!(Shell::from_shell_path("/usr/bin/xon"), None);
+    }
+
+    #[test]
+    fn shell_of_parent_process_name() {
+        assert_eq!(shell_from_process_name("-zsh"), Some(Shell::Zsh));
+        assert_eq!(shell_from_process_name("bash\n"), Some(Shell::Bash));
+        assert_eq!(shell_from_process_name("-/bin/zsh"), Some(Shell::Zsh));
+        assert_eq!(
+            shell_from_process_name("/usr/local/bin/fish"),
+            Some(Shell::Fish)
+        );
+        assert_eq!(shell_from_process_name("-"), None);
+        assert_eq!(shell_from_process_name("cargo"), None);
+    }
+
+    #[test]
+    fn unsupported_cmd_completion() {
+        assert!(Shell::Cmd.unsupported_reason().is_some());
+        assert!(Shell::Xonsh.unsupported_reason().is_none());
+
+        let mut out = Vec::new();
+        generate_completion(Shell::Cmd, "rustowl", &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.starts_with("rem "));
+        assert_eq!(script.lines().count(), 1);
+
+        let list = completions_list("rustowl", Some((Shell::Cmd, None)));
+        assert!(list.contains("cmd (detected)"));
+        assert!(list.contains(CMD_UNSUPPORTED));
+    }
+
+    #[test]
+    fn generate_bash_completion() {
+        let mut out = Vec::new();
+        generate_completion(Shell::Bash, "rustowl", &mut out);
+        let script = String::from_utf8(out).unwrap();
+        assert!(script.contains("rustowl"));
+        assert!(script.contains("completions"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Export of a [`Workspace`] into SQLite, for SQL queries over many crates
+//!
+//! The schema is stable: tables and columns are only ever added.
+//! Ranges are char positions in the file, `from` inclusive and `until` exclusive,
+//! like in the JSON output.
+//!
+//! ```sql
+//! -- files with the most drops
+//! SELECT files.path, count(*) AS drops FROM ranges
+//! JOIN decls ON decls.id = ranges.decl_id
+//! JOIN functions ON functions.id = decls.function_id
+//! JOIN files ON files.id = functions.file_id
+//! WHERE ranges.kind = 'drop' GROUP BY files.id ORDER BY drops DESC;
+//! ```
+
+use crate::models::*;
+use rusqlite::{Connection, params};
+
+/// Tables created by [`write_workspace`]
+pub const SCHEMA: &str = "
+-- a crate by its key in the output, `<name>#<disambiguator>`
+CREATE TABLE crates (
+    id INTEGER PRIMARY KEY,
+    key TEXT NOT NULL
+);
+CREATE TABLE files (
+    id INTEGER PRIMARY KEY,
+    crate_id INTEGER NOT NULL REFERENCES crates(id),
+    path TEXT NOT NULL,
+    -- written by a build script
+    generated INTEGER NOT NULL
+);
+CREATE TABLE functions (
+    id INTEGER PRIMARY KEY,
+    file_id INTEGER NOT NULL REFERENCES files(id),
+    fn_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    span_from INTEGER,
+    span_until INTEGER,
+    test INTEGER NOT NULL
+);
+CREATE TABLE decls (
+    id INTEGER PRIMARY KEY,
+    function_id INTEGER NOT NULL REFERENCES functions(id),
+    local INTEGER NOT NULL,
+    -- NULL for locals the compiler introduced
+    name TEXT,
+    ty TEXT NOT NULL,
+    -- whether the local has drop glue
+    has_drop INTEGER NOT NULL
+);
+-- `kind` is one of lives, shared_borrow, mutable_borrow, drop, must_live_at
+-- and borrow_conflict, where shared and mutable borrows overlap
+CREATE TABLE ranges (
+    decl_id INTEGER NOT NULL REFERENCES decls(id),
+    kind TEXT NOT NULL,
+    range_from INTEGER NOT NULL,
+    range_until INTEGER NOT NULL
+);
+-- a local moved out by a statement or a call
+CREATE TABLE moves (
+    function_id INTEGER NOT NULL REFERENCES functions(id),
+    local INTEGER NOT NULL,
+    range_from INTEGER NOT NULL,
+    range_until INTEGER NOT NULL
+);
+";
+
+/// Write `ws` into the database at `path`, which must not exist yet
+pub fn export(ws: &Workspace, path: &std::path::Path) -> rusqlite::Result<()> {
+    let mut conn = Connection::open(path)?;
+    write_workspace(&mut conn, ws)
+}
+
+/// Create the tables of [`SCHEMA`] and fill them with `ws` in one transaction
+pub fn write_workspace(conn: &mut Connection, ws: &Workspace) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    {
+        let mut insert_crate = tx.prepare("INSERT INTO crates (key) VALUES (?1)")?;
+        let mut insert_file =
+            tx.prepare("INSERT INTO files (crate_id, path, generated) VALUES (?1, ?2, ?3)")?;
+        let mut insert_function = tx.prepare(
+            "INSERT INTO functions (file_id, fn_id, name, span_from, span_until, test)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut insert_decl = tx.prepare(
+            "INSERT INTO decls (function_id, local, name, ty, has_drop) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        let mut insert_range = tx.prepare(
+            "INSERT INTO ranges (decl_id, kind, range_from, range_until) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let mut insert_move = tx.prepare(
+            "INSERT INTO moves (function_id, local, range_from, range_until)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+
+        let mut crates: Vec<_> = ws.0.iter().collect();
+        crates.sort_by_key(|(key, _)| *key);
+        for (key, krate) in crates {
+            insert_crate.execute(params![key])?;
+            let crate_id = tx.last_insert_rowid();
+            let mut files: Vec<_> = krate.0.iter().collect();
+            files.sort_by_key(|(path, _)| *path);
+            for (path, file) in files {
+                insert_file.execute(params![crate_id, path, file.generated])?;
+                let file_id = tx.last_insert_rowid();
+                for func in &file.items {
+                    insert_function.execute(params![
+                        file_id,
+                        func.fn_id,
+                        func.name,
+                        func.span.map(|v| v.from().0),
+                        func.span.map(|v| v.until().0),
+                        func.test,
+                    ])?;
+                    let function_id = tx.last_insert_rowid();
+                    for decl in &func.decls {
+                        let (name, ty, drop) = match decl {
+                            MirDecl::User { name, ty, drop, .. } => (Some(name), ty, drop),
+                            MirDecl::Other { ty, drop, .. } => (None, ty, drop),
+                        };
+                        insert_decl.execute(params![
+                            function_id,
+                            decl.local().id,
+                            name,
+                            ty.name.as_str(),
+                            drop
+                        ])?;
+                        let decl_id = tx.last_insert_rowid();
+                        let conflicts = decl.borrow_conflicts();
+                        let ranges = decl
+                            .fact_ranges()
+                            .into_iter()
+                            .chain([("borrow_conflict", conflicts.as_slice())]);
+                        for (kind, ranges) in ranges {
+                            for range in ranges {
+                                insert_range.execute(params![
+                                    decl_id,
+                                    kind,
+                                    range.from().0,
+                                    range.until().0
+                                ])?;
+                            }
+                        }
+                    }
+                    for (local, range) in moves(func) {
+                        insert_move.execute(params![
+                            function_id,
+                            local.id,
+                            range.from().0,
+                            range.until().0
+                        ])?;
+                    }
+                }
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Locals moved out by the statements and calls of `func`
+fn moves(func: &Function) -> Vec<(FnLocal, Range)> {
+    let mut moves = Vec::new();
+    let mut push = |operand: &MirOperand, range: Option<Range>| {
+        if let (MirOperand::Move { place }, Some(range)) = (operand, range) {
+            moves.push((place.local, range));
+        }
+    };
+    for bb in &func.basic_blocks {
+        for stmt in &bb.statements {
+            if let MirStatementKind::Assign { rval, .. } = &stmt.kind {
+                match rval {
+                    MirRval::Use { operand }
+                    | MirRval::Repeat { operand }
+                    | MirRval::Cast { operand }
+                    | MirRval::UnaryOp { operand } => push(operand, stmt.range),
+                    MirRval::BinaryOp { left, right } => {
+                        push(left, stmt.range);
+                        push(right, stmt.range);
+                    }
+                    MirRval::Aggregate { fields } => {
+                        for field in fields {
+                            push(field, stmt.range);
+                        }
+                    }
+                    MirRval::Ref { .. } | MirRval::Other => {}
+                }
+            }
+        }
+        if let MirTerminatorKind::Call { args, .. } | MirTerminatorKind::TailCall { args, .. } =
+            &bb.terminator.kind
+        {
+            for arg in args {
+                push(arg, bb.terminator.range);
+            }
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: u32, until: u32) -> Range {
+        Range::new(Loc(from), Loc(until)).unwrap()
+    }
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn workspace_rows() {
+        let place = |id| MirPlace {
+            local: FnLocal::new(id, 1),
+            projection: Vec::new(),
+        };
+        let decl = |id, name: Option<&str>, shared, mutable| {
+            let ty = MirType {
+                name: "String".into(),
+                reference: None,
+            };
+            let lives = vec![range(0, 20)];
+            let drop_range = vec![range(0, 25)];
+            match name {
+                Some(name) => MirDecl::User {
+                    local: FnLocal::new(id, 1),
+                    name: name.to_owned(),
+                    span: range(0, 1),
+                    ty,
+                    lives,
+                    shared_borrow: shared,
+                    mutable_borrow: mutable,
+                    drop: true,
+                    drop_range,
+                    must_live_at: Vec::new(),
+                    storage_range: Vec::new(),
+                    definitely_live_at: Vec::new(),
+                    maybe_init_at: Vec::new(),
+                },
+                None => MirDecl::Other {
+                    local: FnLocal::new(id, 1),
+                    ty,
+                    lives,
+                    shared_borrow: shared,
+                    mutable_borrow: mutable,
+                    drop: false,
+                    drop_range,
+                    must_live_at: Vec::new(),
+                    storage_range: Vec::new(),
+                    definitely_live_at: Vec::new(),
+                    maybe_init_at: Vec::new(),
+                },
+            }
+        };
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: Some(range(0, 30)),
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![MirBasicBlock {
+                statements: vec![MirStatement {
+                    kind: MirStatementKind::Assign {
+                        place: place(2),
+                        rval: MirRval::Use {
+                            operand: MirOperand::Move { place: place(1) },
+                        },
+                    },
+                    range: Some(range(5, 10)),
+                }],
+                terminator: MirTerminator {
+                    kind: MirTerminatorKind::Call {
+                        func: MirOperand::Other,
+                        args: vec![
+                            MirOperand::Move { place: place(2) },
+                            MirOperand::Copy { place: place(3) },
+                        ],
+                        destination: place(3),
+                        target: None,
+                        fn_range: None,
+                    },
+                    range: Some(range(12, 18)),
+                },
+                is_cleanup: false,
+            }],
+            decls: vec![
+                // shared and mutable borrows overlap at 8..10
+                decl(1, Some("s"), vec![range(2, 10)], vec![range(8, 14)]),
+                decl(2, None, Vec::new(), Vec::new()),
+            ],
+        };
+        let file = |items| File {
+            items,
+            generated: false,
+            std: false,
+        };
+        let ws = Workspace(OutputMap::from_iter([
+            (
+                crate_key("a", "0"),
+                Crate(OutputMap::from_iter([
+                    ("src/lib.rs".to_owned(), file(vec![func.clone()])),
+                    ("src/empty.rs".to_owned(), file(Vec::new())),
+                ])),
+            ),
+            (
+                crate_key("b", "1"),
+                Crate(OutputMap::from_iter([(
+                    "src/main.rs".to_owned(),
+                    file(vec![func.clone(), func]),
+                )])),
+            ),
+        ]));
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        write_workspace(&mut conn, &ws).unwrap();
+        assert_eq!(count(&conn, "SELECT count(*) FROM crates"), 2);
+        assert_eq!(count(&conn, "SELECT count(*) FROM files"), 3);
+        assert_eq!(count(&conn, "SELECT count(*) FROM functions"), 3);
+        assert_eq!(count(&conn, "SELECT count(*) FROM decls"), 6);
+        assert_eq!(
+            count(&conn, "SELECT count(*) FROM decls WHERE name IS NULL"),
+            3
+        );
+        // per function: 2 lives, 2 drops, 1 shared, 1 mutable and 1 conflict
+        assert_eq!(count(&conn, "SELECT count(*) FROM ranges"), 21);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT count(*) FROM ranges WHERE kind = 'borrow_conflict'"
+            ),
+            3
+        );
+        // `_1` by the statement and `_2` by the call, but not the copied `_3`
+        assert_eq!(count(&conn, "SELECT count(*) FROM moves"), 6);
+
+        let (path, drops): (String, i64) = conn
+            .query_row(
+                "SELECT files.path, count(*) AS drops FROM ranges
+                 JOIN decls ON decls.id = ranges.decl_id
+                 JOIN functions ON functions.id = decls.function_id
+                 JOIN files ON files.id = functions.file_id
+                 WHERE ranges.kind = 'drop' GROUP BY files.id ORDER BY drops DESC",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((path.as_str(), drops), ("src/main.rs", 4));
+    }
+}
@@ -2,6 +2,7 @@ use std::env;
 use std::fs::read_dir;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use tokio::fs::{create_dir_all, read_to_string, remove_dir_all, rename};
 
 use flate2::read::GzDecoder;
@@ -12,6 +13,43 @@ pub const HOST_TUPLE: &str = env!("HOST_TUPLE");
 const TOOLCHAIN_CHANNEL: &str = env!("TOOLCHAIN_CHANNEL");
 const TOOLCHAIN_DATE: Option<&str> = option_env!("TOOLCHAIN_DATE");
 
+/// Flag making `rustowlc` print [`rustowl_version`] instead of compiling
+pub const VERSION_PROBE_FLAG: &str = "--rustowl-version";
+
+/// Version of RustOwl and its output format, shared by `rustowl` and `rustowlc`
+pub fn rustowl_version() -> String {
+    format!(
+        "{}+output.{}",
+        env!("CARGO_PKG_VERSION"),
+        crate::models::OUTPUT_VERSION
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VersionMatch {
+    Same,
+    /// Built from another release, but writes the same output format
+    PackageDiffers,
+    /// Writes another output format
+    OutputDiffers,
+}
+
+/// Compare the [`rustowl_version`]s of `rustowl` and `rustowlc`
+pub fn compare_versions(expected: &str, actual: &str) -> VersionMatch {
+    let (expected, actual) = (expected.trim(), actual.trim());
+    let output = |v: &str| {
+        v.split_once("+output.")
+            .map(|(_, output)| output.to_owned())
+    };
+    if expected == actual {
+        VersionMatch::Same
+    } else if output(expected).is_some() && output(expected) == output(actual) {
+        VersionMatch::PackageDiffers
+    } else {
+        VersionMatch::OutputDiffers
+    }
+}
+
 pub static FALLBACK_RUNTIME_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     let opt = PathBuf::from("/opt/rustowl");
     if sysroot_from_runtime(&opt).is_dir() {
@@ -43,18 +81,88 @@ pub fn sysroot_from_runtime(runtime: impl AsRef<Path>) -> PathBuf {
     runtime.as_ref().join("sysroot").join(TOOLCHAIN)
 }
 
-async fn get_runtime_dir() -> PathBuf {
-    let sysroot = sysroot_from_runtime(&*FALLBACK_RUNTIME_DIR);
-    if FALLBACK_RUNTIME_DIR.is_dir() && sysroot.is_dir() {
-        return FALLBACK_RUNTIME_DIR.clone();
+/// Whether a missing toolchain is downloaded, unset by `--no-toolchain-download`
+static TOOLCHAIN_DOWNLOAD: AtomicBool = AtomicBool::new(true);
+
+/// Never download the toolchain; a missing one is an error telling how to install it
+pub fn disable_toolchain_download() {
+    TOOLCHAIN_DOWNLOAD.store(false, Ordering::Relaxed);
+}
+
+/// Worker threads of `rustowlc` set by `--analysis-threads`; 0 if unset
+static ANALYSIS_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Run the analysis of `rustowlc` on `threads` worker threads, passed to it
+/// as `RUSTOWL_ANALYSIS_THREADS`
+pub fn set_analysis_threads(threads: std::num::NonZeroUsize) {
+    ANALYSIS_THREADS.store(threads.get(), Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub enum ToolchainError {
+    /// The sysroot is missing and downloading it is disabled
+    Missing { sysroot: PathBuf },
+    /// Downloading or installing the toolchain failed, for the given cause
+    Setup(String),
+}
+
+impl std::fmt::Display for ToolchainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing { sysroot } => {
+                let runtime = sysroot.parent().and_then(|v| v.parent()).unwrap_or(sysroot);
+                writeln!(
+                    f,
+                    "toolchain not found at {}, and --no-toolchain-download is set",
+                    sysroot.display()
+                )?;
+                writeln!(f, "to install it, either")?;
+                writeln!(
+                    f,
+                    "  - run `rustowl toolchain install --path {}`, or",
+                    runtime.display()
+                )?;
+                write!(
+                    f,
+                    "  - place the `{TOOLCHAIN}` toolchain with the rustc, rust-std and cargo \
+                     components there, e.g. link the sysroot printed by \
+                     `rustc +{TOOLCHAIN_CHANNEL} --print sysroot`"
+                )
+            }
+            Self::Setup(cause) => write!(
+                f,
+                "failed to set up the toolchain: {cause}\nrun `rustowl toolchain install` to retry"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ToolchainError {}
+
+/// `runtime` if its sysroot exists, else after installing the toolchain
+/// into it unless `download` is disabled
+async fn find_or_setup_runtime(runtime: &Path, download: bool) -> Result<PathBuf, ToolchainError> {
+    let sysroot = sysroot_from_runtime(runtime);
+    if runtime.is_dir() && sysroot.is_dir() {
+        return Ok(runtime.to_path_buf());
+    }
+    if !download {
+        return Err(ToolchainError::Missing { sysroot });
     }
 
     log::info!("sysroot not found; start setup toolchain");
-    if let Err(e) = setup_toolchain(&*FALLBACK_RUNTIME_DIR, false).await {
-        log::error!("{e:?}");
-        std::process::exit(1);
-    } else {
-        FALLBACK_RUNTIME_DIR.clone()
+    setup_toolchain(runtime, false).await?;
+    Ok(runtime.to_path_buf())
+}
+
+async fn get_runtime_dir() -> PathBuf {
+    let download = TOOLCHAIN_DOWNLOAD.load(Ordering::Relaxed);
+    match find_or_setup_runtime(&FALLBACK_RUNTIME_DIR, download).await {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
     }
 }
 
@@ -62,61 +170,343 @@ pub async fn get_sysroot() -> PathBuf {
     sysroot_from_runtime(get_runtime_dir().await)
 }
 
-fn progress_bar_style() -> Result<indicatif::ProgressStyle, ()> {
+fn progress_bar_style() -> Result<indicatif::ProgressStyle, String> {
     use indicatif::*;
     Ok(
         ProgressStyle::with_template("{spinner:.green} {msg:<10} [{bar:30.cyan/blue}]  {pos:>3}%")
-            .map_err(|_| {
-                log::error!("failed to setup progress bar");
-            })?
+            .map_err(|e| format!("failed to setup progress bar: {e}"))?
             .progress_chars("#>-"),
     )
 }
 
-async fn download(url: &str, set_progress: impl Fn(usize)) -> Result<Vec<u8>, ()> {
-    log::debug!("start downloading {url}...");
-    let mut resp = match reqwest::get(url).await.and_then(|v| v.error_for_status()) {
-        Ok(v) => v,
-        Err(e) => {
-            log::error!("failed to download tarball");
-            log::error!("{e:?}");
-            return Err(());
+/// File where the partial download of `url` is kept for resuming.
+///
+/// It is keyed by the hash of the whole URL, as the file names of archives are
+/// the same across toolchain versions.
+fn partial_download_path(url: &str) -> PathBuf {
+    let file_name = url.rsplit('/').next().unwrap_or(url);
+    let key = &sha256_hex(url.as_bytes())[..16];
+    env::temp_dir()
+        .join("rustowl-downloads")
+        .join(format!("{key}-{file_name}.part"))
+}
+
+/// Lock the partial download at `partial_path`, waiting while another process
+/// downloads the same URL; released on drop
+async fn lock_partial_download(partial_path: &Path) -> Result<std::fs::File, DownloadError> {
+    if let Some(parent) = partial_path.parent() {
+        create_dir_all(parent).await.map_err(DownloadError::Io)?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(partial_path.with_extension("lock"))
+        .map_err(DownloadError::Io)?;
+    let mut waiting = false;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(std::fs::TryLockError::WouldBlock) => {
+                if !waiting {
+                    log::info!("waiting for another download of the same archive");
+                    waiting = true;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            }
+            Err(std::fs::TryLockError::Error(e)) => return Err(DownloadError::Io(e)),
+        }
+    }
+}
+
+/// `Range` header value to resume from `offset`
+fn range_header(offset: u64) -> Option<String> {
+    if 0 < offset {
+        Some(format!("bytes={offset}-"))
+    } else {
+        None
+    }
+}
+
+/// Offset where the response body starts.
+///
+/// The server may ignore the `Range` header and send the whole content,
+/// in which case the partial data must be discarded.
+fn resume_offset(requested: u64, status: reqwest::StatusCode) -> u64 {
+    if status == reqwest::StatusCode::PARTIAL_CONTENT {
+        requested
+    } else {
+        0
+    }
+}
+
+/// Total size from a `Content-Range` header value (e.g. `bytes 100-199/200`)
+fn total_size_from_content_range(value: &str) -> Option<u64> {
+    value.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// Why a download failed
+#[derive(Debug)]
+enum DownloadError {
+    /// The connection failed or was interrupted
+    Network(reqwest::Error),
+    /// The server responded with an error status
+    Status(reqwest::StatusCode),
+    /// The server sent another size than it announced
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The partial download file could not be read or written
+    Io(std::io::Error),
+}
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(e) => write!(f, "network error: {e}"),
+            Self::Status(status) => write!(f, "server responded with {status}"),
+            Self::SizeMismatch { expected, actual } => write!(
+                f,
+                "downloaded size mismatch: expected {expected} bytes, got {actual} bytes"
+            ),
+            Self::Io(e) => write!(f, "failed to access the partial download file: {e}"),
+        }
+    }
+}
+impl std::error::Error for DownloadError {}
+impl DownloadError {
+    /// Whether trying again may succeed
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Network(_) | Self::SizeMismatch { .. } => true,
+            Self::Status(status) => {
+                status.is_server_error() || *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Io(_) => false,
         }
+    }
+}
+
+/// Number of retries of a failed download, set by `RUSTOWL_DOWNLOAD_RETRIES`
+static DOWNLOAD_RETRIES: LazyLock<u32> =
+    LazyLock::new(|| parse_download_retries(env::var("RUSTOWL_DOWNLOAD_RETRIES").ok().as_deref()));
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+
+fn parse_download_retries(value: Option<&str>) -> u32 {
+    let Some(value) = value else {
+        return DEFAULT_DOWNLOAD_RETRIES;
     };
+    match value.trim().parse() {
+        Ok(retries) => retries,
+        Err(_) => {
+            log::warn!(
+                "invalid RUSTOWL_DOWNLOAD_RETRIES `{value}`; retry {DEFAULT_DOWNLOAD_RETRIES} times"
+            );
+            DEFAULT_DOWNLOAD_RETRIES
+        }
+    }
+}
 
-    let content_length = resp.content_length().unwrap_or(200_000_000) as usize;
-    let mut data = Vec::with_capacity(content_length);
-    let mut received = 0;
-    while let Some(chunk) = match resp.chunk().await {
-        Ok(v) => v,
-        Err(e) => {
-            log::error!("failed to download runtime archive");
-            log::error!("{e:?}");
-            return Err(());
+/// Wait before the retry following `attempt` failed attempts: 1s, 2s, 4s, ... up to 30s
+fn backoff_delay(attempt: u32) -> std::time::Duration {
+    const MAX_SECS: u64 = 30;
+    let secs = 1u64.checked_shl(attempt).unwrap_or(MAX_SECS);
+    std::time::Duration::from_secs(secs.min(MAX_SECS))
+}
+
+/// Download `url`, retrying with exponential backoff on failures that may be transient.
+/// Each retry resumes from the data already received.
+async fn download(url: &str, set_progress: impl Fn(usize)) -> Result<Vec<u8>, DownloadError> {
+    let partial_path = partial_download_path(url);
+    let _lock = lock_partial_download(&partial_path).await?;
+    let retries = *DOWNLOAD_RETRIES;
+    let mut attempt = 0;
+    loop {
+        match download_once(url, &partial_path, &set_progress).await {
+            Ok(data) => return Ok(data),
+            Err(e) if e.is_retryable() && attempt < retries => {
+                let delay = backoff_delay(attempt);
+                attempt += 1;
+                log::warn!(
+                    "failed to download {url}: {e}; retry {attempt}/{retries} in {}s",
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Download `url` once, resuming from the data at `partial_path`
+async fn download_once(
+    url: &str,
+    partial_path: &Path,
+    set_progress: &impl Fn(usize),
+) -> Result<Vec<u8>, DownloadError> {
+    use tokio::io::AsyncWriteExt;
+
+    log::debug!("start downloading {url}...");
+    let requested = tokio::fs::metadata(partial_path)
+        .await
+        .map(|v| v.len())
+        .unwrap_or(0);
+
+    let mut request = reqwest::Client::new().get(url);
+    if let Some(range) = range_header(requested) {
+        log::debug!("resume downloading {url} from {requested} bytes");
+        request = request.header(reqwest::header::RANGE, range);
+    }
+    let mut resp = match request.send().await {
+        Ok(v) if v.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE => {
+            // the partial data is broken or already complete; start over
+            log::debug!("range not satisfiable; restart downloading {url}");
+            tokio::fs::remove_file(partial_path).await.ok();
+            return Box::pin(download_once(url, partial_path, set_progress)).await;
         }
-    } {
-        data.extend_from_slice(&chunk);
-        let current = data.len() * 100 / content_length;
+        Ok(v) if !v.status().is_success() => return Err(DownloadError::Status(v.status())),
+        Ok(v) => v,
+        Err(e) => return Err(DownloadError::Network(e)),
+    };
+
+    let offset = resume_offset(requested, resp.status());
+    let total = resp
+        .headers()
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(total_size_from_content_range)
+        .or_else(|| resp.content_length().map(|v| v + offset));
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(0 < offset)
+        .truncate(offset == 0)
+        .open(&partial_path)
+        .await
+        .map_err(DownloadError::Io)?;
+
+    let content_length = total.unwrap_or(200_000_000) as usize;
+    let mut written = offset as usize;
+    // continue the progress from the resumed offset
+    let mut received = written * 100 / content_length;
+    set_progress(received);
+    while let Some(chunk) = resp.chunk().await.map_err(DownloadError::Network)? {
+        file.write_all(&chunk).await.map_err(DownloadError::Io)?;
+        written += chunk.len();
+        let current = written * 100 / content_length;
         if received != current {
             set_progress(current);
             log::debug!("received from {url}: {current:3}%");
             received = current;
         }
     }
+    file.flush().await.map_err(DownloadError::Io)?;
+    drop(file);
+
+    if let Some(total) = total
+        && total != written as u64
+    {
+        tokio::fs::remove_file(&partial_path).await.ok();
+        return Err(DownloadError::SizeMismatch {
+            expected: total,
+            actual: written as u64,
+        });
+    }
+    let data = tokio::fs::read(&partial_path)
+        .await
+        .map_err(DownloadError::Io)?;
+    tokio::fs::remove_file(&partial_path).await.ok();
     log::debug!("download finished");
     Ok(data)
 }
+/// Expected SHA-256 from a `.sha256` file, which reads `<hex digest>  <file name>`
+fn parse_sha256_file(content: &str) -> Option<String> {
+    let digest = content.split_whitespace().next()?;
+    if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(digest.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Compare the SHA-256 of `data` with `expected`, returning the actual one on mismatch
+fn verify_sha256(data: &[u8], expected: &str) -> Result<(), String> {
+    let actual = sha256_hex(data);
+    if actual.eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(actual)
+    }
+}
+
+/// Whether to skip checksum verification (`RUSTOWL_SKIP_CHECKSUM=1`),
+/// for mirrors which do not serve the `.sha256` files
+fn skip_checksum() -> bool {
+    env::var("RUSTOWL_SKIP_CHECKSUM").is_ok_and(|v| v == "1" || v == "true")
+}
+
+/// Where `.sha256` files are published next to the archives: the Rust
+/// distribution server. The RustOwl releases on GitHub do not publish them.
+const CHECKSUMMED_DIST: &str = "https://static.rust-lang.org/";
+
+/// Whether a `.sha256` file is published next to `url`
+fn publishes_checksum(url: &str) -> bool {
+    url.starts_with(CHECKSUMMED_DIST)
+}
+
+/// Check the downloaded `data` against the `.sha256` file published next to `url`,
+/// for the downloads which have one
+async fn verify_download(url: &str, data: &[u8]) -> Result<(), String> {
+    if skip_checksum() {
+        log::debug!("skip checksum verification of {url}");
+        return Ok(());
+    }
+    if !publishes_checksum(url) {
+        log::debug!("no checksum is published for {url}; skip verification");
+        return Ok(());
+    }
+    let checksum_url = format!("{url}.sha256");
+    let content = match reqwest::get(&checksum_url)
+        .await
+        .and_then(|v| v.error_for_status())
+    {
+        Ok(resp) => resp
+            .text()
+            .await
+            .map_err(|e| format!("failed to download {checksum_url}: {e}"))?,
+        Err(e) => {
+            return Err(format!(
+                "failed to download {checksum_url}: {e}; \
+                 set RUSTOWL_SKIP_CHECKSUM=1 to install without verification"
+            ));
+        }
+    };
+    let Some(expected) = parse_sha256_file(&content) else {
+        return Err(format!("invalid checksum file {checksum_url}"));
+    };
+    verify_sha256(data, &expected).map_err(|actual| {
+        format!("checksum mismatch of {url}: expected {expected}, but got {actual}")
+    })?;
+    log::debug!("checksum of {url} verified");
+    Ok(())
+}
+
 async fn download_tarball_and_extract(
     url: &str,
     dest: &Path,
     set_progress: impl Fn(usize),
-) -> Result<(), ()> {
-    let data = download(url, set_progress).await?;
+) -> Result<(), String> {
+    let data = download(url, set_progress)
+        .await
+        .map_err(|e| format!("failed to download {url}: {e}"))?;
+    verify_download(url, &data).await?;
     let decoder = GzDecoder::new(&*data);
     let mut archive = Archive::new(decoder);
-    archive.unpack(dest).map_err(|_| {
-        log::error!("failed to unpack tarball");
-    })?;
+    archive
+        .unpack(dest)
+        .map_err(|e| format!("failed to unpack {url}: {e}"))?;
     log::debug!("successfully unpacked");
     Ok(())
 }
@@ -125,22 +515,18 @@ async fn download_zip_and_extract(
     url: &str,
     dest: &Path,
     set_progress: impl Fn(usize),
-) -> Result<(), ()> {
+) -> Result<(), String> {
     use zip::ZipArchive;
-    let data = download(url, set_progress).await?;
+    let data = download(url, set_progress)
+        .await
+        .map_err(|e| format!("failed to download {url}: {e}"))?;
     let cursor = std::io::Cursor::new(&*data);
 
-    let mut archive = match ZipArchive::new(cursor) {
-        Ok(archive) => archive,
-        Err(e) => {
-            log::error!("failed to read ZIP archive");
-            log::error!("{e:?}");
-            return Err(());
-        }
-    };
-    archive.extract(dest).map_err(|e| {
-        log::error!("failed to unpack zip: {e}");
-    })?;
+    let mut archive =
+        ZipArchive::new(cursor).map_err(|e| format!("failed to read ZIP archive {url}: {e}"))?;
+    archive
+        .extract(dest)
+        .map_err(|e| format!("failed to unpack {url}: {e}"))?;
     log::debug!("successfully unpacked");
     Ok(())
 }
@@ -148,7 +534,7 @@ async fn download_zip_and_extract(
 async fn install_components(
     components: impl IntoIterator<Item = impl AsRef<str>>,
     dest: PathBuf,
-) -> Result<(), ()> {
+) -> Result<(), String> {
     use indicatif::*;
     let m = MultiProgress::new();
 
@@ -161,10 +547,12 @@ async fn install_components(
         let pb = m.add(ProgressBar::new(100));
         pb.set_style(progress_bar_style()?);
         pb.set_message(component.clone());
+        let name = component.clone();
 
         let dest = dest.clone();
         let handle = tokio::spawn(async move {
-            let tempdir = tempfile::tempdir().map_err(|_| ())?;
+            let tempdir = tempfile::tempdir()
+                .map_err(|e| format!("failed to create a temporary directory: {e}"))?;
             // Using `tempdir.path()` more than once causes SEGV, so we use `tempdir.path().to_owned()`.
             let temp_path = tempdir.path().to_owned();
             log::debug!("temp dir is made: {}", temp_path.display());
@@ -184,76 +572,78 @@ async fn install_components(
             let extracted_path = temp_path.join(&component_toolchain);
             let components = read_to_string(extracted_path.join("components"))
                 .await
-                .map_err(|_| {
-                    log::error!("failed to read components list");
-                })?;
+                .map_err(|e| format!("failed to read components list: {e}"))?;
             let components = components.split_whitespace();
 
             for component in components {
                 let component_path = extracted_path.join(component);
                 for from in recursive_read_dir(&component_path) {
-                    let rel_path = match from.strip_prefix(&component_path) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            log::error!("path error: {e}");
-                            return Err(());
-                        }
-                    };
+                    let rel_path = from
+                        .strip_prefix(&component_path)
+                        .map_err(|e| format!("path error: {e}"))?;
                     let to = dest.join(rel_path);
-                    if let Err(e) = create_dir_all(to.parent().unwrap()).await {
-                        log::error!("failed to create dir: {e}");
-                        return Err(());
-                    }
+                    create_dir_all(to.parent().unwrap())
+                        .await
+                        .map_err(|e| format!("failed to create dir: {e}"))?;
                     if let Err(e) = rename(&from, &to).await {
                         log::warn!("file rename failed: {e}, falling back to copy and delete");
-                        if let Err(copy_err) = tokio::fs::copy(&from, &to).await {
-                            log::error!("file copy error (after rename failure): {copy_err}");
-                            return Err(());
-                        }
-                        if let Err(del_err) = tokio::fs::remove_file(&from).await {
-                            log::error!("file delete error (after copy): {del_err}");
-                            return Err(());
-                        }
+                        tokio::fs::copy(&from, &to)
+                            .await
+                            .map_err(|e| format!("file copy error (after rename failure): {e}"))?;
+                        tokio::fs::remove_file(&from)
+                            .await
+                            .map_err(|e| format!("file delete error (after copy): {e}"))?;
                     }
                 }
                 log::debug!("component {component} successfully installed");
             }
             pb.finish_and_clear();
-            Ok(())
+            Ok::<_, String>(())
         });
-        threads.push(handle);
+        threads.push((name, handle));
     }
-    for thread in threads {
-        if let Ok(res) = thread.await {
-            if res.is_err() {
-                log::error!("failed to install component")
-            }
+    // wait for every component, reporting the first failure
+    let mut result = Ok(());
+    for (component, thread) in threads {
+        let failure = match thread.await {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) => format!("failed to install component {component}: {e}"),
+            Err(e) => format!("failed to join installation task of {component}: {e}"),
+        };
+        if result.is_ok() {
+            result = Err(failure);
         } else {
-            log::error!("failed to join component installation task");
+            log::error!("{failure}");
         }
     }
-    Ok(())
+    result
 }
-pub async fn setup_toolchain(dest: impl AsRef<Path>, skip_rustowl: bool) -> Result<(), ()> {
-    setup_rust_toolchain(&dest).await?;
+pub async fn setup_toolchain(
+    dest: impl AsRef<Path>,
+    skip_rustowl: bool,
+) -> Result<(), ToolchainError> {
+    setup_rust_toolchain(&dest)
+        .await
+        .map_err(ToolchainError::Setup)?;
     if !skip_rustowl {
-        setup_rustowl_toolchain(&dest).await?;
+        setup_rustowl_toolchain(&dest)
+            .await
+            .map_err(ToolchainError::Setup)?;
     }
     Ok(())
 }
-pub async fn setup_rust_toolchain(dest: impl AsRef<Path>) -> Result<(), ()> {
+pub async fn setup_rust_toolchain(dest: impl AsRef<Path>) -> Result<(), String> {
     let sysroot = sysroot_from_runtime(dest.as_ref());
-    if create_dir_all(&sysroot).await.is_err() {
-        log::error!("failed to create toolchain directory");
-        return Err(());
-    }
+    create_dir_all(&sysroot)
+        .await
+        .map_err(|e| format!("failed to create toolchain directory: {e}"))?;
 
     log::info!("start installing Rust toolchain...");
     install_components(&["rustc", "rust-std", "cargo"], sysroot).await?;
     log::info!("installing Rust toolchain finished");
     Ok(())
 }
-pub async fn setup_rustowl_toolchain(dest: impl AsRef<Path>) -> Result<(), ()> {
+pub async fn setup_rustowl_toolchain(dest: impl AsRef<Path>) -> Result<(), String> {
     let pb = indicatif::ProgressBar::new(100);
     pb.set_style(progress_bar_style()?);
 
@@ -281,10 +671,11 @@ pub async fn setup_rustowl_toolchain(dest: impl AsRef<Path>) -> Result<(), ()> {
         .await
     };
     pb.finish_and_clear();
-    if rustowl_toolchain_result.is_ok() {
-        log::info!("installing RustOwl toolchain finished");
-    } else {
-        log::warn!("could not install RustOwl toolchain; local installed rustowlc will be used");
+    match rustowl_toolchain_result {
+        Ok(()) => log::info!("installing RustOwl toolchain finished"),
+        Err(e) => log::warn!(
+            "could not install RustOwl toolchain: {e}; local installed rustowlc will be used"
+        ),
     }
     Ok(())
 }
@@ -328,44 +719,189 @@ pub async fn get_executable_path(name: &str) -> String {
     exec_name.to_owned()
 }
 
+/// Path of `rustowlc`, which the `RUSTOWLC` env var overrides
+async fn rustowlc_path() -> String {
+    match env::var("RUSTOWLC") {
+        Ok(v) => v,
+        Err(_) => get_executable_path("rustowlc").await,
+    }
+}
+
+static RUSTOWLC_VERIFIED: AtomicBool = AtomicBool::new(false);
+
+/// Warn once if the resolved `rustowlc` is not the one built with this `rustowl`,
+/// such as a stale one left in `PATH` by a previous install
+pub async fn verify_rustowlc() {
+    if RUSTOWLC_VERIFIED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let rustowlc = rustowlc_path().await;
+    let mut command = tokio::process::Command::new(&rustowlc);
+    command.arg(VERSION_PROBE_FLAG);
+    set_rustc_env(&mut command, &get_sysroot().await);
+    let actual = match command.output().await {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        _ => {
+            log::warn!("failed to get the version of {rustowlc}; it may be stale");
+            return;
+        }
+    };
+    let expected = rustowl_version();
+    match compare_versions(&expected, &actual) {
+        VersionMatch::Same => {}
+        VersionMatch::PackageDiffers => log::warn!(
+            "{rustowlc} is version {}, but rustowl is {expected}",
+            actual.trim()
+        ),
+        VersionMatch::OutputDiffers => log::error!(
+            "{rustowlc} is version {}, which writes output unreadable by rustowl {expected}; reinstall RustOwl",
+            actual.trim()
+        ),
+    }
+}
+
+/// Oldest cargo reading `CARGO_ENCODED_RUSTFLAGS`, which passes the sysroot to `rustowlc`
+const MIN_CARGO_VERSION: (u32, u32) = (1, 55);
+
+/// `(major, minor)` of the output of `cargo -V`, such as `cargo 1.97.0-nightly (eb94155a9 2026-04-09)`
+fn parse_cargo_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.trim().strip_prefix("cargo ")?;
+    let mut parts = version.split(['.', '-', ' ']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Check that `cargo` runs and is recent enough, with advice on how to fix it otherwise
+pub async fn check_cargo(cargo: &str) -> Result<(), String> {
+    let advice =
+        "install the toolchain with `rustowl toolchain install`, or put a recent cargo in PATH";
+    let output = match tokio::process::Command::new(cargo).arg("-V").output().await {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            return Err(format!(
+                "`{cargo} -V` failed with {}; {advice}",
+                output.status
+            ));
+        }
+        Err(e) => return Err(format!("failed to run cargo at `{cargo}`: {e}; {advice}")),
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+    let Some(version) = parse_cargo_version(&output) else {
+        return Err(format!(
+            "`{cargo}` does not look like cargo: `{}`; {advice}",
+            output.trim()
+        ));
+    };
+    if version < MIN_CARGO_VERSION {
+        return Err(format!(
+            "{} is too old; RustOwl needs cargo {}.{} or newer; {advice}",
+            output.trim(),
+            MIN_CARGO_VERSION.0,
+            MIN_CARGO_VERSION.1
+        ));
+    }
+    Ok(())
+}
+
+/// [`check_cargo`] for the cargo used by [`setup_cargo_command`]
+pub async fn verify_cargo() -> Result<(), String> {
+    check_cargo(&get_executable_path("cargo").await).await
+}
+
 pub async fn setup_cargo_command() -> tokio::process::Command {
+    setup_cargo_command_with_cfgs(&[]).await
+}
+
+/// [`setup_cargo_command`] compiling with `--cfg <spec>` for each of `cfgs`
+pub async fn setup_cargo_command_with_cfgs(cfgs: &[String]) -> tokio::process::Command {
     let cargo = get_executable_path("cargo").await;
     let mut command = tokio::process::Command::new(&cargo);
-    let rustowlc = get_executable_path("rustowlc").await;
-
-    // check user set flags
-    let delimiter = 0x1f as char;
-    let rustflags = env::var("RUSTFLAGS")
-        .unwrap_or("".to_string())
-        .split_whitespace()
-        .fold("".to_string(), |acc, x| format!("{acc}{delimiter}{x}"));
-    let encoded_flags = env::var("CARGO_ENCODED_RUSTFLAGS")
-        .map(|v| format!("{v}{delimiter}"))
-        .unwrap_or("".to_string());
 
     let sysroot = get_sysroot().await;
     // use `RUSTOWLC` and `RUSTOWLC_WORKSPACE_WRAPPER` env var to configure `rustowlc` path
-    let rustowlc = env::var("RUSTOWLC").unwrap_or(rustowlc);
+    let rustowlc = rustowlc_path().await;
     let rustowlc_workspace = env::var("RUSTOWLC_WORKSPACE_WRAPPER").unwrap_or(rustowlc.clone());
     command
         .env("RUSTC", &rustowlc)
         .env("RUSTC_WORKSPACE_WRAPPER", &rustowlc_workspace)
         .env(
             "CARGO_ENCODED_RUSTFLAGS",
-            format!(
-                "{}--sysroot={}{}",
-                encoded_flags,
-                sysroot.display(),
-                rustflags
+            encode_rustflags(
+                env::var("CARGO_ENCODED_RUSTFLAGS").ok().as_deref(),
+                env::var("RUSTFLAGS").ok().as_deref(),
+                env::var("RUSTOWL_RUSTFLAGS").ok().as_deref(),
+                &sysroot,
+                cfgs,
             ),
         );
     set_rustc_env(&mut command, &sysroot);
     command
 }
 
+/// Flags of `rustowlc` for humans reading its output directly, which change
+/// the output so that `rustowl` cannot read it line by line
+pub const RUSTOWLC_OUTPUT_FLAGS: &[&str] = &["--pretty", "--decorations-only", "--byte-ranges"];
+
+/// Flags of `RUSTOWL_RUSTFLAGS`, except those of [`RUSTOWLC_OUTPUT_FLAGS`]
+pub fn rustowl_rustflags(flags: &str) -> impl Iterator<Item = &str> {
+    flags.split_whitespace().filter(|flag| {
+        let output = RUSTOWLC_OUTPUT_FLAGS.contains(flag);
+        if output {
+            log::warn!("ignore `{flag}` of RUSTOWL_RUSTFLAGS, whose output rustowl cannot read");
+        }
+        !output
+    })
+}
+
+/// `CARGO_ENCODED_RUSTFLAGS` for `rustowlc`: the user's encoded flags, the
+/// sysroot, the user's `RUSTFLAGS`, the flags of `RUSTOWL_RUSTFLAGS` meant for
+/// the analysis only, and then `--cfg <spec>` for each of `cfgs`, separated by `0x1f`
+pub fn encode_rustflags(
+    encoded: Option<&str>,
+    rustflags: Option<&str>,
+    rustowl_rustflags: Option<&str>,
+    sysroot: &Path,
+    cfgs: &[String],
+) -> String {
+    let delimiter = 0x1f as char;
+    let mut flags = match encoded {
+        Some(encoded) => format!("{encoded}{delimiter}"),
+        None => String::new(),
+    };
+    flags.push_str(&format!("--sysroot={}", sysroot.display()));
+    for flag in rustflags
+        .into_iter()
+        .flat_map(|v| v.split_whitespace())
+        .chain(
+            rustowl_rustflags
+                .into_iter()
+                .flat_map(self::rustowl_rustflags),
+        )
+    {
+        flags.push(delimiter);
+        flags.push_str(flag);
+    }
+    for cfg in cfgs {
+        flags.push(delimiter);
+        flags.push_str("--cfg");
+        flags.push(delimiter);
+        flags.push_str(cfg);
+    }
+    flags
+}
+
 pub fn set_rustc_env(command: &mut tokio::process::Command, sysroot: &Path) {
     command.env("RUSTC_BOOTSTRAP", "1"); // Support nightly projects
     command.env("RUST_LOG", log::max_level().to_string());
+    match ANALYSIS_THREADS.load(Ordering::Relaxed) {
+        0 => {}
+        threads => {
+            command.env("RUSTOWL_ANALYSIS_THREADS", threads.to_string());
+        }
+    }
 
     #[cfg(target_os = "linux")]
     {
@@ -393,3 +929,244 @@ pub fn set_rustc_env(command: &mut tokio::process::Command, sysroot: &Path) {
         command.env("Path", paths);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rustflags_encoding() {
+        let sysroot = Path::new("/owl/sysroot");
+        assert_eq!(
+            encode_rustflags(None, None, None, sysroot, &[]),
+            "--sysroot=/owl/sysroot"
+        );
+        assert_eq!(
+            encode_rustflags(
+                Some("-Cdebuginfo=0"),
+                Some(" -Dwarnings  --cap-lints=warn "),
+                Some("-Zcrate-attr=feature(never_type) --pretty --decorations-only"),
+                sysroot,
+                &["tokio_unstable".to_owned(), r#"feature="serde""#.to_owned()],
+            ),
+            [
+                "-Cdebuginfo=0",
+                "--sysroot=/owl/sysroot",
+                "-Dwarnings",
+                "--cap-lints=warn",
+                "-Zcrate-attr=feature(never_type)",
+                "--cfg",
+                "tokio_unstable",
+                "--cfg",
+                r#"feature="serde""#,
+            ]
+            .join("\x1f")
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_toolchain_without_download() {
+        let runtime = tempfile::tempdir().unwrap();
+        let sysroot = sysroot_from_runtime(runtime.path());
+        let err = find_or_setup_runtime(runtime.path(), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, ToolchainError::Missing { sysroot: v } if *v == sysroot));
+        let message = err.to_string();
+        assert!(message.contains(&sysroot.display().to_string()));
+        assert!(message.contains(&format!(
+            "rustowl toolchain install --path {}",
+            runtime.path().display()
+        )));
+
+        std::fs::create_dir_all(&sysroot).unwrap();
+        let found = find_or_setup_runtime(runtime.path(), false).await.unwrap();
+        assert_eq!(found, runtime.path());
+    }
+
+    #[test]
+    fn setup_error_names_its_cause() {
+        let err = ToolchainError::Setup("failed to download https://example.com: timed out".into());
+        let message = err.to_string();
+        assert!(message.contains("failed to download https://example.com: timed out"));
+        assert!(message.contains("rustowl toolchain install"));
+    }
+
+    #[test]
+    fn range_header_construction() {
+        assert_eq!(range_header(0), None);
+        assert_eq!(range_header(1024).as_deref(), Some("bytes=1024-"));
+    }
+
+    #[tokio::test]
+    async fn partial_downloads_are_kept_apart() {
+        // the same archive name of two toolchain versions
+        let old = "https://static.rust-lang.org/dist/2025-01-01/cargo-nightly.tar.gz";
+        let new = "https://static.rust-lang.org/dist/2025-02-01/cargo-nightly.tar.gz";
+        assert_ne!(partial_download_path(old), partial_download_path(new));
+        assert_eq!(partial_download_path(old), partial_download_path(old));
+        assert!(
+            partial_download_path(old)
+                .to_string_lossy()
+                .ends_with("-cargo-nightly.tar.gz.part")
+        );
+
+        // a concurrent download of the same URL waits for the lock
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("owl.tar.gz.part");
+        let lock = lock_partial_download(&path).await.unwrap();
+        let waiting = tokio::time::timeout(
+            std::time::Duration::from_millis(300),
+            lock_partial_download(&path),
+        );
+        assert!(waiting.await.is_err());
+        drop(lock);
+        lock_partial_download(&path).await.unwrap();
+    }
+
+    #[test]
+    fn resume_offset_follows_status() {
+        use reqwest::StatusCode;
+        assert_eq!(resume_offset(1024, StatusCode::PARTIAL_CONTENT), 1024);
+        // server does not support range requests and sends the whole content
+        assert_eq!(resume_offset(1024, StatusCode::OK), 0);
+        assert_eq!(resume_offset(0, StatusCode::OK), 0);
+    }
+
+    #[test]
+    fn content_range_total_size() {
+        assert_eq!(
+            total_size_from_content_range("bytes 100-199/200"),
+            Some(200)
+        );
+        assert_eq!(total_size_from_content_range("bytes 100-199/*"), None);
+    }
+
+    #[test]
+    fn download_backoff() {
+        use std::time::Duration;
+        assert_eq!(backoff_delay(0), Duration::from_secs(1));
+        assert_eq!(backoff_delay(1), Duration::from_secs(2));
+        assert_eq!(backoff_delay(2), Duration::from_secs(4));
+        assert_eq!(backoff_delay(4), Duration::from_secs(16));
+        // capped, without overflowing
+        assert_eq!(backoff_delay(5), Duration::from_secs(30));
+        assert_eq!(backoff_delay(64), Duration::from_secs(30));
+        assert_eq!(backoff_delay(u32::MAX), Duration::from_secs(30));
+
+        assert_eq!(parse_download_retries(None), 3);
+        assert_eq!(parse_download_retries(Some("0")), 0);
+        assert_eq!(parse_download_retries(Some(" 5 ")), 5);
+        assert_eq!(parse_download_retries(Some("many")), 3);
+
+        use reqwest::StatusCode;
+        assert!(DownloadError::Status(StatusCode::BAD_GATEWAY).is_retryable());
+        assert!(DownloadError::Status(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!DownloadError::Status(StatusCode::NOT_FOUND).is_retryable());
+        assert!(
+            DownloadError::SizeMismatch {
+                expected: 2,
+                actual: 1
+            }
+            .is_retryable()
+        );
+        assert!(!DownloadError::Io(std::io::Error::other("disk full")).is_retryable());
+    }
+
+    #[test]
+    fn checksum_verification() {
+        // SHA-256 of `hello`
+        let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let file = format!("{hello}  rustc-nightly-x86_64-unknown-linux-gnu.tar.gz\n");
+        assert_eq!(parse_sha256_file(&file).as_deref(), Some(hello));
+        assert_eq!(
+            parse_sha256_file(&hello.to_ascii_uppercase()).as_deref(),
+            Some(hello)
+        );
+        assert_eq!(parse_sha256_file("<html>not found</html>"), None);
+        assert_eq!(parse_sha256_file(""), None);
+
+        assert_eq!(verify_sha256(b"hello", hello), Ok(()));
+        assert_eq!(verify_sha256(b"hello", &hello.to_ascii_uppercase()), Ok(()));
+        // truncated download
+        assert_eq!(verify_sha256(b"hell", hello), Err(sha256_hex(b"hell")));
+
+        assert!(publishes_checksum(
+            "https://static.rust-lang.org/dist/rustc-nightly-x86_64-unknown-linux-gnu.tar.gz"
+        ));
+        assert!(!publishes_checksum(
+            "https://github.com/cordx56/rustowl/releases/download/v1.0.0/rustowl-x86_64-unknown-linux-gnu.tar.gz"
+        ));
+    }
+
+    #[test]
+    fn rustowlc_version_comparison() {
+        let version = rustowl_version();
+        assert!(version.ends_with(crate::models::OUTPUT_VERSION));
+        assert_eq!(
+            compare_versions(&version, &format!("{version}\n")),
+            VersionMatch::Same
+        );
+        assert_eq!(
+            compare_versions("1.0.0+output.1.3", "0.9.0+output.1.3"),
+            VersionMatch::PackageDiffers
+        );
+        assert_eq!(
+            compare_versions("1.0.0+output.1.3", "1.0.0+output.1.2"),
+            VersionMatch::OutputDiffers
+        );
+        // an old `rustowlc` does not know the probe and prints something else
+        assert_eq!(
+            compare_versions("1.0.0+output.1.3", "rustc 1.95.0-nightly"),
+            VersionMatch::OutputDiffers
+        );
+    }
+
+    #[test]
+    fn cargo_version_parsing() {
+        assert_eq!(
+            parse_cargo_version("cargo 1.97.0-nightly (eb94155a9 2026-04-09)\n"),
+            Some((1, 97))
+        );
+        assert_eq!(parse_cargo_version("cargo 1.54.0"), Some((1, 54)));
+        assert_eq!(parse_cargo_version("rustc 1.97.0"), None);
+        assert_eq!(parse_cargo_version("cargo nightly"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cargo_presence_and_version() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let fake_cargo = |name: &str, script: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().to_string()
+        };
+
+        let current = fake_cargo(
+            "current",
+            "echo 'cargo 1.97.0-nightly (eb94155a9 2026-04-09)'",
+        );
+        assert_eq!(check_cargo(&current).await, Ok(()));
+
+        let old = fake_cargo("old", "echo 'cargo 1.50.0 (f04e7fab7 2021-02-04)'");
+        let error = check_cargo(&old).await.unwrap_err();
+        assert!(error.contains("too old"), "{error}");
+
+        let other = fake_cargo("other", "echo 'something else'");
+        let error = check_cargo(&other).await.unwrap_err();
+        assert!(error.contains("does not look like cargo"), "{error}");
+
+        let failing = fake_cargo("failing", "exit 1");
+        let error = check_cargo(&failing).await.unwrap_err();
+        assert!(error.contains("failed"), "{error}");
+
+        let missing = dir.path().join("missing").to_string_lossy().to_string();
+        let error = check_cargo(&missing).await.unwrap_err();
+        assert!(error.contains("failed to run cargo"), "{error}");
+        assert!(error.contains("rustowl toolchain install"), "{error}");
+    }
+}
@@ -1,18 +1,18 @@
 use crate::models::*;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use unicode_segmentation::UnicodeSegmentation;
 
+/// Whether `r1` strictly contains `r2`
 pub fn is_super_range(r1: Range, r2: Range) -> bool {
-    (r1.from() < r2.from() && r2.until() <= r1.until())
-        || (r1.from() <= r2.from() && r2.until() < r1.until())
+    r1 != r2 && r1.contains(r2.from()) && r2.until() <= r1.until()
 }
 
 pub fn common_range(r1: Range, r2: Range) -> Option<Range> {
-    if r2.from() < r1.from() {
-        return common_range(r2, r1);
-    }
-    if r1.until() <= r2.from() {
+    if !r1.overlaps(r2) {
         return None;
     }
-    let from = r2.from();
+    let from = r1.from().max(r2.from());
     let until = r1.until().min(r2.until());
     Range::new(from, until)
 }
@@ -60,6 +60,33 @@ pub fn eliminated_ranges(mut ranges: Vec<Range>) -> Vec<Range> {
     ranges
 }
 
+/// Merge ranges separated by a gap of at most `tolerance`, such as the boundary
+/// between two statements, so that a continuously live region is one range.
+///
+/// With `tolerance` of 0, only overlapping or adjacent ranges are merged.
+pub fn coalesce_ranges(ranges: Vec<Range>, tolerance: u32) -> Vec<Range> {
+    let mut ranges = eliminated_ranges(ranges);
+    ranges.sort_by_key(|v| v.from());
+    let mut coalesced: Vec<Range> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if let Some(last) = coalesced.last_mut()
+            && range.from().0 - last.until().0 <= tolerance
+            && let Some(merged) = Range::new(last.from(), last.until().max(range.until()))
+        {
+            *last = merged;
+        } else {
+            coalesced.push(range);
+        }
+    }
+    coalesced
+}
+
+/// Canonical form of a range set: sorted, with overlapping and touching
+/// ranges merged, so that equivalent sets compare equal.
+pub fn normalize_ranges(ranges: Vec<Range>) -> Vec<Range> {
+    coalesce_ranges(ranges, 0)
+}
+
 /// Compute intersection of two range lists.
 /// Returns ranges that are covered by both lists.
 pub fn intersect_ranges(ranges1: Vec<Range>, ranges2: Vec<Range>) -> Vec<Range> {
@@ -82,33 +109,44 @@ pub fn union_ranges(ranges1: Vec<Range>, ranges2: Vec<Range>) -> Vec<Range> {
     eliminated_ranges(combined)
 }
 
-pub fn exclude_ranges(mut from: Vec<Range>, excludes: Vec<Range>) -> Vec<Range> {
-    let mut i = 0;
-    'outer: while i < from.len() {
-        let mut j = 0;
-        while j < excludes.len() {
-            if let Some(common) = common_range(from[i], excludes[j]) {
-                if let Some(r) = Range::new(from[i].from(), common.from() - 1) {
-                    from.push(r);
-                }
-                if let Some(r) = Range::new(common.until() + 1, from[i].until()) {
-                    from.push(r);
-                }
-                from.remove(i);
-                continue 'outer;
+/// Remove `excludes` from `from`, sorted by position.
+///
+/// A range is cut around each exclude overlapping it, along with one position
+/// on each side of the exclude. Overlapping and touching excludes are merged
+/// first, so that a single sweep over them covers every range.
+pub fn exclude_ranges(from: Vec<Range>, mut excludes: Vec<Range>) -> Vec<Range> {
+    excludes.sort_by_key(|v| v.from());
+    let excludes: Vec<_> = RangeSet::from(excludes).into();
+    let mut pieces = Vec::with_capacity(from.len());
+    for range in from {
+        let mut cursor = range.from();
+        let first = excludes.partition_point(|v| v.until() <= range.from());
+        for exclude in excludes[first..]
+            .iter()
+            .take_while(|v| v.from() < range.until())
+        {
+            if let Some(r) = Range::new(cursor, exclude.from() - 1) {
+                pieces.push(r);
             }
-            j += 1;
+            cursor = cursor.max(exclude.until() + 1);
+        }
+        if let Some(r) = Range::new(cursor, range.until()) {
+            pieces.push(r);
         }
-        i += 1;
     }
-    eliminated_ranges(from)
+    pieces.sort_by_key(|v| v.from());
+    RangeSet::from(pieces).into()
 }
 
 #[allow(unused)]
 pub trait MirVisitor {
     fn visit_func(&mut self, func: &Function) {}
     fn visit_decl(&mut self, decl: &MirDecl) {}
+    /// Called at the start of each basic block, before its statements
+    fn visit_basic_block(&mut self, bb: &MirBasicBlock) {}
     fn visit_stmt(&mut self, stmt: &MirStatement) {}
+    /// Called with the right-hand side of each assignment, after [`Self::visit_stmt`]
+    fn visit_rval(&mut self, rval: &MirRval) {}
     fn visit_term(&mut self, term: &MirTerminator) {}
 }
 pub fn mir_visit(func: &Function, visitor: &mut impl MirVisitor) {
@@ -117,8 +155,12 @@ pub fn mir_visit(func: &Function, visitor: &mut impl MirVisitor) {
         visitor.visit_decl(decl);
     }
     for bb in &func.basic_blocks {
+        visitor.visit_basic_block(bb);
         for stmt in &bb.statements {
             visitor.visit_stmt(stmt);
+            if let MirStatementKind::Assign { rval, .. } = &stmt.kind {
+                visitor.visit_rval(rval);
+            }
         }
         visitor.visit_term(&bb.terminator);
     }
@@ -177,6 +219,28 @@ pub fn index_to_line_char(s: &str, idx: Loc) -> (u32, u32) {
     // or when idx is out of bounds
     (line, col)
 }
+/// Same as [`index_to_line_char`], but a tab advances the column
+/// to the next multiple of `tab_width` as editors display it.
+pub fn index_to_line_char_tabs(s: &str, idx: Loc, tab_width: u32) -> (u32, u32) {
+    let tab_width = tab_width.max(1);
+    let mut line = 0;
+    let mut col = 0;
+    // it seems that the compiler is ignoring CR
+    for (i, c) in s.chars().filter(|c| *c != '\r').enumerate() {
+        if idx == Loc::from(i as u32) {
+            return (line, col);
+        }
+        match c {
+            '\n' => {
+                line += 1;
+                col = 0;
+            }
+            '\t' => col = (col / tab_width + 1) * tab_width,
+            _ => col += 1,
+        }
+    }
+    (line, col)
+}
 pub fn line_char_to_index(s: &str, mut line: u32, char: u32) -> u32 {
     let mut col = 0;
     // it seems that the compiler is ignoring CR
@@ -193,3 +257,832 @@ pub fn line_char_to_index(s: &str, mut line: u32, char: u32) -> u32 {
     }
     0
 }
+/// Same as [`index_to_line_char`], but the column counts UTF-16 code units,
+/// which is the default for `Position.character` in LSP
+pub fn index_to_line_utf16(s: &str, idx: Loc) -> (u32, u32) {
+    index_to_line_units(s, idx, char::len_utf16)
+}
+/// Inverse of [`index_to_line_utf16`]; a column in the middle of
+/// a surrogate pair is taken as the following character
+pub fn line_utf16_to_index(s: &str, line: u32, col: u32) -> u32 {
+    line_units_to_index(s, line, col, char::len_utf16)
+}
+/// Same as [`index_to_line_char`], but the column counts UTF-8 bytes
+pub fn index_to_line_utf8(s: &str, idx: Loc) -> (u32, u32) {
+    index_to_line_units(s, idx, char::len_utf8)
+}
+/// Inverse of [`index_to_line_utf8`]; a column in the middle of
+/// a character is taken as the following character
+pub fn line_utf8_to_index(s: &str, line: u32, col: u32) -> u32 {
+    line_units_to_index(s, line, col, char::len_utf8)
+}
+/// Same as [`index_to_line_char`], but the column counts extended grapheme
+/// clusters, e.g. an emoji with a skin tone or a letter with combining marks is
+/// one column. A char inside a cluster is at the column of the cluster.
+pub fn index_to_line_grapheme(s: &str, idx: Loc) -> (u32, u32) {
+    let mut line = 0;
+    let mut col = 0;
+    let mut i = 0;
+    // it seems that the compiler is ignoring CR
+    for g in s.replace('\r', "").graphemes(true) {
+        let len = g.chars().count() as u32;
+        if (i..i + len).contains(&idx.0) {
+            return (line, col);
+        }
+        if g == "\n" {
+            line += 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+        i += len;
+    }
+    (line, col)
+}
+/// Inverse of [`index_to_line_grapheme`], the index of the first char of the cluster
+pub fn line_grapheme_to_index(s: &str, mut line: u32, target: u32) -> u32 {
+    let mut col = 0;
+    let mut i = 0;
+    // it seems that the compiler is ignoring CR
+    for g in s.replace('\r', "").graphemes(true) {
+        if line == 0 && target <= col {
+            return i;
+        }
+        if g == "\n" && 0 < line {
+            line -= 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+        i += g.chars().count() as u32;
+    }
+    0
+}
+/// Line and column of `idx`, the column counted in `units` of each char
+fn index_to_line_units(s: &str, idx: Loc, units: fn(char) -> usize) -> (u32, u32) {
+    let mut line = 0;
+    let mut col = 0;
+    // it seems that the compiler is ignoring CR
+    for (i, c) in s.chars().filter(|c| *c != '\r').enumerate() {
+        if idx == Loc::from(i as u32) {
+            return (line, col);
+        }
+        if c == '\n' {
+            line += 1;
+            col = 0;
+        } else {
+            col += units(c) as u32;
+        }
+    }
+    (line, col)
+}
+fn line_units_to_index(s: &str, mut line: u32, target: u32, units: fn(char) -> usize) -> u32 {
+    let mut col = 0;
+    // it seems that the compiler is ignoring CR
+    for (i, c) in s.chars().filter(|c| *c != '\r').enumerate() {
+        if line == 0 && target <= col {
+            return i as u32;
+        }
+        if c == '\n' && 0 < line {
+            line -= 1;
+            col = 0;
+        } else {
+            col += units(c) as u32;
+        }
+    }
+    0
+}
+
+/// [`Loc`] to byte offset map of a source, built once per file
+pub struct ByteIndex {
+    /// byte offset of each char except CR, followed by the source length
+    offsets: Vec<u32>,
+}
+impl ByteIndex {
+    pub fn new(source: &str) -> Self {
+        // it seems that the compiler is ignoring CR
+        let offsets = source
+            .char_indices()
+            .filter(|(_, c)| *c != '\r')
+            .map(|(i, _)| i as u32)
+            .chain([source.len() as u32])
+            .collect();
+        Self { offsets }
+    }
+    /// Byte offset of `loc`; the source length if it is out of the source
+    pub fn byte_offset(&self, loc: Loc) -> u32 {
+        self.checked_byte_offset(loc)
+            .unwrap_or(*self.offsets.last().unwrap())
+    }
+    /// Byte offset of `loc`; `None` if it is past the end of the source
+    pub fn checked_byte_offset(&self, loc: Loc) -> Option<u32> {
+        self.offsets.get(loc.0 as usize).copied()
+    }
+    pub fn dual_range(&self, range: Range) -> DualRange {
+        DualRange {
+            range,
+            bytes: Some(ByteRange {
+                from: self.byte_offset(range.from()),
+                until: self.byte_offset(range.until()),
+            }),
+        }
+    }
+}
+
+/// [`Loc`] to line and column map of a source, built once per file
+///
+/// Gives the same positions as [`index_to_line_char`] without rescanning the source.
+pub struct LineIndex {
+    /// [`Loc`] of the first char of each line
+    line_starts: Vec<u32>,
+    /// number of chars except CR
+    len: u32,
+}
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        let mut len = 0;
+        // it seems that the compiler is ignoring CR
+        for c in source.chars().filter(|c| *c != '\r') {
+            len += 1;
+            if c == '\n' {
+                line_starts.push(len);
+            }
+        }
+        Self { line_starts, len }
+    }
+    /// 0-based line and column of `loc`; the end of the source if it is out of the source
+    pub fn line_char(&self, loc: Loc) -> (u32, u32) {
+        let loc = loc.0.min(self.len);
+        let line = self.line_starts.partition_point(|start| *start <= loc) - 1;
+        (line as u32, loc - self.line_starts[line])
+    }
+}
+
+/// Read a source file, transcoding it to UTF-8 as `RUSTOWL_SOURCE_ENCODING` says.
+///
+/// See [`decode_source`]. Positions are relative to the transcoded text.
+pub fn read_source(path: &Path) -> std::io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    let encoding = std::env::var("RUSTOWL_SOURCE_ENCODING").ok();
+    decode_source(bytes, encoding.as_deref())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// Decode a source file which may not be UTF-8.
+///
+/// `encoding` is `None` to accept UTF-8 only, `auto` to detect the encoding,
+/// or a label such as `shift_jis` or `latin1`. UTF-8 sources are always taken as is.
+pub fn decode_source(bytes: Vec<u8>, encoding: Option<&str>) -> Result<String, String> {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(source) => return Ok(source),
+        Err(e) => e.into_bytes(),
+    };
+    let encoding = match encoding.map(str::trim) {
+        None | Some("") => return Err("source is not valid UTF-8".to_owned()),
+        Some("auto") => {
+            let mut detector =
+                chardetng::EncodingDetector::new(chardetng::Iso2022JpDetection::Allow);
+            detector.feed(&bytes, true);
+            // the source is already known not to be UTF-8
+            detector.guess(None, chardetng::Utf8Detection::Deny)
+        }
+        Some(label) => encoding_rs::Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| format!("unknown encoding `{label}`"))?,
+    };
+    let (source, _, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        return Err(format!("source is not valid {}", encoding.name()));
+    }
+    log::debug!("source transcoded from {}", encoding.name());
+    Ok(source.into_owned())
+}
+
+/// Whether `path` points into a build output directory.
+///
+/// Code generated by `build.rs` into `OUT_DIR` and `include!`'d has spans
+/// pointing into the target directory, which editors cannot map to a buffer.
+pub fn is_generated_file(path: &Path, out_dir: Option<&Path>, target_dir: Option<&Path>) -> bool {
+    out_dir.is_some_and(|dir| path.starts_with(dir))
+        || target_dir.is_some_and(|dir| path.starts_with(dir))
+}
+
+/// Lock `mutex`, recovering it if a thread panicked while holding it
+///
+/// A panic while analyzing one function should not abort the whole run,
+/// so the data is used as is and the poison is cleared.
+pub fn lock_or_recover<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        log::warn!("recovered {name} lock poisoned by a panicked thread");
+        mutex.clear_poison();
+        e.into_inner()
+    })
+}
+
+const MIN_WORKER_THREADS: usize = 2;
+const MAX_WORKER_THREADS: usize = 8;
+/// Used when the available parallelism cannot be detected, as in some sandboxes
+const FALLBACK_WORKER_THREADS: usize = 4;
+
+/// Variables overriding the number of worker threads, in order of precedence;
+/// `RUSTOWL_PARALLELISM` is the older name
+const WORKER_THREADS_VARS: [&str; 2] = ["RUSTOWL_ANALYSIS_THREADS", "RUSTOWL_PARALLELISM"];
+
+/// Number of worker threads for analysis
+///
+/// `RUSTOWL_ANALYSIS_THREADS`, set by `rustowl --analysis-threads`, takes
+/// precedence; otherwise half of the available parallelism is used, within
+/// a small range. Resolved and logged once per process.
+pub fn worker_threads() -> usize {
+    static THREADS: LazyLock<usize> = LazyLock::new(|| {
+        let threads = worker_threads_with(
+            |var| std::env::var(var).ok(),
+            std::thread::available_parallelism().ok().map(|v| v.get()),
+        );
+        log::debug!("analysis runs on {threads} worker threads");
+        threads
+    });
+    *THREADS
+}
+
+fn worker_threads_with(env: impl Fn(&str) -> Option<String>, available: Option<usize>) -> usize {
+    for var in WORKER_THREADS_VARS {
+        if let Some(value) = env(var) {
+            match value.trim().parse::<usize>() {
+                Ok(n) if 0 < n => return n,
+                _ => log::warn!("invalid {var} `{value}`; expected at least 1"),
+            }
+        }
+    }
+    available
+        .map(|n| (n / 2).clamp(MIN_WORKER_THREADS, MAX_WORKER_THREADS))
+        .unwrap_or(FALLBACK_WORKER_THREADS)
+}
+
+/// Doc comment line that excludes a function from the output,
+/// where `#[rustowl::ignore]` cannot be used
+pub const IGNORE_DOC_MARKER: &str = "rustowl::ignore";
+
+/// Whether an attribute excludes the function from the output.
+///
+/// `path` is the attribute path, e.g. `["rustowl", "ignore"]`,
+/// and `doc` is the text of a doc comment attribute.
+pub fn is_ignore_attribute(path: &[&str], doc: Option<&str>) -> bool {
+    path == ["rustowl", "ignore"]
+        || doc.is_some_and(|doc| doc.lines().any(|line| line.trim() == IGNORE_DOC_MARKER))
+}
+
+/// Whether `ty` is the type of the descriptor the test harness generates for
+/// each `#[test]` function, which is how test functions are recognized.
+///
+/// The path is relative to the module, e.g. `tests::test::TestDescAndFn`.
+pub fn is_test_descriptor_type(ty: &str) -> bool {
+    ty == "TestDescAndFn" || ty.ends_with("test::TestDescAndFn")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mir_visit_hooks() {
+        #[derive(Default)]
+        struct Counter {
+            events: Vec<&'static str>,
+            mutable_refs: usize,
+        }
+        impl MirVisitor for Counter {
+            fn visit_func(&mut self, _func: &Function) {
+                self.events.push("func");
+            }
+            fn visit_basic_block(&mut self, _bb: &MirBasicBlock) {
+                self.events.push("bb");
+            }
+            fn visit_stmt(&mut self, _stmt: &MirStatement) {
+                self.events.push("stmt");
+            }
+            fn visit_rval(&mut self, rval: &MirRval) {
+                self.events.push("rval");
+                if let MirRval::Ref { mutable: true, .. } = rval {
+                    self.mutable_refs += 1;
+                }
+            }
+            fn visit_term(&mut self, _term: &MirTerminator) {
+                self.events.push("term");
+            }
+        }
+
+        let place = |id| MirPlace {
+            local: FnLocal::new(id, 1),
+            projection: Vec::new(),
+        };
+        let stmt = |kind| MirStatement { kind, range: None };
+        let block = |statements| MirBasicBlock {
+            statements,
+            terminator: MirTerminator {
+                kind: MirTerminatorKind::Return,
+                range: None,
+            },
+            is_cleanup: false,
+        };
+        let func = Function {
+            fn_id: 1,
+            name: "f".to_owned(),
+            span: None,
+            test: false,
+            unavailable: false,
+            basic_blocks: vec![
+                block(vec![
+                    stmt(MirStatementKind::StorageLive {
+                        local: FnLocal::new(1, 1),
+                    }),
+                    stmt(MirStatementKind::Assign {
+                        place: place(1),
+                        rval: MirRval::Ref {
+                            place: place(2),
+                            mutable: true,
+                            reason: None,
+                        },
+                    }),
+                ]),
+                block(vec![stmt(MirStatementKind::Assign {
+                    place: place(3),
+                    rval: MirRval::Use {
+                        operand: MirOperand::Copy { place: place(1) },
+                    },
+                })]),
+                block(Vec::new()),
+            ],
+            decls: Vec::new(),
+        };
+
+        let mut counter = Counter::default();
+        mir_visit(&func, &mut counter);
+        assert_eq!(
+            counter.events,
+            [
+                "func", "bb", "stmt", "stmt", "rval", "term", "bb", "stmt", "rval", "term", "bb",
+                "term"
+            ]
+        );
+        assert_eq!(counter.mutable_refs, 1);
+    }
+
+    #[test]
+    fn test_descriptor_type() {
+        assert!(is_test_descriptor_type("test::TestDescAndFn"));
+        assert!(is_test_descriptor_type("tests::test::TestDescAndFn"));
+        assert!(!is_test_descriptor_type("test::TestDesc"));
+        assert!(!is_test_descriptor_type("i32"));
+    }
+
+    #[test]
+    fn coalesce_fragmented_ranges() {
+        let r = |from: u32, until: u32| Range::new(Loc(from), Loc(until)).unwrap();
+        let fragmented = vec![r(20, 25), r(0, 5), r(6, 10), r(10, 12)];
+
+        assert_eq!(
+            coalesce_ranges(fragmented.clone(), 0),
+            vec![r(0, 5), r(6, 12), r(20, 25)]
+        );
+        assert_eq!(
+            coalesce_ranges(fragmented.clone(), 1),
+            vec![r(0, 12), r(20, 25)]
+        );
+        assert_eq!(coalesce_ranges(fragmented, 8), vec![r(0, 25)]);
+        assert_eq!(
+            coalesce_ranges(
+                vec![r(u32::MAX - 3, u32::MAX - 2), r(u32::MAX - 1, u32::MAX)],
+                1
+            ),
+            vec![r(u32::MAX - 3, u32::MAX)]
+        );
+        assert_eq!(coalesce_ranges(Vec::new(), 1), Vec::new());
+    }
+
+    #[test]
+    fn ranges_at_upper_bound() {
+        let r = |from: u32, until: u32| Range::new(Loc(from), Loc(until)).unwrap();
+        let last = r(u32::MAX - 1, u32::MAX);
+        let before = r(u32::MAX - 10, u32::MAX - 1);
+
+        assert_eq!(Loc(u32::MAX) + 1, Loc(u32::MAX));
+        assert_eq!(Loc(u32::MAX) - -1, Loc(u32::MAX));
+        assert_eq!(Loc(3) - 5, Loc(0));
+        assert_eq!(Loc(3) + -5, Loc(0));
+        assert_eq!(Loc(u32::MAX) - 1, Loc(u32::MAX - 1));
+
+        assert_eq!(merge_ranges(before, last), Some(r(u32::MAX - 10, u32::MAX)));
+        assert_eq!(
+            eliminated_ranges(vec![last, before, last]),
+            vec![r(u32::MAX - 10, u32::MAX)]
+        );
+        assert_eq!(common_range(before, last), None);
+        assert_eq!(
+            union_ranges(vec![last], vec![before]),
+            vec![r(u32::MAX - 10, u32::MAX)]
+        );
+
+        // nothing is left after the excluded range at the upper bound
+        assert_eq!(
+            exclude_ranges(vec![r(u32::MAX - 10, u32::MAX)], vec![last]),
+            vec![r(u32::MAX - 10, u32::MAX - 2)]
+        );
+        assert_eq!(exclude_ranges(vec![last], vec![last]), Vec::new());
+        assert_eq!(exclude_ranges(vec![last], vec![before]), vec![last]);
+    }
+
+    /// `exclude_ranges` before the sweep, splitting each range by the first
+    /// overlapping exclude in the order given, until none overlaps
+    fn exclude_ranges_baseline(mut from: Vec<Range>, excludes: Vec<Range>) -> Vec<Range> {
+        let mut i = 0;
+        'outer: while i < from.len() {
+            let mut j = 0;
+            while j < excludes.len() {
+                if let Some(common) = common_range(from[i], excludes[j]) {
+                    if let Some(r) = Range::new(from[i].from(), common.from() - 1) {
+                        from.push(r);
+                    }
+                    if let Some(r) = Range::new(common.until() + 1, from[i].until()) {
+                        from.push(r);
+                    }
+                    from.remove(i);
+                    continue 'outer;
+                }
+                j += 1;
+            }
+            i += 1;
+        }
+        eliminated_ranges(from)
+    }
+
+    #[test]
+    fn exclude_ranges_sweep_matches_baseline() {
+        let r = |from, until| Range::new(Loc(from), Loc(until)).unwrap();
+        let mut state = 0x9e37_79b9_u32;
+        let mut next = |bound: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % bound
+        };
+        let baseline = |from, excludes| {
+            let mut ranges = exclude_ranges_baseline(from, excludes);
+            ranges.sort_by_key(|v| v.from());
+            ranges
+        };
+        for _ in 0..1000 {
+            let from: Vec<_> = (0..next(6))
+                .map(|_| {
+                    let start = next(50);
+                    r(start, start + 1 + next(15))
+                })
+                .collect();
+            // separated excludes, given out of order
+            let mut excludes = Vec::new();
+            let mut start = next(5);
+            while start < 60 {
+                let until = start + 1 + next(4);
+                excludes.push(r(start, until));
+                start = until + 1 + next(6);
+            }
+            excludes.reverse();
+            assert_eq!(
+                exclude_ranges(from.clone(), excludes.clone()),
+                baseline(from.clone(), excludes),
+                "{from:?}"
+            );
+
+            // where excludes overlap or touch, the baseline depends on their
+            // order; the sweep excludes their union
+            let excludes: Vec<_> = (0..next(6))
+                .map(|_| {
+                    let start = next(50);
+                    r(start, start + 1 + next(6))
+                })
+                .collect();
+            assert_eq!(
+                exclude_ranges(from.clone(), excludes.clone()),
+                baseline(from.clone(), normalize_ranges(excludes.clone())),
+                "{from:?} {excludes:?}"
+            );
+        }
+
+        // the whole range is excluded
+        assert_eq!(exclude_ranges(vec![r(5, 10)], vec![r(0, 20)]), Vec::new());
+        assert_eq!(exclude_ranges(vec![r(5, 10)], vec![r(5, 10)]), Vec::new());
+        assert_eq!(exclude_ranges(Vec::new(), vec![r(5, 10)]), Vec::new());
+        assert_eq!(exclude_ranges(vec![r(5, 10)], Vec::new()), vec![r(5, 10)]);
+        // one position around the exclude is cut too, but not of ranges only touching it
+        assert_eq!(
+            exclude_ranges(vec![r(0, 20)], vec![r(8, 10)]),
+            vec![r(0, 7), r(11, 20)]
+        );
+        assert_eq!(
+            exclude_ranges(vec![r(0, 8), r(10, 20)], vec![r(8, 10)]),
+            vec![r(0, 8), r(10, 20)]
+        );
+        // touching excludes are merged, whatever their order
+        for excludes in [vec![r(5, 7), r(4, 5)], vec![r(4, 5), r(5, 7)]] {
+            assert_eq!(
+                exclude_ranges(vec![r(0, 10)], excludes),
+                vec![r(0, 3), r(8, 10)]
+            );
+        }
+    }
+
+    #[test]
+    fn normalized_ranges() {
+        let r = |from, until| Range::new(Loc(from), Loc(until)).unwrap();
+        let canonical = vec![r(0, 10), r(12, 20)];
+        assert_eq!(normalize_ranges(vec![r(12, 20), r(0, 10)]), canonical);
+        assert_eq!(
+            normalize_ranges(vec![r(5, 10), r(12, 20), r(0, 5)]),
+            canonical
+        );
+        assert_eq!(
+            normalize_ranges(vec![r(0, 3), r(2, 8), r(8, 10), r(12, 15), r(15, 20)]),
+            canonical
+        );
+        // a gap is not bridged
+        assert_ne!(
+            normalize_ranges(vec![r(0, 4), r(5, 10), r(12, 20)]),
+            canonical
+        );
+        assert_ne!(normalize_ranges(vec![r(0, 10), r(12, 21)]), canonical);
+    }
+
+    #[test]
+    fn line_index_matches_index_to_line_char() {
+        for source in ["", "fn f() {}", "a\nb日本\n\nc\n", "\n\nlet é = 1;"] {
+            let index = LineIndex::new(source);
+            let len = source.chars().filter(|c| *c != '\r').count() as u32;
+            for i in 0..len + 3 {
+                assert_eq!(
+                    index.line_char(Loc(i)),
+                    index_to_line_char(source, Loc(i)),
+                    "{source:?} at {i}"
+                );
+            }
+        }
+        // CRs are not counted, like `Loc::new` does
+        let index = LineIndex::new("a\r\nb\r\nc");
+        assert_eq!(index.line_char(Loc(2)), (1, 0));
+        assert_eq!(index.line_char(Loc(5)), (2, 1));
+    }
+
+    #[test]
+    fn byte_ranges_of_multibyte_source() {
+        let source = "let s = \"日本語\";\r\nlet é = s;\n";
+        let index = ByteIndex::new(source);
+        let byte_offset = |loc: Loc| {
+            source
+                .char_indices()
+                .filter(|(_, c)| *c != '\r')
+                .nth(loc.0 as usize)
+                .map(|(i, _)| i as u32)
+                .unwrap_or(source.len() as u32)
+        };
+        for from in 0..25 {
+            for until in from + 1..26 {
+                let range = Range::new(Loc(from), Loc(until)).unwrap();
+                let dual = index.dual_range(range);
+                assert_eq!(dual.range, range);
+                let bytes = dual.bytes.unwrap();
+                assert_eq!(bytes.from, byte_offset(range.from()));
+                assert_eq!(bytes.until, byte_offset(range.until()));
+            }
+        }
+        // chars of `日本語` take 3 bytes each and CR is skipped
+        let e = Range::new(Loc(19), Loc(20)).unwrap();
+        let bytes = index.dual_range(e).bytes.unwrap();
+        assert_eq!(&source[bytes.from as usize..bytes.until as usize], "é");
+    }
+
+    #[test]
+    fn shift_jis_source() {
+        let text = "fn main() {\n    let 挨拶 = \"こんにちは、世界。日本語のソースです。\";\n}\n";
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode(text);
+        let bytes = bytes.into_owned();
+        assert!(std::str::from_utf8(&bytes).is_err());
+
+        assert!(decode_source(bytes.clone(), None).is_err());
+        assert!(decode_source(bytes.clone(), Some("no-such-encoding")).is_err());
+        for encoding in ["shift_jis", "auto"] {
+            let source = decode_source(bytes.clone(), Some(encoding)).unwrap();
+            assert_eq!(source, text);
+            // positions are chars of the transcoded text
+            let name = Loc(line_char_to_index(&source, 1, 8));
+            assert_eq!(index_to_line_char(&source, name), (1, 8));
+            let range = Range::new(name, name + 2).unwrap();
+            assert_eq!(range.text(&source), Some("挨拶"));
+        }
+
+        // UTF-8 is taken as is regardless of the setting
+        let utf8 = text.as_bytes().to_vec();
+        assert_eq!(decode_source(utf8.clone(), None).unwrap(), text);
+        assert_eq!(decode_source(utf8, Some("shift_jis")).unwrap(), text);
+    }
+
+    #[test]
+    fn poisoned_lock_recovery() {
+        let mutex = Mutex::new(vec![1]);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = mutex.lock().unwrap();
+                guard.push(2);
+                panic!("analysis panicked");
+            })
+            .join()
+            .unwrap_err();
+        });
+        assert!(mutex.is_poisoned());
+
+        let mut guard = lock_or_recover(&mutex, "test");
+        assert_eq!(*guard, vec![1, 2]);
+        guard.push(3);
+        drop(guard);
+        assert!(!mutex.is_poisoned());
+        assert_eq!(*mutex.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn worker_thread_count() {
+        let env = |vars: &[(&str, &str)]| {
+            let vars: Vec<_> = vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            move |var: &str| vars.iter().find(|(k, _)| k == var).map(|(_, v)| v.clone())
+        };
+        let threads = |value| env(&[("RUSTOWL_ANALYSIS_THREADS", value)]);
+
+        // env override wins
+        assert_eq!(worker_threads_with(threads("16"), Some(8)), 16);
+        assert_eq!(worker_threads_with(threads("3"), Some(64)), 3);
+        assert_eq!(worker_threads_with(threads(" 32 "), None), 32);
+        // invalid overrides fall back to the heuristic
+        assert_eq!(worker_threads_with(threads("0"), Some(8)), 4);
+        assert_eq!(worker_threads_with(threads("-2"), Some(12)), 6);
+        assert_eq!(
+            worker_threads_with(threads("many"), None),
+            FALLBACK_WORKER_THREADS
+        );
+        // the older name is still read, after the new one
+        let both = |value| {
+            env(&[
+                ("RUSTOWL_ANALYSIS_THREADS", value),
+                ("RUSTOWL_PARALLELISM", "5"),
+            ])
+        };
+        assert_eq!(worker_threads_with(both("2"), Some(8)), 2);
+        assert_eq!(worker_threads_with(both("none"), Some(8)), 5);
+        // detection failed
+        assert_eq!(worker_threads_with(env(&[]), None), FALLBACK_WORKER_THREADS);
+        // clamped
+        assert_eq!(worker_threads_with(env(&[]), Some(1)), MIN_WORKER_THREADS);
+        assert_eq!(worker_threads_with(env(&[]), Some(12)), 6);
+        assert_eq!(worker_threads_with(env(&[]), Some(128)), MAX_WORKER_THREADS);
+    }
+
+    #[test]
+    fn ignore_attribute_detection() {
+        let ignored = |attrs: &[(&[&str], Option<&str>)]| {
+            attrs
+                .iter()
+                .any(|(path, doc)| is_ignore_attribute(path, *doc))
+        };
+        assert!(ignored(&[(&["rustowl", "ignore"], None)]));
+        assert!(ignored(&[
+            (&["inline"], None),
+            (&["doc"], Some(" Generated code.\n rustowl::ignore")),
+        ]));
+        assert!(!ignored(&[]));
+        assert!(!ignored(&[(&["allow"], None), (&["ignore"], None)]));
+        assert!(!ignored(&[(&["rustowl", "other"], None)]));
+        assert!(!ignored(&[(&["doc"], Some(" see `rustowl::ignore`"))]));
+    }
+
+    #[test]
+    fn tab_aware_columns() {
+        let source = "\tlet a = 1;\nlet\tb\t= 2;\n";
+        let loc_of = |line: usize, needle: &str| {
+            let start: usize = source
+                .lines()
+                .take(line)
+                .map(|l| l.chars().count() + 1)
+                .sum();
+            let col = source.lines().nth(line).unwrap().find(needle).unwrap();
+            Loc((start + col) as u32)
+        };
+
+        // leading tab
+        assert_eq!(index_to_line_char_tabs(source, loc_of(0, "let"), 4), (0, 4));
+        assert_eq!(index_to_line_char_tabs(source, loc_of(0, "let"), 8), (0, 8));
+        assert_eq!(index_to_line_char(source, loc_of(0, "let")), (0, 1));
+
+        // embedded tabs advance to the next tab stop
+        assert_eq!(index_to_line_char_tabs(source, loc_of(1, "b"), 4), (1, 4));
+        assert_eq!(index_to_line_char_tabs(source, loc_of(1, "="), 4), (1, 8));
+        assert_eq!(index_to_line_char_tabs(source, loc_of(1, "b"), 8), (1, 8));
+        assert_eq!(index_to_line_char_tabs(source, loc_of(1, "="), 8), (1, 16));
+        assert_eq!(index_to_line_char(source, loc_of(1, "="),), (1, 6));
+    }
+
+    #[test]
+    fn utf16_columns() {
+        // the crab is one char but two UTF-16 code units
+        let source = "a\u{1f980}b";
+        assert_eq!(index_to_line_char(source, Loc(2)), (0, 2));
+        assert_eq!(index_to_line_utf16(source, Loc(1)), (0, 1));
+        assert_eq!(index_to_line_utf16(source, Loc(2)), (0, 3));
+        assert_eq!(index_to_line_utf16(source, Loc(3)), (0, 4));
+        for loc in 0..3 {
+            let (line, col) = index_to_line_utf16(source, Loc(loc));
+            assert_eq!(line_utf16_to_index(source, line, col), loc);
+        }
+        // inside the surrogate pair
+        assert_eq!(line_utf16_to_index(source, 0, 2), 2);
+
+        // CR is skipped as by the compiler
+        let source = "\u{1f980}\r\n\u{1f980}b";
+        assert_eq!(index_to_line_utf16(source, Loc(3)), (1, 2));
+        assert_eq!(line_utf16_to_index(source, 1, 2), 3);
+
+        // four UTF-8 bytes
+        let source = "a\u{1f980}b";
+        assert_eq!(index_to_line_utf8(source, Loc(2)), (0, 5));
+        assert_eq!(line_utf8_to_index(source, 0, 5), 2);
+        assert_eq!(line_utf8_to_index(source, 0, 3), 2);
+    }
+
+    #[test]
+    fn grapheme_columns() {
+        // thumbs up with a skin tone: two chars, one grapheme
+        let source = "a\u{1f44d}\u{1f3fd}b";
+        let b = Loc(3);
+        assert_eq!(index_to_line_char(source, b), (0, 3));
+        assert_eq!(index_to_line_grapheme(source, b), (0, 2));
+        assert_eq!(line_grapheme_to_index(source, 0, 2), 3);
+        // the modifier is in the cluster of the emoji
+        assert_eq!(index_to_line_grapheme(source, Loc(2)), (0, 1));
+        assert_eq!(line_grapheme_to_index(source, 0, 1), 1);
+
+        // `e` followed by combining acute and grave accents
+        let source = "let e\u{301}\u{300} = 1;\r\nx\u{301}y";
+        let eq = Loc(source.chars().position(|c| c == '=').unwrap() as u32);
+        assert_eq!(index_to_line_char(source, eq), (0, 8));
+        assert_eq!(index_to_line_grapheme(source, eq), (0, 6));
+        assert_eq!(line_grapheme_to_index(source, 0, 6), eq.0);
+        // CR is skipped as by the compiler
+        let y = Loc(source
+            .replace('\r', "")
+            .chars()
+            .position(|c| c == 'y')
+            .unwrap() as u32);
+        assert_eq!(index_to_line_utf16(source, y), (1, 2));
+        assert_eq!(index_to_line_grapheme(source, y), (1, 1));
+        assert_eq!(line_grapheme_to_index(source, 1, 1), y.0);
+
+        // plain ASCII is the same either way
+        let source = "fn main() {\n    let v = 1;\n}";
+        for loc in 0..source.len() as u32 {
+            let (line, col) = index_to_line_grapheme(source, Loc(loc));
+            assert_eq!((line, col), index_to_line_char(source, Loc(loc)));
+            assert_eq!(line_grapheme_to_index(source, line, col), loc);
+        }
+    }
+
+    #[test]
+    fn generated_file_detection() {
+        let target = Path::new("/work/project/target/owl");
+        let out_dir = Path::new("/work/project/target/owl/debug/build/foo-1234/out");
+
+        assert!(is_generated_file(
+            &out_dir.join("bindings.rs"),
+            Some(out_dir),
+            Some(target)
+        ));
+        assert!(is_generated_file(
+            &target.join("debug/build/bar-5678/out/gen.rs"),
+            None,
+            Some(target)
+        ));
+        assert!(!is_generated_file(
+            Path::new("/work/project/src/lib.rs"),
+            Some(out_dir),
+            Some(target)
+        ));
+        assert!(!is_generated_file(
+            Path::new("/work/project/src/lib.rs"),
+            None,
+            None
+        ));
+        // a sibling directory sharing the prefix is not inside the target dir
+        assert!(!is_generated_file(
+            Path::new("/work/project/target/owl-src/lib.rs"),
+            None,
+            Some(target)
+        ));
+    }
+}
@@ -509,7 +509,7 @@ pub fn show_variable(
             .ok_or_else(|| VisualizeError::FileNotFound(file_path_str.clone()))?;
 
         // Read the source file
-        let source = std::fs::read_to_string(file_path)?;
+        let source = utils::read_source(file_path)?;
         let renderer = CliRenderer::new(&source);
 
         // Calculate decorations for this variable
@@ -580,15 +580,18 @@ fn print_legend() {
 #[cfg(test)]
 mod tests {
     use super::find_file;
-    use crate::models::{Crate, File};
-    use std::collections::HashMap;
+    use crate::models::{Crate, File, OutputMap};
     use std::path::Path;
 
     #[test]
     fn find_file_matches_relative_suffix() {
-        let crate_data = Crate(HashMap::from([(
+        let crate_data = Crate(OutputMap::from_iter([(
             String::from("algo-tests/src/vec.rs"),
-            File { items: vec![] },
+            File {
+                items: vec![],
+                generated: false,
+                std: false,
+            },
         )]));
 
         let file = find_file(&crate_data, Path::new("./algo-tests/src/vec.rs"));
@@ -598,9 +601,13 @@ mod tests {
     #[cfg(windows)]
     #[test]
     fn find_file_matches_windows_verbatim_path() {
-        let crate_data = Crate(HashMap::from([(
+        let crate_data = Crate(OutputMap::from_iter([(
             String::from("C:/repo/algo-tests/src/vec.rs"),
-            File { items: vec![] },
+            File {
+                items: vec![],
+                generated: false,
+                std: false,
+            },
         )]));
 
         let file = find_file(&crate_data, Path::new(r"\\?\C:\repo\algo-tests\src\vec.rs"));
@@ -1,49 +1,12 @@
-use std::process::Command;
-use std::sync::Once;
-
-static BUILD_ONCE: Once = Once::new();
+mod common;
 
-fn ensure_rustowl_built() {
-    BUILD_ONCE.call_once(|| {
-        let mut cmd = Command::new("cargo");
-        if cfg!(windows) {
-            cmd.args(["build", "--profile", "windows-release"]);
-        } else {
-            cmd.args(["build", "--release"]);
-        }
-        let output = cmd
-            .output()
-            .unwrap_or_else(|e| panic!("Failed to execute cargo build: {e}"));
-        assert!(
-            output.status.success(),
-            "Failed to build rustowl.\nstdout: {}\nstderr: {}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        );
-    });
-}
+use common::{ensure_rustowl_built, rustowl_path};
+use std::process::Command;
 
 fn get_rustowl_output(function_path: &str, variable: &str) -> String {
     ensure_rustowl_built();
 
-    let exe_name = if cfg!(windows) {
-        "rustowl.exe"
-    } else {
-        "rustowl"
-    };
-    let profile_dir = if cfg!(windows) {
-        "windows-release"
-    } else {
-        "release"
-    };
-
-    let rustowl_path = format!(
-        "target{}{}{}{}",
-        std::path::MAIN_SEPARATOR,
-        profile_dir,
-        std::path::MAIN_SEPARATOR,
-        exe_name
-    );
+    let rustowl_path = rustowl_path();
 
     let output = Command::new(&rustowl_path)
         .args([
@@ -0,0 +1,42 @@
+//! Helpers shared by the integration tests, which run the release binary
+
+use std::path::MAIN_SEPARATOR;
+use std::process::Command;
+use std::sync::Once;
+
+static BUILD_ONCE: Once = Once::new();
+
+pub fn ensure_rustowl_built() {
+    BUILD_ONCE.call_once(|| {
+        let mut cmd = Command::new("cargo");
+        if cfg!(windows) {
+            cmd.args(["build", "--profile", "windows-release"]);
+        } else {
+            cmd.args(["build", "--release"]);
+        }
+        let output = cmd
+            .output()
+            .unwrap_or_else(|e| panic!("Failed to execute cargo build: {e}"));
+        assert!(
+            output.status.success(),
+            "Failed to build rustowl.\nstdout: {}\nstderr: {}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    });
+}
+
+/// Path of the binary built by [`ensure_rustowl_built`]
+pub fn rustowl_path() -> String {
+    let exe_name = if cfg!(windows) {
+        "rustowl.exe"
+    } else {
+        "rustowl"
+    };
+    let profile_dir = if cfg!(windows) {
+        "windows-release"
+    } else {
+        "release"
+    };
+    format!("target{MAIN_SEPARATOR}{profile_dir}{MAIN_SEPARATOR}{exe_name}")
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{ensure_rustowl_built, rustowl_path};
+use rustowl::models::Workspace;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Names of the functions analyzed in `feature-tests` with the extra `args`
+fn analyzed_functions(output_name: &str, args: &[&str]) -> Vec<String> {
+    ensure_rustowl_built();
+
+    let rustowl_path = rustowl_path();
+    let output_dir: PathBuf = ["target", "feature-tests", output_name].iter().collect();
+    if output_dir.exists() {
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    let output = Command::new(&rustowl_path)
+        .args(["check", "feature-tests", "--output-dir"])
+        .arg(&output_dir)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("Failed to execute {rustowl_path}: {e}"));
+    assert!(
+        output.status.success(),
+        "{rustowl_path} command failed.\nstdout: {}\nstderr: {}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(&output_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let file = std::fs::File::open(Path::new(&path)).unwrap();
+        let ws = Workspace::from_json_reader(file).unwrap();
+        for krate in ws.0.values() {
+            for file in krate.0.values() {
+                names.extend(file.items.iter().map(|v| v.name.clone()));
+            }
+        }
+    }
+    names
+}
+
+fn has_function(names: &[String], name: &str) -> bool {
+    names.iter().any(|v| v.rsplit("::").next() == Some(name))
+}
+
+#[test]
+fn test_feature_gated_function() {
+    let names = analyzed_functions("default", &[]);
+    assert!(has_function(&names, "always"), "{names:?}");
+    assert!(!has_function(&names, "gated"), "{names:?}");
+
+    let names = analyzed_functions("gated", &["--features", "gated"]);
+    assert!(has_function(&names, "always"), "{names:?}");
+    assert!(has_function(&names, "gated"), "{names:?}");
+
+    let names = analyzed_functions(
+        "package",
+        &["-p", "feature-tests", "-F", "feature-tests/gated"],
+    );
+    assert!(has_function(&names, "gated"), "{names:?}");
+}
+
+#[test]
+fn test_features_without_output_dir() {
+    ensure_rustowl_built();
+
+    // only an analysis with the feature enabled fails
+    let check = |args: &[&str]| {
+        Command::new(rustowl_path())
+            .args(["check", "feature-tests"])
+            .args(args)
+            .status()
+            .unwrap_or_else(|e| panic!("Failed to execute rustowl: {e}"))
+            .success()
+    };
+    assert!(check(&[]));
+    assert!(!check(&["--features", "broken"]));
+}